@@ -0,0 +1,54 @@
+//! Lock-free access counters. Every managed segment gets a dense index into a pre-sized
+//! array of atomics, so counting an access on the datapath is a single relaxed `fetch_add`;
+//! the pin/unpin thread drains the counters into the cache builder once per repin cycle.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug)]
+pub struct AtomicAccessCounts {
+    counts: Box<[AtomicU64]>,
+}
+
+impl AtomicAccessCounts {
+    pub fn new(capacity: usize) -> Self {
+        AtomicAccessCounts {
+            counts: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Count one access. Returns false if `index` is beyond the pre-sized capacity.
+    pub fn increment(&self, index: usize) -> bool {
+        match self.counts.get(index) {
+            Some(count) => {
+                count.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Accesses counted since the last drain.
+    pub fn get(&self, index: usize) -> u64 {
+        self.counts
+            .get(index)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Take the accesses counted since the last drain, resetting the counter to zero.
+    pub fn drain(&self, index: usize) -> u64 {
+        self.counts
+            .get(index)
+            .map(|count| count.swap(0, Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    pub fn clear(&self) {
+        for count in self.counts.iter() {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Async pinning engine for tokio-based servers. `run_pin_loop` sleeps with
+//! `tokio::time::sleep` and runs each repin cycle on the blocking pool, since a cycle may wait
+//! for in-flight IO to drain, so it never stalls the runtime's worker threads. An adaptive
+//! repin interval set on the cache replaces the fixed one after the first cycle.
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::data_structures::{DatapathSlab, ZeroCopyCache};
+use crate::segment_policy::SegmentPolicy;
+
+/// Tells a running `run_pin_loop` to stop.
+#[derive(Debug)]
+pub struct PinLoopShutdown {
+    sender: watch::Sender<bool>,
+}
+
+impl PinLoopShutdown {
+    /// A shutdown handle and the receiver to pass to `run_pin_loop`.
+    pub fn new() -> (Self, watch::Receiver<bool>) {
+        let (sender, receiver) = watch::channel(false);
+        (PinLoopShutdown { sender }, receiver)
+    }
+
+    pub fn shutdown(&self) {
+        let _ = self.sender.send(true);
+    }
+}
+
+impl<Slab, CB> ZeroCopyCache<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug + 'static,
+    Slab::PrivateInfo: Send + 'static,
+    CB: SegmentPolicy<Slab> + 'static,
+    Self: Send,
+{
+    /// Run a repin cycle every `interval` until `shutdown` fires, then unpin every segment.
+    /// Returns the number of cycles run.
+    pub async fn run_pin_loop(
+        self,
+        priv_info: Slab::PrivateInfo,
+        interval: Duration,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> u64 {
+        let mut state = Some((self, priv_info));
+        let mut cycles = 0;
+        let mut sleep_for = interval;
+        loop {
+            if *shutdown.borrow() {
+                break;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(sleep_for) => {}
+                changed = shutdown.changed() => {
+                    if changed.is_err() || *shutdown.borrow() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            let (mut zero_copy_cache, priv_info) = state.take().unwrap();
+            let cycle = tokio::task::spawn_blocking(move || {
+                let churn = zero_copy_cache.update_pinned_list(&priv_info);
+                let next = zero_copy_cache.next_repin_interval(&churn, interval);
+                (zero_copy_cache, priv_info, next)
+            });
+            match cycle.await {
+                Ok((zero_copy_cache, priv_info, next)) => {
+                    state = Some((zero_copy_cache, priv_info));
+                    sleep_for = next;
+                }
+                Err(e) => {
+                    tracing::error!("Repin cycle failed: {:?}", e);
+                    return cycles;
+                }
+            }
+            cycles += 1;
+        }
+        let (mut zero_copy_cache, _) = state.take().unwrap();
+        let unpin = tokio::task::spawn_blocking(move || zero_copy_cache.unpin_all_segments());
+        match unpin.await {
+            Ok(unpinned) => tracing::debug!("Pin loop stopped, unpinned {} segments", unpinned),
+            Err(e) => tracing::error!("Unpinning on shutdown failed: {:?}", e),
+        }
+        cycles
+    }
+}
@@ -0,0 +1,252 @@
+//! Named, validated construction of a `ZeroCopyCache`. Every setting starts at the same
+//! default `ZeroCopyCache::new` uses; `build` checks that the settings fit together before
+//! any slab is registered, instead of the first repin cycle misbehaving.
+use std::time::Duration;
+
+use crate::data_structures::{
+    DatapathSlab, DecayPolicy, DecaySchedule, QuiesceTimeout, QuiesceTimeoutAction, ZeroCopyCache,
+    DEFAULT_REPIN_INTERVAL, DEFAULT_SEGMENT_SIZE,
+};
+use crate::segment_policy::SegmentPolicy;
+use crate::sharded::ShardedCacheBuilder;
+
+/// Why `ZeroCopyCacheBuilder::build` rejected a configuration.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ConfigError {
+    ZeroRepinInterval,
+    ZeroSegmentSize,
+    ZeroDecayInterval,
+    /// Not even one segment would fit under the pinning limit.
+    SegmentLargerThanLimit {
+        segment_size: usize,
+        pinning_limit: usize,
+    },
+    /// A pinning limit percentage outside 0 to 100.
+    InvalidPinningLimitPercent(f64),
+    /// A budget split outside 0 to 1.
+    InvalidBudgetSplit(f64),
+    /// A negative hysteresis displace margin.
+    InvalidDisplaceMargin(f64),
+}
+
+pub struct ZeroCopyCacheBuilder<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    cache_builder: ShardedCacheBuilder<Slab, CB>,
+    pinning_limit: Option<usize>,
+    pinning_limit_percent: Option<f64>,
+    segment_size: usize,
+    repin_interval: Duration,
+    decay_schedule: Option<DecaySchedule>,
+    hysteresis: Option<(f64, u64)>,
+    quiesce_timeout: Option<QuiesceTimeout>,
+    budget_split: Option<f64>,
+    max_changes_per_cycle: Option<usize>,
+    min_pin_duration: Option<Duration>,
+    max_overshoot_bytes: Option<usize>,
+    pin_on_demand: bool,
+    slab_pinning_limits: Vec<(Slab::SlabId, usize)>,
+}
+
+impl<Slab, CB> ZeroCopyCacheBuilder<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    pub fn new(cache_builder: CB) -> Self {
+        Self::from_sharded_builder(ShardedCacheBuilder::from_builder(cache_builder))
+    }
+
+    /// See `ZeroCopyCache::new_sharded`.
+    pub fn sharded(num_shards: usize, make_builder: impl FnMut(usize) -> CB) -> Self {
+        Self::from_sharded_builder(ShardedCacheBuilder::new(num_shards, make_builder))
+    }
+
+    pub fn from_sharded_builder(cache_builder: ShardedCacheBuilder<Slab, CB>) -> Self {
+        ZeroCopyCacheBuilder {
+            cache_builder,
+            pinning_limit: None,
+            pinning_limit_percent: None,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            repin_interval: DEFAULT_REPIN_INTERVAL,
+            decay_schedule: None,
+            hysteresis: None,
+            quiesce_timeout: None,
+            budget_split: None,
+            max_changes_per_cycle: None,
+            min_pin_duration: None,
+            max_overshoot_bytes: None,
+            pin_on_demand: false,
+            slab_pinning_limits: Vec::new(),
+        }
+    }
+
+    /// See `ZeroCopyCache::set_pinning_limit`. Replaces a percentage limit.
+    pub fn pinning_limit(mut self, bytes: usize) -> Self {
+        self.pinning_limit = Some(bytes);
+        self.pinning_limit_percent = None;
+        self
+    }
+
+    /// See `ZeroCopyCache::set_pinning_limit_percent`. Replaces a byte limit.
+    pub fn pinning_limit_percent(mut self, percent: f64) -> Self {
+        self.pinning_limit_percent = Some(percent);
+        self.pinning_limit = None;
+        self
+    }
+
+    /// See `ZeroCopyCache::set_segment_size`.
+    pub fn segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// See `ZeroCopyCache::set_repin_interval`.
+    pub fn repin_interval(mut self, interval: Duration) -> Self {
+        self.repin_interval = interval;
+        self
+    }
+
+    /// See `ZeroCopyCache::set_decay_schedule`.
+    pub fn decay(mut self, policy: DecayPolicy, interval: Duration) -> Self {
+        self.decay_schedule = Some(DecaySchedule { policy, interval });
+        self
+    }
+
+    /// See `ZeroCopyCache::set_hysteresis`.
+    pub fn hysteresis(mut self, displace_margin: f64, min_pinned_cycles: u64) -> Self {
+        self.hysteresis = Some((displace_margin, min_pinned_cycles));
+        self
+    }
+
+    /// See `ZeroCopyCache::set_quiesce_timeout`.
+    pub fn quiesce_timeout(mut self, timeout: Duration, action: QuiesceTimeoutAction) -> Self {
+        self.quiesce_timeout = Some(QuiesceTimeout { timeout, action });
+        self
+    }
+
+    /// See `ZeroCopyCache::set_budget_split`.
+    pub fn budget_split(mut self, policy_fraction: f64) -> Self {
+        self.budget_split = Some(policy_fraction);
+        self
+    }
+
+    /// See `ZeroCopyCache::set_max_changes_per_cycle`.
+    pub fn max_changes_per_cycle(mut self, max_changes: usize) -> Self {
+        self.max_changes_per_cycle = Some(max_changes);
+        self
+    }
+
+    /// See `ZeroCopyCache::set_min_pin_duration`.
+    pub fn min_pin_duration(mut self, min_pin_duration: Duration) -> Self {
+        self.min_pin_duration = Some(min_pin_duration);
+        self
+    }
+
+    /// See `ZeroCopyCache::set_max_overshoot_bytes`.
+    pub fn max_overshoot_bytes(mut self, bytes: usize) -> Self {
+        self.max_overshoot_bytes = Some(bytes);
+        self
+    }
+
+    /// See `ZeroCopyCache::set_pin_on_demand`.
+    pub fn pin_on_demand(mut self, pin_on_demand: bool) -> Self {
+        self.pin_on_demand = pin_on_demand;
+        self
+    }
+
+    /// See `ZeroCopyCache::set_slab_pinning_limit`.
+    pub fn slab_pinning_limit(mut self, slab_id: Slab::SlabId, bytes: usize) -> Self {
+        self.slab_pinning_limits.push((slab_id, bytes));
+        self
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.repin_interval.is_zero() {
+            return Err(ConfigError::ZeroRepinInterval);
+        }
+        if self.segment_size == 0 {
+            return Err(ConfigError::ZeroSegmentSize);
+        }
+        if let Some(pinning_limit) = self.pinning_limit {
+            if self.segment_size > pinning_limit {
+                return Err(ConfigError::SegmentLargerThanLimit {
+                    segment_size: self.segment_size,
+                    pinning_limit,
+                });
+            }
+        }
+        if let Some(percent) = self.pinning_limit_percent {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(ConfigError::InvalidPinningLimitPercent(percent));
+            }
+        }
+        if let Some(schedule) = self.decay_schedule {
+            if schedule.interval.is_zero() {
+                return Err(ConfigError::ZeroDecayInterval);
+            }
+        }
+        if let Some(policy_fraction) = self.budget_split {
+            if !(0.0..=1.0).contains(&policy_fraction) {
+                return Err(ConfigError::InvalidBudgetSplit(policy_fraction));
+            }
+        }
+        if let Some((displace_margin, _)) = self.hysteresis {
+            if displace_margin.is_nan() || displace_margin < 0.0 {
+                return Err(ConfigError::InvalidDisplaceMargin(displace_margin));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<ZeroCopyCache<Slab, CB>, ConfigError> {
+        self.validate()?;
+        let mut zero_copy_cache = ZeroCopyCache::from_sharded_builder(self.cache_builder);
+        if let Some(bytes) = self.pinning_limit {
+            zero_copy_cache.set_pinning_limit(bytes);
+        }
+        if let Some(percent) = self.pinning_limit_percent {
+            zero_copy_cache.set_pinning_limit_percent(percent);
+        }
+        zero_copy_cache.set_segment_size(self.segment_size);
+        zero_copy_cache.set_repin_interval(self.repin_interval);
+        if let Some(schedule) = self.decay_schedule {
+            zero_copy_cache.set_decay_schedule(schedule.policy, schedule.interval);
+        }
+        if let Some((displace_margin, min_pinned_cycles)) = self.hysteresis {
+            zero_copy_cache.set_hysteresis(displace_margin, min_pinned_cycles);
+        }
+        if let Some(quiesce_timeout) = self.quiesce_timeout {
+            zero_copy_cache.set_quiesce_timeout(quiesce_timeout.timeout, quiesce_timeout.action);
+        }
+        if let Some(policy_fraction) = self.budget_split {
+            zero_copy_cache.set_budget_split(policy_fraction);
+        }
+        if let Some(max_changes) = self.max_changes_per_cycle {
+            zero_copy_cache.set_max_changes_per_cycle(max_changes);
+        }
+        if let Some(min_pin_duration) = self.min_pin_duration {
+            zero_copy_cache.set_min_pin_duration(min_pin_duration);
+        }
+        if let Some(bytes) = self.max_overshoot_bytes {
+            zero_copy_cache.set_max_overshoot_bytes(bytes);
+        }
+        zero_copy_cache.set_pin_on_demand(self.pin_on_demand);
+        for (slab_id, bytes) in self.slab_pinning_limits {
+            zero_copy_cache.set_slab_pinning_limit(slab_id, bytes);
+        }
+        Ok(zero_copy_cache)
+    }
+}
+
+impl<Slab, CB> ZeroCopyCache<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    pub fn builder(cache_builder: CB) -> ZeroCopyCacheBuilder<Slab, CB> {
+        ZeroCopyCacheBuilder::new(cache_builder)
+    }
+}
@@ -0,0 +1,309 @@
+//! File-based configuration. `ZccConfig` holds the settings a deployment usually keeps in a
+//! config file. With the `serde` feature it (de)serializes with any serde format, and
+//! `from_toml_str` and `from_yaml_str` read it from TOML or YAML; unset keys keep their
+//! defaults and unknown keys are rejected:
+//!
+//! ```toml
+//! cache_type = "ewma"
+//! pinning_limit = 1073741824
+//! segment_size = 2097152
+//! pin_on_demand = true
+//! repin_interval_ms = 500
+//!
+//! [hysteresis]
+//! displace_margin = 0.1
+//! min_pinned_cycles = 2
+//!
+//! [slab_budgets]
+//! 1 = 268435456
+//! ```
+//!
+//! The YAML form has the same keys, with the tables as nested mappings.
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::builder::{ConfigError, ZeroCopyCacheBuilder};
+use crate::data_structures::{
+    DatapathSlab, Hysteresis, ZeroCopyCache, DEFAULT_CACHE_SIZE, DEFAULT_REPIN_INTERVAL,
+    DEFAULT_SEGMENT_SIZE,
+};
+use crate::segment_policy::SegmentPolicy;
+
+/// The access-statistics policies a cache can be configured with.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CacheType {
+    /// `MfuCache`.
+    #[default]
+    Mfu,
+    /// `EwmaCache`.
+    Ewma,
+    /// `WindowCache`.
+    Window,
+    /// `SketchCache`.
+    Sketch,
+    /// `RecencyFrequencyCache`.
+    RecencyFrequency,
+    /// `SpaceSavingCache`.
+    SpaceSaving,
+}
+
+impl CacheType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheType::Mfu => "mfu",
+            CacheType::Ewma => "ewma",
+            CacheType::Window => "window",
+            CacheType::Sketch => "sketch",
+            CacheType::RecencyFrequency => "recency_frequency",
+            CacheType::SpaceSaving => "space_saving",
+        }
+    }
+}
+
+impl FromStr for CacheType {
+    type Err = ConfigParseError;
+
+    /// Parse a cache type name, ignoring case and accepting `-` for `_`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase().replace('-', "_");
+        [
+            CacheType::Mfu,
+            CacheType::Ewma,
+            CacheType::Window,
+            CacheType::Sketch,
+            CacheType::RecencyFrequency,
+            CacheType::SpaceSaving,
+        ]
+        .into_iter()
+        .find(|cache_type| cache_type.as_str() == name)
+        .ok_or_else(|| ConfigParseError::new(0, format!("unknown cache type {:?}", s)))
+    }
+}
+
+/// Why a config file could not be read.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ConfigParseError {
+    /// 1-based line of the offending entry, 0 if not tied to a line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl ConfigParseError {
+    fn new(line: usize, message: String) -> Self {
+        ConfigParseError { line, message }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "SlabId: Hash + Eq + std::fmt::Display",
+        deserialize = "SlabId: Hash + Eq + FromStr + serde::Deserialize<'de>"
+    ))
+)]
+pub struct ZccConfig<SlabId = usize>
+where
+    SlabId: Hash + Eq,
+{
+    pub cache_type: CacheType,
+    /// Hotset size the cache builder picks, in segments.
+    pub num_segments: usize,
+    /// Cap on bytes pinned, if any.
+    pub pinning_limit: Option<usize>,
+    /// Cap on the percentage of managed memory pinned, if any; overrides `pinning_limit`.
+    pub pinning_limit_percent: Option<f64>,
+    /// Size of the segments `add_slab` carves slabs into.
+    pub segment_size: usize,
+    /// Pin missed segments on the request path, see `record_access_as_configured`.
+    pub pin_on_demand: bool,
+    /// Sleep between repin cycles.
+    pub repin_interval_ms: u64,
+    pub hysteresis: Option<Hysteresis>,
+    /// Cap on bytes pinned per slab.
+    #[cfg_attr(feature = "serde", serde(with = "slab_budgets"))]
+    pub slab_budgets: HashMap<SlabId, usize>,
+}
+
+impl<SlabId> Default for ZccConfig<SlabId>
+where
+    SlabId: Hash + Eq,
+{
+    fn default() -> Self {
+        ZccConfig {
+            cache_type: CacheType::default(),
+            num_segments: DEFAULT_CACHE_SIZE,
+            pinning_limit: None,
+            pinning_limit_percent: None,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            pin_on_demand: false,
+            repin_interval_ms: DEFAULT_REPIN_INTERVAL.as_millis() as u64,
+            hysteresis: None,
+            slab_budgets: HashMap::default(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<SlabId> ZccConfig<SlabId>
+where
+    SlabId: Hash + Eq + FromStr + serde::de::DeserializeOwned,
+{
+    pub fn from_toml_str(text: &str) -> Result<Self, ConfigParseError> {
+        toml::from_str(text).map_err(|error| {
+            let line = error
+                .span()
+                .map_or(0, |span| text[..span.start].matches('\n').count() + 1);
+            ConfigParseError::new(line, error.message().to_string())
+        })
+    }
+
+    pub fn from_yaml_str(text: &str) -> Result<Self, ConfigParseError> {
+        serde_yaml::from_str(text).map_err(|error| {
+            let line = error.location().map_or(0, |location| location.line());
+            ConfigParseError::new(line, error.to_string())
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<SlabId> ZccConfig<SlabId>
+where
+    SlabId: Hash + Eq + std::fmt::Display,
+{
+    pub fn to_toml_string(&self) -> Result<String, ConfigParseError> {
+        toml::to_string(self).map_err(|error| ConfigParseError::new(0, error.to_string()))
+    }
+
+    pub fn to_yaml_string(&self) -> Result<String, ConfigParseError> {
+        serde_yaml::to_string(self).map_err(|error| ConfigParseError::new(0, error.to_string()))
+    }
+}
+
+/// `slab_budgets` keyed by slab id. TOML keys are always strings, so ids are written as
+/// strings and parsed back with `FromStr`; ids that arrive as integers, as YAML keys usually
+/// do, are deserialized directly.
+#[cfg(feature = "serde")]
+mod slab_budgets {
+    use std::collections::HashMap;
+    use std::fmt::Display;
+    use std::hash::Hash;
+    use std::marker::PhantomData;
+    use std::str::FromStr;
+
+    use serde::de::value::{I64Deserializer, U64Deserializer};
+    use serde::de::{Error, Visitor};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(PartialEq, Eq, Hash)]
+    struct SlabKey<SlabId>(SlabId);
+
+    struct SlabKeyVisitor<SlabId>(PhantomData<SlabId>);
+
+    impl<'de, SlabId> Visitor<'de> for SlabKeyVisitor<SlabId>
+    where
+        SlabId: FromStr + Deserialize<'de>,
+    {
+        type Value = SlabKey<SlabId>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("a slab id")
+        }
+
+        fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+            value
+                .parse()
+                .map(SlabKey)
+                .map_err(|_| E::custom(format!("invalid slab id {:?}", value)))
+        }
+
+        fn visit_u64<E: Error>(self, value: u64) -> Result<Self::Value, E> {
+            SlabId::deserialize(U64Deserializer::new(value)).map(SlabKey)
+        }
+
+        fn visit_i64<E: Error>(self, value: i64) -> Result<Self::Value, E> {
+            SlabId::deserialize(I64Deserializer::new(value)).map(SlabKey)
+        }
+    }
+
+    impl<'de, SlabId> Deserialize<'de> for SlabKey<SlabId>
+    where
+        SlabId: FromStr + Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(SlabKeyVisitor(PhantomData))
+        }
+    }
+
+    pub fn serialize<S, SlabId>(
+        budgets: &HashMap<SlabId, usize>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        SlabId: Display,
+    {
+        serializer.collect_map(budgets.iter().map(|(slab_id, bytes)| (slab_id.to_string(), bytes)))
+    }
+
+    pub fn deserialize<'de, D, SlabId>(deserializer: D) -> Result<HashMap<SlabId, usize>, D::Error>
+    where
+        D: Deserializer<'de>,
+        SlabId: Hash + Eq + FromStr + Deserialize<'de>,
+    {
+        let budgets = HashMap::<SlabKey<SlabId>, usize>::deserialize(deserializer)?;
+        Ok(budgets.into_iter().map(|(SlabKey(slab_id), bytes)| (slab_id, bytes)).collect())
+    }
+}
+
+impl<SlabId> ZccConfig<SlabId>
+where
+    SlabId: Hash + Eq + Copy,
+{
+    /// A builder applying every setting but `cache_type` and `num_segments`, which choose
+    /// `cache_builder`; `build` picks the builder from them too.
+    pub fn to_builder<Slab, CB>(&self, cache_builder: CB) -> ZeroCopyCacheBuilder<Slab, CB>
+    where
+        Slab: DatapathSlab<SlabId = SlabId> + std::fmt::Debug,
+        CB: SegmentPolicy<Slab>,
+    {
+        let mut builder = ZeroCopyCacheBuilder::new(cache_builder)
+            .segment_size(self.segment_size)
+            .pin_on_demand(self.pin_on_demand)
+            .repin_interval(Duration::from_millis(self.repin_interval_ms));
+        if let Some(bytes) = self.pinning_limit {
+            builder = builder.pinning_limit(bytes);
+        }
+        if let Some(percent) = self.pinning_limit_percent {
+            builder = builder.pinning_limit_percent(percent);
+        }
+        if let Some(hysteresis) = self.hysteresis {
+            builder = builder.hysteresis(hysteresis.displace_margin, hysteresis.min_pinned_cycles);
+        }
+        for (slab_id, bytes) in self.slab_budgets.iter() {
+            builder = builder.slab_pinning_limit(*slab_id, *bytes);
+        }
+        builder
+    }
+}
+
+impl<Slab, CB> ZeroCopyCache<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    /// A cache configured from `config`, with `cache_builder` as its policy instead of the
+    /// config's `cache_type`.
+    pub fn from_config(
+        config: &ZccConfig<Slab::SlabId>,
+        cache_builder: CB,
+    ) -> Result<Self, ConfigError> {
+        config.to_builder(cache_builder).build()
+    }
+}
@@ -0,0 +1,241 @@
+//! `DatapathSlab` adapter for CUDA pinned host memory. Pinning a segment page-locks it with
+//! `cudaHostRegister` and unpinning releases it with `cudaHostUnregister`, so the hotset
+//! decides which host buffers the GPU can DMA to and from at full speed. `IOInfo` is the
+//! device pointer for the segment from `cudaHostGetDevicePointer`.
+//!
+//! The CUDA runtime symbols are declared here but not linked; the application links
+//! `cudart` itself.
+use std::os::raw::{c_int, c_uint, c_void};
+
+use crate::data_structures::DatapathSlab;
+use crate::pagesizes::PageSize;
+
+/// Make the registration visible to every CUDA context, not just the current one.
+pub const CUDA_HOST_REGISTER_PORTABLE: c_uint = 0x01;
+/// Map the memory into the device address space; needed for a device pointer.
+pub const CUDA_HOST_REGISTER_MAPPED: c_uint = 0x02;
+
+const CUDA_SUCCESS: c_int = 0;
+
+extern "C" {
+    fn cudaHostRegister(ptr: *mut c_void, size: usize, flags: c_uint) -> c_int;
+    fn cudaHostUnregister(ptr: *mut c_void) -> c_int;
+    fn cudaHostGetDevicePointer(
+        p_device: *mut *mut c_void,
+        p_host: *mut c_void,
+        flags: c_uint,
+    ) -> c_int;
+}
+
+/// A page-locked segment.
+#[derive(Debug)]
+struct CudaRegistration {
+    host: usize,
+    device: usize,
+}
+
+#[derive(Debug)]
+pub struct CudaPinningState {
+    registration: Option<CudaRegistration>,
+    flags: c_uint,
+}
+
+#[derive(Debug)]
+pub struct CudaHostSlab {
+    slab_id: usize,
+    start_address: *mut c_void,
+    num_pages: usize,
+    page_size: PageSize,
+    flags: c_uint,
+}
+
+// SAFETY: the slab only describes memory owned by the application; see `CudaHostSlab::new`.
+unsafe impl Send for CudaHostSlab {}
+unsafe impl Sync for CudaHostSlab {}
+
+impl CudaHostSlab {
+    /// Describe `num_pages` pages of `page_size` at `start_address`, registered with the
+    /// `CUDA_HOST_REGISTER_*` `flags` when pinned. `CUDA_HOST_REGISTER_MAPPED` is always
+    /// added, since `IOInfo` is the device pointer.
+    ///
+    /// # Safety
+    /// The memory must stay mapped for as long as the slab's segments are managed by a cache,
+    /// and must not be registered with CUDA by anyone else in the meantime.
+    pub unsafe fn new(
+        slab_id: usize,
+        start_address: *mut c_void,
+        num_pages: usize,
+        page_size: PageSize,
+        flags: c_uint,
+    ) -> Self {
+        CudaHostSlab {
+            slab_id,
+            start_address,
+            num_pages,
+            page_size,
+            flags: flags | CUDA_HOST_REGISTER_MAPPED,
+        }
+    }
+}
+
+impl DatapathSlab for CudaHostSlab {
+    type SlabId = usize;
+    /// Device pointer to the start of the segment, 0 while it is not pinned.
+    type IOInfo = usize;
+    type PinningState = CudaPinningState;
+    type PrivateInfo = ();
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        CudaPinningState {
+            registration: None,
+            flags: self.flags,
+        }
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.slab_id
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        pinning_state.registration.is_some()
+    }
+
+    // The range comes from the slab's own memory; the trait fixes the signature.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        _private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        if pinning_state.registration.is_some() {
+            return;
+        }
+        let ret = unsafe { cudaHostRegister(start_address, len, pinning_state.flags) };
+        if ret != CUDA_SUCCESS {
+            tracing::warn!("cudaHostRegister of {} bytes failed: error {}", len, ret);
+            return;
+        }
+        let mut device = std::ptr::null_mut();
+        let ret = unsafe { cudaHostGetDevicePointer(&mut device, start_address, 0) };
+        if ret != CUDA_SUCCESS {
+            tracing::warn!("cudaHostGetDevicePointer failed: error {}", ret);
+            unsafe { cudaHostUnregister(start_address) };
+            return;
+        }
+        pinning_state.registration = Some(CudaRegistration {
+            host: start_address as usize,
+            device: device as usize,
+        });
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        if let Some(registration) = pinning_state.registration.take() {
+            let ret = unsafe { cudaHostUnregister(registration.host as *mut c_void) };
+            if ret != CUDA_SUCCESS {
+                tracing::warn!("cudaHostUnregister failed: error {}", ret);
+            }
+        }
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        pinning_state
+            .registration
+            .as_ref()
+            .map_or(0, |registration| registration.device)
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.start_address
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.page_size.clone()
+    }
+}
+
+/// Stand-ins for the CUDA runtime calls above, so tests link without cudart and can check how
+/// segments are page-locked. Calls are recorded per thread.
+#[cfg(test)]
+pub(crate) mod fake_cudart {
+    use std::cell::{Cell, RefCell};
+    use std::os::raw::{c_int, c_uint, c_void};
+
+    use super::CUDA_SUCCESS;
+
+    /// Distance of the fake device pointers from the host addresses they map.
+    pub const DEVICE_OFFSET: usize = 1 << 40;
+
+    /// `cudaErrorInvalidValue`.
+    const CUDA_ERROR_INVALID_VALUE: c_int = 1;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum CudaCall {
+        Register {
+            ptr: usize,
+            size: usize,
+            flags: c_uint,
+        },
+        Unregister {
+            ptr: usize,
+        },
+        GetDevicePointer {
+            host: usize,
+        },
+    }
+
+    thread_local! {
+        static CALLS: RefCell<Vec<CudaCall>> = const { RefCell::new(Vec::new()) };
+        static FAIL_DEVICE_POINTER: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Calls made on this thread since the last `take_calls`.
+    pub fn take_calls() -> Vec<CudaCall> {
+        CALLS.with(|calls| calls.take())
+    }
+
+    /// Make `cudaHostGetDevicePointer` fail on this thread.
+    pub fn fail_device_pointer(fail: bool) {
+        FAIL_DEVICE_POINTER.with(|fail_device_pointer| fail_device_pointer.set(fail));
+    }
+
+    fn record(call: CudaCall) {
+        CALLS.with(|calls| calls.borrow_mut().push(call));
+    }
+
+    #[no_mangle]
+    extern "C" fn cudaHostRegister(ptr: *mut c_void, size: usize, flags: c_uint) -> c_int {
+        record(CudaCall::Register {
+            ptr: ptr as usize,
+            size,
+            flags,
+        });
+        CUDA_SUCCESS
+    }
+
+    #[no_mangle]
+    extern "C" fn cudaHostUnregister(ptr: *mut c_void) -> c_int {
+        record(CudaCall::Unregister { ptr: ptr as usize });
+        CUDA_SUCCESS
+    }
+
+    #[no_mangle]
+    extern "C" fn cudaHostGetDevicePointer(
+        p_device: *mut *mut c_void,
+        p_host: *mut c_void,
+        _flags: c_uint,
+    ) -> c_int {
+        record(CudaCall::GetDevicePointer {
+            host: p_host as usize,
+        });
+        if FAIL_DEVICE_POINTER.with(Cell::get) {
+            return CUDA_ERROR_INVALID_VALUE;
+        }
+        unsafe { *p_device = (p_host as usize + DEVICE_OFFSET) as *mut c_void };
+        CUDA_SUCCESS
+    }
+}
@@ -1,22 +1,74 @@
-use std::default;
-use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, Duration};
-use std::thread::sleep;
+use std::num::NonZeroUsize;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{SystemTime, Duration, Instant};
+use std::thread::{self, sleep};
 use std::{collections::HashMap, hash::Hash, collections::HashSet};
 
+use lru::LruCache;
+use smallvec::SmallVec;
+
+use crate::accesscounts::AtomicAccessCounts;
+use crate::doorkeeper::{Doorkeeper, DEFAULT_DOORKEEPER_BITS, DEFAULT_DOORKEEPER_HASHES};
+use crate::epoch::{PinnedSetSnapshot, SnapshotPublisher};
+use crate::events::{NoopEventListener, ZccEventListener};
+use crate::eviction::{EvictionQueue, EvictionWorker};
+use crate::history::{HistoryBucket, SegmentHistory};
+use crate::interval::AdaptiveInterval;
+use crate::metrics::{
+    recover, LockOrRecover, Metrics, MetricsSink, MetricsSnapshot, MissReason, NoopMetricsSink,
+    PinningSample, PinningTimeSeries,
+};
 use crate::pagesizes;
+use crate::pipeline::{AccessEventSender, AccessPipeline};
+use crate::rangeindex::SegmentIndex;
+use crate::recorder::ThreadLocalRecorder;
+use crate::segment_policy::SegmentPolicy;
+use crate::sharded::ShardedCacheBuilder;
+#[cfg(feature = "shm-stats")]
+use crate::shmstats::SharedStatsRegion;
+use crate::worker::PinWorker;
+use crate::snapshot::{HotsetSnapshot, SegmentSnapshot};
+use crate::trace::AccessTracer;
+use crate::trigger::MissRateTrigger;
 
 pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+/// How long the pinning engine sleeps between repin cycles.
+pub const DEFAULT_REPIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Size of the segments `add_slab` carves slabs into.
+pub const DEFAULT_SEGMENT_SIZE: usize = pagesizes::PGSIZE_2MB;
+/// Unmanaged 2MB pages each clone remembers, so repeated foreign buffers miss in O(1).
+pub const DEFAULT_UNMANAGED_PAGES: usize = 64;
 // TODO: Convert all the page sizes, and stuff to an enum with constants.
 
 pub type SegmentStatMap<ID> = HashMap<ID, Stats>;
 
+/// Identifier of a segment: the slab it belongs to and its index within that slab.
+pub type SegmentId<Slab> = (<Slab as DatapathSlab>::SlabId, usize);
+
+/// The parts of a buffer in each segment it covers, usually just one.
+pub type PinnedSlices<Slab> =
+    SmallVec<[PinnedSlice<SegmentId<Slab>, <Slab as DatapathSlab>::IOInfo>; 2]>;
+
+/// `(start, end, segment id)` of the segments a buffer covers, in address order.
+type SegmentRanges<Slab> = SmallVec<[(usize, usize, SegmentId<Slab>); 2]>;
+
+/// A managed segment, shared between clones of the cache.
+type SegmentEntry<Slab> = Arc<DatapathSegment<Slab>>;
+
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct Stats {
     pub access_count: i64,
     pub last_access_time: SystemTime,
 }
 
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stats {
     pub fn new() -> Self {
         Stats {
@@ -43,9 +95,305 @@ impl Stats {
     }
 }
 
+/// How access statistics are aged by the periodic decay.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum DecayPolicy {
+    /// Halve every access count.
+    Halving,
+    /// Subtract the smallest count from every count, dropping segments that reach zero.
+    SubtractMin,
+    /// Drop all statistics, same as `SegmentPolicy::reset`.
+    FullReset,
+}
+
+/// Decay applied by the pin/unpin thread every `interval`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct DecaySchedule {
+    pub policy: DecayPolicy,
+    pub interval: Duration,
+}
+
+/// What to do with a segment whose in-flight IO has not drained when the quiesce timeout
+/// expires.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum QuiesceTimeoutAction {
+    /// Unregister the segment anyway.
+    ForceUnpin,
+    /// Leave it pinned and try again on the next repin cycle.
+    Skip,
+}
+
+/// How long unpinning waits for a segment's in-flight IO to drain.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct QuiesceTimeout {
+    pub timeout: Duration,
+    pub action: QuiesceTimeoutAction,
+}
+
+/// The part of a buffer that falls in one pinned segment.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct PinnedSlice<ID, IO> {
+    pub segment_id: ID,
+    pub io_info: IO,
+    /// Offset of the slice from the start of the segment.
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Where a buffer sits in the pinned segment holding it, with everything needed to build an
+/// IO descriptor.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ZccIoHandle<ID, IO> {
+    pub segment_id: ID,
+    pub io_info: IO,
+    /// Start address of the registered segment.
+    pub segment_base: usize,
+    /// Offset of the buffer from `segment_base`.
+    pub offset: usize,
+    /// Registered bytes from the start of the buffer to the end of the segment. A buffer
+    /// longer than this runs past the registration.
+    pub remaining_len: usize,
+    /// Times the segment had been unpinned when the handle was made; see
+    /// `ZeroCopyCache::validate`.
+    pub generation: u64,
+}
+
+/// IO counted against the pinned segments a buffer covers, completed when the lease is
+/// dropped, so an early return or a panic between send and completion can't leave a segment
+/// that never drains. Derefs to the buffer's slices.
+pub struct IoLease<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    slices: PinnedSlices<Slab>,
+    segments: SmallVec<[SegmentEntry<Slab>; 2]>,
+}
+
+impl<Slab> IoLease<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn slices(&self) -> &[PinnedSlice<SegmentId<Slab>, Slab::IOInfo>] {
+        &self.slices
+    }
+
+    /// Complete the IO, the same as dropping the lease but easier to spot in a completion
+    /// handler.
+    pub fn complete(self) {}
+}
+
+impl<Slab> std::ops::Deref for IoLease<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    type Target = [PinnedSlice<SegmentId<Slab>, Slab::IOInfo>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.slices
+    }
+}
+
+impl<Slab> Drop for IoLease<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn drop(&mut self) {
+        for segment in self.segments.iter() {
+            segment.end_io();
+        }
+    }
+}
+
+impl<Slab> std::fmt::Debug for IoLease<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let segment_ids: SmallVec<[SegmentId<Slab>; 2]> =
+            self.slices.iter().map(|slice| slice.segment_id).collect();
+        f.debug_struct("IoLease").field("segment_ids", &segment_ids).finish()
+    }
+}
+
+/// Why `remove_slab` left a slab in place.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RemoveSlabError<ID> {
+    /// No segments of the slab are managed by this cache.
+    UnknownSlab,
+    /// These segments still had IO in flight when the quiesce timeout expired, so they were
+    /// left pinned. The slab's other segments are unpinned; retry once the IO completes.
+    InFlightIo(Vec<ID>),
+}
+
+/// Why `pin` left a segment unpinned.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PinError {
+    /// The segment is not managed by this cache.
+    UnknownSegment,
+    /// Pinning it would go over the pinning limit, or its slab's or NUMA node's limit.
+    OverBudget,
+    /// The slab failed to register the segment.
+    RegistrationFailed,
+}
+
+/// How strongly the application wants a segment pinned, e.g. for index pages or headers.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Default)]
+pub enum Priority {
+    /// Pinned only if its access statistics put it in the hotset.
+    #[default]
+    Normal,
+    /// Ranked ahead of every `Normal` segment when choosing the hotset.
+    High,
+    /// Always pinned and never evicted.
+    Forever,
+}
+
+/// Damping applied to the hotset so segments near the top-k boundary don't flap in and out
+/// of the pinned set every cycle.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Hysteresis {
+    /// Fraction by which a newcomer's access count must exceed the coldest pinned segment's
+    /// to displace it; 0.1 means 10% more accesses.
+    pub displace_margin: f64,
+    /// Repin cycles a segment stays pinned before it can be displaced.
+    pub min_pinned_cycles: u64,
+}
+
+/// Which NUMA node a cache clone runs on, and how much less a remote-node segment is worth
+/// pinning than a local one.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct NumaPreference {
+    pub local_node: usize,
+    /// Factor applied to a remote segment's accesses per byte when ranking segments against
+    /// a pinning limit; 0.5 means a remote segment needs twice the accesses of a local one.
+    pub remote_weight: f64,
+}
+
+/// How much the pinned set changed in one repin cycle.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct HotsetChurn {
+    pub num_pinned: usize,
+    pub num_unpinned: usize,
+    /// Size of the new pinned set.
+    pub hotset_size: usize,
+    /// Jaccard similarity between the previous and new pinned sets (1.0 if both are empty).
+    pub jaccard_similarity: f64,
+}
+
+/// Churn accumulated over all repin cycles.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct ChurnStats {
+    pub cycles: u64,
+    pub total_pinned: u64,
+    pub total_unpinned: u64,
+    pub last_cycle: Option<HotsetChurn>,
+}
+
+impl ChurnStats {
+    pub fn record_cycle(&mut self, churn: HotsetChurn) {
+        self.cycles += 1;
+        self.total_pinned += churn.num_pinned as u64;
+        self.total_unpinned += churn.num_unpinned as u64;
+        self.last_cycle = Some(churn);
+    }
+}
+
+pub fn jaccard_similarity<T: Hash + Eq>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// Pinning state of one slab's segments.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub struct SlabStats {
+    pub num_segments: usize,
+    pub pinned_segments: usize,
+    pub total_bytes: usize,
+    pub pinned_bytes: usize,
+    /// NUMA node of the slab's memory, if known.
+    pub numa_node: Option<usize>,
+    /// Counters for accesses and pinning operations on this slab only.
+    pub metrics: MetricsSnapshot,
+}
+
+/// Bytes and segments registered, kept up to date by the segments as they are pinned and
+/// unpinned.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub struct PinnedUsage {
+    pub bytes: usize,
+    pub segments: usize,
+}
+
+/// State of one managed segment, as reported by `ZeroCopyCache::segments`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct SegmentInfo<ID> {
+    pub segment_id: ID,
+    pub start_address: usize,
+    /// Length of the segment in bytes.
+    pub size: usize,
+    pub pinned: bool,
+    /// IO handed out against the segment and not yet completed.
+    pub in_flight_io: usize,
+    /// Accesses the cache builder has recorded, if it has seen the segment.
+    pub access_count: Option<i64>,
+}
+
+/// Measured registration cost of a segment, maintained by the pinning engine.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub struct PinCost {
+    pub pin_count: u64,
+    pub unpin_count: u64,
+    pub total_pin_latency: Duration,
+    pub total_unpin_latency: Duration,
+    pub last_pin_latency: Duration,
+    pub last_unpin_latency: Duration,
+}
+
+impl PinCost {
+    pub fn new() -> Self {
+        PinCost::default()
+    }
+
+    pub fn record_pin(&mut self, latency: Duration) {
+        self.pin_count += 1;
+        self.total_pin_latency += latency;
+        self.last_pin_latency = latency;
+    }
+
+    pub fn record_unpin(&mut self, latency: Duration) {
+        self.unpin_count += 1;
+        self.total_unpin_latency += latency;
+        self.last_unpin_latency = latency;
+    }
+
+    pub fn average_pin_latency(&self) -> Duration {
+        match self.pin_count {
+            0 => Duration::ZERO,
+            n => self.total_pin_latency / n as u32,
+        }
+    }
+
+    pub fn average_unpin_latency(&self) -> Duration {
+        match self.unpin_count {
+            0 => Duration::ZERO,
+            n => self.total_unpin_latency / n as u32,
+        }
+    }
+
+    /// Expected cost of evicting the segment and registering it again later.
+    pub fn repin_cost(&self) -> Duration {
+        self.average_pin_latency() + self.average_unpin_latency()
+    }
+}
+
 pub trait DatapathSlab {
-    type SlabId: Hash + PartialEq + Eq + Clone + Copy + std::fmt::Debug;
-    type IOInfo: PartialEq + Eq + Clone + Copy;
+    type SlabId: Hash + PartialEq + Eq + Clone + Copy + std::fmt::Debug + Send + Sync;
+    type IOInfo: PartialEq + Eq + Clone + Copy + Send + Sync;
     type PinningState: std::fmt::Debug + Send + Sync;
     type PrivateInfo;
 
@@ -72,29 +420,89 @@ pub trait DatapathSlab {
 
     fn get_page_size(&self) -> pagesizes::PageSize;
 
+    /// NUMA node the slab's memory lives on, if the slab knows it. Can also be set with
+    /// `ZeroCopyCache::set_slab_numa_node`.
+    fn get_numa_node(&self) -> Option<usize> {
+        None
+    }
+
     fn get_page_size_as_num(&self) -> usize {
-        match self.get_page_size() {
-            pagesizes::PageSize::PG4KB => pagesizes::PGSIZE_4KB,
-            pagesizes::PageSize::PG2MB => pagesizes::PGSIZE_2MB,
-            pagesizes::PageSize::PG1GB => pagesizes::PGSIZE_1GB,
-        }
+        self.get_page_size().bytes()
     }
 }
 
+/// Registration state of a segment, changed only by the pinning engine.
+#[derive(Debug)]
+struct SegmentState<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pinning_state: Slab::PinningState,
+    pin_cost: PinCost,
+}
+
 #[derive(Debug)]
 pub struct DatapathSegment<Slab>
 where
     Slab: DatapathSlab + std::fmt::Debug,
 {
-    start_address: *mut ::std::os::raw::c_void,
+    start_address: SegmentAddress,
     num_pages: usize,
     page_size: pagesizes::PageSize,
-    pinning_state: Slab::PinningState,
     id: (Slab::SlabId, usize),
+    state: Mutex<SegmentState<Slab>>,
+    /// IO handed out against the segment and not yet completed.
+    in_flight_io: AtomicUsize,
+    /// Set while the segment is being unpinned, so no new IO is handed out against it.
+    quiescing: AtomicBool,
+    /// Signaled when the last in-flight IO of a quiescing segment completes.
+    drain_lock: Mutex<()>,
+    drained: Condvar,
+    /// Totals this segment adds its size to while it is registered.
+    pinned_usage: Arc<Mutex<PinnedUsage>>,
+    /// Bumped on every unpin, so IO info handed out before it can be told apart.
+    generation: AtomicU64,
 }
 
-unsafe impl<Slab> Send for DatapathSegment<Slab> where Slab: DatapathSlab + std::fmt::Debug {}
-unsafe impl<Slab> Sync for DatapathSegment<Slab> where Slab: DatapathSlab + std::fmt::Debug {}
+/// Start address of a segment. The cache only does arithmetic on it and hands it to the
+/// slab's `pin_segment`; it never reads or writes through it.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct SegmentAddress(NonNull<::std::os::raw::c_void>);
+
+// SAFETY: the address is never dereferenced by the cache, so sharing it across threads is
+// no different from sharing a `usize`. Slabs that dereference it in `pin_segment` are
+// responsible for synchronizing access to the memory itself.
+unsafe impl Send for SegmentAddress {}
+unsafe impl Sync for SegmentAddress {}
+
+impl SegmentAddress {
+    /// `None` for a null pointer.
+    pub fn new(address: *mut ::std::os::raw::c_void) -> Option<Self> {
+        NonNull::new(address).map(SegmentAddress)
+    }
+
+    pub fn as_ptr(&self) -> *mut ::std::os::raw::c_void {
+        self.0.as_ptr()
+    }
+
+    pub fn as_usize(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+}
+
+/// Segments are shared by every clone of the cache, so this runs once the last clone is
+/// gone. Anything still registered at that point would leak in the driver.
+impl<Slab> Drop for DatapathSegment<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn drop(&mut self) {
+        if self.is_pinned() {
+            tracing::warn!("Segment {:?} dropped while pinned, unpinning", self.id);
+            self.unregister();
+        }
+    }
+}
 
 impl<Slab> DatapathSegment<Slab>
 where
@@ -108,11 +516,41 @@ where
         slab: &Slab,
     ) -> Self {
         DatapathSegment {
-            start_address,
+            start_address: SegmentAddress::new(start_address).expect("segment start address is null"),
             num_pages,
             page_size,
-            pinning_state: slab.default_pinning_state(),
             id: (slab.get_slab_id(), segment_id),
+            state: Mutex::new(SegmentState {
+                pinning_state: slab.default_pinning_state(),
+                pin_cost: PinCost::new(),
+            }),
+            in_flight_io: AtomicUsize::new(0),
+            quiescing: AtomicBool::new(false),
+            drain_lock: Mutex::new(()),
+            drained: Condvar::new(),
+            pinned_usage: Arc::default(),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Account this segment's registration in `pinned_usage`, shared with other segments.
+    pub(crate) fn with_pinned_usage(mut self, pinned_usage: Arc<Mutex<PinnedUsage>>) -> Self {
+        self.pinned_usage = pinned_usage;
+        self
+    }
+
+    /// Add or remove `reglen` bytes from the totals if the pin state changed.
+    fn account_pin_change(&self, was_pinned: bool, is_pinned: bool, reglen: usize) {
+        if was_pinned == is_pinned {
+            return;
+        }
+        let mut usage = self.pinned_usage.lock_or_recover();
+        if is_pinned {
+            usage.bytes += reglen;
+            usage.segments += 1;
+        } else {
+            usage.bytes -= reglen;
+            usage.segments -= 1;
         }
     }
 
@@ -121,365 +559,2594 @@ where
     }
 
     pub fn get_page_size_as_num(&self) -> usize {
-        match self.page_size {
-            pagesizes::PageSize::PG4KB => pagesizes::PGSIZE_4KB,
-            pagesizes::PageSize::PG2MB => pagesizes::PGSIZE_2MB,
-            pagesizes::PageSize::PG1GB => pagesizes::PGSIZE_1GB,
-        }
+        self.page_size.bytes()
     }
 
-    pub fn register(&mut self, priv_info: &Slab::PrivateInfo) {
+    #[cfg_attr(
+        feature = "trace-spans",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(segment_id = ?self.id, bytes = self.get_size())
+        )
+    )]
+    pub fn register(&self, priv_info: &Slab::PrivateInfo) {
         let reglen = self.num_pages * self.get_page_size_as_num();
+        let mut state = self.state.lock_or_recover();
+        let was_pinned = Slab::is_pinned(&state.pinning_state);
+        let start = Instant::now();
         Slab::pin_segment(
-            &mut self.pinning_state,
+            &mut state.pinning_state,
             priv_info,
-            self.start_address,
+            self.start_address.as_ptr(),
             reglen,
-        )
+        );
+        state.pin_cost.record_pin(start.elapsed());
+        self.account_pin_change(was_pinned, Slab::is_pinned(&state.pinning_state), reglen);
     }
 
-    pub fn unregister(&mut self) {
-        Slab::unpin_segment(&mut self.pinning_state);
+    #[cfg_attr(
+        feature = "trace-spans",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(segment_id = ?self.id, bytes = self.get_size())
+        )
+    )]
+    pub fn unregister(&self) {
+        let mut state = self.state.lock_or_recover();
+        let was_pinned = Slab::is_pinned(&state.pinning_state);
+        let start = Instant::now();
+        Slab::unpin_segment(&mut state.pinning_state);
+        state.pin_cost.record_unpin(start.elapsed());
+        let is_pinned = Slab::is_pinned(&state.pinning_state);
+        if was_pinned && !is_pinned {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+        self.account_pin_change(was_pinned, is_pinned, self.get_size());
     }
 
     pub fn is_pinned(&self) -> bool {
-        Slab::is_pinned(&self.pinning_state)
+        Slab::is_pinned(&self.state.lock_or_recover().pinning_state)
     }
 
-    pub fn get_page_size(&self) -> pagesizes::PageSize {
-        self.page_size.clone()
+    pub fn get_in_flight_io(&self) -> usize {
+        self.in_flight_io.load(Ordering::SeqCst)
     }
 
-    pub fn get_start_address(&self) -> *mut ::std::os::raw::c_void {
-        self.start_address
+    /// Times the segment has been unpinned.
+    pub fn get_generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
     }
 
-    pub fn get_io_info(&self) -> Slab::IOInfo {
-        Slab::get_io_info(&self.pinning_state)
+    pub fn is_quiescing(&self) -> bool {
+        self.quiescing.load(Ordering::SeqCst)
+    }
+
+    /// Count one IO against the segment unless it is quiescing. The flag is checked again
+    /// after incrementing, so an unpin that started concurrently either sees the IO or the
+    /// IO backs off.
+    fn begin_io(&self) -> bool {
+        if self.is_quiescing() {
+            return false;
+        }
+        self.in_flight_io.fetch_add(1, Ordering::SeqCst);
+        if self.is_quiescing() {
+            self.end_io();
+            return false;
+        }
+        true
     }
 
-    fn get_num_pages(&self) -> usize {
-        self.num_pages
+    fn end_io(&self) -> usize {
+        self.end_ios(1)
     }
 
-    fn get_1gb_pages(&self) -> Vec<usize> {
-        match self.page_size {
-            pagesizes::PageSize::PG1GB => (0..self.get_num_pages())
-                .map(|i| self.get_start_address() as usize + self.get_page_size_as_num() * i)
-                .collect::<Vec<usize>>(),
-            _ => {
-                vec![]
-            }
+    /// Complete `count` IOs at once, waking a drain waiter if that was the last. The count
+    /// never goes below zero: completions beyond the IO in flight, e.g. repeated ones, are
+    /// ignored and their number returned.
+    fn end_ios(&self, count: usize) -> usize {
+        if count == 0 {
+            return 0;
         }
+        let in_flight = self
+            .in_flight_io
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                Some(in_flight.saturating_sub(count))
+            })
+            .unwrap_or_else(|in_flight| in_flight);
+        if in_flight != 0 && in_flight <= count && self.is_quiescing() {
+            let _guard = self.drain_lock.lock_or_recover();
+            self.drained.notify_all();
+        }
+        let orphaned = count.saturating_sub(in_flight);
+        if orphaned != 0 {
+            tracing::warn!("Segment {:?}: {} completions with no IO in flight", self.id, orphaned);
+        }
+        orphaned
     }
 
-    fn get_2mb_pages(&self) -> Vec<usize> {
-        match self.page_size {
-            pagesizes::PageSize::PG2MB => (0..self.get_num_pages())
-                .map(|i| self.get_start_address() as usize + self.get_page_size_as_num() * i)
-                .collect::<Vec<usize>>(),
-            _ => {
-                vec![]
+    /// Block until no IO is in flight, or `timeout` elapses. Returns whether it drained.
+    fn wait_for_io_drain(&self, timeout: Option<Duration>) -> bool {
+        let guard = self.drain_lock.lock_or_recover();
+        let in_flight = |_: &mut ()| self.get_in_flight_io() != 0;
+        match timeout {
+            Some(timeout) => {
+                let (_guard, result) =
+                    recover(self.drained.wait_timeout_while(guard, timeout, in_flight));
+                !result.timed_out()
+            }
+            None => {
+                let _guard = recover(self.drained.wait_while(guard, in_flight));
+                true
             }
         }
     }
 
-    fn get_4kb_pages(&self) -> Vec<usize> {
-        match self.page_size {
-            pagesizes::PageSize::PG4KB => (0..self.get_num_pages())
-                .map(|i| self.get_start_address() as usize + self.get_page_size_as_num() * i)
-                .collect::<Vec<usize>>(),
-            _ => {
-                vec![]
-            }
-        }
+    pub fn get_page_size(&self) -> pagesizes::PageSize {
+        self.page_size.clone()
+    }
+
+    pub fn get_start_address(&self) -> *mut ::std::os::raw::c_void {
+        self.start_address.as_ptr()
+    }
+
+    pub fn get_io_info(&self) -> Slab::IOInfo {
+        Slab::get_io_info(&self.state.lock_or_recover().pinning_state)
+    }
+
+    pub fn get_pin_cost(&self) -> PinCost {
+        self.state.lock_or_recover().pin_cost
+    }
+
+    /// Length of the registered region in bytes.
+    pub fn get_size(&self) -> usize {
+        self.num_pages * self.get_page_size_as_num()
     }
 }
 
 #[derive(Debug)]
-pub struct ZeroCopyCache<Slab>
+pub struct ZeroCopyCache<Slab, CB>
 where
-    Slab: DatapathSlab + std::fmt::Debug, // C: CacheBuilder<S>
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
 {
-    /// Stats maintained for each segment.
-    // TODO: Work on locking this 
-    pub segment_stats: Arc<Mutex<SegmentStatMap<(Slab::SlabId, usize)>>>,
+    /// Policy holding the access statistics and choosing the hotset, sharded by segment.
+    pub cache_builder: Arc<ShardedCacheBuilder<Slab, CB>>,
     /// Current hotset.
     pub current_pinned_list: HashSet<(Slab::SlabId, usize)>,
     /// Actual segments themselves to be pinned or unpinned, along with associated metadata.
     // TODO: Convert the segment part into a struct
-    segments: HashMap<(Slab::SlabId, usize), Arc<Mutex<(DatapathSegment<Slab>, usize, bool)>>>,
-    /// Cache page addresses to segment ID of size 2mb.
-    page_cache_2mb: HashMap<usize, (Slab::SlabId, usize)>,
-    /// Cache page addresses to segment ID for size 4kb.
-    page_cache_4kb: HashMap<usize, (Slab::SlabId, usize)>,
-    /// Cache page addresses to segment ID for size 1gb.
-    page_cache_1gb: HashMap<usize, (Slab::SlabId, usize)>,
-    // pub cache_builder: C
+    segments: HashMap<(Slab::SlabId, usize), SegmentEntry<Slab>>,
+    /// Address ranges of the slabs and their segment layout, to resolve buffers to segment IDs.
+    segment_ranges: SegmentIndex<Slab::SlabId>,
+    /// Range and ID of the segment this clone's datapath last resolved a buffer to, since
+    /// consecutive buffers usually fall in the same segment.
+    last_segment: Option<(usize, usize, SegmentId<Slab>)>,
+    /// 2MB pages no segment overlaps that this clone's datapath saw buffers from, if enabled.
+    unmanaged_pages: Option<LruCache<usize, ()>>,
+    /// Periodic aging of the cache builder statistics, if any.
+    decay_schedule: Option<DecaySchedule>,
+    /// Bound on how long unpinning waits for in-flight IO, if any.
+    quiesce_timeout: Option<QuiesceTimeout>,
+    /// Damping of hotset changes, if any.
+    hysteresis: Option<Hysteresis>,
+    /// Size of the segments `add_slab` carves slabs into.
+    segment_size: usize,
+    /// How long a pin worker started with `get_repin_interval` sleeps between cycles.
+    repin_interval: Duration,
+    /// Whether `record_access_as_configured` pins missed segments on demand.
+    pin_on_demand: bool,
+    /// NUMA node of each slab, where known.
+    slab_numa_nodes: HashMap<Slab::SlabId, usize>,
+    /// Cap on bytes pinned per NUMA node.
+    node_pinning_limits: HashMap<usize, usize>,
+    /// Cap on bytes pinned per slab.
+    slab_pinning_limits: HashMap<Slab::SlabId, usize>,
+    /// Preference for segments on this clone's own NUMA node, if any.
+    numa_preference: Option<NumaPreference>,
+    /// Cap on bytes pinned at once, `usize::MAX` for none. Shared by every clone so it can be
+    /// changed while a pin worker runs.
+    pinning_limit: Arc<AtomicUsize>,
+    /// Percentage of managed memory the pinning limit is derived from, if set that way.
+    pinning_limit_percent: Arc<Mutex<Option<f64>>>,
+    /// How far a repin cycle may go over the limit by pinning before it unpins, if at all.
+    max_overshoot_bytes: Option<usize>,
+    /// Repin interval adjusted to the hotset churn, if enabled.
+    adaptive_interval: Option<AdaptiveInterval>,
+    /// Cap on pins plus unpins per repin cycle, if any.
+    max_changes_per_cycle: Option<usize>,
+    /// Time a segment must stay pinned before it can be unpinned, if any.
+    min_pin_duration: Option<Duration>,
+    /// Repin cycles run so far, and the cycle and time each pinned segment was pinned at.
+    repin_cycle: u64,
+    pinned_since: HashMap<SegmentId<Slab>, (u64, Instant)>,
+    /// When the statistics were last decayed.
+    last_decay: Instant,
+    /// Per-segment access histograms, if enabled.
+    history: Option<Arc<Mutex<SegmentHistory<SegmentId<Slab>>>>>,
+    /// Churn of the pinned set across repin cycles.
+    churn_stats: Arc<Mutex<ChurnStats>>,
+    /// Datapath and pinning engine counters.
+    metrics: Arc<Metrics>,
+    /// Pinned set published for the datapath after each repin cycle.
+    pinned_snapshots: Arc<SnapshotPublisher<Slab>>,
+    /// The last snapshot this clone saw.
+    cached_snapshot: Arc<PinnedSetSnapshot<Slab>>,
+    /// The same counters, broken down per slab.
+    slab_metrics: HashMap<Slab::SlabId, Arc<Metrics>>,
+    /// Receives every metrics event as it happens.
+    metrics_sink: Arc<dyn MetricsSink<SegmentId<Slab>>>,
+    /// Receives every pin and unpin of a segment.
+    event_listener: Arc<dyn ZccEventListener<Slab>>,
+    /// Receives every datapath access, e.g. to record a trace.
+    access_tracer: Option<Arc<dyn AccessTracer<SegmentId<Slab>>>>,
+    /// Bytes and segments registered, shared by every clone and updated by the segments.
+    pinned_usage: Arc<Mutex<PinnedUsage>>,
+    /// Bytes and segments pinned after each repin cycle.
+    pinning_time_series: Arc<Mutex<PinningTimeSeries>>,
+    /// Dense index of every managed segment, and the reverse mapping.
+    segment_indices: HashMap<SegmentId<Slab>, usize>,
+    indexed_segments: Vec<SegmentId<Slab>>,
+    /// Lock-free access counters drained into the cache builder each cycle, if enabled.
+    access_counts: Option<Arc<AtomicAccessCounts>>,
+    /// Channel to the statistics thread, if accesses are recorded through a pipeline.
+    access_events: Option<AccessEventSender<Slab>>,
+    /// This clone's buffer of accesses not yet handed to the cache builder, if batching.
+    recorder: Option<ThreadLocalRecorder<Slab, CB>>,
+    /// Segments evicted by on-demand pinning, waiting for the eviction worker, if one runs.
+    eviction_queue: Option<Arc<EvictionQueue<Slab>>>,
+    /// Segments pinned by `record_and_pin_on_demand` that the background policy hasn't taken
+    /// over yet, shared by every clone.
+    on_demand_pins: Arc<Mutex<HashSet<SegmentId<Slab>>>>,
+    /// Fraction of the pinning limit the background policy may use; the rest is reserved for
+    /// on-demand pins. `None` lets both use the whole limit.
+    budget_split: Option<f64>,
+    /// Wakes the pin worker when the datapath miss rate spikes, if enabled.
+    miss_rate_trigger: Option<Arc<MissRateTrigger>>,
+    /// Filter admitting a segment to on-demand pinning on its second touch, if enabled.
+    doorkeeper: Option<Arc<Doorkeeper>>,
+    /// Shared-memory region the stats are published to after each repin cycle, if any.
+    #[cfg(feature = "shm-stats")]
+    shm_stats: Option<Arc<SharedStatsRegion>>,
 }
 
-impl<Slab> Clone for ZeroCopyCache<Slab>
+impl<Slab, CB> Clone for ZeroCopyCache<Slab, CB>
 where
     Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
 {
     fn clone(&self) -> Self {
         ZeroCopyCache {
-            segment_stats: self.segment_stats.clone(),
+            cache_builder: self.cache_builder.clone(),
             current_pinned_list: self.current_pinned_list.clone(),
             segments: self.segments.clone(),
-            page_cache_2mb: self.page_cache_2mb.clone(),
-            page_cache_4kb: self.page_cache_4kb.clone(),
-            page_cache_1gb: self.page_cache_1gb.clone(),
+            segment_ranges: self.segment_ranges.clone(),
+            last_segment: None,
+            unmanaged_pages: self.unmanaged_pages.as_ref().map(|pages| LruCache::new(pages.cap())),
+            decay_schedule: self.decay_schedule,
+            quiesce_timeout: self.quiesce_timeout,
+            hysteresis: self.hysteresis,
+            segment_size: self.segment_size,
+            repin_interval: self.repin_interval,
+            pin_on_demand: self.pin_on_demand,
+            slab_numa_nodes: self.slab_numa_nodes.clone(),
+            node_pinning_limits: self.node_pinning_limits.clone(),
+            slab_pinning_limits: self.slab_pinning_limits.clone(),
+            numa_preference: self.numa_preference,
+            pinning_limit: self.pinning_limit.clone(),
+            pinning_limit_percent: self.pinning_limit_percent.clone(),
+            max_overshoot_bytes: self.max_overshoot_bytes,
+            adaptive_interval: self.adaptive_interval,
+            max_changes_per_cycle: self.max_changes_per_cycle,
+            repin_cycle: self.repin_cycle,
+            min_pin_duration: self.min_pin_duration,
+            pinned_since: self.pinned_since.clone(),
+            last_decay: self.last_decay,
+            history: self.history.clone(),
+            churn_stats: self.churn_stats.clone(),
+            metrics: self.metrics.clone(),
+            pinned_snapshots: self.pinned_snapshots.clone(),
+            cached_snapshot: self.cached_snapshot.clone(),
+            slab_metrics: self.slab_metrics.clone(),
+            metrics_sink: self.metrics_sink.clone(),
+            event_listener: self.event_listener.clone(),
+            access_tracer: self.access_tracer.clone(),
+            pinned_usage: self.pinned_usage.clone(),
+            pinning_time_series: self.pinning_time_series.clone(),
+            segment_indices: self.segment_indices.clone(),
+            indexed_segments: self.indexed_segments.clone(),
+            access_counts: self.access_counts.clone(),
+            access_events: self.access_events.clone(),
+            recorder: self.recorder.as_ref().map(|recorder| recorder.fork()),
+            eviction_queue: self.eviction_queue.clone(),
+            on_demand_pins: self.on_demand_pins.clone(),
+            budget_split: self.budget_split,
+            miss_rate_trigger: self.miss_rate_trigger.clone(),
+            doorkeeper: self.doorkeeper.clone(),
+            #[cfg(feature = "shm-stats")]
+            shm_stats: self.shm_stats.clone(),
         }
     }
 }
 
-impl<Slab> ZeroCopyCache<Slab>
+impl<Slab, CB> ZeroCopyCache<Slab, CB>
 where
     Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
 {
-    pub fn new() -> Self {
+    pub fn new(cache_builder: CB) -> Self {
+        Self::from_sharded_builder(ShardedCacheBuilder::from_builder(cache_builder))
+    }
+
+    /// Spread the access statistics over `num_shards` builders so concurrent datapath
+    /// accesses rarely contend on the same lock.
+    pub fn new_sharded(num_shards: usize, make_builder: impl FnMut(usize) -> CB) -> Self {
+        Self::from_sharded_builder(ShardedCacheBuilder::new(num_shards, make_builder))
+    }
+
+    pub fn from_sharded_builder(cache_builder: ShardedCacheBuilder<Slab, CB>) -> Self {
         ZeroCopyCache {
-            // segment_stats: Arc::new(SegmentStatMap::<(Slab::SlabId, usize)>::default()),
-            segment_stats: Arc::new(Mutex::new(SegmentStatMap::<(Slab::SlabId, usize)>::default())),
+            cache_builder: Arc::new(cache_builder),
             current_pinned_list: HashSet::default(),
             segments: HashMap::default(),
-            page_cache_2mb: HashMap::default(),
-            page_cache_4kb: HashMap::default(),
-            page_cache_1gb: HashMap::default(),
+            segment_ranges: SegmentIndex::default(),
+            last_segment: None,
+            unmanaged_pages: NonZeroUsize::new(DEFAULT_UNMANAGED_PAGES).map(LruCache::new),
+            decay_schedule: None,
+            quiesce_timeout: None,
+            hysteresis: None,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            repin_interval: DEFAULT_REPIN_INTERVAL,
+            pin_on_demand: false,
+            slab_numa_nodes: HashMap::default(),
+            node_pinning_limits: HashMap::default(),
+            slab_pinning_limits: HashMap::default(),
+            numa_preference: None,
+            pinning_limit: Arc::new(AtomicUsize::new(usize::MAX)),
+            pinning_limit_percent: Arc::new(Mutex::new(None)),
+            max_overshoot_bytes: None,
+            adaptive_interval: None,
+            max_changes_per_cycle: None,
+            repin_cycle: 0,
+            min_pin_duration: None,
+            pinned_since: HashMap::default(),
+            last_decay: Instant::now(),
+            history: None,
+            churn_stats: Arc::new(Mutex::new(ChurnStats::default())),
+            metrics: Arc::new(Metrics::new()),
+            pinned_snapshots: Arc::new(SnapshotPublisher::default()),
+            cached_snapshot: Arc::new(PinnedSetSnapshot::empty()),
+            slab_metrics: HashMap::default(),
+            metrics_sink: Arc::new(NoopMetricsSink),
+            event_listener: Arc::new(NoopEventListener),
+            access_tracer: None,
+            pinned_usage: Arc::default(),
+            pinning_time_series: Arc::new(Mutex::new(PinningTimeSeries::default())),
+            segment_indices: HashMap::default(),
+            indexed_segments: Vec::new(),
+            access_counts: None,
+            access_events: None,
+            recorder: None,
+            eviction_queue: None,
+            on_demand_pins: Arc::new(Mutex::new(HashSet::default())),
+            budget_split: None,
+            miss_rate_trigger: None,
+            doorkeeper: None,
+            #[cfg(feature = "shm-stats")]
+            shm_stats: None,
         }
     }
 
-    pub fn pin_and_unpin_thread(&mut self, priv_info: Slab::PrivateInfo) {
-        loop {
-            let new_pinned_list = self.return_all_segments_sized();
-            tracing::debug!("The current hotset is: {:?}", new_pinned_list);
-            // tracing::debug!("The segment stats is: {:?}", self.segment_stats);
-            for item in self.current_pinned_list.difference(&new_pinned_list){
-                // UNPINNING THE ITEMS
-                let segment = self.segments.get(item);
-                match segment{
-                    Some(extracted_segment) => {
-                        loop {
-                            let mut locked_segment = extracted_segment.lock().unwrap();
-                            locked_segment.2 = true;
-                            if locked_segment.1 == 0 {
-                                tracing::debug!("Unpinning segment: {:?}", locked_segment);
-                                locked_segment.0.unregister();
-                                locked_segment.2 = false; 
-                                break;
-                            }
-                        }
-                    }
-                    None => {
-                        tracing::error!("Segment ID: {:?} Not found", item.0);
-                    }
-                }
-            }
+    /// Start recording per-segment access counts in buckets of `bucket_width`, keeping the
+    /// most recent `max_buckets` buckets for each segment.
+    pub fn enable_segment_history(&mut self, bucket_width: Duration, max_buckets: usize) {
+        self.history = Some(Arc::new(Mutex::new(SegmentHistory::new(
+            bucket_width,
+            max_buckets,
+        ))));
+    }
 
-            for item in new_pinned_list.difference(&self.current_pinned_list){
-                let segment = self.segments.get(item);
-                match segment{
-                    Some(extracted_segment) => {
-                        let mut locked_segment = extracted_segment.lock().unwrap();
-                        locked_segment.0.register(&priv_info);
-                        tracing::debug!("Pinning segment: {:?}", locked_segment);
-                    },
-                    None => {
-                        tracing::error!("Segment ID: {:?} Not found", item.0);
-                    }
-                }
-            }
+    pub fn disable_segment_history(&mut self) {
+        self.history = None;
+    }
 
-            self.current_pinned_list = new_pinned_list;
-            sleep(Duration::new(1, 0));
-        }
+    /// Bucketed access counts for a segment, oldest first, if history is enabled.
+    pub fn segment_history(&self, segment_id: (Slab::SlabId, usize)) -> Option<Vec<HistoryBucket>> {
+        self.history
+            .as_ref()
+            .and_then(|history| history.lock_or_recover().segment_history(segment_id))
     }
 
-    pub fn initialize_slab(
-        &mut self,
-        slab: &Slab,
-        num_registrations: usize,
-        register_at_start: bool,
-        priv_info: Slab::PrivateInfo,
-    ) {
-        tracing::debug!("Initializing slab with {} registrations", num_registrations);
-        let pages_per_registration = slab.get_total_num_pages() / num_registrations;
-        let reg_size = pages_per_registration * slab.get_page_size_as_num();
-        let segs: Vec<Arc<Mutex<(DatapathSegment<Slab>, usize, bool)>>> = (0..num_registrations)
-            .map(|reg| {
-                let start_address = slab.get_start_address() as usize + reg_size * reg;
-                let seg = Arc::new(Mutex::new((
-                    DatapathSegment::new(
-                        start_address as *mut ::std::os::raw::c_void,
-                        pages_per_registration,
-                        slab.get_page_size(),
-                        reg,
-                        slab,
-                    ),
-                    0usize,
-                    false
-                )));
-                if let Ok(ref mut s) = seg.lock() {
-                    for page in s.0.get_4kb_pages() {
-                        self.page_cache_4kb.insert(page, (slab.get_slab_id(), reg));
-                    }
-                    for page in s.0.get_2mb_pages() {
-                        self.page_cache_2mb.insert(page, (slab.get_slab_id(), reg));
-                    }
-                    for page in s.0.get_1gb_pages() {
-                        self.page_cache_1gb.insert(page, (slab.get_slab_id(), reg));
-                    }
-                    // if register at start, register slab
-                    if register_at_start {
-                        s.0.register(&priv_info);
-                    }
-                }
+    /// Have the pin/unpin thread decay the cache builder statistics every `interval`.
+    pub fn set_decay_schedule(&mut self, policy: DecayPolicy, interval: Duration) {
+        self.decay_schedule = Some(DecaySchedule { policy, interval });
+        self.last_decay = Instant::now();
+    }
 
-                seg
-            })
-            .collect();
+    pub fn clear_decay_schedule(&mut self) {
+        self.decay_schedule = None;
+    }
 
-        for (i, seg) in segs.into_iter().enumerate() {
-            self.segments.insert((slab.get_slab_id(), i), seg);
-        }
+    pub fn get_decay_schedule(&self) -> Option<DecaySchedule> {
+        self.decay_schedule
     }
 
-    /// Get segment ID for raw address.
-    pub fn get_segment_id(&self, buf: &[u8]) -> Option<(Slab::SlabId, usize)> {
-        match self
-            .page_cache_2mb
-            .get(&pagesizes::closest_2mb_page(buf.as_ptr()))
-        {
-            Some(m) => {
-                return Some(*m);
-            }
-            None => {}
-        }
-        match self
-            .page_cache_4kb
-            .get(&pagesizes::closest_4k_page(buf.as_ptr()))
-        {
-            Some(m) => {
-                return Some(*m);
-            }
-            None => {}
-        }
-        match self
-            .page_cache_1gb
-            .get(&pagesizes::closest_1g_page(buf.as_ptr()))
-        {
-            Some(m) => {
-                return Some(*m);
-            }
-            None => {}
-        }
-        return None;
+    /// Wait at most `timeout` for a segment's in-flight IO to drain before unpinning it, then
+    /// apply `action`. Without a timeout, unpinning waits for as long as it takes.
+    pub fn set_quiesce_timeout(&mut self, timeout: Duration, action: QuiesceTimeoutAction) {
+        self.quiesce_timeout = Some(QuiesceTimeout { timeout, action });
     }
 
-    pub fn record_io_completion(&mut self, addr: &[u8]) {
-        if let Some(segment_id) = self.get_segment_id(addr) {
-            if let Some(segment_arc) = self.segments.get(&segment_id) {
-                segment_arc.lock().unwrap().1 -= 1;
-            }
-        }
+    pub fn clear_quiesce_timeout(&mut self) {
+        self.quiesce_timeout = None;
     }
 
-    pub fn record_access_and_get_io_info_if_pinned(
-        &mut self,
-        buf: &[u8],
-    ) -> Option<(Slab::SlabId, Slab::IOInfo)> {
-        match self.get_segment_id(buf) {
-            Some(segment_id) => {
-                tracing::debug!("IO was in segment: {:?}", segment_id);
-                // update access to segment
-                self.update_stats(segment_id);
-
-                // try to get lock around segment and count to update
-                match self.segments.get(&segment_id) {
-                    Some(segment_arc) => {
-                        let mut lock = segment_arc.try_lock();
-                        // if we can lock
-                        if let Ok(ref mut mutex) = lock {
-                            if mutex.0.is_pinned() {
-                                // increment IO count
-                                mutex.1 += 1;
-                                // Checking for pinned segment
-                                if mutex.2{
-                                    return None;
-                                }
-                                // return segment id and io info to caller
-                                let slab_id = segment_id.0;
-                                return Some((slab_id, mutex.0.get_io_info()));
-                            } else {
-                                return None;
-                            }
-                        } else {
-                            // someone else has lock
-                            return None;
-                        }
-                    }
+    pub fn get_quiesce_timeout(&self) -> Option<QuiesceTimeout> {
+        self.quiesce_timeout
+    }
 
-                    None => {
-                        return None;
-                    }
-                }
-            }
-            None => {
-                return None;
-            }
-        };
+    /// Only let a segment displace a pinned one if its access count is `displace_margin`
+    /// higher, and keep segments pinned for at least `min_pinned_cycles` repin cycles.
+    pub fn set_hysteresis(&mut self, displace_margin: f64, min_pinned_cycles: u64) {
+        self.hysteresis = Some(Hysteresis {
+            displace_margin,
+            min_pinned_cycles,
+        });
     }
 
-    pub fn update_stats(&mut self, segment_id: (Slab::SlabId, usize)) {
-        // println!("Inside update stats");
-        let mut unlocked_segment_stats = self.segment_stats.lock().unwrap();
-        if unlocked_segment_stats.contains_key(&segment_id){
-            unlocked_segment_stats.get_mut(&segment_id)
-            .unwrap()
-            .update_stats();
-        }else {
-            unlocked_segment_stats.insert(segment_id, Stats::new());
-        }
+    pub fn clear_hysteresis(&mut self) {
+        self.hysteresis = None;
     }
 
-    pub fn get_segment_access_count(&self, segment_id: (Slab::SlabId, usize)) -> Option<i64> {
-        let cloned_segment = self.segment_stats.lock().unwrap();
-        match cloned_segment.get(&segment_id) {
-            Some(s) => Some(s.get_access_count()),
-            None => None
-        }
+    pub fn get_hysteresis(&self) -> Option<Hysteresis> {
+        self.hysteresis
+    }
+
+    /// Carve slabs passed to `add_slab` into segments of `segment_size` bytes.
+    pub fn set_segment_size(&mut self, segment_size: usize) {
+        self.segment_size = segment_size;
     }
 
-    /// Currently ineffecient strategy of sorting through the vector and getting the top segments. 
-    /// Need better strategies to performance these actions.
-    pub fn calculate_hotset_v0(&mut self) -> HashSet<(Slab::SlabId, usize)>{
+    pub fn get_segment_size(&self) -> usize {
+        self.segment_size
+    }
 
-        let mut sorting_vec: Vec<((Slab::SlabId, usize), i64)> = Vec::new();
-        let mut pinned_list = HashSet::new();
-        let cloned_segment_list = self.segment_stats.lock().unwrap();
-        let curr_val = cloned_segment_list.clone();
-        std::mem::drop(cloned_segment_list);
-        for (k, v) in curr_val.clone().into_iter() {
-            sorting_vec.push((k, v.access_count));
-        }
-        sorting_vec.sort_by(|a, b| a.1.cmp(&b.1));
-        for (seg_id, _) in sorting_vec {
-            pinned_list.insert(seg_id);
+    /// Interval to spawn pin workers with.
+    pub fn set_repin_interval(&mut self, interval: Duration) {
+        self.repin_interval = interval;
+    }
+
+    pub fn get_repin_interval(&self) -> Duration {
+        self.repin_interval
+    }
+
+    pub fn set_pin_on_demand(&mut self, pin_on_demand: bool) {
+        self.pin_on_demand = pin_on_demand;
+    }
+
+    pub fn get_pin_on_demand(&self) -> bool {
+        self.pin_on_demand
+    }
+
+    /// Keep at most `bytes` pinned. Takes effect on the next repin cycle, which unpins the
+    /// coldest segments past the limit once their in-flight IO drains. `Forever` segments
+    /// are kept regardless.
+    pub fn set_pinning_limit(&self, bytes: usize) {
+        *self.pinning_limit_percent.lock_or_recover() = None;
+        self.pinning_limit.store(bytes, Ordering::SeqCst);
+    }
+
+    /// Keep at most `percent` (0 to 100) of the memory managed by this cache pinned. The byte
+    /// limit is recomputed whenever a slab is added or removed, so the same setting fits
+    /// machines of any size.
+    pub fn set_pinning_limit_percent(&self, percent: f64) {
+        *self.pinning_limit_percent.lock_or_recover() = Some(percent.clamp(0.0, 100.0));
+        self.recompute_pinning_limit();
+    }
+
+    pub fn get_pinning_limit_percent(&self) -> Option<f64> {
+        *self.pinning_limit_percent.lock_or_recover()
+    }
+
+    pub fn clear_pinning_limit(&self) {
+        *self.pinning_limit_percent.lock_or_recover() = None;
+        self.pinning_limit.store(usize::MAX, Ordering::SeqCst);
+    }
+
+    /// Derive the byte limit from the percentage, if the limit was set as one.
+    fn recompute_pinning_limit(&self) {
+        let percent = self.pinning_limit_percent.lock_or_recover();
+        if let Some(percent) = *percent {
+            let bytes = (self.managed_bytes() as f64 * percent / 100.0) as usize;
+            tracing::debug!("Pinning limit is {}% of managed memory, {} bytes", percent, bytes);
+            self.pinning_limit.store(bytes, Ordering::SeqCst);
         }
-        
-        pinned_list
     }
 
-     pub fn return_all_segments_sized(&mut self) -> HashSet<(Slab::SlabId, usize)>{
-        tracing::debug!("Going into the return all segments");
-        let mut pinned_list = HashSet::new();
-        let cloned_segment_list = self.segment_stats.lock().unwrap();
-        let current_values = cloned_segment_list.clone();
-        std::mem::drop(cloned_segment_list);
-        for (seg_id, _) in current_values{
-            pinned_list.insert(seg_id);
+    pub fn get_pinning_limit(&self) -> Option<usize> {
+        match self.pinning_limit.load(Ordering::SeqCst) {
+            usize::MAX => None,
+            bytes => Some(bytes),
         }
-        pinned_list
-     }
+    }
+
+    /// Record the NUMA node of a slab's memory, overriding what the slab reported.
+    pub fn set_slab_numa_node(&mut self, slab_id: Slab::SlabId, node: usize) {
+        self.slab_numa_nodes.insert(slab_id, node);
+    }
+
+    pub fn get_slab_numa_node(&self, slab_id: Slab::SlabId) -> Option<usize> {
+        self.slab_numa_nodes.get(&slab_id).copied()
+    }
+
+    /// Keep at most `bytes` of segments on NUMA node `node` pinned, in addition to the
+    /// overall pinning limit. Segments of slabs with no known node are not counted.
+    pub fn set_node_pinning_limit(&mut self, node: usize, bytes: usize) {
+        self.node_pinning_limits.insert(node, bytes);
+    }
+
+    pub fn clear_node_pinning_limit(&mut self, node: usize) {
+        self.node_pinning_limits.remove(&node);
+    }
+
+    pub fn get_node_pinning_limit(&self, node: usize) -> Option<usize> {
+        self.node_pinning_limits.get(&node).copied()
+    }
+
+    /// Keep at most `bytes` of the segments of `slab_id` pinned, in addition to the overall
+    /// pinning limit.
+    pub fn set_slab_pinning_limit(&mut self, slab_id: Slab::SlabId, bytes: usize) {
+        self.slab_pinning_limits.insert(slab_id, bytes);
+    }
+
+    pub fn clear_slab_pinning_limit(&mut self, slab_id: Slab::SlabId) {
+        self.slab_pinning_limits.remove(&slab_id);
+    }
+
+    pub fn get_slab_pinning_limit(&self, slab_id: Slab::SlabId) -> Option<usize> {
+        self.slab_pinning_limits.get(&slab_id).copied()
+    }
+
+    /// Prefer segments on `local_node` when a pinning limit forces a choice, e.g. on a
+    /// clone serving a core of that node.
+    pub fn set_numa_preference(&mut self, local_node: usize, remote_weight: f64) {
+        self.numa_preference = Some(NumaPreference {
+            local_node,
+            remote_weight: remote_weight.max(0.0),
+        });
+    }
+
+    pub fn clear_numa_preference(&mut self) {
+        self.numa_preference = None;
+    }
+
+    pub fn get_numa_preference(&self) -> Option<NumaPreference> {
+        self.numa_preference
+    }
+
+    /// Bytes currently registered on each NUMA node, for slabs whose node is known.
+    pub fn bytes_pinned_per_node(&self) -> HashMap<usize, usize> {
+        let mut bytes: HashMap<usize, usize> = HashMap::default();
+        for (segment_id, segment) in self.segments.iter() {
+            if let Some(node) = self.slab_numa_nodes.get(&segment_id.0) {
+                if segment.is_pinned() {
+                    *bytes.entry(*node).or_insert(0) += segment.get_size();
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Drop the coldest segments of the hotset until it fits in `limit` bytes and in every
+    /// per-node and per-slab limit, ranking by pin hint first like the cache builder does.
+    /// Segments are ranked by accesses per byte, so with segments of different sizes a large
+    /// one has to earn its space, and remote-node segments are discounted by the NUMA
+    /// preference.
+    fn trim_to_pinning_limit(
+        &self,
+        limit: usize,
+        new_pinned_list: HashSet<SegmentId<Slab>>,
+    ) -> HashSet<SegmentId<Slab>> {
+        let size = |segment_id: &SegmentId<Slab>| {
+            self.segments.get(segment_id).map_or(0, |s| s.get_size())
+        };
+        let node =
+            |segment_id: &SegmentId<Slab>| self.slab_numa_nodes.get(&segment_id.0).copied();
+        let density = |segment_id: &SegmentId<Slab>| {
+            let count = self.cache_builder.get_access_count(*segment_id).unwrap_or(0);
+            let weight = match self.numa_preference {
+                Some(preference) if node(segment_id) != Some(preference.local_node) => {
+                    preference.remote_weight
+                }
+                _ => 1.0,
+            };
+            weight * count as f64 / size(segment_id).max(1) as f64
+        };
+        let mut ranked: Vec<_> = new_pinned_list
+            .into_iter()
+            .map(|segment_id| {
+                let priority = self.cache_builder.get_priority(&segment_id);
+                (priority, density(&segment_id), segment_id)
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.total_cmp(&a.1)));
+        let mut bytes = 0;
+        let mut node_bytes: HashMap<usize, usize> = HashMap::default();
+        let mut slab_bytes: HashMap<Slab::SlabId, usize> = HashMap::default();
+        ranked
+            .into_iter()
+            .map(|(_, _, segment_id)| segment_id)
+            .filter(|segment_id| {
+                let size = size(segment_id);
+                let node = node(segment_id);
+                let fits_node = node.is_none_or(|node| {
+                    self.node_pinning_limits.get(&node).is_none_or(|node_limit| {
+                        node_bytes.get(&node).copied().unwrap_or(0) + size <= *node_limit
+                    })
+                });
+                let fits_slab = self.slab_pinning_limits.get(&segment_id.0).is_none_or(|limit| {
+                    slab_bytes.get(&segment_id.0).copied().unwrap_or(0) + size <= *limit
+                });
+                let keep = (bytes + size <= limit && fits_node && fits_slab)
+                    || self.cache_builder.get_priority(segment_id) == Priority::Forever;
+                if keep {
+                    bytes += size;
+                    if let Some(node) = node {
+                        *node_bytes.entry(node).or_insert(0) += size;
+                    }
+                    *slab_bytes.entry(segment_id.0).or_insert(0) += size;
+                }
+                keep
+            })
+            .collect()
+    }
+
+    /// Cap the number of pins and unpins a repin cycle may perform; the remaining changes are
+    /// picked up by the following cycles.
+    pub fn set_max_changes_per_cycle(&mut self, max_changes: usize) {
+        self.max_changes_per_cycle = Some(max_changes);
+    }
+
+    pub fn clear_max_changes_per_cycle(&mut self) {
+        self.max_changes_per_cycle = None;
+    }
+
+    pub fn get_max_changes_per_cycle(&self) -> Option<usize> {
+        self.max_changes_per_cycle
+    }
+
+    /// Segments joining the hotset, hottest first, and segments leaving it, coldest first.
+    #[allow(clippy::type_complexity)]
+    fn hotset_changes(
+        &self,
+        new_pinned_list: &HashSet<SegmentId<Slab>>,
+    ) -> (Vec<SegmentId<Slab>>, Vec<SegmentId<Slab>>) {
+        let count = |segment_id: &SegmentId<Slab>| {
+            self.cache_builder.get_access_count(*segment_id).unwrap_or(0)
+        };
+        let mut incoming: Vec<_> = new_pinned_list
+            .difference(&self.current_pinned_list)
+            .copied()
+            .collect();
+        incoming.sort_by_key(|segment_id| std::cmp::Reverse(count(segment_id)));
+        let mut outgoing: Vec<_> = self
+            .current_pinned_list
+            .difference(new_pinned_list)
+            .copied()
+            .collect();
+        outgoing.sort_by_key(count);
+        (incoming, outgoing)
+    }
+
+    /// Swap in a new policy without touching the registered slabs or the pinned set: shard
+    /// `i` of the cache builder becomes `make_builder(i)`, for this cache and every clone. With
+    /// `seed`, each managed segment's access count carries over, and pinned segments get at
+    /// least one access, so the next repin cycle starts from the current hotset instead of an
+    /// empty one.
+    pub fn replace_cache_builder(&self, mut make_builder: impl FnMut(usize) -> CB, seed: bool) {
+        let mut shard_segments: Vec<Vec<SegmentId<Slab>>> =
+            vec![Vec::new(); self.cache_builder.num_shards()];
+        if seed {
+            for segment_id in self.segments.keys() {
+                shard_segments[self.cache_builder.shard_index(segment_id)].push(*segment_id);
+            }
+        }
+        self.cache_builder.replace_shards(|shard, old| {
+            let mut cache_builder = make_builder(shard);
+            for segment_id in shard_segments[shard].iter() {
+                let mut count = old.get_access_count(*segment_id).unwrap_or(0).max(0) as u64;
+                if self.current_pinned_list.contains(segment_id) {
+                    count = count.max(1);
+                }
+                cache_builder.update_access_by(*segment_id, count);
+            }
+            cache_builder
+        });
+        tracing::info!("Replaced the cache builder, seeded: {}", seed);
+    }
+
+    /// Tell the pinning engine how strongly to keep a segment pinned. `High` segments win the
+    /// hotset over hotter `Normal` ones and `Forever` segments are pinned on the next repin
+    /// cycle and never evicted. `Normal` removes the hint.
+    pub fn pin_hint(&self, segment_id: SegmentId<Slab>, priority: Priority) {
+        self.cache_builder.set_priority(segment_id, priority);
+    }
+
+    pub fn get_pin_hint(&self, segment_id: SegmentId<Slab>) -> Priority {
+        self.cache_builder.get_priority(&segment_id)
+    }
+
+    /// Mark the segment holding `buf` as `Forever`. Returns its ID, or `None` if `buf` is not
+    /// in a managed segment.
+    pub fn pin_forever(&self, buf: &[u8]) -> Option<SegmentId<Slab>> {
+        let segment_id = self.get_segment_id(buf)?;
+        self.pin_hint(segment_id, Priority::Forever);
+        Some(segment_id)
+    }
+
+    /// Pin a segment now, whatever the policy thinks of it, e.g. ahead of a known burst of
+    /// accesses. The segment must fit in the pinning limits next to what is already pinned;
+    /// once pinned it is marked `Forever`, so repin cycles keep it until `unpin`, and like any
+    /// `Forever` segment it takes one of the hotset's slots.
+    pub fn pin(
+        &mut self,
+        segment_id: SegmentId<Slab>,
+        priv_info: &Slab::PrivateInfo,
+    ) -> Result<(), PinError> {
+        let segment = self.segments.get(&segment_id).ok_or(PinError::UnknownSegment)?;
+        if !segment.is_pinned() {
+            let size = segment.get_size();
+            let node = self.slab_numa_nodes.get(&segment_id.0).copied();
+            let (mut bytes, mut slab_bytes, mut node_bytes) = (0, 0, 0);
+            for (id, pinned) in self.segments.iter() {
+                if !pinned.is_pinned() || pinned.is_quiescing() {
+                    continue;
+                }
+                bytes += pinned.get_size();
+                if id.0 == segment_id.0 {
+                    slab_bytes += pinned.get_size();
+                }
+                if node.is_some() && self.slab_numa_nodes.get(&id.0).copied() == node {
+                    node_bytes += pinned.get_size();
+                }
+            }
+            let fits = |used: usize, limit: Option<&usize>| limit.is_none_or(|l| used + size <= *l);
+            if !fits(bytes, self.get_pinning_limit().as_ref())
+                || !fits(slab_bytes, self.slab_pinning_limits.get(&segment_id.0))
+                || !fits(node_bytes, node.and_then(|node| self.node_pinning_limits.get(&node)))
+            {
+                return Err(PinError::OverBudget);
+            }
+            if !self.pin_for_repin(segment_id, priv_info) {
+                return Err(PinError::RegistrationFailed);
+            }
+            self.publish_pinned_set(&[]);
+        }
+        self.current_pinned_list.insert(segment_id);
+        self.pin_hint(segment_id, Priority::Forever);
+        Ok(())
+    }
+
+    /// Unpin a segment now, waiting for its in-flight IO, and drop any pin hint on it. The
+    /// policy may pin it again in a later repin cycle if it is still hot. Returns whether the
+    /// segment was unpinned.
+    pub fn unpin(&mut self, segment_id: SegmentId<Slab>) -> bool {
+        self.pin_hint(segment_id, Priority::Normal);
+        let segment = match self.segments.get(&segment_id) {
+            Some(segment) if segment.is_pinned() => segment.clone(),
+            _ => return false,
+        };
+        if !self.quiesce_and_unpin(segment_id, &segment, true) {
+            return false;
+        }
+        self.current_pinned_list.remove(&segment_id);
+        self.pinned_since.remove(&segment_id);
+        self.on_demand_pins.lock_or_recover().remove(&segment_id);
+        self.publish_pinned_set(&[segment_id]);
+        true
+    }
+
+    /// Keep every segment pinned for at least `min_pin_duration` once it is pinned, so a
+    /// short-lived spike elsewhere doesn't waste its registration.
+    pub fn set_min_pin_duration(&mut self, min_pin_duration: Duration) {
+        self.min_pin_duration = Some(min_pin_duration);
+    }
+
+    pub fn clear_min_pin_duration(&mut self) {
+        self.min_pin_duration = None;
+    }
+
+    pub fn get_min_pin_duration(&self) -> Option<Duration> {
+        self.min_pin_duration
+    }
+
+    /// Whether a pinned segment is too young to be displaced, by cycles or by time.
+    fn is_pin_protected(&self, segment_id: &SegmentId<Slab>) -> bool {
+        let Some(&(cycle, pinned_at)) = self.pinned_since.get(segment_id) else {
+            return false;
+        };
+        let min_cycles = self.hysteresis.map_or(0, |h| h.min_pinned_cycles);
+        let too_few_cycles = self.repin_cycle.saturating_sub(cycle) < min_cycles;
+        let too_recent = self
+            .min_pin_duration
+            .is_some_and(|min_pin_duration| pinned_at.elapsed() < min_pin_duration);
+        too_few_cycles || too_recent
+    }
+
+    /// Keep pinned segments the builder's hotset dropped if they were pinned too recently or
+    /// the newcomer replacing them isn't hotter by the hysteresis margin. Newcomers are
+    /// matched hottest first against the coldest pinned segments, so the hotset never grows
+    /// past its size.
+    fn damp_hotset_changes(
+        &self,
+        mut new_pinned_list: HashSet<SegmentId<Slab>>,
+    ) -> HashSet<SegmentId<Slab>> {
+        let count = |segment_id: &SegmentId<Slab>| {
+            self.cache_builder.get_access_count(*segment_id).unwrap_or(0)
+        };
+        let displace_margin = self.hysteresis.map_or(0.0, |h| h.displace_margin);
+        let (incoming, outgoing) = self.hotset_changes(&new_pinned_list);
+        for (newcomer, pinned) in incoming.into_iter().zip(outgoing) {
+            // A segment with a stronger pin hint always gets in.
+            let priority = |segment_id| self.cache_builder.get_priority(segment_id);
+            if priority(&newcomer) > priority(&pinned) {
+                continue;
+            }
+            let threshold = count(&pinned) as f64 * (1.0 + displace_margin);
+            let beats_margin = self.hysteresis.is_none() || (count(&newcomer) as f64) > threshold;
+            if self.is_pin_protected(&pinned) || !beats_margin {
+                new_pinned_list.remove(&newcomer);
+                new_pinned_list.insert(pinned);
+            }
+        }
+        new_pinned_list
+    }
+
+    /// Move the pinned set at most `max_changes` pins and unpins towards the new hotset.
+    /// Unpins are spent first whenever pinning another segment would grow the set past the
+    /// hotset's size.
+    fn limit_hotset_changes(
+        &self,
+        max_changes: usize,
+        new_pinned_list: HashSet<SegmentId<Slab>>,
+    ) -> HashSet<SegmentId<Slab>> {
+        let (incoming, outgoing) = self.hotset_changes(&new_pinned_list);
+        if incoming.len() + outgoing.len() <= max_changes {
+            return new_pinned_list;
+        }
+        let current_len = self.current_pinned_list.len();
+        let (mut num_pins, mut num_unpins) = (0, 0);
+        while num_pins + num_unpins < max_changes {
+            let has_room = current_len + num_pins - num_unpins < new_pinned_list.len();
+            if num_pins < incoming.len() && has_room {
+                num_pins += 1;
+            } else if num_unpins < outgoing.len() {
+                num_unpins += 1;
+            } else {
+                break;
+            }
+        }
+        tracing::debug!(
+            "Deferring {} pins and {} unpins to later cycles",
+            incoming.len() - num_pins,
+            outgoing.len() - num_unpins
+        );
+        let mut limited = self.current_pinned_list.clone();
+        for segment_id in &outgoing[..num_unpins] {
+            limited.remove(segment_id);
+        }
+        limited.extend(&incoming[..num_pins]);
+        limited
+    }
+
+    /// Decay the statistics if the schedule's interval has elapsed. Returns whether it did.
+    pub fn apply_scheduled_decay(&mut self) -> bool {
+        match self.decay_schedule {
+            Some(schedule) if self.last_decay.elapsed() >= schedule.interval => {
+                tracing::debug!("Decaying access statistics with {:?}", schedule.policy);
+                self.cache_builder.decay(schedule.policy);
+                self.last_decay = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop handing out IO against a segment, wait for its in-flight IO to drain and
+    /// unregister it. `evicted` is set when the segment left the hotset. Returns false if the
+    /// quiesce timed out and the segment was left pinned. The segment stays quiescing until
+    /// `publish_pinned_set` runs.
+    fn quiesce_and_unpin(
+        &self,
+        item: SegmentId<Slab>,
+        segment: &SegmentEntry<Slab>,
+        evicted: bool,
+    ) -> bool {
+        self.quiesce_and_unpin_with(item, segment, evicted, true)
+    }
+
+    /// `quiesce_and_unpin`, but with `may_skip` false a `Skip` quiesce timeout forces the
+    /// unpin too.
+    fn quiesce_and_unpin_with(
+        &self,
+        item: SegmentId<Slab>,
+        segment: &SegmentEntry<Slab>,
+        evicted: bool,
+        may_skip: bool,
+    ) -> bool {
+        segment.quiescing.store(true, Ordering::SeqCst);
+        self.event_listener.on_unpinning(item);
+        let timeout = self.quiesce_timeout.map(|q| q.timeout);
+        if !segment.wait_for_io_drain(timeout) {
+            let in_flight = segment.get_in_flight_io();
+            match self.quiesce_timeout.map(|q| q.action) {
+                Some(QuiesceTimeoutAction::Skip) if may_skip => {
+                    tracing::warn!(
+                        "Segment {:?} still has {} IOs in flight, skipping unpin",
+                        item,
+                        in_flight
+                    );
+                    segment.quiescing.store(false, Ordering::SeqCst);
+                    self.event_listener.on_pinned(item, segment.get_io_info());
+                    return false;
+                }
+                _ => tracing::warn!(
+                    "Segment {:?} still has {} IOs in flight, forcing unpin",
+                    item,
+                    in_flight
+                ),
+            }
+        }
+        tracing::debug!("Unpinning segment: {:?}", segment);
+        #[cfg(feature = "trace-spans")]
+        tracing::debug!(
+            segment_id = ?item,
+            bytes = segment.get_size(),
+            reason = if evicted { "left hotset" } else { "unpin all" },
+            "unpin decision"
+        );
+        segment.unregister();
+        self.record_metric(item.0, |m| {
+            m.record_unpin();
+            if evicted {
+                m.record_eviction();
+            }
+        });
+        self.metrics_sink.on_unpin(item, segment.get_size());
+        self.event_listener.on_unpinned(item);
+        self.cache_builder.record_pin_cost(item, segment.get_pin_cost());
+        true
+    }
+
+    /// Publish a snapshot of every pinned segment's IO info for the datapath, then let IO
+    /// through again on the segments that were just unpinned.
+    fn publish_pinned_set(&self, unpinned: &[SegmentId<Slab>]) {
+        let io_info = self
+            .segments
+            .iter()
+            .filter(|(_, segment)| segment.is_pinned())
+            .map(|(segment_id, segment)| (*segment_id, segment.get_io_info()))
+            .collect();
+        let epoch = self.pinned_snapshots.publish(io_info);
+        tracing::debug!("Published pinned set epoch {}", epoch);
+        for segment_id in unpinned {
+            if let Some(segment) = self.segments.get(segment_id) {
+                segment.quiescing.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// The pinned set the datapath currently sees.
+    pub fn pinned_set_snapshot(&self) -> Arc<PinnedSetSnapshot<Slab>> {
+        self.pinned_snapshots.load()
+    }
+
+    /// Unpin every registered segment, e.g. before shutting down. Returns how many were
+    /// unpinned; with a `Skip` quiesce timeout, segments with stuck IO stay pinned.
+    pub fn unpin_all_segments(&mut self) -> usize {
+        self.unpin_every_segment(true)
+    }
+
+    fn unpin_every_segment(&mut self, may_skip: bool) -> usize {
+        let mut unpinned = Vec::new();
+        for (segment_id, segment) in self.segments.iter() {
+            if segment.is_pinned()
+                && self.quiesce_and_unpin_with(*segment_id, segment, false, may_skip)
+            {
+                unpinned.push(*segment_id);
+            }
+        }
+        self.publish_pinned_set(&unpinned);
+        self.current_pinned_list.clear();
+        self.on_demand_pins.lock_or_recover().clear();
+        self.pinned_since.clear();
+        unpinned.len()
+    }
+
+    /// Unpin every segment even if its IO does not drain within the quiesce timeout, and
+    /// optionally drop the access statistics, keeping the slabs and pin hints. For when the
+    /// device is reset or its context recreated and every registration is invalid anyway; the
+    /// next repin cycle registers the hotset again. Returns how many segments were unpinned.
+    pub fn reset_pinned_set(&mut self, reset_stats: bool) -> usize {
+        let num_unpinned = self.unpin_every_segment(false);
+        if reset_stats {
+            self.cache_builder.reset();
+        }
+        tracing::info!("Reset the pinned set, unpinned {} segments", num_unpinned);
+        num_unpinned
+    }
+
+    /// Wait for in-flight IO, unpin every segment and forget every slab, leaving the cache
+    /// empty. Returns how many segments were unpinned. Other clones keep their own view of the
+    /// segments but see them unpinned.
+    pub fn shutdown(&mut self) -> usize {
+        let num_unpinned = self.unpin_all_segments();
+        self.segments.clear();
+        self.segment_ranges.clear();
+        self.last_segment = None;
+        self.forget_unmanaged_pages();
+        self.segment_indices.clear();
+        self.indexed_segments.clear();
+        self.slab_metrics.clear();
+        self.cache_builder.reset();
+        self.cache_builder.clear_priorities();
+        self.publish_pinned_set(&[]);
+        tracing::info!("Zero-copy cache shut down, unpinned {} segments", num_unpinned);
+        num_unpinned
+    }
+
+    /// Run repin cycles every `interval` on a background thread. The returned handle can
+    /// pause, resume or trigger the worker, and unpins everything when shut down.
+    pub fn spawn_pin_worker(&self, priv_info: Slab::PrivateInfo, interval: Duration) -> PinWorker
+    where
+        Slab: 'static,
+        Slab::PrivateInfo: Send + 'static,
+        CB: 'static,
+        Self: Send,
+    {
+        PinWorker::spawn(self.clone(), priv_info, interval, |zero_copy_cache, priv_info| {
+            zero_copy_cache.update_pinned_list(priv_info)
+        })
+    }
+
+    /// Like `spawn_pin_worker`, but each cycle runs through `update_pinned_list_sharded` with
+    /// `num_threads` pin/unpin threads.
+    pub fn spawn_sharded_pin_worker(
+        &self,
+        priv_info: Slab::PrivateInfo,
+        interval: Duration,
+        num_threads: usize,
+    ) -> PinWorker
+    where
+        Slab: 'static,
+        Slab::PrivateInfo: Send + Sync + 'static,
+        CB: 'static,
+        Self: Send + Sync,
+    {
+        PinWorker::spawn(self.clone(), priv_info, interval, move |zero_copy_cache, priv_info| {
+            zero_copy_cache.update_pinned_list_sharded(priv_info, num_threads)
+        })
+    }
+
+    pub fn pin_and_unpin_thread(&mut self, priv_info: Slab::PrivateInfo) {
+        loop {
+            let churn = self.update_pinned_list(&priv_info);
+            sleep(self.next_repin_interval(&churn, DEFAULT_REPIN_INTERVAL));
+        }
+    }
+
+    /// Sleep between repin cycles for `min` to `max`, shorter while the hotset churns and
+    /// longer while it is stable, instead of a fixed interval. Set it before spawning a
+    /// worker, which keeps its own controller.
+    pub fn set_adaptive_repin_interval(&mut self, min: Duration, max: Duration) {
+        self.adaptive_interval = Some(AdaptiveInterval::new(min, max));
+    }
+
+    pub fn set_adaptive_interval_controller(&mut self, adaptive_interval: AdaptiveInterval) {
+        self.adaptive_interval = Some(adaptive_interval);
+    }
+
+    pub fn clear_adaptive_repin_interval(&mut self) {
+        self.adaptive_interval = None;
+    }
+
+    pub fn get_adaptive_interval(&self) -> Option<AdaptiveInterval> {
+        self.adaptive_interval
+    }
+
+    /// How long to wait after a cycle with `churn`: `fixed` unless the interval is adaptive.
+    pub fn next_repin_interval(&mut self, churn: &HotsetChurn, fixed: Duration) -> Duration {
+        match &mut self.adaptive_interval {
+            Some(adaptive_interval) => adaptive_interval.update(churn),
+            None => fixed,
+        }
+    }
+
+    /// Run one repin cycle: ask the cache builder for the hotset, unpin segments that left
+    /// it and pin the ones that joined. With `set_max_overshoot_bytes`, the ones that joined
+    /// are pinned first as far as the overshoot allows.
+    #[cfg_attr(
+        feature = "trace-spans",
+        tracing::instrument(level = "debug", skip_all, fields(hotset_size, bytes_pinned))
+    )]
+    pub fn update_pinned_list(&mut self, priv_info: &Slab::PrivateInfo) -> HotsetChurn {
+        let (new_pinned_list, to_unpin, to_pin) = self.plan_repin_cycle();
+        let (early_pins, late_pins) = self.split_early_pins(&new_pinned_list, to_pin);
+        let pin_all = |this: &Self, items: Vec<SegmentId<Slab>>| {
+            items
+                .into_iter()
+                .filter(|item| this.pin_for_repin(*item, priv_info))
+                .count()
+        };
+        let mut num_pinned = pin_all(self, early_pins);
+        let unpin_results: Vec<_> = to_unpin
+            .into_iter()
+            .map(|item| (item, self.unpin_for_repin(item)))
+            .collect();
+        num_pinned += pin_all(self, late_pins);
+        self.finish_repin_cycle(new_pinned_list, unpin_results, num_pinned)
+    }
+
+    /// Run one repin cycle with the pin and unpin calls spread over `num_threads` threads, each
+    /// responsible for the segments whose index falls in its shard. Every unpin finishes
+    /// before the remaining pins start, so past the overshoot allowed by
+    /// `set_max_overshoot_bytes` the pinned set only grows once the segments that left the
+    /// hotset are gone, and total pinned bytes stay within the limit.
+    #[cfg_attr(
+        feature = "trace-spans",
+        tracing::instrument(level = "debug", skip_all, fields(hotset_size, bytes_pinned))
+    )]
+    pub fn update_pinned_list_sharded(
+        &mut self,
+        priv_info: &Slab::PrivateInfo,
+        num_threads: usize,
+    ) -> HotsetChurn
+    where
+        Slab::PrivateInfo: Sync,
+        Self: Sync,
+    {
+        let num_threads = num_threads.max(1);
+        let (new_pinned_list, to_unpin, to_pin) = self.plan_repin_cycle();
+        let (early_pins, late_pins) = self.split_early_pins(&new_pinned_list, to_pin);
+        let mut num_pinned = self.pin_sharded(early_pins, priv_info, num_threads);
+        let this = &*self;
+        let unpin_shards = this.shard_segments(to_unpin, num_threads);
+        let unpin_results: Vec<(SegmentId<Slab>, Option<bool>)> = thread::scope(|scope| {
+            let handles: Vec<_> = unpin_shards
+                .into_iter()
+                .map(|shard| {
+                    scope.spawn(move || {
+                        shard
+                            .into_iter()
+                            .map(|item| (item, this.unpin_for_repin(item)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        num_pinned += self.pin_sharded(late_pins, priv_info, num_threads);
+        self.finish_repin_cycle(new_pinned_list, unpin_results, num_pinned)
+    }
+
+    /// Pin `items` over `num_threads` threads. Returns how many were pinned.
+    fn pin_sharded(
+        &self,
+        items: Vec<SegmentId<Slab>>,
+        priv_info: &Slab::PrivateInfo,
+        num_threads: usize,
+    ) -> usize
+    where
+        Slab::PrivateInfo: Sync,
+        Self: Sync,
+    {
+        let pin_shards = self.shard_segments(items, num_threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = pin_shards
+                .into_iter()
+                .map(|shard| {
+                    scope.spawn(move || {
+                        shard
+                            .into_iter()
+                            .filter(|item| self.pin_for_repin(*item, priv_info))
+                            .count()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        })
+    }
+
+    /// Let a repin cycle pin newly hot segments before unpinning the cold ones, going over the
+    /// pinning limit (or the hotset's size without one) by at most `bytes` in between, so a
+    /// hot segment is never unavailable while the cold ones drain.
+    pub fn set_max_overshoot_bytes(&mut self, bytes: usize) {
+        self.max_overshoot_bytes = Some(bytes);
+    }
+
+    pub fn clear_max_overshoot_bytes(&mut self) {
+        self.max_overshoot_bytes = None;
+    }
+
+    pub fn get_max_overshoot_bytes(&self) -> Option<usize> {
+        self.max_overshoot_bytes
+    }
+
+    /// Split the segments to pin into those pinned before the cycle's unpins, hottest first
+    /// while they fit under the overshoot, and those pinned after.
+    #[allow(clippy::type_complexity)]
+    fn split_early_pins(
+        &self,
+        new_pinned_list: &HashSet<SegmentId<Slab>>,
+        mut to_pin: Vec<SegmentId<Slab>>,
+    ) -> (Vec<SegmentId<Slab>>, Vec<SegmentId<Slab>>) {
+        let overshoot = match self.max_overshoot_bytes {
+            Some(overshoot) => overshoot,
+            None => return (Vec::new(), to_pin),
+        };
+        let size = |segment_id: &SegmentId<Slab>| {
+            self.segments.get(segment_id).map_or(0, |s| s.get_size())
+        };
+        let limit = self
+            .policy_pinning_limit()
+            .unwrap_or_else(|| new_pinned_list.iter().map(size).sum());
+        let ceiling = limit.saturating_add(overshoot);
+        to_pin.sort_by_key(|segment_id| {
+            std::cmp::Reverse(self.cache_builder.get_access_count(*segment_id).unwrap_or(0))
+        });
+        let mut bytes = self.current_bytes_pinned();
+        let num_early = to_pin
+            .iter()
+            .take_while(|segment_id| {
+                bytes += size(segment_id);
+                bytes <= ceiling
+            })
+            .count();
+        let late_pins = to_pin.split_off(num_early);
+        (to_pin, late_pins)
+    }
+
+    /// The part of the pinning limit the background policy may use.
+    fn policy_pinning_limit(&self) -> Option<usize> {
+        let limit = self.get_pinning_limit()?;
+        Some(match self.budget_split {
+            Some(policy_fraction) => (limit as f64 * policy_fraction) as usize,
+            None => limit,
+        })
+    }
+
+    /// Split `items` into `num_shards` groups by segment index.
+    fn shard_segments(
+        &self,
+        items: Vec<SegmentId<Slab>>,
+        num_shards: usize,
+    ) -> Vec<Vec<SegmentId<Slab>>> {
+        let mut shards = vec![Vec::new(); num_shards];
+        for item in items {
+            let index = self.segment_indices.get(&item).copied().unwrap_or(0);
+            shards[index % num_shards].push(item);
+        }
+        shards
+    }
+
+    /// Drain pending access counts, compute the new hotset and return it along with the
+    /// segments to unpin and to pin.
+    #[allow(clippy::type_complexity)]
+    fn plan_repin_cycle(
+        &mut self,
+    ) -> (HashSet<SegmentId<Slab>>, Vec<SegmentId<Slab>>, Vec<SegmentId<Slab>>) {
+        self.flush_access_counts();
+        self.flush_recorded_accesses();
+        self.repin_cycle += 1;
+        let mut new_pinned_list = self.cache_builder.return_top_segments_to_pin();
+        if self.hysteresis.is_some() || self.min_pin_duration.is_some() {
+            new_pinned_list = self.damp_hotset_changes(new_pinned_list);
+        }
+        let limit = self.policy_pinning_limit();
+        if limit.is_some()
+            || !self.node_pinning_limits.is_empty()
+            || !self.slab_pinning_limits.is_empty()
+        {
+            let limit = limit.unwrap_or(usize::MAX);
+            new_pinned_list = self.trim_to_pinning_limit(limit, new_pinned_list);
+        }
+        if let Some(max_changes) = self.max_changes_per_cycle {
+            new_pinned_list = self.limit_hotset_changes(max_changes, new_pinned_list);
+        }
+        // Segments pinned on demand that made it into the hotset are now the policy's.
+        {
+            let mut on_demand_pins = self.on_demand_pins.lock_or_recover();
+            for segment_id in new_pinned_list.iter() {
+                if on_demand_pins.remove(segment_id) {
+                    self.current_pinned_list.insert(*segment_id);
+                }
+            }
+        }
+        self.apply_scheduled_decay();
+        tracing::debug!("The current hotset is: {:?}", new_pinned_list);
+        let to_unpin = self.current_pinned_list.difference(&new_pinned_list).copied().collect();
+        let to_pin = new_pinned_list.difference(&self.current_pinned_list).copied().collect();
+        (new_pinned_list, to_unpin, to_pin)
+    }
+
+    /// Unpin a segment that left the hotset. `None` if the segment is unknown.
+    fn unpin_for_repin(&self, item: SegmentId<Slab>) -> Option<bool> {
+        match self.segments.get(&item) {
+            Some(extracted_segment) => Some(self.quiesce_and_unpin(item, extracted_segment, true)),
+            None => {
+                tracing::error!("Segment ID: {:?} Not found", item.0);
+                None
+            }
+        }
+    }
+
+    /// Pin a segment that joined the hotset. Returns false if the segment is unknown.
+    fn pin_for_repin(&self, item: SegmentId<Slab>, priv_info: &Slab::PrivateInfo) -> bool {
+        match self.segments.get(&item) {
+            Some(extracted_segment) => {
+                extracted_segment.register(priv_info);
+                if !extracted_segment.is_pinned() {
+                    tracing::warn!("Pinning segment {:?} failed, retrying next cycle", item);
+                    return false;
+                }
+                self.record_metric(item.0, |m| m.record_pin());
+                self.metrics_sink.on_pin(item, extracted_segment.get_size());
+                self.event_listener.on_pinned(item, extracted_segment.get_io_info());
+                tracing::debug!("Pinning segment: {:?}", extracted_segment);
+                #[cfg(feature = "trace-spans")]
+                tracing::debug!(
+                    segment_id = ?item,
+                    bytes = extracted_segment.get_size(),
+                    reason = "joined hotset",
+                    "pin decision"
+                );
+                self.cache_builder
+                    .record_pin_cost(item, extracted_segment.get_pin_cost());
+                true
+            }
+            None => {
+                tracing::error!("Segment ID: {:?} Not found", item.0);
+                false
+            }
+        }
+    }
+
+    /// Publish the new pinned set and record churn, the time series and stats for the cycle.
+    fn finish_repin_cycle(
+        &mut self,
+        mut new_pinned_list: HashSet<SegmentId<Slab>>,
+        unpin_results: Vec<(SegmentId<Slab>, Option<bool>)>,
+        num_pinned: usize,
+    ) -> HotsetChurn {
+        let mut unpinned = Vec::new();
+        let mut still_pinned = Vec::new();
+        for (item, result) in unpin_results {
+            match result {
+                Some(true) => unpinned.push(item),
+                Some(false) => still_pinned.push(item),
+                None => {}
+            }
+        }
+        self.publish_pinned_set(&unpinned);
+        // Drop segments whose pin failed, so the next cycle tries them again.
+        let segments = &self.segments;
+        new_pinned_list.retain(|item| segments.get(item).is_some_and(|s| s.is_pinned()));
+
+        let churn = HotsetChurn {
+            num_pinned,
+            num_unpinned: unpinned.len(),
+            hotset_size: new_pinned_list.len(),
+            jaccard_similarity: jaccard_similarity(&self.current_pinned_list, &new_pinned_list),
+        };
+        tracing::debug!("Hotset churn: {:?}", churn);
+        self.churn_stats.lock_or_recover().record_cycle(churn);
+        self.metrics_sink.on_cycle(&churn);
+        self.current_pinned_list = new_pinned_list;
+        // Segments whose unpin was skipped are retried next cycle.
+        self.current_pinned_list.extend(still_pinned);
+        let current_pinned_list = &self.current_pinned_list;
+        self.pinned_since
+            .retain(|segment_id, _| current_pinned_list.contains(segment_id));
+        let now = Instant::now();
+        for segment_id in current_pinned_list {
+            self.pinned_since
+                .entry(*segment_id)
+                .or_insert((self.repin_cycle, now));
+        }
+        let sample = PinningSample {
+            timestamp: SystemTime::now(),
+            bytes_pinned: self.current_bytes_pinned(),
+            segments_pinned: self.current_segments_pinned(),
+        };
+        self.pinning_time_series.lock_or_recover().push(sample);
+        #[cfg(feature = "shm-stats")]
+        if let Some(region) = &self.shm_stats {
+            region.write(&self.get_metrics(), sample.bytes_pinned, sample.segments_pinned);
+        }
+        #[cfg(feature = "trace-spans")]
+        {
+            let span = tracing::Span::current();
+            span.record("hotset_size", churn.hotset_size);
+            span.record("bytes_pinned", sample.bytes_pinned);
+        }
+        churn
+    }
+
+    /// Every managed segment, in no particular order.
+    pub fn segments(&self) -> impl Iterator<Item = SegmentInfo<SegmentId<Slab>>> + '_ {
+        self.segments.iter().map(|(segment_id, segment)| SegmentInfo {
+            segment_id: *segment_id,
+            start_address: segment.get_start_address() as usize,
+            size: segment.get_size(),
+            pinned: segment.is_pinned(),
+            in_flight_io: segment.get_in_flight_io(),
+            access_count: self.cache_builder.get_access_count(*segment_id),
+        })
+    }
+
+    /// Current pinned set with per-segment access counts and timestamps.
+    pub fn hotset_snapshot(&self) -> HotsetSnapshot<SegmentId<Slab>> {
+        let cache_builder = &self.cache_builder;
+        let segments = self
+            .segments
+            .iter()
+            .map(|(segment_id, segment_arc)| SegmentSnapshot {
+                segment_id: *segment_id,
+                pinned: segment_arc.is_pinned(),
+                access_count: cache_builder.get_access_count(*segment_id),
+                last_access_time: cache_builder.get_last_access_time(*segment_id),
+            })
+            .collect();
+        HotsetSnapshot::new(segments)
+    }
+
+    /// The pinned set and access statistics, to persist across a restart and hand to
+    /// `import_state`. No memory contents are included; serialize it with the `serde` feature.
+    pub fn export_state(&self) -> HotsetSnapshot<SegmentId<Slab>> {
+        self.hotset_snapshot()
+    }
+
+    /// Warm up from an earlier process's `export_state`, once the slabs are registered again:
+    /// seed the cache builder with the recorded access counts, counting previously pinned
+    /// segments at least once, and run a repin cycle so the old hotset is pinned right away
+    /// instead of converging from cold statistics. Segments this cache does not manage are
+    /// skipped. Returns how many segments were seeded.
+    pub fn import_state(
+        &mut self,
+        state: &HotsetSnapshot<SegmentId<Slab>>,
+        priv_info: &Slab::PrivateInfo,
+    ) -> usize {
+        let pinned: HashSet<&SegmentId<Slab>> = state.pinned.iter().collect();
+        let mut num_seeded = 0;
+        for segment in state.segments.iter() {
+            if !self.segments.contains_key(&segment.segment_id) {
+                continue;
+            }
+            let mut count = segment.access_count.unwrap_or(0).max(0) as u64;
+            if pinned.contains(&segment.segment_id) {
+                count = count.max(1);
+            }
+            if count == 0 {
+                continue;
+            }
+            self.cache_builder.update_access_by(segment.segment_id, count);
+            num_seeded += 1;
+        }
+        let churn = self.update_pinned_list(priv_info);
+        tracing::info!(
+            "Imported state for {} segments, pinned {}",
+            num_seeded,
+            churn.num_pinned
+        );
+        num_seeded
+    }
+
+    /// Bytes and segments currently registered, read together.
+    pub fn pinned_usage(&self) -> PinnedUsage {
+        *self.pinned_usage.lock_or_recover()
+    }
+
+    /// Bytes currently registered across all segments.
+    pub fn current_bytes_pinned(&self) -> usize {
+        self.pinned_usage().bytes
+    }
+
+    /// Bytes managed by the cache, pinned or not, across every initialized slab.
+    pub fn managed_bytes(&self) -> usize {
+        self.segments.values().map(|segment| segment.get_size()).sum()
+    }
+
+    /// Number of segments currently registered.
+    pub fn current_segments_pinned(&self) -> usize {
+        self.pinned_usage().segments
+    }
+
+    /// Pinned and total segments and bytes, per slab.
+    pub fn slab_stats(&self) -> HashMap<Slab::SlabId, SlabStats> {
+        let mut stats: HashMap<Slab::SlabId, SlabStats> = HashMap::default();
+        for ((slab_id, _), segment) in self.segments.iter() {
+            let slab_stats = stats.entry(*slab_id).or_insert_with(|| SlabStats {
+                metrics: self
+                    .slab_metrics
+                    .get(slab_id)
+                    .map(|m| m.snapshot())
+                    .unwrap_or_default(),
+                numa_node: self.slab_numa_nodes.get(slab_id).copied(),
+                ..Default::default()
+            });
+            slab_stats.num_segments += 1;
+            slab_stats.total_bytes += segment.get_size();
+            if segment.is_pinned() {
+                slab_stats.pinned_segments += 1;
+                slab_stats.pinned_bytes += segment.get_size();
+            }
+        }
+        stats
+    }
+
+    /// Bytes and segments pinned after each repin cycle, oldest first.
+    pub fn get_pinning_time_series(&self) -> Vec<PinningSample> {
+        self.pinning_time_series.lock_or_recover().samples()
+    }
+
+    /// Number of repin cycles kept in the pinning time series.
+    pub fn set_pinning_time_series_len(&self, len: usize) {
+        self.pinning_time_series.lock_or_recover().set_capacity(len);
+    }
+
+    /// Publish the stats to `region` after every repin cycle, starting now.
+    #[cfg(feature = "shm-stats")]
+    pub fn set_shm_stats_region(&mut self, region: Arc<SharedStatsRegion>) {
+        region.write(
+            &self.get_metrics(),
+            self.current_bytes_pinned(),
+            self.current_segments_pinned(),
+        );
+        self.shm_stats = Some(region);
+    }
+
+    #[cfg(feature = "shm-stats")]
+    pub fn clear_shm_stats_region(&mut self) {
+        self.shm_stats = None;
+    }
+
+    /// Route metrics events to `sink` in addition to the built-in counters.
+    pub fn set_metrics_sink(&mut self, sink: Arc<dyn MetricsSink<SegmentId<Slab>>>) {
+        self.metrics_sink = sink;
+    }
+
+    /// Notify `listener` of every pin and unpin, for this clone and clones made from it
+    /// afterwards.
+    pub fn set_event_listener(&mut self, listener: Arc<dyn ZccEventListener<Slab>>) {
+        self.event_listener = listener;
+    }
+
+    /// Report every datapath access to `tracer`, e.g. a `TraceRecorder`, for this clone and
+    /// clones made from it afterwards.
+    pub fn set_access_tracer(&mut self, tracer: Arc<dyn AccessTracer<SegmentId<Slab>>>) {
+        self.access_tracer = Some(tracer);
+    }
+
+    pub fn clear_access_tracer(&mut self) {
+        self.access_tracer = None;
+    }
+
+    pub fn get_metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+        for slab_metrics in self.slab_metrics.values() {
+            slab_metrics.reset();
+        }
+    }
+
+    /// Churn of the pinned set, accumulated over all repin cycles.
+    pub fn get_churn_stats(&self) -> ChurnStats {
+        *self.churn_stats.lock_or_recover()
+    }
+
+    pub fn initialize_slab(
+        &mut self,
+        slab: &Slab,
+        num_registrations: usize,
+        register_at_start: bool,
+        priv_info: Slab::PrivateInfo,
+    ) {
+        tracing::debug!("Initializing slab with {} registrations", num_registrations);
+        let pages_per_registration = slab.get_total_num_pages() / num_registrations;
+        let segment_pages = vec![pages_per_registration; num_registrations];
+        self.initialize_segments(slab, &segment_pages, register_at_start, priv_info);
+    }
+
+    /// Like `initialize_slab`, but split the slab into segments of `segment_size` bytes
+    /// (rounded down to whole pages, at least one), so each slab can use the registration
+    /// granularity that suits its page size. The last segment holds whatever is left over.
+    pub fn initialize_slab_with_segment_size(
+        &mut self,
+        slab: &Slab,
+        segment_size: usize,
+        register_at_start: bool,
+        priv_info: Slab::PrivateInfo,
+    ) {
+        let pages_per_segment = (segment_size / slab.get_page_size_as_num()).max(1);
+        let total_pages = slab.get_total_num_pages();
+        tracing::debug!("Initializing slab with {} page segments", pages_per_segment);
+        let segment_pages: Vec<usize> = (0..total_pages)
+            .step_by(pages_per_segment)
+            .map(|first_page| pages_per_segment.min(total_pages - first_page))
+            .collect();
+        self.initialize_segments(slab, &segment_pages, register_at_start, priv_info);
+    }
+
+    /// `initialize_slab_with_segment_size` with the segment size this cache was configured
+    /// with.
+    pub fn add_slab(&mut self, slab: &Slab, register_at_start: bool, priv_info: Slab::PrivateInfo) {
+        let segment_size = self.segment_size;
+        self.initialize_slab_with_segment_size(slab, segment_size, register_at_start, priv_info);
+    }
+
+    /// Carve consecutive segments of `segment_pages[i]` pages out of the start of `slab`.
+    fn initialize_segments(
+        &mut self,
+        slab: &Slab,
+        segment_pages: &[usize],
+        register_at_start: bool,
+        priv_info: Slab::PrivateInfo,
+    ) {
+        self.slab_metrics
+            .entry(slab.get_slab_id())
+            .or_insert_with(|| Arc::new(Metrics::new()));
+        if let Some(node) = slab.get_numa_node() {
+            self.slab_numa_nodes.insert(slab.get_slab_id(), node);
+        }
+        let page_size = slab.get_page_size_as_num();
+        self.last_segment = None;
+        self.forget_unmanaged_pages();
+        // Every segment but the last has the same number of pages.
+        let pages_per_segment = segment_pages.first().copied().unwrap_or(1);
+        debug_assert!(segment_pages.iter().rev().skip(1).all(|&pages| pages == pages_per_segment));
+        self.segment_ranges.insert_slab(
+            slab.get_slab_id(),
+            slab.get_start_address() as usize,
+            &slab.get_page_size(),
+            pages_per_segment,
+            segment_pages.iter().sum(),
+        );
+        let mut offset = 0;
+        let segs: Vec<SegmentEntry<Slab>> = segment_pages
+            .iter()
+            .enumerate()
+            .map(|(reg, &num_pages)| {
+                let start_address = slab.get_start_address() as usize + offset;
+                offset += num_pages * page_size;
+                let seg = Arc::new(DatapathSegment::new(
+                    start_address as *mut ::std::os::raw::c_void,
+                    num_pages,
+                    slab.get_page_size(),
+                    reg,
+                    slab,
+                )
+                .with_pinned_usage(self.pinned_usage.clone()));
+                // if register at start, register slab
+                if register_at_start {
+                    seg.register(&priv_info);
+                    self.record_metric(slab.get_slab_id(), |m| m.record_pin());
+                    self.metrics_sink.on_pin((slab.get_slab_id(), reg), seg.get_size());
+                    self.event_listener.on_pinned((slab.get_slab_id(), reg), seg.get_io_info());
+                    self.cache_builder
+                        .record_pin_cost((slab.get_slab_id(), reg), seg.get_pin_cost());
+                }
+
+                seg
+            })
+            .collect();
+
+        for (i, seg) in segs.into_iter().enumerate() {
+            let segment_id = (slab.get_slab_id(), i);
+            self.segment_indices.insert(segment_id, self.indexed_segments.len());
+            self.indexed_segments.push(segment_id);
+            self.segments.insert(segment_id, seg);
+        }
+        self.recompute_pinning_limit();
+        if register_at_start {
+            self.publish_pinned_set(&[]);
+        }
+    }
+
+    /// Stop managing a slab, e.g. before its mempool is torn down: wait for in-flight IO
+    /// and unpin its segments, then forget them along with their statistics. Returns how
+    /// many segments were unpinned. Like `initialize_slab`, this only changes this clone's
+    /// view of the segments.
+    pub fn remove_slab(
+        &mut self,
+        slab_id: Slab::SlabId,
+    ) -> Result<usize, RemoveSlabError<SegmentId<Slab>>> {
+        let segment_ids: Vec<SegmentId<Slab>> = self
+            .segments
+            .keys()
+            .filter(|segment_id| segment_id.0 == slab_id)
+            .copied()
+            .collect();
+        if segment_ids.is_empty() {
+            return Err(RemoveSlabError::UnknownSlab);
+        }
+        let mut unpinned = Vec::new();
+        let mut stuck = Vec::new();
+        for segment_id in segment_ids.iter() {
+            let segment = &self.segments[segment_id];
+            if !segment.is_pinned() {
+                continue;
+            }
+            if self.quiesce_and_unpin(*segment_id, segment, false) {
+                unpinned.push(*segment_id);
+            } else {
+                stuck.push(*segment_id);
+            }
+        }
+        for segment_id in unpinned.iter() {
+            self.current_pinned_list.remove(segment_id);
+        }
+        if !stuck.is_empty() {
+            self.publish_pinned_set(&unpinned);
+            return Err(RemoveSlabError::InFlightIo(stuck));
+        }
+
+        for segment_id in segment_ids.iter() {
+            self.segments.remove(segment_id);
+            self.current_pinned_list.remove(segment_id);
+            self.pinned_since.remove(segment_id);
+            if let Some(index) = self.segment_indices.remove(segment_id) {
+                // The dense index stays allocated; drop any counts left in it.
+                if let Some(access_counts) = &self.access_counts {
+                    access_counts.drain(index);
+                }
+            }
+            self.cache_builder.remove_segment(*segment_id);
+            if let Some(history) = &self.history {
+                history.lock_or_recover().remove(*segment_id);
+            }
+        }
+        self.on_demand_pins
+            .lock_or_recover()
+            .retain(|segment_id| segment_id.0 != slab_id);
+        self.segment_ranges.retain_slabs(|id| *id != slab_id);
+        self.last_segment = None;
+        self.forget_unmanaged_pages();
+        self.slab_metrics.remove(&slab_id);
+        self.slab_numa_nodes.remove(&slab_id);
+        self.slab_pinning_limits.remove(&slab_id);
+        self.recompute_pinning_limit();
+        self.publish_pinned_set(&[]);
+        tracing::info!("Removed slab {:?}, unpinned {} segments", slab_id, unpinned.len());
+        Ok(unpinned.len())
+    }
+
+    /// Get segment ID for raw address.
+    pub fn get_segment_id(&self, buf: &[u8]) -> Option<(Slab::SlabId, usize)> {
+        let addr = buf.as_ptr() as usize;
+        match self.last_segment {
+            Some((start, end, segment_id)) if start <= addr && addr < end => Some(segment_id),
+            _ => self.segment_ranges.get(addr),
+        }
+    }
+
+    /// `get_segment_id` for the datapath, remembering the segment for the next lookup, and
+    /// unmanaged pages so that buffers from them miss without a search.
+    fn resolve_segment_id(&mut self, buf: &[u8]) -> Option<SegmentId<Slab>> {
+        self.resolve_segment_range(buf).map(|(_, _, segment_id)| segment_id)
+    }
+
+    /// `(start, end, segment id)` of the segment holding the start of `buf`; see
+    /// `resolve_segment_id`.
+    fn resolve_segment_range(&mut self, buf: &[u8]) -> Option<(usize, usize, SegmentId<Slab>)> {
+        let addr = buf.as_ptr() as usize;
+        if let Some(range @ (start, end, _)) = self.last_segment {
+            if start <= addr && addr < end {
+                return Some(range);
+            }
+        }
+        let page = pagesizes::closest_2mb_page(buf.as_ptr());
+        if let Some(unmanaged_pages) = &mut self.unmanaged_pages {
+            if unmanaged_pages.get(&page).is_some() {
+                return None;
+            }
+        }
+        match self.segment_ranges.get_range(addr) {
+            Some(range) => {
+                self.last_segment = Some(range);
+                Some(range)
+            }
+            None => {
+                let page_end = page + pagesizes::PGSIZE_2MB;
+                if let Some(unmanaged_pages) = &mut self.unmanaged_pages {
+                    if self.segment_ranges.overlapping(page, page_end).next().is_none() {
+                        unmanaged_pages.put(page, ());
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// Remember up to `num_pages` unmanaged 2MB pages per clone, 0 to turn it off.
+    pub fn set_unmanaged_page_cache_size(&mut self, num_pages: usize) {
+        self.unmanaged_pages = NonZeroUsize::new(num_pages).map(LruCache::new);
+    }
+
+    /// Unmanaged 2MB pages this clone currently remembers.
+    pub fn num_unmanaged_pages_cached(&self) -> usize {
+        self.unmanaged_pages.as_ref().map_or(0, |pages| pages.len())
+    }
+
+    fn forget_unmanaged_pages(&mut self) {
+        if let Some(unmanaged_pages) = &mut self.unmanaged_pages {
+            unmanaged_pages.clear();
+        }
+    }
+
+    /// IDs of every segment `buf` overlaps, in address order.
+    pub fn get_segment_ids(&self, buf: &[u8]) -> SmallVec<[(Slab::SlabId, usize); 2]> {
+        let (start, end) = Self::buf_range(buf);
+        self.segment_ranges
+            .overlapping(start, end)
+            .map(|(_, _, segment_id)| segment_id)
+            .collect()
+    }
+
+    /// Address range of `buf`, at least one byte long so that empty buffers still resolve.
+    fn buf_range(buf: &[u8]) -> (usize, usize) {
+        let start = buf.as_ptr() as usize;
+        (start, start + buf.len().max(1))
+    }
+
+    /// Measured pin/unpin latencies for a segment.
+    pub fn get_segment_pin_cost(&self, segment_id: (Slab::SlabId, usize)) -> Option<PinCost> {
+        self.segments
+            .get(&segment_id)
+            .map(|segment| segment.get_pin_cost())
+    }
+
+    /// IO handed out against a segment and not yet completed.
+    pub fn get_segment_in_flight_io(&self, segment_id: (Slab::SlabId, usize)) -> Option<usize> {
+        self.segments
+            .get(&segment_id)
+            .map(|segment| segment.get_in_flight_io())
+    }
+
+    /// Complete an IO counted against the segment holding `addr`. Completions for unmanaged
+    /// buffers are ignored, and so are completions for a segment with no IO in flight, which
+    /// are counted in `MetricsSnapshot::orphan_completions`.
+    pub fn record_io_completion(&mut self, addr: &[u8]) {
+        if let Some(segment_id) = self.resolve_segment_id(addr) {
+            self.complete_io(segment_id, 1);
+        }
+    }
+
+    /// Complete an IO counted against `segment_id`, for datapaths that kept the id from the
+    /// returned handle instead of the buffer. Returns false if the segment is not managed or
+    /// has no IO in flight, as for `record_io_completion`.
+    pub fn record_io_completion_by_id(&self, segment_id: SegmentId<Slab>) -> bool {
+        self.complete_io(segment_id, 1) == 1
+    }
+
+    /// `record_io_completion_by_id` for each id, e.g. a batch polled off a completion queue.
+    /// Completions are added up per segment first, so each segment's counter is updated
+    /// once. Returns how many completed an IO.
+    pub fn record_io_completions(&self, segment_ids: &[SegmentId<Slab>]) -> usize {
+        let mut counts: SmallVec<[(SegmentId<Slab>, usize); 16]> = SmallVec::new();
+        for segment_id in segment_ids {
+            match counts.iter_mut().find(|(id, _)| id == segment_id) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((*segment_id, 1)),
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(segment_id, count)| self.complete_io(segment_id, count))
+            .sum()
+    }
+
+    /// Complete `count` IOs against a segment, ignoring unknown segments and recording
+    /// completions beyond its IO in flight. Returns how many completed an IO.
+    fn complete_io(&self, segment_id: SegmentId<Slab>, count: usize) -> usize {
+        let segment_arc = match self.segments.get(&segment_id) {
+            Some(segment_arc) => segment_arc,
+            None => return 0,
+        };
+        let orphaned = segment_arc.end_ios(count);
+        if orphaned != 0 {
+            self.record_metric(segment_id.0, |m| m.record_orphan_completions(orphaned));
+        }
+        count - orphaned
+    }
+
+    /// IO info of the segment holding `buf` if it is pinned, without recording an access or
+    /// counting an IO, for deciding between copying and zero-copy ahead of the send. The
+    /// segment can still be unpinned afterwards; `record_access_and_get_io_info` is what keeps
+    /// it pinned for the IO.
+    pub fn get_io_info_if_pinned(&self, buf: &[u8]) -> Option<(Slab::SlabId, Slab::IOInfo)> {
+        let segment_id = self.get_segment_id(buf)?;
+        if self.segments.get(&segment_id)?.is_quiescing() {
+            return None;
+        }
+        let io_info = self.pinned_snapshots.load().get_io_info(&segment_id)?;
+        Some((segment_id.0, io_info))
+    }
+
+    /// Whether the segment holding `buf` is pinned; see `get_io_info_if_pinned`.
+    pub fn is_address_pinned(&self, buf: &[u8]) -> bool {
+        self.get_io_info_if_pinned(buf).is_some()
+    }
+
+    /// Record an access to every segment `buf` covers and, if all of them are pinned, count
+    /// an IO against each and return a lease over the part of `buf` in each segment. The IO
+    /// is completed when the lease is dropped.
+    pub fn record_access_and_get_io_info_if_pinned(&mut self, buf: &[u8]) -> Option<IoLease<Slab>> {
+        let slices = self.record_access_and_get_io_info_if_pinned_manual(buf)?;
+        let segments = slices
+            .iter()
+            .filter_map(|slice| self.segments.get(&slice.segment_id).cloned())
+            .collect();
+        Some(IoLease { slices, segments })
+    }
+
+    /// Like `record_access_and_get_io_info_if_pinned`, but without a lease: the caller must
+    /// complete the IO with `record_io_completion_of`, or the segments never drain.
+    pub fn record_access_and_get_io_info_if_pinned_manual(
+        &mut self,
+        buf: &[u8],
+    ) -> Option<PinnedSlices<Slab>> {
+        let (start, end) = Self::buf_range(buf);
+        match self.covering_ranges(start, end) {
+            Some(ranges) => self.pinned_slices(start, end, ranges, true),
+            None => {
+                self.record_unmanaged_miss(buf.len());
+                None
+            }
+        }
+    }
+
+    /// Like `record_access_and_get_io_info_if_pinned` for each of `bufs`, e.g. the elements of
+    /// a scatter-gather list. The accesses are added up per segment first, so each segment's
+    /// statistics are updated once and each cache builder shard is locked once.
+    pub fn record_accesses(&mut self, bufs: &[&[u8]]) -> Vec<Option<PinnedSlices<Slab>>> {
+        let resolved: Vec<_> = bufs
+            .iter()
+            .map(|buf| {
+                let (start, end) = Self::buf_range(buf);
+                self.covering_ranges(start, end).map(|ranges| (start, end, ranges))
+            })
+            .collect();
+        let mut counts: SmallVec<[(SegmentId<Slab>, u64); 16]> = SmallVec::new();
+        for (_, _, ranges) in resolved.iter().flatten() {
+            for (_, _, segment_id) in ranges.iter() {
+                match counts.iter_mut().find(|(id, _)| id == segment_id) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((*segment_id, 1)),
+                }
+            }
+        }
+        self.update_stats_by(&counts);
+        resolved
+            .into_iter()
+            .zip(bufs)
+            .map(|(resolved, buf)| match resolved {
+                Some((start, end, ranges)) => self.pinned_slices(start, end, ranges, false),
+                None => {
+                    self.record_unmanaged_miss(buf.len());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Ranges of the segments covering `[start, end)`, or `None` if part of it is unmanaged.
+    fn covering_ranges(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> Option<SegmentRanges<Slab>> {
+        let ranges: SegmentRanges<Slab> = self.segment_ranges.overlapping(start, end).collect();
+        let mut covered = start;
+        for &(range_start, range_end, _) in ranges.iter() {
+            if range_start > covered {
+                break;
+            }
+            covered = range_end;
+        }
+        (covered >= end).then_some(ranges)
+    }
+
+    fn record_unmanaged_miss(&self, len: usize) {
+        self.metrics.record_miss(MissReason::Unmanaged);
+        self.metrics_sink.on_miss(None, MissReason::Unmanaged);
+        if let Some(tracer) = &self.access_tracer {
+            tracer.on_access(None, false, len);
+        }
+    }
+
+    /// Count an IO against each segment in `ranges`, recording the access first if
+    /// `record_access`, and return the part of `[start, end)` in each. If one is not pinned,
+    /// the IO already counted is completed again and `None` returned.
+    fn pinned_slices(
+        &mut self,
+        start: usize,
+        end: usize,
+        ranges: SegmentRanges<Slab>,
+        record_access: bool,
+    ) -> Option<PinnedSlices<Slab>> {
+        let mut slices = SmallVec::new();
+        for (range_start, range_end, segment_id) in ranges {
+            let slice_start = start.max(range_start);
+            let len = end.min(range_end) - slice_start;
+            let result = match record_access {
+                true => self.record_segment_access(segment_id, len),
+                false => self.count_segment_io(segment_id, len),
+            };
+            match result {
+                Ok((_, io_info)) => slices.push(PinnedSlice {
+                    segment_id,
+                    io_info,
+                    offset: slice_start - range_start,
+                    len,
+                }),
+                Err(_) => {
+                    self.record_io_completion_of(&slices);
+                    return None;
+                }
+            }
+        }
+        Some(slices)
+    }
+
+    /// Complete the IO counted by `record_access_and_get_io_info_if_pinned`.
+    pub fn record_io_completion_of(
+        &mut self,
+        slices: &[PinnedSlice<SegmentId<Slab>, Slab::IOInfo>],
+    ) {
+        for slice in slices {
+            self.complete_io(slice.segment_id, 1);
+        }
+    }
+
+    /// Like `record_access_and_get_io_info_if_pinned`, but says why the access missed.
+    #[cfg_attr(
+        feature = "trace-spans",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(addr = buf.as_ptr() as usize, len = buf.len(), segment_id, reason)
+        )
+    )]
+    pub fn record_access_and_get_io_info(
+        &mut self,
+        buf: &[u8],
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        let segment_id = match self.resolve_segment_id(buf) {
+            Some(segment_id) => segment_id,
+            None => {
+                self.record_unmanaged_miss(buf.len());
+                return Err(MissReason::Unmanaged);
+            }
+        };
+        self.record_segment_access(segment_id, buf.len())
+    }
+
+    /// Like `record_access_and_get_io_info`, but also say where `buf` sits in the segment.
+    pub fn record_access_and_get_io_handle(
+        &mut self,
+        buf: &[u8],
+    ) -> Result<ZccIoHandle<SegmentId<Slab>, Slab::IOInfo>, MissReason> {
+        let (segment_base, segment_end, segment_id) = match self.resolve_segment_range(buf) {
+            Some(range) => range,
+            None => {
+                self.record_unmanaged_miss(buf.len());
+                return Err(MissReason::Unmanaged);
+            }
+        };
+        let (_, io_info) = self.record_segment_access(segment_id, buf.len())?;
+        let addr = buf.as_ptr() as usize;
+        Ok(ZccIoHandle {
+            segment_id,
+            io_info,
+            segment_base,
+            offset: addr - segment_base,
+            remaining_len: segment_end - addr,
+            generation: self.segments[&segment_id].get_generation(),
+        })
+    }
+
+    /// Whether the IO info in `handle` is still current: the segment is managed and has not
+    /// been unpinned since, even if it was pinned again with a new lkey or IOVA. Costs one
+    /// lookup and an atomic load, so cached descriptors can be checked before every reuse.
+    pub fn validate(&self, handle: &ZccIoHandle<SegmentId<Slab>, Slab::IOInfo>) -> bool {
+        self.segments
+            .get(&handle.segment_id)
+            .is_some_and(|segment| segment.get_generation() == handle.generation)
+    }
+
+    /// Record an access of `len` bytes to a managed segment and count an IO against it if it
+    /// is pinned.
+    fn record_segment_access(
+        &mut self,
+        segment_id: SegmentId<Slab>,
+        len: usize,
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        tracing::debug!("IO was in segment: {:?}", segment_id);
+        self.update_stats(segment_id);
+        self.count_segment_io(segment_id, len)
+    }
+
+    /// Count an IO against a segment whose access is already recorded, and record the hit or
+    /// miss.
+    fn count_segment_io(
+        &mut self,
+        segment_id: SegmentId<Slab>,
+        len: usize,
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        let result = self.count_io(segment_id);
+        self.record_io_result(segment_id, len, result)
+    }
+
+    /// Record the outcome of `count_io` as a hit or miss in the metrics, the metrics sink, the
+    /// miss-rate trigger and the access tracer.
+    fn record_io_result(
+        &self,
+        segment_id: SegmentId<Slab>,
+        len: usize,
+        result: Result<(Slab::SlabId, Slab::IOInfo), MissReason>,
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        if let Some(tracer) = &self.access_tracer {
+            tracer.on_access(Some(segment_id), result.is_ok(), len);
+        }
+        match result {
+            Ok(_) => {
+                #[cfg(feature = "trace-spans")]
+                {
+                    let span = tracing::Span::current();
+                    span.record("segment_id", tracing::field::debug(segment_id));
+                    span.record("reason", "hit");
+                }
+                self.record_metric(segment_id.0, |m| m.record_hit());
+                self.metrics_sink.on_hit(segment_id);
+                if let Some(trigger) = &self.miss_rate_trigger {
+                    trigger.record(true);
+                }
+            }
+            Err(reason) => {
+                tracing::debug!("Access missed: {:?}", reason);
+                #[cfg(feature = "trace-spans")]
+                {
+                    let span = tracing::Span::current();
+                    span.record("segment_id", tracing::field::debug(segment_id));
+                    span.record("reason", tracing::field::debug(reason));
+                }
+                self.record_metric(segment_id.0, |m| m.record_miss(reason));
+                self.metrics_sink.on_miss(Some(segment_id), reason);
+                if let Some(trigger) = &self.miss_rate_trigger {
+                    trigger.record(false);
+                }
+            }
+        }
+        result
+    }
+
+    /// Like `record_access_and_get_io_info`, but pin the segment right away if it is not
+    /// pinned. To stay under the pinning limit the coldest pinned segments are handed to the
+    /// eviction worker, so this never unpins on the request path; without a running eviction
+    /// worker, a segment that doesn't fit is left unpinned. With a doorkeeper, the first
+    /// touch in a window only records the segment.
+    #[cfg_attr(
+        feature = "trace-spans",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(addr = buf.as_ptr() as usize, len = buf.len(), segment_id, reason)
+        )
+    )]
+    pub fn record_and_pin_on_demand(
+        &mut self,
+        buf: &[u8],
+        priv_info: &Slab::PrivateInfo,
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        let segment_id = match self.resolve_segment_id(buf) {
+            Some(segment_id) => segment_id,
+            None => {
+                self.record_unmanaged_miss(buf.len());
+                return Err(MissReason::Unmanaged);
+            }
+        };
+        tracing::debug!("IO was in segment: {:?}", segment_id);
+        self.update_stats(segment_id);
+        let mut result = self.count_io(segment_id);
+        if matches!(result, Err(MissReason::NotPinned))
+            && self
+                .doorkeeper
+                .as_ref()
+                .is_none_or(|doorkeeper| doorkeeper.check_and_insert(&segment_id))
+            && self.pin_on_demand(segment_id, priv_info)
+        {
+            result = self.count_io(segment_id);
+        }
+        // Recorded once, so an access served by pinning on demand is a hit.
+        self.record_io_result(segment_id, buf.len(), result)
+    }
+
+    /// `record_and_pin_on_demand` if this cache was configured to pin on demand, otherwise
+    /// `record_access_and_get_io_info`.
+    pub fn record_access_as_configured(
+        &mut self,
+        buf: &[u8],
+        priv_info: &Slab::PrivateInfo,
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        match self.pin_on_demand {
+            true => self.record_and_pin_on_demand(buf, priv_info),
+            false => self.record_access_and_get_io_info(buf),
+        }
+    }
+
+    /// Give the background policy `policy_fraction` of the pinning limit and reserve the rest
+    /// for on-demand pins of segments the policy hasn't caught up with yet. Set it before
+    /// spawning workers, which keep the split their clone had.
+    pub fn set_budget_split(&mut self, policy_fraction: f64) {
+        self.budget_split = Some(policy_fraction.clamp(0.0, 1.0));
+    }
+
+    pub fn clear_budget_split(&mut self) {
+        self.budget_split = None;
+    }
+
+    pub fn get_budget_split(&self) -> Option<f64> {
+        self.budget_split
+    }
+
+    /// Segments pinned on demand and not yet taken over by the background policy.
+    pub fn on_demand_pinned_segments(&self) -> HashSet<SegmentId<Slab>> {
+        self.on_demand_pins.lock_or_recover().clone()
+    }
+
+    /// Run a repin cycle as soon as more than `threshold` of the last `window` accesses
+    /// missed, but no more often than every `min_interval`. A pin worker spawned from this
+    /// clone afterwards is woken automatically; other targets can be attached to the
+    /// returned trigger.
+    pub fn enable_miss_rate_trigger(
+        &mut self,
+        window: u64,
+        threshold: f64,
+        min_interval: Duration,
+    ) -> Arc<MissRateTrigger> {
+        let trigger = Arc::new(MissRateTrigger::new(window, threshold, min_interval));
+        self.miss_rate_trigger = Some(trigger.clone());
+        trigger
+    }
+
+    pub fn disable_miss_rate_trigger(&mut self) {
+        self.miss_rate_trigger = None;
+    }
+
+    pub fn get_miss_rate_trigger(&self) -> Option<Arc<MissRateTrigger>> {
+        self.miss_rate_trigger.clone()
+    }
+
+    /// Only pin a segment on demand once it has been touched twice within `window`. Shared
+    /// by clones made afterwards.
+    pub fn enable_doorkeeper(&mut self, window: Duration) {
+        self.doorkeeper = Some(Arc::new(Doorkeeper::new(
+            DEFAULT_DOORKEEPER_BITS,
+            DEFAULT_DOORKEEPER_HASHES,
+            window,
+        )));
+    }
+
+    pub fn disable_doorkeeper(&mut self) {
+        self.doorkeeper = None;
+    }
+
+    /// Pin one segment, queueing evictions first if it would not fit. Returns whether the
+    /// segment was pinned.
+    fn pin_on_demand(
+        &mut self,
+        segment_id: SegmentId<Slab>,
+        priv_info: &Slab::PrivateInfo,
+    ) -> bool {
+        let segment = match self.segments.get(&segment_id) {
+            Some(segment) => segment.clone(),
+            None => return false,
+        };
+        if let Some(limit) = self.get_pinning_limit() {
+            // With a split budget, on-demand pins only compete with each other for their share.
+            let (limit, pool) = match self.budget_split {
+                Some(policy_fraction) => (
+                    limit - (limit as f64 * policy_fraction) as usize,
+                    Some(self.on_demand_pins.lock_or_recover().clone()),
+                ),
+                None => (limit, None),
+            };
+            let active = |id: &SegmentId<Slab>, s: &SegmentEntry<Slab>| {
+                pool.as_ref().is_none_or(|pool| pool.contains(id))
+                    && s.is_pinned()
+                    && !s.is_quiescing()
+            };
+            let mut bytes: usize = self
+                .segments
+                .iter()
+                .filter(|(id, s)| active(id, s))
+                .map(|(_, s)| s.get_size())
+                .sum();
+            if bytes + segment.get_size() > limit {
+                let queue = match &self.eviction_queue {
+                    Some(queue) if queue.is_open() => queue.clone(),
+                    _ => return false,
+                };
+                let mut candidates: Vec<_> = self
+                    .segments
+                    .iter()
+                    .filter(|(id, s)| {
+                        active(id, s) && self.cache_builder.get_priority(id) != Priority::Forever
+                    })
+                    .map(|(id, s)| {
+                        let count = self.cache_builder.get_access_count(*id).unwrap_or(0);
+                        (count, *id, s.get_size())
+                    })
+                    .collect();
+                candidates.sort_by_key(|(count, _, _)| *count);
+                let mut victims = Vec::new();
+                for (_, victim, size) in candidates {
+                    if bytes + segment.get_size() <= limit {
+                        break;
+                    }
+                    victims.push(victim);
+                    bytes -= size;
+                }
+                if bytes + segment.get_size() > limit {
+                    return false;
+                }
+                for victim in victims {
+                    tracing::debug!("Evicting segment {:?} for {:?}", victim, segment_id);
+                    // Stop new IO now; the worker unpins once the rest drains.
+                    self.segments[&victim].quiescing.store(true, Ordering::SeqCst);
+                    self.current_pinned_list.remove(&victim);
+                    self.on_demand_pins.lock_or_recover().remove(&victim);
+                    queue.push(victim);
+                }
+            }
+        }
+        segment.register(priv_info);
+        self.record_metric(segment_id.0, |m| m.record_pin());
+        self.metrics_sink.on_pin(segment_id, segment.get_size());
+        self.event_listener.on_pinned(segment_id, segment.get_io_info());
+        self.cache_builder.record_pin_cost(segment_id, segment.get_pin_cost());
+        self.on_demand_pins.lock_or_recover().insert(segment_id);
+        self.publish_pinned_set(&[]);
+        true
+    }
+
+    /// Start the thread that unpins segments evicted by `record_and_pin_on_demand`. Call it
+    /// after initializing the slabs; this clone and clones made from it afterwards hand
+    /// their evictions to the worker.
+    pub fn spawn_eviction_worker(&mut self) -> EvictionWorker<Slab>
+    where
+        Slab: 'static,
+        CB: 'static,
+        Self: Send,
+    {
+        let queue = Arc::new(EvictionQueue::default());
+        self.eviction_queue = Some(queue.clone());
+        EvictionWorker::spawn(self.clone(), queue)
+    }
+
+    /// Every managed segment, for modules that aggregate over segment state.
+    pub(crate) fn segment_entries(&self) -> impl Iterator<Item = &DatapathSegment<Slab>> {
+        self.segments.values().map(|segment| segment.as_ref())
+    }
+
+    /// Unpin a segment queued for eviction. Returns whether it was unpinned.
+    pub(crate) fn unpin_evicted(&self, segment_id: SegmentId<Slab>) -> bool {
+        let segment = match self.segments.get(&segment_id) {
+            Some(segment) => segment,
+            None => {
+                tracing::error!("Evicted segment {:?} not found", segment_id);
+                return false;
+            }
+        };
+        if !segment.is_pinned() {
+            segment.quiescing.store(false, Ordering::SeqCst);
+            return false;
+        }
+        if !self.quiesce_and_unpin(segment_id, segment, true) {
+            return false;
+        }
+        self.publish_pinned_set(&[segment_id]);
+        true
+    }
+
+    /// Apply a counter update to both the global and the slab's metrics.
+    fn record_metric(&self, slab_id: Slab::SlabId, record: impl Fn(&Metrics)) {
+        record(&self.metrics);
+        if let Some(slab_metrics) = self.slab_metrics.get(&slab_id) {
+            record(slab_metrics);
+        }
+    }
+
+    /// Count an IO against the segment and fetch its IO info.
+    fn count_io(
+        &mut self,
+        segment_id: (Slab::SlabId, usize),
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        let segment_arc = match self.segments.get(&segment_id) {
+            Some(segment_arc) => segment_arc,
+            None => return Err(MissReason::Unmanaged),
+        };
+        // Segment is being unpinned, don't hand out new IO against it
+        if !segment_arc.begin_io() {
+            return Err(MissReason::Quiescing);
+        }
+        // Checked after counting the IO: an unpin publishes a snapshot without the segment
+        // before it clears the quiescing flag.
+        self.pinned_snapshots.refresh(&mut self.cached_snapshot);
+        match self.cached_snapshot.get_io_info(&segment_id) {
+            Some(io_info) => Ok((segment_id.0, io_info)),
+            None => {
+                segment_arc.end_io();
+                Err(MissReason::NotPinned)
+            }
+        }
+    }
+
+    /// `update_stats` `count` times for each segment, locking each cache builder shard once
+    /// when the accesses go straight to the cache builder.
+    fn update_stats_by(&mut self, counts: &[(SegmentId<Slab>, u64)]) {
+        if self.access_counts.is_some() || self.access_events.is_some() || self.recorder.is_some()
+        {
+            for &(segment_id, count) in counts {
+                for _ in 0..count {
+                    self.update_stats(segment_id);
+                }
+            }
+            return;
+        }
+        self.cache_builder.update_accesses_by(counts);
+        if let Some(history) = &self.history {
+            let mut history = history.lock_or_recover();
+            for &(segment_id, count) in counts {
+                for _ in 0..count {
+                    history.record_access(segment_id);
+                }
+            }
+        }
+    }
+
+    pub fn update_stats(&mut self, segment_id: (Slab::SlabId, usize)) {
+        let counted = match (&self.access_counts, self.segment_indices.get(&segment_id)) {
+            (Some(access_counts), Some(index)) => access_counts.increment(*index),
+            _ => false,
+        };
+        if !counted {
+            match (&self.access_events, &mut self.recorder) {
+                (Some(access_events), _) => {
+                    access_events.send(segment_id);
+                }
+                (None, Some(recorder)) => recorder.record(segment_id),
+                (None, None) => self.cache_builder.update_access(segment_id),
+            }
+        }
+        if let Some(history) = &self.history {
+            history.lock_or_recover().record_access(segment_id);
+        }
+    }
+
+    /// Accesses recorded by the cache builder, plus any not yet drained from the atomic
+    /// counters or this clone's batch buffer.
+    pub fn get_segment_access_count(&self, segment_id: (Slab::SlabId, usize)) -> Option<i64> {
+        let mut pending = match (&self.access_counts, self.segment_indices.get(&segment_id)) {
+            (Some(access_counts), Some(index)) => access_counts.get(*index) as i64,
+            _ => 0,
+        };
+        if let Some(recorder) = &self.recorder {
+            pending += recorder.pending_count(&segment_id) as i64;
+        }
+        match self.cache_builder.get_access_count(segment_id) {
+            Some(count) => Some(count + pending),
+            None if pending > 0 => Some(pending),
+            None => None,
+        }
+    }
+
+    /// Access count of every managed segment accessed so far.
+    pub fn segment_stats(&self) -> HashMap<SegmentId<Slab>, i64> {
+        self.segments
+            .keys()
+            .filter_map(|id| Some((*id, self.get_segment_access_count(*id)?)))
+            .collect()
+    }
+
+    /// Every managed segment accessed so far, regardless of the cache builder's hotset size.
+    pub fn return_all_segments_sized(&mut self) -> HashSet<SegmentId<Slab>> {
+        self.segment_stats().into_keys().collect()
+    }
+
+    /// The original unbounded hotset, same as `return_all_segments_sized`; pinning goes
+    /// through `cache_builder` instead.
+    pub fn calculate_hotset_v0(&mut self) -> HashSet<SegmentId<Slab>> {
+        self.return_all_segments_sized()
+    }
+
+    /// Count accesses with pre-sized atomics instead of locking the cache builder on every
+    /// access. Segments beyond `capacity` keep using the cache builder directly.
+    pub fn enable_atomic_access_counts(&mut self, capacity: usize) {
+        self.flush_access_counts();
+        self.access_counts = Some(Arc::new(AtomicAccessCounts::new(capacity)));
+    }
+
+    /// Go back to counting in the cache builder, draining any pending counts first.
+    pub fn disable_atomic_access_counts(&mut self) {
+        self.flush_access_counts();
+        self.access_counts = None;
+    }
+
+    /// Buffer this clone's accesses and hand them to the cache builder every `max_batch`
+    /// accesses or `max_delay`, whichever comes first. Clones made afterwards get their own
+    /// empty buffer with the same limits.
+    pub fn enable_batched_access_recording(&mut self, max_batch: usize, max_delay: Duration) {
+        self.flush_recorded_accesses();
+        self.recorder = Some(ThreadLocalRecorder::new(
+            self.cache_builder.clone(),
+            max_batch,
+            max_delay,
+        ));
+    }
+
+    /// Stop batching; anything still buffered is flushed.
+    pub fn disable_batched_access_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    /// Send accesses over a bounded channel of `capacity` events to a dedicated statistics
+    /// thread instead of touching the cache builder on the datapath. Clones made afterwards
+    /// send to the same thread; events are dropped and counted when the channel is full.
+    pub fn enable_access_pipeline(&mut self, capacity: usize) -> AccessPipeline
+    where
+        Slab: 'static,
+        CB: Send + 'static,
+    {
+        let (pipeline, sender) = AccessPipeline::spawn(self.cache_builder.clone(), capacity);
+        self.access_events = Some(sender);
+        pipeline
+    }
+
+    /// Stop sending this clone's accesses to the statistics thread.
+    pub fn disable_access_pipeline(&mut self) {
+        self.access_events = None;
+    }
+
+    /// Access events dropped because the pipeline's channel was full.
+    pub fn get_dropped_access_events(&self) -> u64 {
+        self.access_events
+            .as_ref()
+            .map(|access_events| access_events.dropped_events())
+            .unwrap_or(0)
+    }
+
+    /// Hand this clone's buffered accesses to the cache builder now.
+    pub fn flush_recorded_accesses(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.flush();
+        }
+    }
+
+    /// Feed the accesses counted by the atomics since the last flush into the cache builder.
+    /// The pinning engine calls this at the start of every repin cycle.
+    pub fn flush_access_counts(&self) {
+        if let Some(access_counts) = &self.access_counts {
+            for (index, segment_id) in self.indexed_segments.iter().enumerate() {
+                let count = access_counts.drain(index);
+                if count > 0 {
+                    self.cache_builder.update_access_by(*segment_id, count);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,270 @@
+//! Registration deduplication across cache instances. When several `ZeroCopyCache`s (say one
+//! per core, not clones of each other) manage the same slab, each would register the same
+//! segments with the driver. Wrapping the slab in a `DedupSlab` that shares one
+//! `RegistrationCache` makes them share registrations instead: the first pin of a
+//! (device, address range) calls the driver, later ones only take a reference, and the
+//! driver unpin happens when the last reference is dropped.
+//!
+//! `RegistrationCache::global` gives every instance in the process the same cache. Only
+//! identical ranges are coalesced, so every instance should carve the slab into the same
+//! segments. Driver calls happen with the cache locked, so concurrent pins of the same
+//! range never race each other into the driver.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::data_structures::DatapathSlab;
+use crate::metrics::LockOrRecover;
+use crate::pagesizes::PageSize;
+
+/// Device, start address and length of a registration.
+type RegistrationKey = (usize, usize, usize);
+
+#[derive(Debug)]
+struct SharedRegistration<S>
+where
+    S: DatapathSlab,
+{
+    refs: usize,
+    state: S::PinningState,
+}
+
+/// Registrations shared by every `DedupSlab` created with it.
+#[derive(Debug)]
+pub struct RegistrationCache<S>
+where
+    S: DatapathSlab,
+{
+    registrations: Mutex<HashMap<RegistrationKey, SharedRegistration<S>>>,
+    driver_pins: AtomicU64,
+    coalesced_pins: AtomicU64,
+}
+
+impl<S> Default for RegistrationCache<S>
+where
+    S: DatapathSlab,
+{
+    fn default() -> Self {
+        RegistrationCache {
+            registrations: Mutex::new(HashMap::default()),
+            driver_pins: AtomicU64::new(0),
+            coalesced_pins: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<S> RegistrationCache<S>
+where
+    S: DatapathSlab,
+{
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// The process-wide cache for slabs of type `S`, created on first use.
+    pub fn global() -> Arc<Self>
+    where
+        S: 'static,
+    {
+        static GLOBAL: OnceLock<Mutex<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>> =
+            OnceLock::new();
+        let caches = GLOBAL.get_or_init(Default::default);
+        let cache = caches
+            .lock_or_recover()
+            .entry(TypeId::of::<S>())
+            .or_insert_with(|| Self::new())
+            .clone();
+        cache.downcast().unwrap()
+    }
+
+    /// Ranges currently registered with the driver.
+    pub fn len(&self) -> usize {
+        self.registrations.lock_or_recover().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// References held on the registration of a range, 0 if it is not registered.
+    pub fn refs(&self, device: usize, start_address: *const c_void, len: usize) -> usize {
+        let key = (device, start_address as usize, len);
+        self.registrations
+            .lock_or_recover()
+            .get(&key)
+            .map_or(0, |registration| registration.refs)
+    }
+
+    /// Pins that reached the driver.
+    pub fn driver_pins(&self) -> u64 {
+        self.driver_pins.load(Ordering::Relaxed)
+    }
+
+    /// Pins served by an existing registration.
+    pub fn coalesced_pins(&self) -> u64 {
+        self.coalesced_pins.load(Ordering::Relaxed)
+    }
+}
+
+/// Device a segment is registered for, and the wrapped slab's own context for it.
+#[derive(Debug, Clone)]
+pub struct DedupContext<P> {
+    /// Identifies the device across instances, e.g. a port or NIC index.
+    pub device: usize,
+    pub inner: P,
+}
+
+pub struct DedupPinningState<S>
+where
+    S: DatapathSlab,
+{
+    /// Registration this segment holds a reference on, while pinned.
+    key: Option<RegistrationKey>,
+    io_info: Option<S::IOInfo>,
+    /// Unpinned state used for the driver call when this segment registers first. It moves
+    /// into the shared registration on success and is replaced by a fresh one from `inner`.
+    spare: S::PinningState,
+    inner: Arc<S>,
+    cache: Arc<RegistrationCache<S>>,
+}
+
+impl<S> std::fmt::Debug for DedupPinningState<S>
+where
+    S: DatapathSlab,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DedupPinningState")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct DedupSlab<S>
+where
+    S: DatapathSlab + std::fmt::Debug,
+{
+    inner: Arc<S>,
+    cache: Arc<RegistrationCache<S>>,
+}
+
+impl<S> DedupSlab<S>
+where
+    S: DatapathSlab + std::fmt::Debug,
+{
+    /// Wrap `inner`, sharing registrations through `cache`. Give every instance managing the
+    /// slab its own `DedupSlab` over the same `inner` and `cache`.
+    pub fn new(inner: Arc<S>, cache: Arc<RegistrationCache<S>>) -> Self {
+        DedupSlab { inner, cache }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> DatapathSlab for DedupSlab<S>
+where
+    S: DatapathSlab + std::fmt::Debug + Send + Sync,
+{
+    type SlabId = S::SlabId;
+    type IOInfo = S::IOInfo;
+    type PinningState = DedupPinningState<S>;
+    type PrivateInfo = DedupContext<S::PrivateInfo>;
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        DedupPinningState {
+            key: None,
+            io_info: None,
+            spare: self.inner.default_pinning_state(),
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.inner.get_slab_id()
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        pinning_state.key.is_some()
+    }
+
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        if pinning_state.key.is_some() {
+            return;
+        }
+        let key = (private_info.device, start_address as usize, len);
+        let cache = pinning_state.cache.clone();
+        let mut registrations = cache.registrations.lock_or_recover();
+        if let Some(registration) = registrations.get_mut(&key) {
+            registration.refs += 1;
+            cache.coalesced_pins.fetch_add(1, Ordering::Relaxed);
+            pinning_state.io_info = Some(S::get_io_info(&registration.state));
+            pinning_state.key = Some(key);
+            return;
+        }
+        S::pin_segment(&mut pinning_state.spare, &private_info.inner, start_address, len);
+        cache.driver_pins.fetch_add(1, Ordering::Relaxed);
+        if !S::is_pinned(&pinning_state.spare) {
+            return;
+        }
+        let fresh = pinning_state.inner.default_pinning_state();
+        let state = std::mem::replace(&mut pinning_state.spare, fresh);
+        pinning_state.io_info = Some(S::get_io_info(&state));
+        pinning_state.key = Some(key);
+        registrations.insert(key, SharedRegistration { refs: 1, state });
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        let key = match pinning_state.key.take() {
+            Some(key) => key,
+            None => return,
+        };
+        pinning_state.io_info = None;
+        let cache = pinning_state.cache.clone();
+        let mut registrations = cache.registrations.lock_or_recover();
+        let registration = match registrations.get_mut(&key) {
+            Some(registration) => registration,
+            None => return,
+        };
+        registration.refs -= 1;
+        if registration.refs > 0 {
+            return;
+        }
+        // The registration may have been made by another instance's segment; either way its
+        // state is unpinned and dropped here, and each segment keeps its own spare.
+        let mut state = registrations.remove(&key).unwrap().state;
+        S::unpin_segment(&mut state);
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        match pinning_state.io_info {
+            Some(io_info) => io_info,
+            None => S::get_io_info(&pinning_state.spare),
+        }
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.inner.get_total_num_pages()
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.inner.get_start_address()
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.inner.get_page_size()
+    }
+
+    fn get_numa_node(&self) -> Option<usize> {
+        self.inner.get_numa_node()
+    }
+}
@@ -0,0 +1,90 @@
+//! TinyLFU-style doorkeeper for on-demand pinning: a Bloom filter remembering which segments
+//! were touched in the current window, so a segment is only pinned on its second touch and
+//! one-off accesses never pay for a registration. The filter is cleared every `window`.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Sized for a few thousand segments per window at a low false-positive rate.
+pub const DEFAULT_DOORKEEPER_BITS: usize = 1 << 16;
+pub const DEFAULT_DOORKEEPER_HASHES: usize = 3;
+
+#[derive(Debug)]
+pub struct Doorkeeper {
+    bits: Box<[AtomicU64]>,
+    num_hashes: usize,
+    window: Duration,
+    created: Instant,
+    /// Nanoseconds after `created` at which the filter was last cleared.
+    last_reset: AtomicU64,
+}
+
+impl Doorkeeper {
+    pub fn new(num_bits: usize, num_hashes: usize, window: Duration) -> Self {
+        let num_words = num_bits.div_ceil(64).max(1);
+        Doorkeeper {
+            bits: (0..num_words).map(|_| AtomicU64::new(0)).collect(),
+            num_hashes: num_hashes.max(1),
+            window,
+            created: Instant::now(),
+            last_reset: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get_window(&self) -> Duration {
+        self.window
+    }
+
+    fn bit_positions<K: Hash>(&self, key: &K) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1 = hasher.finish();
+        // Second hash for double hashing; forced odd so it cycles through every bit.
+        let h2 = h1.rotate_left(32).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let num_bits = (self.bits.len() * 64) as u64;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    /// Record a touch of `key`. Returns true if it was already seen in this window.
+    pub fn check_and_insert<K: Hash>(&self, key: &K) -> bool {
+        self.reset_if_expired();
+        let mut seen = true;
+        for bit in self.bit_positions(key) {
+            let mask = 1 << (bit % 64);
+            let previous = self.bits[bit / 64].fetch_or(mask, Ordering::Relaxed);
+            seen &= previous & mask != 0;
+        }
+        seen
+    }
+
+    pub fn contains<K: Hash>(&self, key: &K) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.bits[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn clear(&self) {
+        for word in self.bits.iter() {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Clear the filter if the window has passed since it was last cleared.
+    fn reset_if_expired(&self) {
+        let now = self.created.elapsed().as_nanos() as u64;
+        let last_reset = self.last_reset.load(Ordering::Relaxed);
+        if now.saturating_sub(last_reset) < self.window.as_nanos() as u64 {
+            return;
+        }
+        // Only the caller that moves the timestamp clears the bits.
+        if self
+            .last_reset
+            .compare_exchange(last_reset, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            tracing::debug!("Clearing doorkeeper after {:?}", self.window);
+            self.clear();
+        }
+    }
+}
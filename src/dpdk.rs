@@ -0,0 +1,361 @@
+//! `DatapathSlab` adapter for DPDK external memory, e.g. the buffers behind a mempool created
+//! with `rte_pktmbuf_pool_create_extbuf`. Pinning a segment registers it with
+//! `rte_extmem_register` and DMA-maps it for the device with `rte_dev_dma_map`; `IOInfo` is
+//! the segment's IOVA.
+//!
+//! The DPDK symbols are declared here but not linked: the application links DPDK itself, as
+//! it has to anyway to initialize the EAL. IOVAs come from `rte_mem_virt2iova`, or are the
+//! virtual address in IOVA-as-VA mode. In IOVA-as-PA mode each segment must be physically
+//! contiguous, e.g. a single hugepage.
+use std::os::raw::{c_int, c_uint, c_void};
+
+use crate::data_structures::DatapathSlab;
+use crate::pagesizes::{self, PageSize};
+
+pub type RteIova = u64;
+
+/// Returned by DPDK for memory it cannot translate, and as `IOInfo` of an unpinned segment.
+pub const RTE_BAD_IOVA: RteIova = u64::MAX;
+
+/// Opaque `struct rte_device`.
+#[repr(C)]
+pub struct RteDevice {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn rte_extmem_register(
+        va_addr: *mut c_void,
+        len: usize,
+        iova_addrs: *mut RteIova,
+        n_pages: c_uint,
+        page_sz: usize,
+    ) -> c_int;
+    fn rte_extmem_unregister(va_addr: *mut c_void, len: usize) -> c_int;
+    fn rte_dev_dma_map(dev: *mut RteDevice, addr: *mut c_void, iova: RteIova, len: usize) -> c_int;
+    fn rte_dev_dma_unmap(
+        dev: *mut RteDevice,
+        addr: *mut c_void,
+        iova: RteIova,
+        len: usize,
+    ) -> c_int;
+    fn rte_mem_virt2iova(virt: *const c_void) -> RteIova;
+}
+
+/// Device segments are DMA-mapped for, passed to the cache as `PrivateInfo`. Without a
+/// device, segments are only registered as external memory.
+#[derive(Debug, Clone, Copy)]
+pub struct DpdkDevice {
+    dev: *mut RteDevice,
+}
+
+// SAFETY: `rte_device` handles are process-wide and DPDK's DMA mapping calls are thread-safe.
+unsafe impl Send for DpdkDevice {}
+unsafe impl Sync for DpdkDevice {}
+
+impl DpdkDevice {
+    /// # Safety
+    /// `dev` must be null or point to an `rte_device` that outlives every segment mapped for
+    /// it, e.g. `rte_eth_devices[port].device`.
+    pub unsafe fn new(dev: *mut RteDevice) -> Self {
+        DpdkDevice { dev }
+    }
+
+    /// Register segments as external memory without DMA-mapping them.
+    pub fn none() -> Self {
+        DpdkDevice {
+            dev: std::ptr::null_mut(),
+        }
+    }
+}
+
+/// A registered (and possibly DMA-mapped) segment.
+#[derive(Debug)]
+struct DpdkMapping {
+    addr: usize,
+    len: usize,
+    iova: RteIova,
+    dev: DpdkDevice,
+}
+
+#[derive(Debug, Default)]
+pub struct DpdkPinningState {
+    mapping: Option<DpdkMapping>,
+}
+
+#[derive(Debug)]
+pub struct DpdkSlab {
+    slab_id: usize,
+    start_address: *mut c_void,
+    num_pages: usize,
+    page_size: PageSize,
+}
+
+// SAFETY: the slab only describes memory owned by the application; see `DpdkSlab::new`.
+unsafe impl Send for DpdkSlab {}
+unsafe impl Sync for DpdkSlab {}
+
+impl DpdkSlab {
+    /// Describe `num_pages` pages of `page_size` at `start_address`.
+    ///
+    /// # Safety
+    /// The memory must stay mapped, and must not be registered with DPDK by anyone else,
+    /// for as long as the slab's segments are managed by a cache.
+    pub unsafe fn new(
+        slab_id: usize,
+        start_address: *mut c_void,
+        num_pages: usize,
+        page_size: PageSize,
+    ) -> Self {
+        DpdkSlab {
+            slab_id,
+            start_address,
+            num_pages,
+            page_size,
+        }
+    }
+}
+
+/// IOVA DPDK uses for `addr`, assuming IOVA-as-VA if it cannot translate it.
+fn iova_of(addr: *mut c_void) -> RteIova {
+    match unsafe { rte_mem_virt2iova(addr) } {
+        RTE_BAD_IOVA => addr as RteIova,
+        iova => iova,
+    }
+}
+
+/// Largest page size that both the start and the length of a range are multiples of, as
+/// `rte_extmem_register` requires.
+fn page_size_for_range(addr: usize, len: usize) -> usize {
+    [pagesizes::PGSIZE_1GB, pagesizes::PGSIZE_2MB]
+        .into_iter()
+        .find(|page_size| addr.is_multiple_of(*page_size) && len.is_multiple_of(*page_size))
+        .unwrap_or(pagesizes::PGSIZE_4KB)
+}
+
+impl DatapathSlab for DpdkSlab {
+    type SlabId = usize;
+    /// IOVA of the start of the segment, `RTE_BAD_IOVA` if it is not pinned.
+    type IOInfo = RteIova;
+    type PinningState = DpdkPinningState;
+    type PrivateInfo = DpdkDevice;
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        DpdkPinningState::default()
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.slab_id
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        pinning_state.mapping.is_some()
+    }
+
+    // The range comes from the slab's own memory; the trait fixes the signature.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        if pinning_state.mapping.is_some() {
+            return;
+        }
+        // Without an IOVA table DPDK only needs a page size the range is aligned to; larger
+        // pages keep the number of memsegs it creates down.
+        let page_size = page_size_for_range(start_address as usize, len);
+        let ret = unsafe {
+            rte_extmem_register(
+                start_address,
+                len,
+                std::ptr::null_mut(),
+                (len / page_size) as c_uint,
+                page_size,
+            )
+        };
+        if ret != 0 {
+            tracing::warn!("rte_extmem_register of {} bytes failed: {}", len, ret);
+            return;
+        }
+        let iova = iova_of(start_address);
+        if !private_info.dev.is_null() {
+            let ret = unsafe { rte_dev_dma_map(private_info.dev, start_address, iova, len) };
+            if ret != 0 {
+                tracing::warn!("rte_dev_dma_map of {} bytes failed: {}", len, ret);
+                unsafe { rte_extmem_unregister(start_address, len) };
+                return;
+            }
+        }
+        pinning_state.mapping = Some(DpdkMapping {
+            addr: start_address as usize,
+            len,
+            iova,
+            dev: *private_info,
+        });
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        let mapping = match pinning_state.mapping.take() {
+            Some(mapping) => mapping,
+            None => return,
+        };
+        let addr = mapping.addr as *mut c_void;
+        let DpdkMapping { len, iova, dev, .. } = mapping;
+        if !dev.dev.is_null() {
+            let ret = unsafe { rte_dev_dma_unmap(dev.dev, addr, iova, len) };
+            if ret != 0 {
+                tracing::warn!("rte_dev_dma_unmap of {} bytes failed: {}", len, ret);
+            }
+        }
+        let ret = unsafe { rte_extmem_unregister(addr, len) };
+        if ret != 0 {
+            tracing::warn!("rte_extmem_unregister of {} bytes failed: {}", len, ret);
+        }
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        pinning_state
+            .mapping
+            .as_ref()
+            .map_or(RTE_BAD_IOVA, |mapping| mapping.iova)
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.start_address
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.page_size.clone()
+    }
+}
+
+/// Stand-ins for the DPDK calls above, so tests link without DPDK and can check how segments
+/// are registered and mapped. Calls are recorded per thread.
+#[cfg(test)]
+pub(crate) mod fake_dpdk {
+    use std::cell::{Cell, RefCell};
+    use std::os::raw::{c_int, c_uint, c_void};
+
+    use super::{RteDevice, RteIova};
+
+    /// Distance of the fake IOVAs from the virtual addresses they translate.
+    pub const IOVA_OFFSET: RteIova = 1 << 40;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum DpdkCall {
+        ExtmemRegister {
+            addr: usize,
+            len: usize,
+            n_pages: u32,
+            page_sz: usize,
+        },
+        ExtmemUnregister {
+            addr: usize,
+            len: usize,
+        },
+        DmaMap {
+            dev: usize,
+            addr: usize,
+            iova: RteIova,
+            len: usize,
+        },
+        DmaUnmap {
+            dev: usize,
+            addr: usize,
+            iova: RteIova,
+            len: usize,
+        },
+    }
+
+    thread_local! {
+        static CALLS: RefCell<Vec<DpdkCall>> = const { RefCell::new(Vec::new()) };
+        static FAIL_DMA_MAP: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Calls made on this thread since the last `take_calls`.
+    pub fn take_calls() -> Vec<DpdkCall> {
+        CALLS.with(|calls| calls.take())
+    }
+
+    /// Make `rte_dev_dma_map` fail on this thread.
+    pub fn fail_dma_map(fail: bool) {
+        FAIL_DMA_MAP.with(|fail_dma_map| fail_dma_map.set(fail));
+    }
+
+    fn record(call: DpdkCall) {
+        CALLS.with(|calls| calls.borrow_mut().push(call));
+    }
+
+    #[no_mangle]
+    extern "C" fn rte_extmem_register(
+        va_addr: *mut c_void,
+        len: usize,
+        _iova_addrs: *mut RteIova,
+        n_pages: c_uint,
+        page_sz: usize,
+    ) -> c_int {
+        record(DpdkCall::ExtmemRegister {
+            addr: va_addr as usize,
+            len,
+            n_pages,
+            page_sz,
+        });
+        0
+    }
+
+    #[no_mangle]
+    extern "C" fn rte_extmem_unregister(va_addr: *mut c_void, len: usize) -> c_int {
+        record(DpdkCall::ExtmemUnregister {
+            addr: va_addr as usize,
+            len,
+        });
+        0
+    }
+
+    #[no_mangle]
+    extern "C" fn rte_dev_dma_map(
+        dev: *mut RteDevice,
+        addr: *mut c_void,
+        iova: RteIova,
+        len: usize,
+    ) -> c_int {
+        record(DpdkCall::DmaMap {
+            dev: dev as usize,
+            addr: addr as usize,
+            iova,
+            len,
+        });
+        if FAIL_DMA_MAP.with(Cell::get) {
+            -1
+        } else {
+            0
+        }
+    }
+
+    #[no_mangle]
+    extern "C" fn rte_dev_dma_unmap(
+        dev: *mut RteDevice,
+        addr: *mut c_void,
+        iova: RteIova,
+        len: usize,
+    ) -> c_int {
+        record(DpdkCall::DmaUnmap {
+            dev: dev as usize,
+            addr: addr as usize,
+            iova,
+            len,
+        });
+        0
+    }
+
+    #[no_mangle]
+    extern "C" fn rte_mem_virt2iova(virt: *const c_void) -> RteIova {
+        virt as RteIova + IOVA_OFFSET
+    }
+}
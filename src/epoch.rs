@@ -0,0 +1,118 @@
+//! Epoch-published snapshot of the pinned set. The pinning engine builds a new snapshot of
+//! every pinned segment's IO info after each repin cycle and bumps the epoch; datapath
+//! clones keep the last snapshot they saw and only touch the publisher's lock when the epoch
+//! moved, so steady-state lookups take no lock at all.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::data_structures::{DatapathSlab, SegmentId};
+use crate::metrics::LockOrRecover;
+
+pub struct PinnedSetSnapshot<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    epoch: u64,
+    io_info: HashMap<SegmentId<Slab>, Slab::IOInfo>,
+}
+
+impl<Slab> std::fmt::Debug for PinnedSetSnapshot<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PinnedSetSnapshot")
+            .field("epoch", &self.epoch)
+            .field("segments", &self.io_info.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<Slab> PinnedSetSnapshot<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn empty() -> Self {
+        PinnedSetSnapshot {
+            epoch: 0,
+            io_info: HashMap::default(),
+        }
+    }
+
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// IO info of a segment that was pinned when the snapshot was published.
+    pub fn get_io_info(&self, segment_id: &SegmentId<Slab>) -> Option<Slab::IOInfo> {
+        self.io_info.get(segment_id).copied()
+    }
+
+    pub fn contains(&self, segment_id: &SegmentId<Slab>) -> bool {
+        self.io_info.contains_key(segment_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.io_info.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.io_info.is_empty()
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = &SegmentId<Slab>> {
+        self.io_info.keys()
+    }
+}
+
+/// Shared by every clone of the cache.
+#[derive(Debug)]
+pub struct SnapshotPublisher<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    epoch: AtomicU64,
+    current: Mutex<Arc<PinnedSetSnapshot<Slab>>>,
+}
+
+impl<Slab> Default for SnapshotPublisher<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        SnapshotPublisher {
+            epoch: AtomicU64::new(0),
+            current: Mutex::new(Arc::new(PinnedSetSnapshot::empty())),
+        }
+    }
+}
+
+impl<Slab> SnapshotPublisher<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Replace the published snapshot and bump the epoch.
+    pub fn publish(&self, io_info: HashMap<SegmentId<Slab>, Slab::IOInfo>) -> u64 {
+        let mut current = self.current.lock_or_recover();
+        let epoch = current.epoch + 1;
+        *current = Arc::new(PinnedSetSnapshot { epoch, io_info });
+        self.epoch.store(epoch, Ordering::SeqCst);
+        epoch
+    }
+
+    pub fn load(&self) -> Arc<PinnedSetSnapshot<Slab>> {
+        self.current.lock_or_recover().clone()
+    }
+
+    /// Refresh `cached` if a newer snapshot has been published since it was taken.
+    pub fn refresh(&self, cached: &mut Arc<PinnedSetSnapshot<Slab>>) {
+        if cached.epoch != self.get_epoch() {
+            *cached = self.load();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! One error type for embedders that want to propagate any of the crate's failures with `?`
+//! and still match on what went wrong. The narrower errors individual calls return convert
+//! into it. With the `eyre` feature it also converts into `color_eyre::Report`, like any
+//! `std::error::Error`.
+use std::fmt;
+use std::sync::PoisonError;
+
+use crate::builder::ConfigError;
+use crate::config::ConfigParseError;
+use crate::data_structures::{PinError, RemoveSlabError};
+use crate::metrics::MissReason;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum ZccError {
+    /// The segment or slab is not managed by this cache.
+    SegmentNotFound,
+    /// The slab failed to register a segment.
+    PinFailed,
+    /// Pinning would go over a pinning limit.
+    BudgetExceeded,
+    /// A buffer could not be served zero-copy.
+    Miss(MissReason),
+    /// Segments still had IO in flight when the quiesce timeout expired.
+    InFlightIo { num_segments: usize },
+    /// The builder settings contradict each other.
+    InvalidConfig(ConfigError),
+    /// A config file could not be read.
+    ConfigParse(ConfigParseError),
+    /// A thread panicked while holding a lock.
+    LockPoisoned,
+}
+
+impl fmt::Display for ZccError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZccError::SegmentNotFound => write!(f, "segment not managed by the cache"),
+            ZccError::PinFailed => write!(f, "slab failed to register the segment"),
+            ZccError::BudgetExceeded => write!(f, "pinning limit exceeded"),
+            ZccError::Miss(reason) => write!(f, "buffer not served zero-copy: {:?}", reason),
+            ZccError::InFlightIo { num_segments } => {
+                write!(f, "{} segments still have IO in flight", num_segments)
+            }
+            ZccError::InvalidConfig(error) => write!(f, "invalid configuration: {:?}", error),
+            ZccError::ConfigParse(error) => {
+                write!(f, "config line {}: {}", error.line, error.message)
+            }
+            ZccError::LockPoisoned => write!(f, "lock poisoned by a panicked thread"),
+        }
+    }
+}
+
+impl std::error::Error for ZccError {}
+
+impl From<PinError> for ZccError {
+    fn from(error: PinError) -> Self {
+        match error {
+            PinError::UnknownSegment => ZccError::SegmentNotFound,
+            PinError::OverBudget => ZccError::BudgetExceeded,
+            PinError::RegistrationFailed => ZccError::PinFailed,
+        }
+    }
+}
+
+impl<ID> From<RemoveSlabError<ID>> for ZccError {
+    fn from(error: RemoveSlabError<ID>) -> Self {
+        match error {
+            RemoveSlabError::UnknownSlab => ZccError::SegmentNotFound,
+            RemoveSlabError::InFlightIo(segments) => ZccError::InFlightIo {
+                num_segments: segments.len(),
+            },
+        }
+    }
+}
+
+impl From<MissReason> for ZccError {
+    fn from(reason: MissReason) -> Self {
+        ZccError::Miss(reason)
+    }
+}
+
+impl From<ConfigError> for ZccError {
+    fn from(error: ConfigError) -> Self {
+        ZccError::InvalidConfig(error)
+    }
+}
+
+impl From<ConfigParseError> for ZccError {
+    fn from(error: ConfigParseError) -> Self {
+        ZccError::ConfigParse(error)
+    }
+}
+
+impl<T> From<PoisonError<T>> for ZccError {
+    fn from(_: PoisonError<T>) -> Self {
+        ZccError::LockPoisoned
+    }
+}
@@ -0,0 +1,27 @@
+//! Notifications from the pinning engine, for datapaths that cache a segment's IO info
+//! outside the cache (say in per-connection state) and need to know when it goes stale.
+use crate::data_structures::{DatapathSlab, SegmentId};
+
+/// Receives pin and unpin events as they happen, on the thread running the repin cycle or
+/// on-demand pin. Keep the hooks cheap, they run in the middle of pinning.
+pub trait ZccEventListener<Slab>: std::fmt::Debug + Send + Sync
+where
+    Slab: DatapathSlab,
+{
+    /// A segment was registered and IO can use `io_info`.
+    fn on_pinned(&self, _segment_id: SegmentId<Slab>, _io_info: Slab::IOInfo) {}
+
+    /// A segment is about to be unpinned: no new IO is handed out against it, and cached IO
+    /// info should be dropped. The unpin waits for IO in flight. If it is skipped because that
+    /// IO does not drain in time, `on_pinned` follows instead of `on_unpinned`.
+    fn on_unpinning(&self, _segment_id: SegmentId<Slab>) {}
+
+    /// A segment was unregistered.
+    fn on_unpinned(&self, _segment_id: SegmentId<Slab>) {}
+}
+
+/// Listener that ignores every event.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopEventListener;
+
+impl<Slab> ZccEventListener<Slab> for NoopEventListener where Slab: DatapathSlab {}
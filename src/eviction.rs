@@ -0,0 +1,185 @@
+//! Deferred unpinning for on-demand pinning. `record_and_pin_on_demand` pins on the request
+//! path but never unpins there: segments it evicts to stay under the pinning limit are
+//! marked quiescing and queued, and an `EvictionWorker` thread unpins them once their
+//! in-flight IO drains.
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::data_structures::{DatapathSlab, SegmentId, ZeroCopyCache};
+use crate::metrics::{recover, LockOrRecover};
+use crate::segment_policy::SegmentPolicy;
+
+#[derive(Debug)]
+struct EvictionState<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pending: VecDeque<SegmentId<Slab>>,
+    /// Segments popped but not unpinned yet.
+    in_progress: usize,
+    evicted: u64,
+    shutdown: bool,
+}
+
+/// Segments waiting to be unpinned, shared by the cache and its eviction worker.
+#[derive(Debug)]
+pub struct EvictionQueue<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    state: Mutex<EvictionState<Slab>>,
+    changed: Condvar,
+}
+
+impl<Slab> Default for EvictionQueue<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        EvictionQueue {
+            state: Mutex::new(EvictionState {
+                pending: VecDeque::new(),
+                in_progress: 0,
+                evicted: 0,
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+}
+
+impl<Slab> EvictionQueue<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Queue a segment for unpinning. Returns false if the worker has shut down.
+    pub fn push(&self, segment_id: SegmentId<Slab>) -> bool {
+        let mut state = self.state.lock_or_recover();
+        if state.shutdown {
+            return false;
+        }
+        state.pending.push_back(segment_id);
+        self.changed.notify_all();
+        true
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.state.lock_or_recover().shutdown
+    }
+
+    /// Segments queued or being unpinned.
+    pub fn pending(&self) -> usize {
+        let state = self.state.lock_or_recover();
+        state.pending.len() + state.in_progress
+    }
+
+    /// Segments unpinned by the worker so far.
+    pub fn evicted(&self) -> u64 {
+        self.state.lock_or_recover().evicted
+    }
+
+    /// Block until nothing is queued or `timeout` elapses. Returns whether the queue drained.
+    pub fn wait_until_idle(&self, timeout: Duration) -> bool {
+        let state = self.state.lock_or_recover();
+        let (state, _) = recover(self.changed.wait_timeout_while(state, timeout, |state| {
+            !state.pending.is_empty() || state.in_progress > 0
+        }));
+        state.pending.is_empty() && state.in_progress == 0
+    }
+
+    /// Wait for the next segment. `None` once shut down and drained.
+    fn pop(&self) -> Option<SegmentId<Slab>> {
+        let mut state = self.state.lock_or_recover();
+        loop {
+            if let Some(segment_id) = state.pending.pop_front() {
+                state.in_progress += 1;
+                return Some(segment_id);
+            }
+            if state.shutdown {
+                return None;
+            }
+            state = recover(self.changed.wait(state));
+        }
+    }
+
+    fn finish(&self, unpinned: bool) {
+        let mut state = self.state.lock_or_recover();
+        state.in_progress -= 1;
+        if unpinned {
+            state.evicted += 1;
+        }
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock_or_recover().shutdown = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Handle to the thread unpinning evicted segments. Dropping it unpins whatever is still
+/// queued and stops the thread.
+#[derive(Debug)]
+pub struct EvictionWorker<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    queue: Arc<EvictionQueue<Slab>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<Slab> EvictionWorker<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub(crate) fn spawn<CB>(
+        zero_copy_cache: ZeroCopyCache<Slab, CB>,
+        queue: Arc<EvictionQueue<Slab>>,
+    ) -> Self
+    where
+        Slab: 'static,
+        CB: SegmentPolicy<Slab> + 'static,
+        ZeroCopyCache<Slab, CB>: Send,
+    {
+        let worker_queue = queue.clone();
+        let handle = thread::spawn(move || {
+            while let Some(segment_id) = worker_queue.pop() {
+                let unpinned = zero_copy_cache.unpin_evicted(segment_id);
+                worker_queue.finish(unpinned);
+            }
+        });
+        EvictionWorker {
+            queue,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn queue(&self) -> &Arc<EvictionQueue<Slab>> {
+        &self.queue
+    }
+
+    /// Unpin everything still queued and join the thread.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.queue.close();
+            if handle.join().is_err() {
+                tracing::error!("Eviction worker thread panicked");
+            }
+        }
+    }
+}
+
+impl<Slab> Drop for EvictionWorker<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
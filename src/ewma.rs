@@ -0,0 +1,169 @@
+//! Exponentially weighted hotness policy: every access adds one to a segment's score, and
+//! scores decay by half every `half_life`, so old bursts stop dominating the hotset.
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::data_structures::{DatapathSlab, DecayPolicy, SegmentId, DEFAULT_CACHE_SIZE};
+use crate::segment_policy::SegmentPolicy;
+
+pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(10);
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct EwmaScore {
+    pub score: f64,
+    pub access_count: i64,
+    pub last_update: Instant,
+}
+
+impl EwmaScore {
+    fn new(now: Instant) -> Self {
+        EwmaScore {
+            score: 0.0,
+            access_count: 0,
+            last_update: now,
+        }
+    }
+
+    /// Score decayed up to `now`.
+    pub fn score_at(&self, now: Instant, half_life: Duration) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.score * 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+    }
+
+    fn record_access(&mut self, now: Instant, half_life: Duration) {
+        self.score = self.score_at(now, half_life) + 1.0;
+        self.access_count += 1;
+        self.last_update = now;
+    }
+}
+
+#[derive(Debug)]
+pub struct EwmaCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Maximum number of segments returned as the hotset.
+    num_segments: usize,
+    /// Time after which a score has decayed to half its value.
+    half_life: Duration,
+    segment_scores: HashMap<SegmentId<Slab>, EwmaScore>,
+}
+
+impl<Slab> Default for EwmaCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        EwmaCache::new(DEFAULT_CACHE_SIZE, DEFAULT_HALF_LIFE)
+    }
+}
+
+impl<Slab> EwmaCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn new(num_segments: usize, half_life: Duration) -> Self {
+        assert!(!half_life.is_zero(), "EWMA half-life must be non-zero");
+        EwmaCache {
+            num_segments,
+            half_life,
+            segment_scores: HashMap::default(),
+        }
+    }
+
+    pub fn get_half_life(&self) -> Duration {
+        self.half_life
+    }
+
+    pub fn set_half_life(&mut self, half_life: Duration) {
+        assert!(!half_life.is_zero(), "EWMA half-life must be non-zero");
+        self.half_life = half_life;
+    }
+
+    pub fn update_access_at(&mut self, segment_id: SegmentId<Slab>, now: Instant) {
+        let half_life = self.half_life;
+        self.segment_scores
+            .entry(segment_id)
+            .or_insert_with(|| EwmaScore::new(now))
+            .record_access(now, half_life);
+    }
+
+    pub fn get_score_at(&self, segment_id: SegmentId<Slab>, now: Instant) -> Option<f64> {
+        self.segment_scores
+            .get(&segment_id)
+            .map(|s| s.score_at(now, self.half_life))
+    }
+
+    pub fn return_top_segments_to_pin_at(&self, now: Instant) -> HashSet<SegmentId<Slab>> {
+        let mut sorting_vec: Vec<(SegmentId<Slab>, f64)> = self
+            .segment_scores
+            .iter()
+            .map(|(k, v)| (*k, v.score_at(now, self.half_life)))
+            .collect();
+        sorting_vec.sort_by(|a, b| b.1.total_cmp(&a.1));
+        sorting_vec
+            .into_iter()
+            .take(self.num_segments)
+            .map(|(seg_id, _)| seg_id)
+            .collect()
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for EwmaCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        self.update_access_at(segment_id, Instant::now());
+    }
+
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.segment_scores.get(&segment_id).map(|s| s.access_count)
+    }
+
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        self.return_top_segments_to_pin_at(Instant::now())
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    fn set_num_segments(&mut self, num_segments: usize) {
+        self.num_segments = num_segments;
+    }
+
+    fn reset(&mut self) {
+        self.segment_scores.clear();
+    }
+
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        self.segment_scores.remove(&segment_id);
+    }
+
+    /// Scores already decay continuously; this applies an extra step on top of that.
+    fn decay(&mut self, policy: DecayPolicy) {
+        let now = Instant::now();
+        let half_life = self.half_life;
+        let min = self
+            .segment_scores
+            .values()
+            .map(|s| s.score_at(now, half_life))
+            .reduce(f64::min)
+            .unwrap_or(0.0);
+        match policy {
+            DecayPolicy::FullReset => self.reset(),
+            DecayPolicy::Halving | DecayPolicy::SubtractMin => {
+                self.segment_scores.retain(|_, s| {
+                    s.score = s.score_at(now, half_life);
+                    s.last_update = now;
+                    match policy {
+                        DecayPolicy::Halving => s.score /= 2.0,
+                        _ => s.score -= min,
+                    }
+                    s.score > 0.0
+                });
+            }
+        }
+    }
+}
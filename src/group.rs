@@ -0,0 +1,271 @@
+//! Per-core caches with a coordinator. Each core gets its own ZeroCopyCache, so the datapath
+//! never shares a lock across cores; the coordinator periodically looks at how many managed
+//! accesses each core saw and divides the global pinning budget between them accordingly.
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::data_structures::{DatapathSlab, ZeroCopyCache};
+use crate::metrics::{recover, LockOrRecover, MetricsSnapshot};
+use crate::segment_policy::SegmentPolicy;
+
+/// Split `budget` proportionally to `weights` using largest remainders, so the shares always
+/// add up to `budget`. If every weight is zero the budget is split evenly.
+pub fn divide_budget(budget: usize, weights: &[u64]) -> Vec<usize> {
+    if weights.is_empty() {
+        return Vec::new();
+    }
+    let total: u128 = weights.iter().map(|w| *w as u128).sum();
+    if total == 0 {
+        let share = budget / weights.len();
+        let extra = budget % weights.len();
+        return (0..weights.len())
+            .map(|i| share + usize::from(i < extra))
+            .collect();
+    }
+    let mut shares: Vec<usize> = Vec::with_capacity(weights.len());
+    let mut remainders: Vec<(u128, usize)> = Vec::with_capacity(weights.len());
+    for (i, weight) in weights.iter().enumerate() {
+        let scaled = budget as u128 * *weight as u128;
+        shares.push((scaled / total) as usize);
+        remainders.push((scaled % total, i));
+    }
+    let mut leftover = budget - shares.iter().sum::<usize>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, i) in remainders {
+        if leftover == 0 {
+            break;
+        }
+        shares[i] += 1;
+        leftover -= 1;
+    }
+    shares
+}
+
+#[derive(Debug)]
+pub struct ZccGroup<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    /// One cache per core, indexed by core.
+    cores: Vec<ZeroCopyCache<Slab, CB>>,
+    /// Shared by every clone, so a coordinator thread and its group agree on the budget and
+    /// on what the last rebalance saw.
+    balance: Arc<Mutex<BalanceState>>,
+}
+
+#[derive(Debug)]
+struct BalanceState {
+    /// Segments to pin across every core.
+    global_budget: usize,
+    /// Managed accesses per core as of the last rebalance.
+    last_accesses: Vec<u64>,
+}
+
+impl<Slab, CB> Clone for ZccGroup<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    fn clone(&self) -> Self {
+        ZccGroup {
+            cores: self.cores.clone(),
+            balance: self.balance.clone(),
+        }
+    }
+}
+
+impl<Slab, CB> ZccGroup<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    /// Create `num_cores` caches, building core `i`'s policy with `make_builder(i)`, and split
+    /// `global_budget` evenly between them.
+    pub fn new(
+        num_cores: usize,
+        global_budget: usize,
+        mut make_builder: impl FnMut(usize) -> CB,
+    ) -> Self {
+        let group = ZccGroup {
+            cores: (0..num_cores)
+                .map(|core| ZeroCopyCache::new(make_builder(core)))
+                .collect(),
+            balance: Arc::new(Mutex::new(BalanceState {
+                global_budget,
+                last_accesses: vec![0; num_cores],
+            })),
+        };
+        group.apply_budgets(&divide_budget(global_budget, &vec![0; num_cores]));
+        group
+    }
+
+    pub fn num_cores(&self) -> usize {
+        self.cores.len()
+    }
+
+    pub fn get_core(&self, core: usize) -> Option<&ZeroCopyCache<Slab, CB>> {
+        self.cores.get(core)
+    }
+
+    pub fn get_core_mut(&mut self, core: usize) -> Option<&mut ZeroCopyCache<Slab, CB>> {
+        self.cores.get_mut(core)
+    }
+
+    pub fn get_global_budget(&self) -> usize {
+        self.balance.lock_or_recover().global_budget
+    }
+
+    /// Change the global budget; the new split is applied on the next rebalance.
+    pub fn set_global_budget(&self, global_budget: usize) {
+        self.balance.lock_or_recover().global_budget = global_budget;
+    }
+
+    /// Hotset size currently assigned to each core.
+    pub fn core_budgets(&self) -> Vec<usize> {
+        self.cores
+            .iter()
+            .map(|zcc| zcc.cache_builder.get_num_segments())
+            .collect()
+    }
+
+    /// Metrics summed across every core.
+    pub fn aggregate_metrics(&self) -> MetricsSnapshot {
+        let mut total = MetricsSnapshot::default();
+        for zcc in self.cores.iter() {
+            total += zcc.get_metrics();
+        }
+        total
+    }
+
+    /// Redivide the global budget by the managed accesses each core saw since the last
+    /// rebalance, and return the new per-core budgets. Each core first keeps up to half an
+    /// even share of its previous budget, so a core that was briefly idle keeps its hottest
+    /// segments pinned instead of unpinning its whole hotset.
+    pub fn rebalance(&self) -> Vec<usize> {
+        let mut balance = self.balance.lock_or_recover();
+        let accesses: Vec<u64> = self
+            .cores
+            .iter()
+            .map(|zcc| {
+                let metrics = zcc.get_metrics();
+                metrics.hits + metrics.misses
+            })
+            .collect();
+        let deltas: Vec<u64> = accesses
+            .iter()
+            .zip(balance.last_accesses.iter())
+            .map(|(now, last)| now.saturating_sub(*last))
+            .collect();
+        balance.last_accesses = accesses;
+        let floor = balance.global_budget / (2 * self.cores.len().max(1));
+        let floors: Vec<usize> = self
+            .core_budgets()
+            .iter()
+            .map(|budget| (*budget).min(floor))
+            .collect();
+        let rest = balance.global_budget - floors.iter().sum::<usize>();
+        let budgets: Vec<usize> = divide_budget(rest, &deltas)
+            .iter()
+            .zip(floors.iter())
+            .map(|(share, floor)| share + floor)
+            .collect();
+        tracing::debug!("Rebalanced per-core budgets: {:?}", budgets);
+        self.apply_budgets(&budgets);
+        budgets
+    }
+
+    fn apply_budgets(&self, budgets: &[usize]) {
+        for (zcc, budget) in self.cores.iter().zip(budgets.iter()) {
+            zcc.cache_builder.set_num_segments(*budget);
+        }
+    }
+
+    /// Rebalance every `interval` from a background thread, until the returned handle is
+    /// shut down or dropped.
+    pub fn spawn_coordinator(&self, interval: Duration) -> Coordinator
+    where
+        Slab: 'static,
+        CB: 'static,
+        ZeroCopyCache<Slab, CB>: Send,
+    {
+        let group = self.clone();
+        let shared = Arc::new(CoordinatorShared::default());
+        let coordinator_shared = shared.clone();
+        let handle = thread::spawn(move || {
+            let mut next_rebalance = Instant::now() + interval;
+            loop {
+                {
+                    let mut control = coordinator_shared.control.lock_or_recover();
+                    loop {
+                        if control.shutdown {
+                            return;
+                        }
+                        let now = Instant::now();
+                        if now >= next_rebalance {
+                            break;
+                        }
+                        let timeout = next_rebalance - now;
+                        let wakeup = &coordinator_shared.wakeup;
+                        control = recover(wakeup.wait_timeout(control, timeout)).0;
+                    }
+                }
+                group.rebalance();
+                next_rebalance = Instant::now() + interval;
+                coordinator_shared.control.lock_or_recover().rebalances += 1;
+            }
+        });
+        Coordinator {
+            shared,
+            handle: Some(handle),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CoordinatorControl {
+    shutdown: bool,
+    rebalances: u64,
+}
+
+#[derive(Debug, Default)]
+struct CoordinatorShared {
+    control: Mutex<CoordinatorControl>,
+    wakeup: Condvar,
+}
+
+/// Handle to a group's coordinator thread. Dropping it stops the coordinator.
+#[derive(Debug)]
+pub struct Coordinator {
+    shared: Arc<CoordinatorShared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Coordinator {
+    /// Rebalances completed so far.
+    pub fn rebalances(&self) -> u64 {
+        self.shared.control.lock_or_recover().rebalances
+    }
+
+    /// Finish any rebalance in progress and join the thread.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.shared.control.lock_or_recover().shutdown = true;
+            self.shared.wakeup.notify_all();
+            if handle.join().is_err() {
+                tracing::error!("Coordinator thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Coordinator {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
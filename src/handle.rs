@@ -0,0 +1,161 @@
+//! Shared datapath handle. The datapath methods of `ZeroCopyCache` take `&mut self` only for
+//! per-clone lookup caches (the last segment resolved, unmanaged pages, the last pinned-set
+//! snapshot); everything else is shared and synchronized. `ZccHandle` is a cloneable,
+//! `Sync` handle exposing those methods on `&self`: each call borrows a cache clone from a
+//! small pool, so callers don't need to wrap the whole cache in their own mutex.
+use std::sync::{Arc, Mutex};
+
+use smallvec::SmallVec;
+
+use crate::data_structures::{
+    DatapathSlab, IoLease, PinnedSlices, SegmentId, ZccIoHandle, ZeroCopyCache,
+};
+use crate::metrics::{LockOrRecover, MetricsSnapshot, MissReason};
+use crate::segment_policy::SegmentPolicy;
+
+#[derive(Debug)]
+struct HandleShared<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    /// The clone new pool entries are cloned from, also used for calls that take `&self`.
+    cache: ZeroCopyCache<Slab, CB>,
+    /// Clones not currently borrowed by a call, each keeping its own lookup caches.
+    idle: Mutex<Vec<ZeroCopyCache<Slab, CB>>>,
+}
+
+/// Cloneable handle to a cache's datapath, usable from many threads at once. It sees the
+/// slabs the cache had when the handle was made, like any clone of the cache; make a new
+/// handle after adding or removing slabs.
+#[derive(Debug)]
+pub struct ZccHandle<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    shared: Arc<HandleShared<Slab, CB>>,
+}
+
+impl<Slab, CB> Clone for ZccHandle<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    fn clone(&self) -> Self {
+        ZccHandle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Slab, CB> ZccHandle<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    pub fn new(cache: ZeroCopyCache<Slab, CB>) -> Self {
+        ZccHandle {
+            shared: Arc::new(HandleShared {
+                cache,
+                idle: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Run `f` on an idle clone, cloning a new one if every clone is in use.
+    fn with_cache<R>(&self, f: impl FnOnce(&mut ZeroCopyCache<Slab, CB>) -> R) -> R {
+        let idle = self.shared.idle.lock_or_recover().pop();
+        let mut cache = idle.unwrap_or_else(|| self.shared.cache.clone());
+        let result = f(&mut cache);
+        self.shared.idle.lock_or_recover().push(cache);
+        result
+    }
+
+    /// See `ZeroCopyCache::record_access_and_get_io_info`.
+    pub fn record_access_and_get_io_info(
+        &self,
+        buf: &[u8],
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        self.with_cache(|cache| cache.record_access_and_get_io_info(buf))
+    }
+
+    /// See `ZeroCopyCache::record_access_and_get_io_info_if_pinned`.
+    pub fn record_access_and_get_io_info_if_pinned(&self, buf: &[u8]) -> Option<IoLease<Slab>> {
+        self.with_cache(|cache| cache.record_access_and_get_io_info_if_pinned(buf))
+    }
+
+    /// See `ZeroCopyCache::record_access_and_get_io_info_if_pinned_manual`.
+    pub fn record_access_and_get_io_info_if_pinned_manual(
+        &self,
+        buf: &[u8],
+    ) -> Option<PinnedSlices<Slab>> {
+        self.with_cache(|cache| cache.record_access_and_get_io_info_if_pinned_manual(buf))
+    }
+
+    /// See `ZeroCopyCache::record_access_and_get_io_handle`.
+    pub fn record_access_and_get_io_handle(
+        &self,
+        buf: &[u8],
+    ) -> Result<ZccIoHandle<SegmentId<Slab>, Slab::IOInfo>, MissReason> {
+        self.with_cache(|cache| cache.record_access_and_get_io_handle(buf))
+    }
+
+    /// See `ZeroCopyCache::record_accesses`.
+    pub fn record_accesses(&self, bufs: &[&[u8]]) -> Vec<Option<PinnedSlices<Slab>>> {
+        self.with_cache(|cache| cache.record_accesses(bufs))
+    }
+
+    /// See `ZeroCopyCache::record_and_pin_on_demand`.
+    pub fn record_and_pin_on_demand(
+        &self,
+        buf: &[u8],
+        priv_info: &Slab::PrivateInfo,
+    ) -> Result<(Slab::SlabId, Slab::IOInfo), MissReason> {
+        self.with_cache(|cache| cache.record_and_pin_on_demand(buf, priv_info))
+    }
+
+    pub fn record_io_completion(&self, addr: &[u8]) {
+        self.with_cache(|cache| cache.record_io_completion(addr))
+    }
+
+    pub fn record_io_completion_by_id(&self, segment_id: SegmentId<Slab>) -> bool {
+        self.shared.cache.record_io_completion_by_id(segment_id)
+    }
+
+    pub fn record_io_completions(&self, segment_ids: &[SegmentId<Slab>]) -> usize {
+        self.shared.cache.record_io_completions(segment_ids)
+    }
+
+    pub fn validate(&self, handle: &ZccIoHandle<SegmentId<Slab>, Slab::IOInfo>) -> bool {
+        self.shared.cache.validate(handle)
+    }
+
+    pub fn get_io_info_if_pinned(&self, buf: &[u8]) -> Option<(Slab::SlabId, Slab::IOInfo)> {
+        self.shared.cache.get_io_info_if_pinned(buf)
+    }
+
+    pub fn is_address_pinned(&self, buf: &[u8]) -> bool {
+        self.shared.cache.is_address_pinned(buf)
+    }
+
+    pub fn get_segment_ids(&self, buf: &[u8]) -> SmallVec<[SegmentId<Slab>; 2]> {
+        self.shared.cache.get_segment_ids(buf)
+    }
+
+    pub fn get_metrics(&self) -> MetricsSnapshot {
+        self.shared.cache.get_metrics()
+    }
+}
+
+impl<Slab, CB> ZeroCopyCache<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    /// A shared datapath handle to this cache, e.g. for a pool of request threads while this
+    /// clone runs the repin cycles.
+    pub fn handle(&self) -> ZccHandle<Slab, CB> {
+        ZccHandle::new(self.clone())
+    }
+}
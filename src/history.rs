@@ -0,0 +1,91 @@
+//! Per-segment access histograms: accesses counted per fixed-width time bucket, so segment
+//! hotness can be plotted over the course of a run.
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct HistoryBucket {
+    /// Offset of the bucket start from when history recording began.
+    pub start: Duration,
+    pub access_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentHistory<ID>
+where
+    ID: Hash + Eq + Copy,
+{
+    bucket_width: Duration,
+    /// Number of most recent buckets retained per segment.
+    max_buckets: usize,
+    started: Instant,
+    /// Per segment: (bucket index, count), oldest first.
+    histories: HashMap<ID, VecDeque<(u64, u64)>>,
+}
+
+impl<ID> SegmentHistory<ID>
+where
+    ID: Hash + Eq + Copy,
+{
+    pub fn new(bucket_width: Duration, max_buckets: usize) -> Self {
+        assert!(!bucket_width.is_zero(), "history bucket width must be non-zero");
+        SegmentHistory {
+            bucket_width,
+            max_buckets: max_buckets.max(1),
+            started: Instant::now(),
+            histories: HashMap::default(),
+        }
+    }
+
+    pub fn get_bucket_width(&self) -> Duration {
+        self.bucket_width
+    }
+
+    fn bucket_index(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.started).as_nanos() / self.bucket_width.as_nanos())
+            as u64
+    }
+
+    pub fn record_access_at(&mut self, segment_id: ID, now: Instant) {
+        let index = self.bucket_index(now);
+        let max_buckets = self.max_buckets as u64;
+        let history = self.histories.entry(segment_id).or_default();
+        match history.back_mut() {
+            Some((last, count)) if *last == index => *count += 1,
+            _ => history.push_back((index, 1)),
+        }
+        while let Some((oldest, _)) = history.front() {
+            if index - oldest < max_buckets {
+                break;
+            }
+            history.pop_front();
+        }
+    }
+
+    pub fn record_access(&mut self, segment_id: ID) {
+        self.record_access_at(segment_id, Instant::now());
+    }
+
+    /// Non-empty buckets for the segment, oldest first.
+    pub fn segment_history(&self, segment_id: ID) -> Option<Vec<HistoryBucket>> {
+        self.histories.get(&segment_id).map(|history| {
+            history
+                .iter()
+                .map(|(index, count)| HistoryBucket {
+                    start: self.bucket_width * *index as u32,
+                    access_count: *count,
+                })
+                .collect()
+        })
+    }
+
+    /// Forget one segment's history, e.g. when its slab is removed.
+    pub fn remove(&mut self, segment_id: ID) {
+        self.histories.remove(&segment_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.histories.clear();
+    }
+}
@@ -0,0 +1,182 @@
+//! Hugepage-backed memory for slabs, so experiments don't need to bring their own. Memory is
+//! either an anonymous `MAP_HUGETLB` mapping, or a hugetlbfs-backed memfd that can be shared
+//! with other processes through its fd. `allocate_slab` and `allocate_memfd_slab` wrap the
+//! memory in an `MlockSlab`, ready for `initialize_slab`; `allocate_best_slab` picks the page
+//! size from the hugepages the system has free.
+use std::ffi::CString;
+use std::io;
+use std::os::raw::c_void;
+use std::os::unix::io::RawFd;
+
+use crate::mlock::MlockSlab;
+use crate::pagesizes::{self, PageSize};
+
+/// A mapping of whole pages, unmapped on drop.
+#[derive(Debug)]
+pub struct HugepageMemory {
+    start_address: *mut c_void,
+    len: usize,
+    page_size: PageSize,
+    /// Backing memfd, for memory created with `memfd`.
+    fd: Option<RawFd>,
+}
+
+// SAFETY: the mapping is owned by this value and only unmapped on drop.
+unsafe impl Send for HugepageMemory {}
+unsafe impl Sync for HugepageMemory {}
+
+/// The system's base page size; larger pages come from the hugetlb pool.
+pub fn base_page_size() -> PageSize {
+    let bytes = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    PageSize::new(bytes as usize).unwrap_or(PageSize::PG4KB)
+}
+
+impl HugepageMemory {
+    /// Map at least `len` zeroed bytes of anonymous memory in `page_size` pages. Pages larger
+    /// than the base page come from the hugetlb pool, which fails if it has no free pages of
+    /// that size.
+    pub fn anonymous(len: usize, page_size: PageSize) -> io::Result<Self> {
+        let len = Self::round_up(len, &page_size);
+        let mut flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+        if page_size != base_page_size() {
+            flags |= libc::MAP_HUGETLB | Self::huge_flags(&page_size, libc::MAP_HUGE_SHIFT);
+        }
+        let start_address = Self::map(len, flags, -1)?;
+        Ok(HugepageMemory {
+            start_address,
+            len,
+            page_size,
+            fd: None,
+        })
+    }
+
+    /// Create a memfd named `name` of at least `len` bytes, backed by hugetlbfs for pages
+    /// larger than the base page, and map it shared.
+    pub fn memfd(name: &str, len: usize, page_size: PageSize) -> io::Result<Self> {
+        let len = Self::round_up(len, &page_size);
+        let c_name = CString::new(name)?;
+        let mut flags = libc::MFD_CLOEXEC;
+        if page_size != base_page_size() {
+            flags |= libc::MFD_HUGETLB
+                | Self::huge_flags(&page_size, libc::MFD_HUGE_SHIFT as libc::c_int) as libc::c_uint;
+        }
+        let fd = unsafe { libc::memfd_create(c_name.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mapped = match unsafe { libc::ftruncate(fd, len as libc::off_t) } {
+            0 => Self::map(len, libc::MAP_SHARED, fd),
+            _ => Err(io::Error::last_os_error()),
+        };
+        match mapped {
+            Ok(start_address) => Ok(HugepageMemory {
+                start_address,
+                len,
+                page_size,
+                fd: Some(fd),
+            }),
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `anonymous`, but fall back to base pages if no hugepages of `page_size` are free.
+    pub fn anonymous_with_fallback(len: usize, page_size: PageSize) -> io::Result<Self> {
+        let base = base_page_size();
+        match Self::anonymous(len, page_size.clone()) {
+            Err(e) if page_size != base => {
+                tracing::warn!("No {:?} pages ({}), using {:?} pages", page_size, e, base);
+                Self::anonymous(len, base)
+            }
+            result => result,
+        }
+    }
+
+    /// The kernel's hugepage size encoding: log2 of the size, `shift` bits up.
+    fn huge_flags(page_size: &PageSize, shift: libc::c_int) -> libc::c_int {
+        (page_size.shift() as libc::c_int) << shift
+    }
+
+    fn round_up(len: usize, page_size: &PageSize) -> usize {
+        let page_size = page_size.bytes();
+        len.div_ceil(page_size).max(1) * page_size
+    }
+
+    fn map(len: usize, flags: libc::c_int, fd: RawFd) -> io::Result<*mut c_void> {
+        let start_address = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                fd,
+                0,
+            )
+        };
+        if start_address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(start_address)
+    }
+
+    pub fn get_start_address(&self) -> *mut c_void {
+        self.start_address
+    }
+
+    /// Size of the mapping in bytes, a whole number of pages.
+    pub fn get_size(&self) -> usize {
+        self.len
+    }
+
+    pub fn get_page_size(&self) -> PageSize {
+        self.page_size.clone()
+    }
+
+    pub fn get_num_pages(&self) -> usize {
+        self.len / self.page_size.bytes()
+    }
+
+    /// The backing memfd, to share the memory with another process.
+    pub fn get_fd(&self) -> Option<RawFd> {
+        self.fd
+    }
+}
+
+impl Drop for HugepageMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.start_address, self.len);
+            if let Some(fd) = self.fd {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// An `MlockSlab` over at least `len` bytes of anonymous memory in `page_size` pages, falling
+/// back to base pages if no hugepages are free.
+pub fn allocate_slab(slab_id: usize, len: usize, page_size: PageSize) -> io::Result<MlockSlab> {
+    let memory = HugepageMemory::anonymous_with_fallback(len, page_size)?;
+    Ok(MlockSlab::from_memory(slab_id, memory))
+}
+
+/// An `MlockSlab` over at least `len` bytes in the largest hugepages with enough free pages,
+/// or base pages if no pool can back it.
+pub fn allocate_best_slab(slab_id: usize, len: usize) -> io::Result<MlockSlab> {
+    let page_size = pagesizes::best_hugepage_size(&pagesizes::detect_hugepages(), len)
+        .unwrap_or_else(base_page_size);
+    allocate_slab(slab_id, len, page_size)
+}
+
+/// An `MlockSlab` over a memfd named `name`; see `HugepageMemory::memfd`.
+pub fn allocate_memfd_slab(
+    slab_id: usize,
+    name: &str,
+    len: usize,
+    page_size: PageSize,
+) -> io::Result<MlockSlab> {
+    let memory = HugepageMemory::memfd(name, len, page_size)?;
+    Ok(MlockSlab::from_memory(slab_id, memory))
+}
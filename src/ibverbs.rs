@@ -0,0 +1,326 @@
+//! `DatapathSlab` adapter for RDMA: pinning a segment registers it as a memory region with
+//! `ibv_reg_mr` and unpinning deregisters it; `IOInfo` carries the region's lkey and rkey.
+//!
+//! Registration failures, most often ENOMEM once `RLIMIT_MEMLOCK` is reached, leave the
+//! segment unpinned so it is retried next cycle, and are recorded on the `IbvPd` so the
+//! application can react, e.g. by lowering the pinning limit. The libibverbs symbols are
+//! declared here but not linked; the application links libibverbs itself.
+use std::io;
+use std::os::raw::{c_int, c_void};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::data_structures::DatapathSlab;
+use crate::metrics::LockOrRecover;
+use crate::pagesizes::PageSize;
+
+pub const IBV_ACCESS_LOCAL_WRITE: c_int = 1;
+pub const IBV_ACCESS_REMOTE_WRITE: c_int = 1 << 1;
+pub const IBV_ACCESS_REMOTE_READ: c_int = 1 << 2;
+
+const ENOMEM: i32 = 12;
+
+/// Opaque `struct ibv_pd`.
+#[repr(C)]
+pub struct IbvPdRaw {
+    _private: [u8; 0],
+}
+
+/// Leading fields of `struct ibv_mr`; only the keys are read.
+#[repr(C)]
+#[allow(dead_code)]
+struct IbvMr {
+    context: *mut c_void,
+    pd: *mut IbvPdRaw,
+    addr: *mut c_void,
+    length: usize,
+    handle: u32,
+    lkey: u32,
+    rkey: u32,
+}
+
+extern "C" {
+    fn ibv_reg_mr(
+        pd: *mut IbvPdRaw,
+        addr: *mut c_void,
+        length: usize,
+        access: c_int,
+    ) -> *mut IbvMr;
+    fn ibv_dereg_mr(mr: *mut IbvMr) -> c_int;
+}
+
+/// Why a segment could not be registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IbvRegError {
+    /// Out of lockable memory or NIC translation resources. Recoverable by pinning less.
+    OutOfMemory,
+    /// Any other errno from `ibv_reg_mr`.
+    Os(i32),
+}
+
+impl From<io::Error> for IbvRegError {
+    fn from(err: io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(errno) if errno == ENOMEM => IbvRegError::OutOfMemory,
+            Some(errno) => IbvRegError::Os(errno),
+            None => IbvRegError::Os(0),
+        }
+    }
+}
+
+/// Protection domain segments are registered in, passed to the cache as `PrivateInfo`.
+#[derive(Debug)]
+pub struct IbvPd {
+    pd: *mut IbvPdRaw,
+    access: c_int,
+    failures: AtomicUsize,
+    last_error: Mutex<Option<IbvRegError>>,
+}
+
+// SAFETY: libibverbs calls on a protection domain are thread-safe.
+unsafe impl Send for IbvPd {}
+unsafe impl Sync for IbvPd {}
+
+impl IbvPd {
+    /// Register segments in `pd` with the `IBV_ACCESS_*` flags in `access`.
+    ///
+    /// # Safety
+    /// `pd` must be a valid protection domain that outlives every segment registered in it.
+    pub unsafe fn new(pd: *mut IbvPdRaw, access: c_int) -> Self {
+        IbvPd {
+            pd,
+            access,
+            failures: AtomicUsize::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Registrations that failed so far.
+    pub fn registration_failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// The most recent registration failure, cleared by reading it.
+    pub fn take_last_error(&self) -> Option<IbvRegError> {
+        self.last_error.lock_or_recover().take()
+    }
+
+    fn record_failure(&self, err: IbvRegError) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock_or_recover() = Some(err);
+    }
+}
+
+/// Keys of a registered segment; both are 0 while it is not pinned.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IbvKeys {
+    pub lkey: u32,
+    pub rkey: u32,
+}
+
+#[derive(Debug)]
+pub struct IbvPinningState {
+    mr: *mut IbvMr,
+}
+
+// SAFETY: the memory region is only used under the segment's state lock.
+unsafe impl Send for IbvPinningState {}
+unsafe impl Sync for IbvPinningState {}
+
+#[derive(Debug)]
+pub struct IbvSlab {
+    slab_id: usize,
+    start_address: *mut c_void,
+    num_pages: usize,
+    page_size: PageSize,
+}
+
+// SAFETY: the slab only describes memory owned by the application; see `IbvSlab::new`.
+unsafe impl Send for IbvSlab {}
+unsafe impl Sync for IbvSlab {}
+
+impl IbvSlab {
+    /// Describe `num_pages` pages of `page_size` at `start_address`.
+    ///
+    /// # Safety
+    /// The memory must stay mapped for as long as the slab's segments are managed by a cache.
+    pub unsafe fn new(
+        slab_id: usize,
+        start_address: *mut c_void,
+        num_pages: usize,
+        page_size: PageSize,
+    ) -> Self {
+        IbvSlab {
+            slab_id,
+            start_address,
+            num_pages,
+            page_size,
+        }
+    }
+}
+
+impl DatapathSlab for IbvSlab {
+    type SlabId = usize;
+    type IOInfo = IbvKeys;
+    type PinningState = IbvPinningState;
+    type PrivateInfo = IbvPd;
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        IbvPinningState {
+            mr: std::ptr::null_mut(),
+        }
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.slab_id
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        !pinning_state.mr.is_null()
+    }
+
+    // The range comes from the slab's own memory; the trait fixes the signature.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        if !pinning_state.mr.is_null() {
+            return;
+        }
+        let mr = unsafe { ibv_reg_mr(private_info.pd, start_address, len, private_info.access) };
+        if mr.is_null() {
+            let err = IbvRegError::from(io::Error::last_os_error());
+            tracing::warn!("ibv_reg_mr of {} bytes failed: {:?}", len, err);
+            private_info.record_failure(err);
+            return;
+        }
+        pinning_state.mr = mr;
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        if pinning_state.mr.is_null() {
+            return;
+        }
+        let ret = unsafe { ibv_dereg_mr(pinning_state.mr) };
+        if ret != 0 {
+            // The region is still registered; keep it so a later unpin can retry.
+            tracing::warn!("ibv_dereg_mr failed: {}", ret);
+            return;
+        }
+        pinning_state.mr = std::ptr::null_mut();
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        if pinning_state.mr.is_null() {
+            return IbvKeys::default();
+        }
+        let mr = unsafe { &*pinning_state.mr };
+        IbvKeys {
+            lkey: mr.lkey,
+            rkey: mr.rkey,
+        }
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.start_address
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.page_size.clone()
+    }
+}
+
+/// Stand-ins for the libibverbs calls above, so tests link without libibverbs and can check
+/// how segments are registered. Calls are recorded per thread.
+#[cfg(test)]
+pub(crate) mod fake_ibverbs {
+    use std::cell::{Cell, RefCell};
+    use std::os::raw::{c_int, c_void};
+
+    use super::{IbvMr, IbvPdRaw, ENOMEM};
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum IbvCall {
+        RegMr {
+            pd: usize,
+            addr: usize,
+            length: usize,
+            access: c_int,
+        },
+        DeregMr {
+            addr: usize,
+            length: usize,
+        },
+    }
+
+    thread_local! {
+        static CALLS: RefCell<Vec<IbvCall>> = const { RefCell::new(Vec::new()) };
+        static FAIL_REG_MR: Cell<bool> = const { Cell::new(false) };
+        static NEXT_KEY: Cell<u32> = const { Cell::new(1) };
+    }
+
+    extern "C" {
+        fn __errno_location() -> *mut c_int;
+    }
+
+    /// Calls made on this thread since the last `take_calls`.
+    pub fn take_calls() -> Vec<IbvCall> {
+        CALLS.with(|calls| calls.take())
+    }
+
+    /// Make `ibv_reg_mr` fail with ENOMEM on this thread.
+    pub fn fail_reg_mr(fail: bool) {
+        FAIL_REG_MR.with(|fail_reg_mr| fail_reg_mr.set(fail));
+    }
+
+    fn record(call: IbvCall) {
+        CALLS.with(|calls| calls.borrow_mut().push(call));
+    }
+
+    /// Registrations get lkey `n` and rkey `n + 1000`, counting from 1.
+    #[no_mangle]
+    extern "C" fn ibv_reg_mr(
+        pd: *mut IbvPdRaw,
+        addr: *mut c_void,
+        length: usize,
+        access: c_int,
+    ) -> *mut IbvMr {
+        record(IbvCall::RegMr {
+            pd: pd as usize,
+            addr: addr as usize,
+            length,
+            access,
+        });
+        if FAIL_REG_MR.with(Cell::get) {
+            unsafe { *__errno_location() = ENOMEM };
+            return std::ptr::null_mut();
+        }
+        let lkey = NEXT_KEY.with(|next_key| next_key.replace(next_key.get() + 1));
+        Box::into_raw(Box::new(IbvMr {
+            context: std::ptr::null_mut(),
+            pd,
+            addr,
+            length,
+            handle: 0,
+            lkey,
+            rkey: lkey + 1000,
+        }))
+    }
+
+    #[no_mangle]
+    extern "C" fn ibv_dereg_mr(mr: *mut IbvMr) -> c_int {
+        let mr = unsafe { Box::from_raw(mr) };
+        record(IbvCall::DeregMr {
+            addr: mr.addr as usize,
+            length: mr.length,
+        });
+        0
+    }
+}
@@ -0,0 +1,56 @@
+//! Adaptive repin interval. After each cycle the interval is halved when the pinned set
+//! churned a lot and doubled when it did not change at all, so the pinning engine reacts
+//! quickly to a shifting hotset and stays idle while it is stable.
+use std::time::Duration;
+
+use crate::data_structures::HotsetChurn;
+
+/// Fraction of the hotset replaced in one cycle above which the interval shrinks.
+pub const DEFAULT_CHURN_THRESHOLD: f64 = 0.1;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct AdaptiveInterval {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    churn_threshold: f64,
+}
+
+impl AdaptiveInterval {
+    /// Start at `min`, so the first cycles converge quickly.
+    pub fn new(min: Duration, max: Duration) -> Self {
+        AdaptiveInterval {
+            min,
+            max: max.max(min),
+            current: min,
+            churn_threshold: DEFAULT_CHURN_THRESHOLD,
+        }
+    }
+
+    pub fn with_churn_threshold(mut self, churn_threshold: f64) -> Self {
+        self.churn_threshold = churn_threshold;
+        self
+    }
+
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Pins and unpins in the cycle, relative to the size of the hotset.
+    pub fn churn_rate(churn: &HotsetChurn) -> f64 {
+        (churn.num_pinned + churn.num_unpinned) as f64 / churn.hotset_size.max(1) as f64
+    }
+
+    /// Adjust the interval after a cycle and return how long to sleep before the next one.
+    pub fn update(&mut self, churn: &HotsetChurn) -> Duration {
+        let rate = Self::churn_rate(churn);
+        if rate > self.churn_threshold {
+            self.current /= 2;
+        } else if rate == 0.0 {
+            self.current *= 2;
+        }
+        self.current = self.current.clamp(self.min, self.max);
+        tracing::debug!("Churn rate {:.3}, next repin in {:?}", rate, self.current);
+        self.current
+    }
+}
@@ -0,0 +1,239 @@
+//! `DatapathSlab` adapter for io_uring fixed buffers. Pinning a segment installs it in a free
+//! slot of the ring's registered buffer table with `IORING_REGISTER_BUFFERS_UPDATE`, and
+//! unpinning clears the slot again; `IOInfo` is the slot index to pass as `buf_index` to
+//! `IORING_OP_READ_FIXED`/`WRITE_FIXED`.
+//!
+//! The buffer table must be sparse, either registered by `IoUringBufferTable::register_sparse`
+//! or by the application with `IORING_RSRC_REGISTER_SPARSE`, since every slot starts empty.
+use std::io;
+use std::os::raw::c_void;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+use crate::data_structures::DatapathSlab;
+use crate::metrics::LockOrRecover;
+use crate::pagesizes::PageSize;
+
+const IORING_REGISTER_BUFFERS2: libc::c_uint = 15;
+const IORING_REGISTER_BUFFERS_UPDATE: libc::c_uint = 16;
+const IORING_RSRC_REGISTER_SPARSE: u32 = 1;
+
+/// `struct io_uring_rsrc_register`.
+#[repr(C)]
+struct RsrcRegister {
+    nr: u32,
+    flags: u32,
+    resv2: u64,
+    data: u64,
+    tags: u64,
+}
+
+/// `struct io_uring_rsrc_update2`.
+#[repr(C)]
+struct RsrcUpdate2 {
+    offset: u32,
+    resv: u32,
+    data: u64,
+    tags: u64,
+    nr: u32,
+    resv2: u32,
+}
+
+fn io_uring_register(
+    ring_fd: RawFd,
+    opcode: libc::c_uint,
+    arg: *const c_void,
+    nr_args: libc::c_uint,
+) -> io::Result<libc::c_long> {
+    let ret = unsafe { libc::syscall(libc::SYS_io_uring_register, ring_fd, opcode, arg, nr_args) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+/// A ring's registered buffer table, handing out its slots to pinned segments.
+#[derive(Debug)]
+pub struct IoUringBufferTable {
+    ring_fd: RawFd,
+    free_slots: Mutex<Vec<u32>>,
+}
+
+impl IoUringBufferTable {
+    /// Register a sparse table of `num_slots` buffers on `ring_fd` and manage all of them.
+    pub fn register_sparse(ring_fd: RawFd, num_slots: u32) -> io::Result<Arc<Self>> {
+        let register = RsrcRegister {
+            nr: num_slots,
+            flags: IORING_RSRC_REGISTER_SPARSE,
+            resv2: 0,
+            data: 0,
+            tags: 0,
+        };
+        io_uring_register(
+            ring_fd,
+            IORING_REGISTER_BUFFERS2,
+            &register as *const RsrcRegister as *const c_void,
+            std::mem::size_of::<RsrcRegister>() as libc::c_uint,
+        )?;
+        Ok(Self::with_slots(ring_fd, 0..num_slots))
+    }
+
+    /// Manage `slots` of a sparse buffer table already registered on `ring_fd`, e.g. to
+    /// leave the other slots to the application.
+    pub fn with_slots(ring_fd: RawFd, slots: impl IntoIterator<Item = u32>) -> Arc<Self> {
+        let mut free_slots: Vec<u32> = slots.into_iter().collect();
+        // Hand out the lowest slots first.
+        free_slots.reverse();
+        Arc::new(IoUringBufferTable {
+            ring_fd,
+            free_slots: Mutex::new(free_slots),
+        })
+    }
+
+    pub fn free_slots(&self) -> usize {
+        self.free_slots.lock_or_recover().len()
+    }
+
+    /// Point `slot` at `len` bytes at `addr`; a null `addr` clears the slot.
+    fn update_slot(&self, slot: u32, addr: *mut c_void, len: usize) -> io::Result<()> {
+        let iovec = libc::iovec {
+            iov_base: addr,
+            iov_len: len,
+        };
+        let update = RsrcUpdate2 {
+            offset: slot,
+            resv: 0,
+            data: &iovec as *const libc::iovec as u64,
+            tags: 0,
+            nr: 1,
+            resv2: 0,
+        };
+        io_uring_register(
+            self.ring_fd,
+            IORING_REGISTER_BUFFERS_UPDATE,
+            &update as *const RsrcUpdate2 as *const c_void,
+            std::mem::size_of::<RsrcUpdate2>() as libc::c_uint,
+        )
+        .map(|_| ())
+    }
+}
+
+#[derive(Debug)]
+pub struct IoUringPinningState {
+    slot: Option<u32>,
+    table: Arc<IoUringBufferTable>,
+}
+
+#[derive(Debug)]
+pub struct IoUringSlab {
+    slab_id: usize,
+    start_address: *mut c_void,
+    num_pages: usize,
+    page_size: PageSize,
+    table: Arc<IoUringBufferTable>,
+}
+
+// SAFETY: the slab only describes memory owned by the application; see `IoUringSlab::new`.
+unsafe impl Send for IoUringSlab {}
+unsafe impl Sync for IoUringSlab {}
+
+impl IoUringSlab {
+    /// Describe `num_pages` pages of `page_size` at `start_address`, pinned into `table`.
+    ///
+    /// # Safety
+    /// The memory must stay mapped for as long as the slab's segments are managed by a cache.
+    pub unsafe fn new(
+        slab_id: usize,
+        start_address: *mut c_void,
+        num_pages: usize,
+        page_size: PageSize,
+        table: Arc<IoUringBufferTable>,
+    ) -> Self {
+        IoUringSlab {
+            slab_id,
+            start_address,
+            num_pages,
+            page_size,
+            table,
+        }
+    }
+}
+
+impl DatapathSlab for IoUringSlab {
+    type SlabId = usize;
+    /// Fixed buffer index, `u32::MAX` while the segment is not pinned.
+    type IOInfo = u32;
+    type PinningState = IoUringPinningState;
+    type PrivateInfo = ();
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        IoUringPinningState {
+            slot: None,
+            table: self.table.clone(),
+        }
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.slab_id
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        pinning_state.slot.is_some()
+    }
+
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        _private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        if pinning_state.slot.is_some() {
+            return;
+        }
+        let table = &pinning_state.table;
+        let slot = match table.free_slots.lock_or_recover().pop() {
+            Some(slot) => slot,
+            None => {
+                tracing::warn!("No free io_uring buffer slot for {} bytes", len);
+                return;
+            }
+        };
+        match table.update_slot(slot, start_address, len) {
+            Ok(()) => pinning_state.slot = Some(slot),
+            Err(e) => {
+                tracing::warn!("Registering {} bytes in buffer slot {} failed: {}", len, slot, e);
+                table.free_slots.lock_or_recover().push(slot);
+            }
+        }
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        let slot = match pinning_state.slot {
+            Some(slot) => slot,
+            None => return,
+        };
+        let table = &pinning_state.table;
+        if let Err(e) = table.update_slot(slot, std::ptr::null_mut(), 0) {
+            tracing::warn!("Clearing buffer slot {} failed: {}", slot, e);
+            return;
+        }
+        pinning_state.slot = None;
+        table.free_slots.lock_or_recover().push(slot);
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        pinning_state.slot.unwrap_or(u32::MAX)
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.start_address
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.page_size.clone()
+    }
+}
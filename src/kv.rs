@@ -0,0 +1,1150 @@
+//! Key-value caches for application objects, implementing `KvCache`. Unlike the segment
+//! policies of `segment_policy`, which only rank segments, these hold the values themselves.
+//! `UnboundedLRUCache` keeps up to `len`
+//! entries behind one lock and evicts the least recently used; the underlying `LruCache` is
+//! unbounded so the limit can be changed without rebuilding it.
+//!
+//! With a weigher, the cache also keeps the total weight of its entries, e.g. their size in
+//! bytes, under a budget, evicting as many least recently used entries as it takes to admit
+//! a new one.
+//!
+//! With an eviction callback, every entry the cache lets go of (evicted, expired, replaced or
+//! too heavy to admit) is handed to it once the cache lock is released, e.g. to return the
+//! buffers backing the values. Without one, `put` returns its evictions and the rest are
+//! dropped.
+//!
+//! `ShardedLruCache` spreads keys over several such caches, for throughput under
+//! contention.
+//!
+//! `ZccKvCache` caches values whose bytes live in slabs managed by a `ZeroCopyCache`: every
+//! `get` counts as an access to the segments backing the value, and comes with an IO lease on
+//! them when they are pinned, so hot values end up served zero-copy.
+//!
+//! `dump` writes the live entries of a cache in a compact binary format, least recently used
+//! first, and `load` puts them back in that order, e.g. to warm a restarted server's cache.
+//! Keys and values are encoded with `KvBytes`.
+//!
+//! Entries put with a TTL expire lazily: a `get` past the deadline drops the entry and
+//! misses. `purge_expired` drops every expired entry at once, and an `ExpirySweeper` calls it
+//! periodically from a background thread.
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+use crate::data_structures::{DatapathSlab, IoLease, PinnedSlice, SegmentId, DEFAULT_CACHE_SIZE};
+use crate::handle::ZccHandle;
+use crate::metrics::{recover, LockOrRecover};
+use crate::segment_policy::SegmentPolicy;
+
+/// A key-value cache shared between threads.
+pub trait KvCache<K, V> {
+    /// Insert or replace `key`. Returns the entries evicted to make room, least recently used
+    /// first, or the new entry itself if it can never fit; with an eviction callback, these go
+    /// to the callback instead.
+    fn put(&self, key: K, value: V) -> Vec<(K, V)>;
+
+    /// `put`, with the entry expiring `ttl` from now.
+    fn put_with_ttl(&self, key: K, value: V, ttl: Duration) -> Vec<(K, V)>;
+
+    /// A copy of `key`'s value, marking it most recently used. Expired entries are dropped
+    /// and miss.
+    fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone;
+
+    /// A copy of `key`'s value, leaving the eviction order and the stats alone, e.g. for
+    /// health checks. Expired entries miss but are kept until a `get` or purge drops them.
+    fn peek(&self, key: &K) -> Option<V>
+    where
+        V: Clone;
+
+    /// `get` for each of `keys`, in order, taking each lock once.
+    fn get_many(&self, keys: &[K]) -> Vec<Option<V>>
+    where
+        V: Clone;
+
+    /// `put` for each entry, in order, taking each lock once. Returns the evictions of all of
+    /// them; a later entry may evict an earlier one.
+    fn put_many(&self, entries: Vec<(K, V)>) -> Vec<(K, V)>;
+
+    /// Whether `key` is cached and not expired, like `peek` without copying the value.
+    fn contains_key(&self, key: &K) -> bool;
+
+    /// A copy of `key`'s value, first inserting `init()` if it isn't cached. Holds the lock
+    /// throughout, so concurrent callers for the same key compute the value once. Entries
+    /// evicted to make room go to the eviction callback, or are dropped.
+    fn get_or_insert_with(&self, key: K, init: impl FnOnce() -> V) -> V
+    where
+        V: Clone,
+    {
+        match self.try_get_or_insert_with(key, || Ok::<V, Infallible>(init())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// `get_or_insert_with` for an `init` that can fail, in which case nothing is inserted and
+    /// its error is returned.
+    fn try_get_or_insert_with<E>(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<V, E>,
+    ) -> Result<V, E>
+    where
+        V: Clone;
+
+    /// Keep at most `size` entries from now on. Shrinking evicts least recently used entries
+    /// right away; returns them, or hands them to the eviction callback.
+    fn resize_cache(&self, size: usize) -> Vec<(K, V)>;
+
+    /// Entries currently cached, including expired ones not dropped yet.
+    fn get_cache_size(&self) -> usize;
+
+    /// Drop every expired entry. Returns how many were dropped.
+    fn purge_expired(&self) -> usize;
+
+    /// Lookup and insert counts since creation or the last `reset_stats`.
+    fn get_stats(&self) -> KvStats;
+
+    fn reset_stats(&self);
+
+    /// Fraction of lookups that found their key.
+    fn get_hit_rate(&self) -> f64 {
+        self.get_stats().hit_rate()
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub struct KvStats {
+    /// `get`s that found their key.
+    pub hits: u64,
+    /// `get`s that did not.
+    pub misses: u64,
+    pub puts: u64,
+    /// Entries evicted to make room for others.
+    pub evictions: u64,
+    /// Entries dropped because their TTL passed.
+    pub expirations: u64,
+}
+
+impl KvStats {
+    /// Every `get`, hit or miss.
+    pub fn lookups(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        match self.lookups() {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+
+    pub fn miss_rate(&self) -> f64 {
+        match self.lookups() {
+            0 => 0.0,
+            lookups => self.misses as f64 / lookups as f64,
+        }
+    }
+}
+
+impl std::ops::AddAssign for KvStats {
+    fn add_assign(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.puts += other.puts;
+        self.evictions += other.evictions;
+        self.expirations += other.expirations;
+    }
+}
+
+/// Atomic counters behind `KvStats`, updated without taking the cache lock.
+#[derive(Debug, Default)]
+struct KvCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    puts: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl KvCounters {
+    fn record_lookup(&self, hit: bool) {
+        let counter = match hit {
+            true => &self.hits,
+            false => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_put(&self) {
+        self.puts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_evictions(&self, count: usize) {
+        self.evictions.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn record_expirations(&self, count: usize) {
+        self.expirations.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> KvStats {
+        KvStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            puts: self.puts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.hits,
+            &self.misses,
+            &self.puts,
+            &self.evictions,
+            &self.expirations,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A cached value, its weight and when it expires, if ever.
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    weight: usize,
+    expires_at: Option<Instant>,
+}
+
+impl<V> Entry<V> {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// The entries, and their total weight.
+struct LruState<K, V>
+where
+    K: Hash + Eq,
+{
+    entries: LruCache<K, Entry<V>>,
+    weight: usize,
+}
+
+impl<K, V> LruState<K, V>
+where
+    K: Hash + Eq,
+{
+    fn new() -> Self {
+        LruState {
+            entries: LruCache::unbounded(),
+            weight: 0,
+        }
+    }
+
+    fn put(&mut self, key: K, entry: Entry<V>) {
+        self.weight += entry.weight;
+        if let Some(old) = self.entries.put(key, entry) {
+            self.weight -= old.weight;
+        }
+    }
+
+    fn pop(&mut self, key: &K) -> Option<(K, Entry<V>)> {
+        let (key, entry) = self.entries.pop_entry(key)?;
+        self.weight -= entry.weight;
+        Some((key, entry))
+    }
+
+    fn pop_lru(&mut self) -> Option<(K, Entry<V>)> {
+        let (key, entry) = self.entries.pop_lru()?;
+        self.weight -= entry.weight;
+        Some((key, entry))
+    }
+}
+
+/// Weight of an entry, e.g. its size in bytes.
+pub type Weigher<K, V> = Box<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+/// Receives each entry the cache lets go of.
+pub type EvictionCallback<K, V> = Box<dyn Fn(K, V) + Send + Sync>;
+
+pub struct UnboundedLRUCache<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Most entries kept.
+    len: AtomicUsize,
+    /// Most total weight kept, `usize::MAX` without a weigher.
+    max_weight: usize,
+    weigher: Option<Weigher<K, V>>,
+    on_evict: Option<EvictionCallback<K, V>>,
+    cache: Mutex<LruState<K, V>>,
+    counters: KvCounters,
+}
+
+impl<K, V> std::fmt::Debug for UnboundedLRUCache<K, V>
+where
+    K: Hash + Eq,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cache = self.cache.lock_or_recover();
+        f.debug_struct("UnboundedLRUCache")
+            .field("len", &self.get_capacity())
+            .field("max_weight", &self.max_weight)
+            .field("entries", &cache.entries)
+            .field("weight", &cache.weight)
+            .field("counters", &self.counters)
+            .finish()
+    }
+}
+
+impl<K, V> Default for UnboundedLRUCache<K, V>
+where
+    K: Hash + Eq,
+{
+    fn default() -> Self {
+        UnboundedLRUCache::new(DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl<K, V> UnboundedLRUCache<K, V>
+where
+    K: Hash + Eq,
+{
+    pub fn new(size: usize) -> Self {
+        UnboundedLRUCache {
+            len: AtomicUsize::new(size),
+            max_weight: usize::MAX,
+            weigher: None,
+            on_evict: None,
+            cache: Mutex::new(LruState::new()),
+            counters: KvCounters::default(),
+        }
+    }
+
+    /// Also keep the total `weigher` weight of the entries at most `max_weight`, e.g. a byte
+    /// budget with a weigher returning value sizes.
+    pub fn with_weight_limit(
+        mut self,
+        max_weight: usize,
+        weigher: impl Fn(&K, &V) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.max_weight = max_weight;
+        self.weigher = Some(Box::new(weigher));
+        self
+    }
+
+    /// Hand every entry the cache lets go of to `on_evict`, see the module docs.
+    pub fn with_eviction_callback(
+        mut self,
+        on_evict: impl Fn(K, V) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_evict = Some(Box::new(on_evict));
+        self
+    }
+
+    /// Most entries kept.
+    pub fn get_capacity(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn get_max_weight(&self) -> usize {
+        self.max_weight
+    }
+
+    /// Total weight of the cached entries, 0 without a weigher.
+    pub fn get_weight(&self) -> usize {
+        self.cache.lock_or_recover().weight
+    }
+
+    /// Copies of the live entries, most recently used first, e.g. to dump the hot keys or to
+    /// warm up another cache. Every entry is copied under the lock before the first one is
+    /// returned, so this holds the lock for O(n); neither recency nor the stats change.
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let now = Instant::now();
+        let cache = self.cache.lock_or_recover();
+        let entries: Vec<(K, V)> = cache
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+        entries.into_iter()
+    }
+
+    /// Keys of the live entries, most recently used first, copied under the lock as for
+    /// `iter`.
+    pub fn keys(&self) -> impl Iterator<Item = K>
+    where
+        K: Clone,
+    {
+        let now = Instant::now();
+        let cache = self.cache.lock_or_recover();
+        cache
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<K>>()
+            .into_iter()
+    }
+
+    fn put_entry(&self, key: K, value: V, expires_at: Option<Instant>) -> Vec<(K, V)> {
+        let mut cache = self.cache.lock_or_recover();
+        let mut replaced = Vec::new();
+        let evicted = self.insert_locked(&mut cache, key, value, expires_at, &mut replaced);
+        drop(cache);
+        self.drop_entries(replaced);
+        self.release(evicted)
+    }
+
+    /// Insert or replace `key` with the lock held. Returns the entries evicted for it, or the
+    /// entry itself if it can never fit, and adds the one it replaced to `replaced`.
+    fn insert_locked(
+        &self,
+        cache: &mut LruState<K, V>,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+        replaced: &mut Vec<(K, V)>,
+    ) -> Vec<(K, V)> {
+        self.counters.record_put();
+        let weight = self.weigher.as_ref().map_or(0, |weigher| weigher(&key, &value));
+        if self.get_capacity() == 0 || weight > self.max_weight {
+            return vec![(key, value)];
+        }
+        replaced.extend(cache.pop(&key).map(|(key, entry)| (key, entry.value)));
+        let evicted = self.make_room(cache, weight);
+        cache.put(key, Entry { value, weight, expires_at });
+        self.counters.record_evictions(evicted.len());
+        evicted
+    }
+
+    /// `get` for each of `keys` under one lock.
+    fn get_many_by_ref<'a>(&self, keys: impl Iterator<Item = &'a K>) -> Vec<Option<V>>
+    where
+        K: 'a,
+        V: Clone,
+    {
+        let now = Instant::now();
+        let mut cache = self.cache.lock_or_recover();
+        let mut expired = Vec::new();
+        let values: Vec<Option<V>> = keys
+            .map(|key| match cache.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => {
+                    expired.extend(cache.pop(key).map(|(key, entry)| (key, entry.value)));
+                    None
+                }
+                entry => entry.map(|entry| entry.value.clone()),
+            })
+            .collect();
+        drop(cache);
+        for value in values.iter() {
+            self.counters.record_lookup(value.is_some());
+        }
+        self.counters.record_expirations(expired.len());
+        self.drop_entries(expired);
+        values
+    }
+
+    /// Evict least recently used entries until one of `weight` fits.
+    fn make_room(&self, cache: &mut LruState<K, V>, weight: usize) -> Vec<(K, V)> {
+        let len = self.get_capacity();
+        let mut evicted = Vec::new();
+        while cache.entries.len() >= len || cache.weight + weight > self.max_weight {
+            match cache.pop_lru() {
+                Some((key, entry)) => evicted.push((key, entry.value)),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Entries for `put` to return, or none if they went to the eviction callback.
+    fn release(&self, evicted: Vec<(K, V)>) -> Vec<(K, V)> {
+        match self.on_evict {
+            Some(_) => {
+                self.drop_entries(evicted);
+                Vec::new()
+            }
+            None => evicted,
+        }
+    }
+
+    /// Hand entries the caller never sees to the eviction callback, if any.
+    fn drop_entries(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        if let Some(on_evict) = &self.on_evict {
+            for (key, value) in entries {
+                on_evict(key, value);
+            }
+        }
+    }
+}
+
+impl<K, V> KvCache<K, V> for UnboundedLRUCache<K, V>
+where
+    K: Hash + Eq,
+{
+    fn put(&self, key: K, value: V) -> Vec<(K, V)> {
+        self.put_entry(key, value, None)
+    }
+
+    fn put_with_ttl(&self, key: K, value: V, ttl: Duration) -> Vec<(K, V)> {
+        self.put_entry(key, value, Instant::now().checked_add(ttl))
+    }
+
+    fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.get_many_by_ref(std::iter::once(key)).pop().flatten()
+    }
+
+    fn get_many(&self, keys: &[K]) -> Vec<Option<V>>
+    where
+        V: Clone,
+    {
+        self.get_many_by_ref(keys.iter())
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>) -> Vec<(K, V)> {
+        let mut cache = self.cache.lock_or_recover();
+        let mut replaced = Vec::new();
+        let mut evicted = Vec::new();
+        for (key, value) in entries {
+            evicted.extend(self.insert_locked(&mut cache, key, value, None, &mut replaced));
+        }
+        drop(cache);
+        self.drop_entries(replaced);
+        self.release(evicted)
+    }
+
+    fn peek(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let cache = self.cache.lock_or_recover();
+        let entry = cache.entries.peek(key)?;
+        match entry.is_expired(Instant::now()) {
+            true => None,
+            false => Some(entry.value.clone()),
+        }
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        let cache = self.cache.lock_or_recover();
+        let now = Instant::now();
+        cache.entries.peek(key).is_some_and(|entry| !entry.is_expired(now))
+    }
+
+    fn try_get_or_insert_with<E>(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<V, E>,
+    ) -> Result<V, E>
+    where
+        V: Clone,
+    {
+        let mut cache = self.cache.lock_or_recover();
+        let mut expired = None;
+        match cache.entries.get(&key) {
+            Some(entry) if entry.is_expired(Instant::now()) => expired = cache.pop(&key),
+            Some(entry) => {
+                let value = entry.value.clone();
+                drop(cache);
+                self.counters.record_lookup(true);
+                return Ok(value);
+            }
+            None => {}
+        }
+        self.counters.record_lookup(false);
+        let result = init();
+        let mut evicted = Vec::new();
+        if let Ok(value) = &result {
+            self.counters.record_put();
+            let weight = self.weigher.as_ref().map_or(0, |weigher| weigher(&key, value));
+            if self.get_capacity() == 0 || weight > self.max_weight {
+                evicted.push((key, value.clone()));
+            } else {
+                evicted = self.make_room(&mut cache, weight);
+                self.counters.record_evictions(evicted.len());
+                let entry = Entry {
+                    value: value.clone(),
+                    weight,
+                    expires_at: None,
+                };
+                cache.put(key, entry);
+            }
+        }
+        drop(cache);
+        if let Some((key, entry)) = expired {
+            self.counters.record_expirations(1);
+            self.drop_entries([(key, entry.value)]);
+        }
+        self.drop_entries(evicted);
+        result
+    }
+
+    fn resize_cache(&self, size: usize) -> Vec<(K, V)> {
+        let mut cache = self.cache.lock_or_recover();
+        self.len.store(size, Ordering::Relaxed);
+        let mut evicted = Vec::new();
+        while cache.entries.len() > size {
+            match cache.pop_lru() {
+                Some((key, entry)) => evicted.push((key, entry.value)),
+                None => break,
+            }
+        }
+        drop(cache);
+        self.counters.record_evictions(evicted.len());
+        self.release(evicted)
+    }
+
+    fn get_cache_size(&self) -> usize {
+        self.cache.lock_or_recover().entries.len()
+    }
+
+    fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut cache = self.cache.lock_or_recover();
+        if !cache.entries.iter().any(|(_, entry)| entry.is_expired(now)) {
+            return 0;
+        }
+        // `LruCache` can't remove entries while iterating, so move the live ones to a new
+        // cache, least recently used first to keep their order.
+        let mut live = LruState::new();
+        let mut expired = Vec::new();
+        while let Some((key, entry)) = cache.pop_lru() {
+            match entry.is_expired(now) {
+                true => expired.push((key, entry.value)),
+                false => live.put(key, entry),
+            }
+        }
+        *cache = live;
+        drop(cache);
+        let num_expired = expired.len();
+        self.counters.record_expirations(num_expired);
+        self.drop_entries(expired);
+        num_expired
+    }
+
+    fn get_stats(&self) -> KvStats {
+        self.counters.snapshot()
+    }
+
+    fn reset_stats(&self) {
+        self.counters.reset();
+    }
+}
+
+/// `UnboundedLRUCache`s partitioned by key hash, each behind its own lock, so concurrent
+/// operations on different keys rarely contend. Recency, limits and stats are per shard; the
+/// stats are added up on demand.
+#[derive(Debug)]
+pub struct ShardedLruCache<K, V>
+where
+    K: Hash + Eq,
+{
+    shards: Vec<UnboundedLRUCache<K, V>>,
+}
+
+impl<K, V> ShardedLruCache<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Create `num_shards` shards (at least one), building shard `i` with `make_shard(i)`,
+    /// e.g. to give each a weight limit or an eviction callback.
+    pub fn new(
+        num_shards: usize,
+        make_shard: impl FnMut(usize) -> UnboundedLRUCache<K, V>,
+    ) -> Self {
+        ShardedLruCache {
+            shards: (0..num_shards.max(1)).map(make_shard).collect(),
+        }
+    }
+
+    /// `num_shards` shards keeping `size` entries between them.
+    pub fn with_capacity(num_shards: usize, size: usize) -> Self {
+        let shard_size = size.div_ceil(num_shards.max(1));
+        ShardedLruCache::new(num_shards, |_| UnboundedLRUCache::new(shard_size))
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard_index(&self, key: &K) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// The shard holding `key`.
+    pub fn shard(&self, key: &K) -> &UnboundedLRUCache<K, V> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Stats of each shard, e.g. to spot a hot one.
+    pub fn get_shard_stats(&self) -> Vec<KvStats> {
+        self.shards.iter().map(|shard| shard.get_stats()).collect()
+    }
+}
+
+impl<K, V> KvCache<K, V> for ShardedLruCache<K, V>
+where
+    K: Hash + Eq,
+{
+    fn put(&self, key: K, value: V) -> Vec<(K, V)> {
+        self.shard(&key).put(key, value)
+    }
+
+    fn put_with_ttl(&self, key: K, value: V, ttl: Duration) -> Vec<(K, V)> {
+        self.shard(&key).put_with_ttl(key, value, ttl)
+    }
+
+    fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key).get(key)
+    }
+
+    fn get_many(&self, keys: &[K]) -> Vec<Option<V>>
+    where
+        V: Clone,
+    {
+        let mut by_shard = vec![Vec::new(); self.shards.len()];
+        for (position, key) in keys.iter().enumerate() {
+            by_shard[self.shard_index(key)].push(position);
+        }
+        let mut values: Vec<Option<V>> = (0..keys.len()).map(|_| None).collect();
+        for (shard, positions) in self.shards.iter().zip(by_shard) {
+            if positions.is_empty() {
+                continue;
+            }
+            let shard_values = shard.get_many_by_ref(positions.iter().map(|&pos| &keys[pos]));
+            for (position, value) in positions.into_iter().zip(shard_values) {
+                values[position] = value;
+            }
+        }
+        values
+    }
+
+    fn put_many(&self, entries: Vec<(K, V)>) -> Vec<(K, V)> {
+        let mut by_shard: Vec<Vec<(K, V)>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (key, value) in entries {
+            by_shard[self.shard_index(&key)].push((key, value));
+        }
+        let mut evicted = Vec::new();
+        for (shard, entries) in self.shards.iter().zip(by_shard) {
+            if !entries.is_empty() {
+                evicted.extend(shard.put_many(entries));
+            }
+        }
+        evicted
+    }
+
+    fn peek(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key).peek(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.shard(key).contains_key(key)
+    }
+
+    fn try_get_or_insert_with<E>(
+        &self,
+        key: K,
+        init: impl FnOnce() -> Result<V, E>,
+    ) -> Result<V, E>
+    where
+        V: Clone,
+    {
+        self.shard(&key).try_get_or_insert_with(key, init)
+    }
+
+    /// Splits `size` evenly between the shards, like `with_capacity`.
+    fn resize_cache(&self, size: usize) -> Vec<(K, V)> {
+        let shard_size = size.div_ceil(self.shards.len());
+        let mut evicted = Vec::new();
+        for shard in self.shards.iter() {
+            evicted.extend(shard.resize_cache(shard_size));
+        }
+        evicted
+    }
+
+    fn get_cache_size(&self) -> usize {
+        self.shards.iter().map(|shard| shard.get_cache_size()).sum()
+    }
+
+    fn purge_expired(&self) -> usize {
+        self.shards.iter().map(|shard| shard.purge_expired()).sum()
+    }
+
+    fn get_stats(&self) -> KvStats {
+        let mut stats = KvStats::default();
+        for shard in self.shards.iter() {
+            stats += shard.get_stats();
+        }
+        stats
+    }
+
+    fn reset_stats(&self) {
+        for shard in self.shards.iter() {
+            shard.reset_stats();
+        }
+    }
+}
+
+/// Byte encoding of keys and values for `dump` and `load`.
+pub trait KvBytes: Sized {
+    fn to_kv_bytes(&self) -> Vec<u8>;
+
+    /// `None` if `bytes` is not an encoding of `Self`.
+    fn from_kv_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl KvBytes for Vec<u8> {
+    fn to_kv_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_kv_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl KvBytes for String {
+    fn to_kv_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_kv_bytes(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl KvBytes for u32 {
+    fn to_kv_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn from_kv_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl KvBytes for u64 {
+    fn to_kv_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn from_kv_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+/// Start of every dump, followed by a format version byte.
+const DUMP_MAGIC: &[u8; 4] = b"ZCKV";
+const DUMP_VERSION: u8 = 1;
+/// Time to live written for entries that never expire.
+const NO_TTL: u64 = u64::MAX;
+
+/// An entry as written by `dump`: key, value and milliseconds left to live.
+type EncodedEntry = (Vec<u8>, Vec<u8>, u64);
+
+fn encode_entry<K: KvBytes, V: KvBytes>(key: &K, entry: &Entry<V>, now: Instant) -> EncodedEntry {
+    let ttl_ms = entry.expires_at.map_or(NO_TTL, |expires_at| {
+        expires_at.saturating_duration_since(now).as_millis().min(NO_TTL as u128 - 1) as u64
+    });
+    (key.to_kv_bytes(), entry.value.to_kv_bytes(), ttl_ms)
+}
+
+/// Write the header, entry count and `entries`, each as a little-endian key length, key,
+/// value length, value and time to live. Returns how many entries were written.
+fn write_dump(writer: &mut impl Write, entries: &[EncodedEntry]) -> io::Result<usize> {
+    writer.write_all(DUMP_MAGIC)?;
+    writer.write_all(&[DUMP_VERSION])?;
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (key, value, ttl_ms) in entries {
+        for bytes in [key, value] {
+            let len = u32::try_from(bytes.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "entry too large"))?;
+            writer.write_all(&len.to_le_bytes())?;
+            writer.write_all(bytes)?;
+        }
+        writer.write_all(&ttl_ms.to_le_bytes())?;
+    }
+    Ok(entries.len())
+}
+
+fn invalid_dump(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_field(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    // Read through `take` rather than allocating `len` up front, so a corrupt length fails
+    // at the end of the input instead of on a huge allocation.
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(invalid_dump("truncated entry"));
+    }
+    Ok(bytes)
+}
+
+/// Read a dump written by `write_dump`, putting each entry into `cache` in order. Returns how
+/// many entries were put.
+fn read_dump<K, V>(cache: &impl KvCache<K, V>, reader: &mut impl Read) -> io::Result<usize>
+where
+    K: KvBytes,
+    V: KvBytes,
+{
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != DUMP_MAGIC || header[4] != DUMP_VERSION {
+        return Err(invalid_dump("not a key-value cache dump"));
+    }
+    let num_entries = read_u64(reader)?;
+    for _ in 0..num_entries {
+        let key = K::from_kv_bytes(&read_field(reader)?).ok_or_else(|| invalid_dump("bad key"))?;
+        let value =
+            V::from_kv_bytes(&read_field(reader)?).ok_or_else(|| invalid_dump("bad value"))?;
+        match read_u64(reader)? {
+            NO_TTL => cache.put(key, value),
+            ttl_ms => cache.put_with_ttl(key, value, Duration::from_millis(ttl_ms)),
+        };
+    }
+    Ok(num_entries as usize)
+}
+
+impl<K, V> UnboundedLRUCache<K, V>
+where
+    K: Hash + Eq + KvBytes,
+    V: KvBytes,
+{
+    /// Live entries encoded for a dump, least recently used first.
+    fn encoded_entries(&self) -> Vec<EncodedEntry> {
+        let now = Instant::now();
+        let cache = self.cache.lock_or_recover();
+        cache
+            .entries
+            .iter()
+            .rev()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| encode_entry(key, entry, now))
+            .collect()
+    }
+
+    /// Write the live entries to `writer`, see the module docs. Returns how many were written.
+    pub fn dump(&self, writer: &mut impl Write) -> io::Result<usize> {
+        write_dump(writer, &self.encoded_entries())
+    }
+
+    /// Put every entry of a dump, restoring their recency order and remaining TTLs. Entries
+    /// that don't fit evict the older ones, as for `put`. Returns how many were read.
+    pub fn load(&self, reader: &mut impl Read) -> io::Result<usize> {
+        read_dump(self, reader)
+    }
+}
+
+impl<K, V> ShardedLruCache<K, V>
+where
+    K: Hash + Eq + KvBytes,
+    V: KvBytes,
+{
+    /// `UnboundedLRUCache::dump` for every shard in turn, so each shard's recency order is
+    /// kept when loading into a cache with the same number of shards.
+    pub fn dump(&self, writer: &mut impl Write) -> io::Result<usize> {
+        let entries: Vec<EncodedEntry> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.encoded_entries())
+            .collect();
+        write_dump(writer, &entries)
+    }
+
+    /// See `UnboundedLRUCache::load`. Dumps from any of the caches can be loaded.
+    pub fn load(&self, reader: &mut impl Read) -> io::Result<usize> {
+        read_dump(self, reader)
+    }
+}
+
+/// A value looked up in a `ZccKvCache`: its bytes, and an IO lease on the segments holding
+/// them if those are pinned. Without one, send a copy of the bytes.
+#[derive(Debug)]
+pub struct ZccValue<'a, Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    bytes: &'a [u8],
+    lease: Option<IoLease<Slab>>,
+}
+
+impl<'a, Slab> ZccValue<'a, Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn is_zero_copy(&self) -> bool {
+        self.lease.is_some()
+    }
+
+    /// IO info, offset and length of the value in each segment holding it, if all of them are
+    /// pinned.
+    pub fn slices(&self) -> Option<&[PinnedSlice<SegmentId<Slab>, Slab::IOInfo>]> {
+        self.lease.as_ref().map(|lease| lease.slices())
+    }
+
+    /// The lease, to keep the IO counted past the value, e.g. until the send completes.
+    pub fn into_lease(self) -> Option<IoLease<Slab>> {
+        self.lease
+    }
+}
+
+/// Key-value cache whose values are buffers in slabs registered with a `ZeroCopyCache`, so
+/// the segment policy sees the object accesses and pins the segments hot values live in.
+pub struct ZccKvCache<'a, K, C, Slab, CB>
+where
+    C: KvCache<K, &'a [u8]>,
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    values: C,
+    zcc: ZccHandle<Slab, CB>,
+    _key: std::marker::PhantomData<fn(K) -> &'a [u8]>,
+}
+
+impl<'a, K, C, Slab, CB> ZccKvCache<'a, K, C, Slab, CB>
+where
+    C: KvCache<K, &'a [u8]>,
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    pub fn new(values: C, zcc: ZccHandle<Slab, CB>) -> Self {
+        ZccKvCache {
+            values,
+            zcc,
+            _key: std::marker::PhantomData,
+        }
+    }
+
+    /// The cache holding the values, for the rest of `KvCache`.
+    pub fn values(&self) -> &C {
+        &self.values
+    }
+
+    pub fn put(&self, key: K, bytes: &'a [u8]) -> Vec<(K, &'a [u8])> {
+        self.values.put(key, bytes)
+    }
+
+    /// `key`'s value, recording an access to the segments holding it and leasing them for IO
+    /// if they are pinned.
+    pub fn get(&self, key: &K) -> Option<ZccValue<'a, Slab>> {
+        let bytes = self.values.get(key)?;
+        let lease = self.zcc.record_access_and_get_io_info_if_pinned(bytes);
+        Some(ZccValue { bytes, lease })
+    }
+}
+
+impl<'a, K, C, Slab, CB> std::fmt::Debug for ZccKvCache<'a, K, C, Slab, CB>
+where
+    C: KvCache<K, &'a [u8]> + std::fmt::Debug,
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZccKvCache")
+            .field("values", &self.values)
+            .field("zcc", &self.zcc)
+            .finish()
+    }
+}
+
+/// Background thread calling `purge_expired` on a cache every `interval`. Dropping it stops
+/// the thread.
+#[derive(Debug)]
+pub struct ExpirySweeper {
+    /// Set to stop the thread, signaled so it doesn't finish its sleep first.
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ExpirySweeper {
+    pub fn spawn<K, V, C>(cache: Arc<C>, interval: Duration) -> Self
+    where
+        C: KvCache<K, V> + Send + Sync + 'static,
+    {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+        let worker_shutdown = shutdown.clone();
+        let handle = thread::spawn(move || {
+            let (stopped, wakeup) = &*worker_shutdown;
+            let mut stopped_guard = stopped.lock_or_recover();
+            while !*stopped_guard {
+                let (guard, result) = recover(wakeup.wait_timeout(stopped_guard, interval));
+                stopped_guard = guard;
+                if result.timed_out() && !*stopped_guard {
+                    let expired = cache.purge_expired();
+                    tracing::debug!("Expiry sweep dropped {} entries", expired);
+                }
+            }
+        });
+        ExpirySweeper {
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            *self.shutdown.0.lock_or_recover() = true;
+            self.shutdown.1.notify_all();
+            if handle.join().is_err() {
+                tracing::error!("Expiry sweeper thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ExpirySweeper {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
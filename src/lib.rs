@@ -1,56 +1,3110 @@
+pub mod accesscounts;
+#[cfg(feature = "async")]
+pub mod asyncloop;
+pub mod builder;
+pub mod config;
+#[cfg(feature = "cuda")]
+pub mod cuda;
 pub mod data_structures;
+pub mod dedup;
+pub mod doorkeeper;
+#[cfg(feature = "dpdk")]
+pub mod dpdk;
+pub mod epoch;
+pub mod error;
+pub mod events;
+pub mod eviction;
+pub mod ewma;
+pub mod group;
+pub mod handle;
+pub mod history;
+#[cfg(feature = "mlock")]
+pub mod hugepage;
+#[cfg(feature = "ibverbs")]
+pub mod ibverbs;
+pub mod interval;
+#[cfg(feature = "io-uring")]
+pub mod iouring;
+pub mod kv;
+pub mod metrics;
+pub mod mfu;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+pub mod multidevice;
+#[cfg(feature = "mlock")]
+pub mod mlock;
+pub mod oracle;
 pub mod pagesizes;
-pub mod zerocopylru;
+pub mod pipeline;
+pub mod policy;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod rangeindex;
+pub mod recencyfrequency;
+pub mod recorder;
+pub mod segment_policy;
+pub mod sharded;
+#[cfg(feature = "sigusr1-dump")]
+pub mod sigdump;
+#[cfg(feature = "shm-stats")]
+pub mod shmstats;
+pub mod sketch;
+pub mod snapshot;
+pub mod spacesaving;
+pub mod trace;
+pub mod trigger;
+pub mod window;
+pub mod worker;
+#[cfg(feature = "xdp")]
+pub mod xdp;
 
 #[cfg(test)]
 mod test {
 
-    use crate::data_structures::Segment;
+    use crate::data_structures::DatapathSlab;
+    use crate::data_structures::DecayPolicy;
+    use crate::data_structures::Priority;
+    use crate::data_structures::QuiesceTimeoutAction;
     use crate::data_structures::SegmentId;
     use crate::data_structures::ZeroCopyCache;
+    use crate::ewma::EwmaCache;
+    use crate::group::{divide_budget, ZccGroup};
+    use crate::history::SegmentHistory;
+    use crate::metrics::{MetricsSink, MissReason};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use crate::mfu::MfuCache;
+    use crate::oracle::OracleCache;
+    use crate::pagesizes;
+    use crate::recencyfrequency::RecencyFrequencyCache;
+    use crate::segment_policy::SegmentPolicy;
+    use crate::sketch::SketchCache;
+    use crate::spacesaving::{SpaceSavingCache, SpaceSavingSummary};
+    use crate::window::{Window, WindowCache};
     use rand::Rng;
+    use std::alloc::{alloc_zeroed, dealloc, Layout};
+    use std::time::{Duration, Instant, SystemTime};
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
-    pub struct ExampleSegment {
-        segment_id: SegmentId,
-        page_size: usize,
+    #[derive(Debug)]
+    pub struct ExampleSlab {
+        slab_id: usize,
+        num_pages: usize,
+        start_address: *mut u8,
     }
 
-    impl ExampleSegment {
-        pub fn new(segment_id: SegmentId, page_size: usize) -> Self {
-            ExampleSegment {
-                segment_id: segment_id,
-                page_size: page_size,
+    impl ExampleSlab {
+        pub fn new(slab_id: usize, num_pages: usize) -> Self {
+            let start_address = unsafe { alloc_zeroed(Self::layout(num_pages)) };
+            ExampleSlab {
+                slab_id,
+                num_pages,
+                start_address,
             }
         }
+
+        fn layout(num_pages: usize) -> Layout {
+            Layout::from_size_align(num_pages * pagesizes::PGSIZE_4KB, pagesizes::PGSIZE_4KB)
+                .unwrap()
+        }
+
+        pub fn buf(&self, page: usize, len: usize) -> &[u8] {
+            unsafe {
+                std::slice::from_raw_parts(
+                    self.start_address.add(page * pagesizes::PGSIZE_4KB),
+                    len,
+                )
+            }
+        }
+    }
+
+    impl Drop for ExampleSlab {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.start_address, Self::layout(self.num_pages)) };
+        }
     }
 
-    impl Segment for ExampleSegment {
-        fn get_page_size(&self) -> u64 {
-            self.page_size as _
+    impl DatapathSlab for ExampleSlab {
+        type SlabId = usize;
+        type IOInfo = usize;
+        type PinningState = bool;
+        type PrivateInfo = ();
+
+        fn default_pinning_state(&self) -> Self::PinningState {
+            false
+        }
+
+        fn get_slab_id(&self) -> Self::SlabId {
+            self.slab_id
+        }
+
+        fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+            *pinning_state
+        }
+
+        fn pin_segment(
+            pinning_state: &mut Self::PinningState,
+            _private_info: &Self::PrivateInfo,
+            _start_address: *mut ::std::os::raw::c_void,
+            _len: usize,
+        ) {
+            *pinning_state = true;
+        }
+
+        fn unpin_segment(pinning_state: &mut Self::PinningState) {
+            *pinning_state = false;
+        }
+
+        fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+            *pinning_state as usize
+        }
+
+        fn get_total_num_pages(&self) -> usize {
+            self.num_pages
+        }
+
+        fn get_start_address(&self) -> *mut ::std::os::raw::c_void {
+            self.start_address as _
         }
 
-        fn get_segment_id(&self) -> i64 {
-            self.segment_id as _
+        fn get_page_size(&self) -> pagesizes::PageSize {
+            pagesizes::PageSize::PG4KB
         }
     }
 
     #[test]
     pub fn test_zcc_segment_insert() {
-        let mut zero_copy_cache = ZeroCopyCache::new();
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
         let new_segments = create_segments(5);
         let access_list = create_random_array(5, 50);
         for val in &access_list {
-            zero_copy_cache.update_stats(&new_segments[*val]);
+            zero_copy_cache.update_stats(new_segments[*val]);
         }
 
         let value_count = access_list.clone().iter().filter(|&n| *n == 3).count() as i64;
         assert_eq!(
-            value_count,
+            if value_count == 0 { None } else { Some(value_count) },
             zero_copy_cache.get_segment_access_count(new_segments[3])
         );
     }
 
+    #[test]
+    pub fn test_zcc_hotset_v0_returns_accessed_segments() {
+        let slab = ExampleSlab::new(1, 8);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(4, 64));
+
+        assert_eq!(zero_copy_cache.segment_stats().get(&(1, 0)), Some(&2));
+        let hotset: HashSet<_> = [(1, 0), (1, 2)].into_iter().collect();
+        assert_eq!(zero_copy_cache.calculate_hotset_v0(), hotset);
+        assert_eq!(zero_copy_cache.return_all_segments_sized(), hotset);
+    }
+
+    #[test]
+    pub fn test_pin_cost_recorded_on_register() {
+        let slab = ExampleSlab::new(1, 8);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        zero_copy_cache.initialize_slab(&slab, 4, true, ());
+
+        let segment_id = zero_copy_cache.get_segment_id(slab.buf(2, 64)).unwrap();
+        assert_eq!(segment_id, (1, 1));
+        let pin_cost = zero_copy_cache.get_segment_pin_cost(segment_id).unwrap();
+        assert_eq!(pin_cost.pin_count, 1);
+        assert_eq!(pin_cost.unpin_count, 0);
+    }
+
+    #[test]
+    pub fn test_oracle_belady_bypasses_far_segments() {
+        let trace = vec![(1, 0), (1, 1), (1, 2), (1, 0), (1, 1)];
+        assert_eq!(OracleCache::<ExampleSlab>::belady_hit_count(&trace, 2), 2);
+
+        let mut oracle = OracleCache::<ExampleSlab>::new(2, trace);
+        oracle.update_access((1, 0));
+        oracle.update_access((1, 1));
+        let pinned = oracle.return_top_segments_to_pin();
+        assert!(pinned.contains(&(1, 2)) && pinned.contains(&(1, 0)));
+    }
+
+    #[test]
+    pub fn test_ewma_prefers_recent_accesses() {
+        let mut ewma = EwmaCache::<ExampleSlab>::new(1, Duration::from_secs(1));
+        let start = Instant::now();
+        for _ in 0..4 {
+            ewma.update_access_at((1, 0), start);
+        }
+        let later = start + Duration::from_secs(3);
+        ewma.update_access_at((1, 1), later);
+        ewma.update_access_at((1, 1), later);
+
+        assert!((ewma.get_score_at((1, 0), later).unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(ewma.get_access_count((1, 0)), Some(4));
+        assert!(ewma.return_top_segments_to_pin_at(later).contains(&(1, 1)));
+    }
+
+    #[test]
+    pub fn test_sketch_tracks_heavy_hitters() {
+        let mut sketch = SketchCache::<ExampleSlab>::new(2, 64, 4);
+        let segments = create_segments(10);
+        for (i, segment) in segments.iter().enumerate() {
+            let accesses = if i < 2 { 20 } else { 1 };
+            for _ in 0..accesses {
+                sketch.update_access(*segment);
+            }
+        }
+        let pinned = sketch.return_top_segments_to_pin();
+        assert_eq!(pinned.len(), 2);
+        assert!(pinned.contains(&segments[0]) && pinned.contains(&segments[1]));
+        assert!(sketch.get_access_count(segments[0]).unwrap() >= 20);
+    }
+
+    #[test]
+    pub fn test_sketch_shrink_keeps_hottest_candidates() {
+        let mut sketch = SketchCache::<ExampleSlab>::new(4, 64, 4);
+        let segments = create_segments(4);
+        for (i, segment) in segments.iter().enumerate() {
+            for _ in 0..(i + 1) * 5 {
+                sketch.update_access(*segment);
+            }
+        }
+        sketch.set_num_segments(2);
+        let pinned = sketch.return_top_segments_to_pin();
+        assert_eq!(pinned, segments[2..].iter().copied().collect());
+
+        sketch.decay(DecayPolicy::SubtractMin);
+        let pinned = sketch.return_top_segments_to_pin();
+        assert_eq!(pinned, segments[3..].iter().copied().collect());
+    }
+
+    #[test]
+    pub fn test_space_saving_replaces_min_counter() {
+        let mut summary = SpaceSavingSummary::new(3);
+        for item in ['a', 'a', 'a', 'b', 'b', 'c'] {
+            assert_eq!(summary.increment(item), None);
+        }
+        assert_eq!(summary.increment('d'), Some('c'));
+        assert_eq!(summary.count(&'d'), Some(2));
+        assert_eq!(summary.guaranteed_count(&'d'), Some(1));
+        assert_eq!(summary.top_k(1), vec![('a', 3)]);
+        assert_eq!(summary.len(), 3);
+    }
+
+    #[test]
+    pub fn test_space_saving_grows_with_hotset_size() {
+        let mut cache = SpaceSavingCache::<ExampleSlab>::new(2, 3);
+        cache.set_num_segments(4);
+        assert_eq!(cache.get_summary().capacity(), 5);
+        let segments = create_segments(5);
+        for segment in segments.iter() {
+            cache.update_access(*segment);
+        }
+        assert_eq!(cache.return_top_segments_to_pin().len(), 4);
+        // Shrinking leaves the summary as it is.
+        cache.set_num_segments(1);
+        assert_eq!(cache.get_summary().capacity(), 5);
+        assert_eq!(cache.return_top_segments_to_pin().len(), 1);
+    }
+
+    #[test]
+    pub fn test_window_expires_old_accesses() {
+        let mut window =
+            WindowCache::<ExampleSlab>::new(1, Window::Time(Duration::from_secs(4)), 4);
+        let start = Instant::now();
+        for _ in 0..5 {
+            window.update_access_at((1, 0), start);
+        }
+        window.update_access_at((1, 1), start + Duration::from_secs(3));
+        assert_eq!(window.get_access_count((1, 0)), Some(5));
+
+        let pinned = window.return_top_segments_to_pin_at(start + Duration::from_millis(4500));
+        assert_eq!(window.get_access_count((1, 0)), None);
+        assert!(pinned.contains(&(1, 1)));
+
+        let mut window = WindowCache::<ExampleSlab>::new(1, Window::Accesses(4), 2);
+        for segment in [(1, 0), (1, 0), (1, 1), (1, 1), (1, 1), (1, 2)] {
+            window.update_access(segment);
+        }
+        assert_eq!(window.get_access_count((1, 0)), None);
+        assert_eq!(window.get_access_count((1, 1)), Some(3));
+    }
+
+    #[test]
+    pub fn test_mfu_decay_policies() {
+        let mut mfu = MfuCache::<ExampleSlab>::default();
+        for (segment, accesses) in [((1, 0), 6), ((1, 1), 3), ((1, 2), 1)] {
+            for _ in 0..accesses {
+                mfu.update_access(segment);
+            }
+        }
+        mfu.decay(DecayPolicy::Halving);
+        assert_eq!(mfu.get_access_count((1, 0)), Some(3));
+        assert_eq!(mfu.get_access_count((1, 2)), None);
+
+        mfu.decay(DecayPolicy::SubtractMin);
+        assert_eq!(mfu.get_access_count((1, 0)), Some(2));
+        assert_eq!(mfu.get_access_count((1, 1)), None);
+
+        let mut zero_copy_cache = ZeroCopyCache::new(mfu);
+        zero_copy_cache.set_decay_schedule(DecayPolicy::FullReset, Duration::ZERO);
+        assert!(zero_copy_cache.apply_scheduled_decay());
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 0)), None);
+    }
+
+    #[test]
+    pub fn test_segment_history_buckets() {
+        let mut history = SegmentHistory::new(Duration::from_secs(1), 2);
+        let start = Instant::now();
+        history.record_access_at((1, 0), start);
+        history.record_access_at((1, 0), start + Duration::from_millis(1500));
+        history.record_access_at((1, 0), start + Duration::from_millis(1700));
+        let buckets = history.segment_history((1, 0)).unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[1].access_count, 2);
+
+        history.record_access_at((1, 0), start + Duration::from_secs(3));
+        let buckets = history.segment_history((1, 0)).unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].start, Duration::from_secs(3));
+
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        assert_eq!(zero_copy_cache.segment_history((1, 0)), None);
+        zero_copy_cache.enable_segment_history(Duration::from_secs(60), 10);
+        zero_copy_cache.update_stats((1, 0));
+        assert_eq!(zero_copy_cache.segment_history((1, 0)).unwrap()[0].access_count, 1);
+    }
+
+    #[test]
+    pub fn test_update_pinned_list_reports_churn() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        for segment in [(1, 0), (1, 0), (1, 1), (1, 1)] {
+            zero_copy_cache.update_stats(segment);
+        }
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_pinned, churn.num_unpinned), (2, 0));
+        assert_eq!(churn.jaccard_similarity, 0.0);
+
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((1, 2));
+        }
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_pinned, churn.num_unpinned), (1, 1));
+        assert!((churn.jaccard_similarity - 1.0 / 3.0).abs() < 1e-9);
+
+        let stats = zero_copy_cache.get_churn_stats();
+        assert_eq!((stats.cycles, stats.total_pinned, stats.total_unpinned), (2, 3, 1));
+
+        zero_copy_cache.set_pinning_time_series_len(1);
+        zero_copy_cache.update_pinned_list(&());
+        let samples = zero_copy_cache.get_pinning_time_series();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].segments_pinned, 2);
+        assert_eq!(samples[0].bytes_pinned, 2 * pagesizes::PGSIZE_4KB);
+    }
+
+    #[test]
+    pub fn test_recency_frequency_alpha_knob() {
+        let mut cache = RecencyFrequencyCache::<ExampleSlab>::new(1, 1.0);
+        let start = SystemTime::now();
+        for _ in 0..5 {
+            cache.update_access_at((1, 0), start);
+        }
+        cache.update_access_at((1, 1), start + Duration::from_secs(1));
+
+        assert!(cache.return_top_segments_to_pin().contains(&(1, 0)));
+        cache.set_alpha(0.0);
+        assert!(cache.return_top_segments_to_pin().contains(&(1, 1)));
+        cache.set_alpha(7.0);
+        assert_eq!(cache.get_alpha(), 1.0);
+    }
+
+    #[test]
+    pub fn test_hotset_snapshot() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+
+        let snapshot = zero_copy_cache.hotset_snapshot();
+        assert_eq!(snapshot.pinned, vec![(1, 1)]);
+        assert_eq!(snapshot.segments.len(), 2);
+        let segment = snapshot.segments.iter().find(|s| s.segment_id == (1, 1)).unwrap();
+        assert_eq!(segment.access_count, Some(1));
+        assert!(segment.last_access_time.is_some());
+    }
+
+    #[test]
+    pub fn test_metrics_count_hits_misses_and_pins() {
+        let slab = ExampleSlab::new(1, 4);
+        let other = ExampleSlab::new(2, 1);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+
+        assert_eq!(
+            zero_copy_cache.record_access_and_get_io_info(slab.buf(0, 64)),
+            Err(MissReason::NotPinned)
+        );
+        assert_eq!(
+            zero_copy_cache.record_access_and_get_io_info(other.buf(0, 64)),
+            Err(MissReason::Unmanaged)
+        );
+        zero_copy_cache.update_pinned_list(&());
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64))
+            .is_some());
+
+        let metrics = zero_copy_cache.get_metrics();
+        assert_eq!((metrics.hits, metrics.misses, metrics.unmanaged), (1, 1, 1));
+        assert_eq!(metrics.misses_not_pinned, 1);
+        assert_eq!((metrics.pins, metrics.unpins, metrics.evictions), (1, 0, 0));
+        assert_eq!(metrics.hit_rate(), 0.5);
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 2 * pagesizes::PGSIZE_4KB);
+        let slab_stats = zero_copy_cache.slab_stats()[&1];
+        assert_eq!(slab_stats.num_segments, 2);
+        assert_eq!(slab_stats.pinned_bytes, 2 * pagesizes::PGSIZE_4KB);
+        assert_eq!(slab_stats.metrics.accesses(), 2);
+        assert_eq!(slab_stats.metrics.hits, 1);
+    }
+
+    #[test]
+    pub fn test_metrics_break_down_per_slab() {
+        let first = ExampleSlab::new(1, 2);
+        let second = ExampleSlab::new(2, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&first, 1, false, ());
+        zero_copy_cache.initialize_slab(&second, 1, false, ());
+
+        assert!(zero_copy_cache.record_access_and_get_io_info(first.buf(0, 64)).is_err());
+        assert!(zero_copy_cache.record_access_and_get_io_info(second.buf(0, 64)).is_err());
+        assert!(zero_copy_cache.record_access_and_get_io_info(second.buf(1, 64)).is_err());
+        zero_copy_cache.update_pinned_list(&());
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned(first.buf(0, 64))
+            .is_some());
+
+        let stats = zero_copy_cache.slab_stats();
+        let (first_metrics, second_metrics) = (stats[&1].metrics, stats[&2].metrics);
+        assert_eq!((first_metrics.hits, first_metrics.misses), (1, 1));
+        assert_eq!((second_metrics.hits, second_metrics.misses), (0, 2));
+        assert_eq!((first_metrics.pins, second_metrics.pins), (1, 1));
+        let metrics = zero_copy_cache.get_metrics();
+        assert_eq!(metrics.hits, first_metrics.hits + second_metrics.hits);
+        assert_eq!(metrics.misses, first_metrics.misses + second_metrics.misses);
+        assert_eq!(metrics.pins, first_metrics.pins + second_metrics.pins);
+
+        zero_copy_cache.reset_metrics();
+        let stats = zero_copy_cache.slab_stats();
+        assert_eq!(stats[&1].metrics.accesses(), 0);
+        assert_eq!(stats[&2].metrics.accesses(), 0);
+    }
+
+    #[derive(Debug, Default)]
+    pub struct CountingSink {
+        pins: AtomicUsize,
+        misses: AtomicUsize,
+        cycles: AtomicUsize,
+    }
+
+    impl MetricsSink<SegmentId<ExampleSlab>> for CountingSink {
+        fn on_pin(&self, _segment_id: SegmentId<ExampleSlab>, _bytes: usize) {
+            self.pins.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_miss(&self, _segment_id: Option<SegmentId<ExampleSlab>>, _reason: MissReason) {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_cycle(&self, _churn: &crate::data_structures::HotsetChurn) {
+            self.cycles.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    pub fn test_metrics_sink_receives_events() {
+        let slab = ExampleSlab::new(1, 2);
+        let sink = Arc::new(CountingSink::default());
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        zero_copy_cache.set_metrics_sink(sink.clone());
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        zero_copy_cache.update_pinned_list(&());
+
+        assert_eq!(sink.misses.load(Ordering::Relaxed), 1);
+        assert_eq!(sink.pins.load(Ordering::Relaxed), 1);
+        assert_eq!(sink.cycles.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(feature = "shm-stats")]
+    #[test]
+    pub fn test_shm_stats_region_published_each_cycle() {
+        use crate::shmstats::{SharedStatsRegion, SHARED_STATS_MAGIC, SHARED_STATS_VERSION};
+
+        let slab = ExampleSlab::new(1, 2);
+        let region = Arc::new(SharedStatsRegion::create_memfd("zcc_stats_test").unwrap());
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.set_shm_stats_region(region.clone());
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        zero_copy_cache.update_pinned_list(&());
+
+        let stats = region.get_stats();
+        assert_eq!(stats.magic, SHARED_STATS_MAGIC);
+        assert_eq!(stats.version, SHARED_STATS_VERSION);
+        assert_eq!(stats.sequence.load(Ordering::Acquire), 4);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.pins, 1);
+        assert_eq!(stats.segments_pinned, 1);
+        assert_eq!(stats.bytes_pinned, 4096);
+    }
+
+    #[cfg(feature = "sigusr1-dump")]
+    #[test]
+    pub fn test_sigusr1_dumps_stats_to_file() {
+        use crate::sigdump::{spawn_sigusr1_dumper, DumpTarget};
+
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        let path = std::env::temp_dir().join(format!("zcc_dump_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        spawn_sigusr1_dumper(zero_copy_cache, DumpTarget::File(path.clone())).unwrap();
+        unsafe {
+            libc::raise(libc::SIGUSR1);
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut dump = String::new();
+        while Instant::now() < deadline && !dump.contains("bytes pinned") {
+            std::thread::sleep(Duration::from_millis(10));
+            dump = std::fs::read_to_string(&path).unwrap_or_default();
+        }
+        let _ = std::fs::remove_file(&path);
+        assert!(dump.contains("pinned: 2 segments, 8192 bytes"), "{}", dump);
+    }
+
+    #[test]
+    pub fn test_group_divides_budget_by_core_load() {
+        assert_eq!(divide_budget(5, &[0, 0]), vec![3, 2]);
+        assert_eq!(divide_budget(10, &[1, 1, 1]).iter().sum::<usize>(), 10);
+
+        let slabs = [ExampleSlab::new(0, 4), ExampleSlab::new(1, 4)];
+        let mut group = ZccGroup::new(2, 4, |_| MfuCache::<ExampleSlab>::default());
+        assert_eq!(group.core_budgets(), vec![2, 2]);
+        for (core, slab) in slabs.iter().enumerate() {
+            let zcc = group.get_core_mut(core).unwrap();
+            zcc.initialize_slab(slab, 4, false, ());
+        }
+        for _ in 0..3 {
+            group
+                .get_core_mut(0)
+                .unwrap()
+                .record_access_and_get_io_info_if_pinned_manual(slabs[0].buf(0, 64));
+        }
+        group
+            .get_core_mut(1)
+            .unwrap()
+            .record_access_and_get_io_info_if_pinned_manual(slabs[1].buf(0, 64));
+
+        assert_eq!(group.rebalance(), vec![3, 1]);
+        assert_eq!(group.core_budgets(), vec![3, 1]);
+        assert_eq!(group.aggregate_metrics().misses, 4);
+        // No new accesses since the last rebalance: split evenly again.
+        assert_eq!(group.rebalance(), vec![2, 2]);
+    }
+
+    #[test]
+    pub fn test_group_coordinator_shares_state_and_stops() {
+        let slab = ExampleSlab::new(0, 4);
+        let mut group = ZccGroup::new(2, 4, |_| MfuCache::<ExampleSlab>::default());
+        let zcc = group.get_core_mut(0).unwrap();
+        zcc.initialize_slab(&slab, 4, false, ());
+        zcc.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+
+        let coordinator = group.spawn_coordinator(Duration::from_millis(5));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while coordinator.rebalances() == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        coordinator.shutdown();
+        // The idle core keeps half an even share.
+        assert_eq!(group.core_budgets(), vec![3, 1]);
+        // The coordinator's rebalance already saw that access.
+        assert_eq!(group.rebalance(), vec![2, 2]);
+    }
+
+    #[test]
+    pub fn test_sharded_builder_merges_hotset() {
+        let slab = ExampleSlab::new(1, 8);
+        let mut zero_copy_cache = ZeroCopyCache::new_sharded(4, |_| MfuCache::<ExampleSlab>::new(2));
+        assert_eq!(zero_copy_cache.cache_builder.num_shards(), 4);
+        zero_copy_cache.initialize_slab(&slab, 8, false, ());
+        for (page, accesses) in [(0, 1), (3, 5), (5, 2), (7, 4)] {
+            for _ in 0..accesses {
+                zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(page, 64));
+            }
+        }
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 3)), Some(5));
+
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(
+            zero_copy_cache.current_pinned_list,
+            [(1, 3), (1, 7)].into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    pub fn test_atomic_access_counts_drained_each_cycle() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        zero_copy_cache.enable_atomic_access_counts(2);
+        for _ in 0..3 {
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+        }
+        // Segment 3 is past the atomics' capacity and goes straight to the builder.
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(3, 64));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), None);
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 1)), Some(3));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 3)), Some(1));
+
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), Some(3));
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 1)));
+    }
+
+    #[test]
+    pub fn test_in_flight_io_counted_without_segment_lock() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+            .is_some());
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+            .is_some());
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(2));
+
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(1));
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(0));
+    }
+
+    #[test]
+    pub fn test_pin_worker_pause_trigger_and_shutdown() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+
+        let worker = zero_copy_cache.spawn_pin_worker((), Duration::from_secs(3600));
+        worker.pause();
+        assert!(worker.is_paused());
+        worker.trigger_now();
+        assert!(worker.wait_for_cycles(1, Duration::from_secs(5)));
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 1);
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64))
+            .is_some());
+        zero_copy_cache.record_io_completion(slab.buf(1, 64));
+
+        worker.shutdown();
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 0);
+    }
+
+    #[test]
+    pub fn test_sharded_repin_spreads_pins_over_threads() {
+        let slab = ExampleSlab::new(1, 8);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(4));
+        zero_copy_cache.initialize_slab(&slab, 8, false, ());
+        for page in 0..4 {
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(page, 64));
+        }
+        let churn = zero_copy_cache.update_pinned_list_sharded(&(), 3);
+        assert_eq!(churn.num_pinned, 4);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 4);
+
+        for page in 4..8 {
+            for _ in 0..3 {
+                zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(page, 64));
+            }
+        }
+        let churn = zero_copy_cache.update_pinned_list_sharded(&(), 3);
+        assert_eq!((churn.num_unpinned, churn.num_pinned), (4, 4));
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 4);
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(5, 64))
+            .is_some());
+        zero_copy_cache.record_io_completion(slab.buf(5, 64));
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_hysteresis_damps_boundary_flapping() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.set_hysteresis(0.5, 2);
+        for _ in 0..4 {
+            zero_copy_cache.update_stats((1, 0));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        let pinned_0: HashSet<SegmentId<ExampleSlab>> = [(1, 0)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, pinned_0);
+
+        // Hotter, but segment 0 was pinned only one cycle ago.
+        for _ in 0..10 {
+            zero_copy_cache.update_stats((1, 1));
+        }
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!(churn.num_pinned, 0);
+        assert_eq!(zero_copy_cache.current_pinned_list, pinned_0);
+
+        // Old enough now, but 10 accesses don't beat 8 by 50%; 13 do.
+        for _ in 0..4 {
+            zero_copy_cache.update_stats((1, 0));
+        }
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!(churn.num_pinned, 0);
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((1, 1));
+        }
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_unpinned, churn.num_pinned), (1, 1));
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 1)));
+    }
+
+    #[test]
+    pub fn test_max_changes_per_cycle_defers_remaining_changes() {
+        let slab = ExampleSlab::new(1, 8);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(4));
+        zero_copy_cache.initialize_slab(&slab, 8, false, ());
+        zero_copy_cache.set_max_changes_per_cycle(3);
+        for index in 0..4 {
+            zero_copy_cache.update_stats((1, index));
+        }
+        assert_eq!(zero_copy_cache.update_pinned_list(&()).num_pinned, 3);
+        assert_eq!(zero_copy_cache.update_pinned_list(&()).num_pinned, 1);
+
+        for index in 4..8 {
+            for _ in 0..3 {
+                zero_copy_cache.update_stats((1, index));
+            }
+        }
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_unpinned, churn.num_pinned), (2, 1));
+        assert!(zero_copy_cache.current_segments_pinned() <= 4);
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_unpinned, churn.num_pinned), (1, 2));
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_unpinned, churn.num_pinned), (1, 1));
+        let hotset: HashSet<SegmentId<ExampleSlab>> = (4..8).map(|index| (1, index)).collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, hotset);
+    }
+
+    #[test]
+    pub fn test_min_pin_duration_delays_unpin() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.set_min_pin_duration(Duration::from_millis(50));
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_pinned_list(&());
+        for _ in 0..5 {
+            zero_copy_cache.update_stats((1, 1));
+        }
+        assert_eq!(zero_copy_cache.update_pinned_list(&()).num_pinned, 0);
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 0)));
+
+        std::thread::sleep(Duration::from_millis(60));
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_unpinned, churn.num_pinned), (1, 1));
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 1)));
+    }
+
+    #[test]
+    pub fn test_pin_hints_rank_and_keep_segments() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        for _ in 0..5 {
+            zero_copy_cache.update_stats((1, 0));
+            zero_copy_cache.update_stats((1, 1));
+        }
+        zero_copy_cache.update_stats((1, 2));
+        assert_eq!(zero_copy_cache.pin_forever(slab.buf(3, 64)), Some((1, 3)));
+        zero_copy_cache.pin_hint((1, 2), Priority::High);
+        zero_copy_cache.update_pinned_list(&());
+        let expected: HashSet<SegmentId<ExampleSlab>> = [(1, 2), (1, 3)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, expected);
+
+        // Forever segments stay pinned even when the limit shrinks below them.
+        zero_copy_cache.pin_hint((1, 2), Priority::Normal);
+        zero_copy_cache.cache_builder.set_num_segments(0);
+        zero_copy_cache.update_pinned_list(&());
+        let expected: HashSet<SegmentId<ExampleSlab>> = [(1, 3)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, expected);
+        assert_eq!(zero_copy_cache.get_pin_hint((1, 3)), Priority::Forever);
+    }
+
+    #[test]
+    pub fn test_pinning_limit_converges_at_runtime() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(4));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        for index in 0..4 {
+            for _ in 0..=index {
+                zero_copy_cache.update_stats((1, index));
+            }
+        }
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 4 * pagesizes::PGSIZE_4KB);
+
+        let other = zero_copy_cache.clone();
+        other.set_pinning_limit(2 * pagesizes::PGSIZE_4KB + 1);
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!(churn.num_unpinned, 2);
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 2 * pagesizes::PGSIZE_4KB);
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 3)));
+
+        zero_copy_cache.clear_pinning_limit();
+        assert_eq!(zero_copy_cache.update_pinned_list(&()).num_pinned, 2);
+    }
+
+    #[test]
+    pub fn test_segment_size_per_slab_and_size_aware_limit() {
+        let small = ExampleSlab::new(1, 4);
+        let large = ExampleSlab::new(2, 10);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(8));
+        zero_copy_cache.initialize_slab_with_segment_size(&small, pagesizes::PGSIZE_4KB, false, ());
+        zero_copy_cache
+            .initialize_slab_with_segment_size(&large, 4 * pagesizes::PGSIZE_4KB, false, ());
+        assert_eq!(zero_copy_cache.get_segment_id(small.buf(3, 64)), Some((1, 3)));
+        assert_eq!(zero_copy_cache.get_segment_id(large.buf(3, 64)), Some((2, 0)));
+        // The last segment of the large slab only gets the two leftover pages.
+        assert_eq!(zero_copy_cache.get_segment_id(large.buf(9, 64)), Some((2, 2)));
+        assert_eq!(zero_copy_cache.slab_stats()[&2].total_bytes, 10 * pagesizes::PGSIZE_4KB);
+
+        // The large segment has more accesses but fewer per byte.
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((2, 0));
+        }
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.set_pinning_limit(4 * pagesizes::PGSIZE_4KB);
+        zero_copy_cache.update_pinned_list(&());
+        let expected: HashSet<SegmentId<ExampleSlab>> = [(1, 0), (1, 1)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, expected);
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 2 * pagesizes::PGSIZE_4KB);
+    }
+
+    #[test]
+    pub fn test_on_demand_pinning_defers_unpin_to_eviction_worker() {
+        let slab = ExampleSlab::new(1, 3);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(3));
+        zero_copy_cache.initialize_slab(&slab, 3, false, ());
+        zero_copy_cache.set_pinning_limit(2 * pagesizes::PGSIZE_4KB);
+        let worker = zero_copy_cache.spawn_eviction_worker();
+
+        // Segment 0 keeps one IO in flight; segment 1 is hotter.
+        assert!(zero_copy_cache.record_and_pin_on_demand(slab.buf(0, 64), &()).is_ok());
+        for _ in 0..2 {
+            assert!(zero_copy_cache.record_and_pin_on_demand(slab.buf(1, 64), &()).is_ok());
+            zero_copy_cache.record_io_completion(slab.buf(1, 64));
+        }
+        assert!(zero_copy_cache.record_and_pin_on_demand(slab.buf(2, 64), &()).is_ok());
+        zero_copy_cache.record_io_completion(slab.buf(2, 64));
+        assert_eq!(
+            zero_copy_cache.record_access_and_get_io_info(slab.buf(0, 64)),
+            Err(MissReason::Quiescing)
+        );
+        assert!(!worker.queue().wait_until_idle(Duration::from_millis(20)));
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 3 * pagesizes::PGSIZE_4KB);
+
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert!(worker.queue().wait_until_idle(Duration::from_secs(5)));
+        assert_eq!(worker.queue().evicted(), 1);
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 2 * pagesizes::PGSIZE_4KB);
+        assert_eq!(
+            zero_copy_cache.record_access_and_get_io_info(slab.buf(0, 64)),
+            Err(MissReason::NotPinned)
+        );
+        worker.shutdown();
+    }
+
+    #[test]
+    pub fn test_doorkeeper_admits_on_second_touch() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.enable_doorkeeper(Duration::from_millis(30));
+        let pin = |zcc: &mut ZeroCopyCache<ExampleSlab, MfuCache<ExampleSlab>>, page| {
+            let result = zcc.record_and_pin_on_demand(slab.buf(page, 64), &());
+            if result.is_ok() {
+                zcc.record_io_completion(slab.buf(page, 64));
+            }
+            result.is_ok()
+        };
+        assert!(!pin(&mut zero_copy_cache, 0));
+        assert!(pin(&mut zero_copy_cache, 0));
+
+        // The window expires between the two touches of segment 1.
+        assert!(!pin(&mut zero_copy_cache, 1));
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(!pin(&mut zero_copy_cache, 1));
+        assert!(pin(&mut zero_copy_cache, 1));
+    }
+
+    #[test]
+    pub fn test_budget_split_reserves_room_for_on_demand_pins() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(4));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        zero_copy_cache.set_pinning_limit(4 * pagesizes::PGSIZE_4KB);
+        zero_copy_cache.set_budget_split(0.5);
+        for index in 0..4 {
+            for _ in 0..=index {
+                zero_copy_cache.update_stats((1, index));
+            }
+        }
+        zero_copy_cache.update_pinned_list(&());
+        let policy: HashSet<SegmentId<ExampleSlab>> = [(1, 2), (1, 3)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, policy);
+
+        // A cold segment still gets pinned right away out of the reserve.
+        assert!(zero_copy_cache.record_and_pin_on_demand(slab.buf(0, 64), &()).is_ok());
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 3 * pagesizes::PGSIZE_4KB);
+        assert!(zero_copy_cache.on_demand_pinned_segments().contains(&(1, 0)));
+
+        // Once the policy ranks it in its share, it takes the segment over without repinning.
+        for _ in 0..10 {
+            zero_copy_cache.update_stats((1, 0));
+        }
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_unpinned, churn.num_pinned), (1, 0));
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 0)));
+        assert!(zero_copy_cache.on_demand_pinned_segments().is_empty());
+    }
+
+    #[derive(Debug, Default)]
+    pub struct OrderSink {
+        events: std::sync::Mutex<Vec<(&'static str, SegmentId<ExampleSlab>)>>,
+    }
+
+    impl MetricsSink<SegmentId<ExampleSlab>> for OrderSink {
+        fn on_pin(&self, segment_id: SegmentId<ExampleSlab>, _bytes: usize) {
+            self.events.lock().unwrap().push(("pin", segment_id));
+        }
+
+        fn on_unpin(&self, segment_id: SegmentId<ExampleSlab>, _bytes: usize) {
+            self.events.lock().unwrap().push(("unpin", segment_id));
+        }
+    }
+
+    #[test]
+    pub fn test_overshoot_pins_before_unpinning() {
+        let slab = ExampleSlab::new(1, 3);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 3, false, ());
+        let sink = Arc::new(OrderSink::default());
+        zero_copy_cache.set_metrics_sink(sink.clone());
+        zero_copy_cache.set_pinning_limit(2 * pagesizes::PGSIZE_4KB);
+        zero_copy_cache.set_max_overshoot_bytes(pagesizes::PGSIZE_4KB);
+        zero_copy_cache.update_stats((1, 0));
+        for _ in 0..2 {
+            zero_copy_cache.update_stats((1, 1));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        sink.events.lock().unwrap().clear();
+
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((1, 2));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(*sink.events.lock().unwrap(), vec![("pin", (1, 2)), ("unpin", (1, 0))]);
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 2 * pagesizes::PGSIZE_4KB);
+
+        // Without any slack the unpin has to come first.
+        zero_copy_cache.set_max_overshoot_bytes(0);
+        sink.events.lock().unwrap().clear();
+        for _ in 0..5 {
+            zero_copy_cache.update_stats((1, 0));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(*sink.events.lock().unwrap(), vec![("unpin", (1, 1)), ("pin", (1, 0))]);
+    }
+
+    #[test]
+    pub fn test_adaptive_repin_interval_follows_churn() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        zero_copy_cache
+            .set_adaptive_repin_interval(Duration::from_millis(10), Duration::from_millis(80));
+        let fixed = Duration::from_secs(1);
+        let next_interval = |zcc: &mut ZeroCopyCache<ExampleSlab, MfuCache<ExampleSlab>>| {
+            let churn = zcc.update_pinned_list(&());
+            zcc.next_repin_interval(&churn, fixed)
+        };
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_stats((1, 1));
+        assert_eq!(next_interval(&mut zero_copy_cache), Duration::from_millis(10));
+        assert_eq!(next_interval(&mut zero_copy_cache), Duration::from_millis(20));
+        assert_eq!(next_interval(&mut zero_copy_cache), Duration::from_millis(40));
+        assert_eq!(next_interval(&mut zero_copy_cache), Duration::from_millis(80));
+        assert_eq!(next_interval(&mut zero_copy_cache), Duration::from_millis(80));
+
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((1, 2));
+        }
+        assert_eq!(next_interval(&mut zero_copy_cache), Duration::from_millis(40));
+        zero_copy_cache.clear_adaptive_repin_interval();
+        assert_eq!(next_interval(&mut zero_copy_cache), fixed);
+    }
+
+    #[test]
+    pub fn test_miss_rate_trigger_wakes_pin_worker() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        let trigger =
+            zero_copy_cache.enable_miss_rate_trigger(4, 0.5, Duration::from_secs(3600));
+        let worker = zero_copy_cache.spawn_pin_worker((), Duration::from_secs(3600));
+        worker.pause();
+
+        for _ in 0..3 {
+            assert!(zero_copy_cache
+                .record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64))
+                .is_none());
+        }
+        assert_eq!(trigger.fired(), 0);
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+        assert_eq!(trigger.fired(), 1);
+        assert!(worker.wait_for_cycles(1, Duration::from_secs(5)));
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 1);
+
+        // Still missing, but within min_interval of the last repin.
+        for _ in 0..4 {
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        }
+        assert_eq!(trigger.fired(), 1);
+        worker.shutdown();
+    }
+
+    #[test]
+    pub fn test_pinning_limit_percent_follows_managed_memory() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(8));
+        zero_copy_cache.set_pinning_limit_percent(50.0);
+        assert_eq!(zero_copy_cache.get_pinning_limit(), Some(0));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        assert_eq!(zero_copy_cache.managed_bytes(), 4 * pagesizes::PGSIZE_4KB);
+        assert_eq!(zero_copy_cache.get_pinning_limit(), Some(2 * pagesizes::PGSIZE_4KB));
+
+        let other_slab = ExampleSlab::new(2, 4);
+        zero_copy_cache.initialize_slab(&other_slab, 4, false, ());
+        assert_eq!(zero_copy_cache.get_pinning_limit(), Some(4 * pagesizes::PGSIZE_4KB));
+        for index in 0..4 {
+            zero_copy_cache.update_stats((1, index));
+            zero_copy_cache.update_stats((2, index));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 4 * pagesizes::PGSIZE_4KB);
+
+        zero_copy_cache.set_pinning_limit(pagesizes::PGSIZE_4KB);
+        assert_eq!(zero_copy_cache.get_pinning_limit_percent(), None);
+        zero_copy_cache.unpin_all_segments();
+    }
+
+    #[cfg(feature = "mlock")]
+    #[test]
+    pub fn test_mlock_slab_locks_hot_segments() {
+        use crate::mlock::MlockSlab;
+
+        let mut slab = MlockSlab::new(1, 4, pagesizes::PageSize::PG4KB).unwrap();
+        slab.as_mut_slice()[0] = 1;
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<MlockSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        let hot = &slab.as_slice()[..64];
+        assert!(zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(hot).is_none());
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 1);
+        let slices = zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(hot).unwrap();
+        assert_eq!(slices[0].io_info, slab.as_slice().as_ptr() as usize);
+        zero_copy_cache.record_io_completion(hot);
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+    }
+
+    #[test]
+    pub fn test_failed_pin_mid_cycle_is_retried() {
+        use crate::mock::MockSlab;
+
+        let slab = MockSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<MockSlab>::new(3));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        for index in 0..3 {
+            zero_copy_cache.update_stats((1, index));
+        }
+        slab.fail_nth_pin(2);
+        slab.set_pin_latency(Duration::from_millis(1));
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!(slab.pin_calls(), 3);
+        assert_eq!(slab.failed_pins(), 1);
+        assert_eq!(churn.num_pinned, 2);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 2);
+
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!(churn.num_pinned, 1);
+        assert_eq!(slab.pin_calls(), 4);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 3);
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 3);
+        assert_eq!(slab.unpin_calls(), 3);
+    }
+
+    #[cfg(feature = "io-uring")]
+    #[test]
+    pub fn test_io_uring_slab_fills_buffer_slots() {
+        use crate::iouring::{IoUringBufferTable, IoUringSlab};
+
+        let mut params = [0u8; 120];
+        let ring_fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, 4, params.as_mut_ptr()) };
+        if ring_fd < 0 {
+            // io_uring is disabled in this environment.
+            return;
+        }
+        let ring_fd = ring_fd as i32;
+        let memory = ExampleSlab::new(0, 4);
+        let table = IoUringBufferTable::register_sparse(ring_fd, 1).unwrap();
+        let page_size = pagesizes::PageSize::PG4KB;
+        let slab =
+            unsafe { IoUringSlab::new(1, memory.start_address as _, 4, page_size, table.clone()) };
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<IoUringSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+        // Only one slot, so only one segment could be pinned.
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 1);
+        assert_eq!(table.free_slots(), 0);
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+        assert_eq!(table.free_slots(), 1);
+        unsafe { libc::close(ring_fd) };
+    }
+
+    #[cfg(feature = "xdp")]
+    #[test]
+    pub fn test_xdp_umem_slab_registers_pinned_segments() {
+        use crate::xdp::{XdpUmemConfig, XdpUmemSlab};
+
+        let probe = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW, 0) };
+        if probe < 0 {
+            // AF_XDP is unavailable or not permitted in this environment.
+            return;
+        }
+        unsafe { libc::close(probe) };
+        let memory = ExampleSlab::new(0, 4);
+        let page_size = pagesizes::PageSize::PG4KB;
+        let config = XdpUmemConfig::default();
+        let slab =
+            unsafe { XdpUmemSlab::new(1, memory.start_address as _, 4, page_size, config) };
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<XdpUmemSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 1);
+        let buf = memory.buf(2, 64);
+        let slices = zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(buf).unwrap();
+        assert!(slices[0].io_info >= 0);
+        zero_copy_cache.record_io_completion(buf);
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+    }
+
+    #[test]
+    pub fn test_multi_device_pins_on_every_device_or_none() {
+        use crate::mock::MockSlab;
+        use crate::multidevice::MultiDeviceSlab;
+
+        let slab = MultiDeviceSlab::<MockSlab, 2>::new(MockSlab::new(1, 2));
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<MultiDeviceSlab<_, 2>>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, [(), ()]);
+        zero_copy_cache.update_stats((1, 0));
+        slab.inner().fail_nth_pin(2);
+        assert_eq!(zero_copy_cache.update_pinned_list(&[(), ()]).num_pinned, 0);
+        assert_eq!(slab.inner().unpin_calls(), 1);
+        assert_eq!(zero_copy_cache.bytes_pinned_per_device(), [0, 0]);
+
+        assert_eq!(zero_copy_cache.update_pinned_list(&[(), ()]).num_pinned, 1);
+        let segment_bytes = pagesizes::PGSIZE_4KB;
+        assert_eq!(zero_copy_cache.bytes_pinned_per_device(), [segment_bytes, segment_bytes]);
+        let buf = slab.inner().buf(0, 64);
+        let first = zero_copy_cache.record_access_and_get_io_info_on_device(buf, 0).unwrap();
+        let second = zero_copy_cache.record_access_and_get_io_info_on_device(buf, 1).unwrap();
+        assert_ne!(first.1, second.1);
+        assert!(zero_copy_cache.record_access_and_get_io_info_on_device(buf, 2).is_err());
+        zero_copy_cache.record_io_completion(buf);
+        zero_copy_cache.record_io_completion(buf);
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+    }
+
+    #[test]
+    pub fn test_remove_slab_unpins_and_forgets_segments() {
+        use crate::data_structures::RemoveSlabError;
+
+        let slab = ExampleSlab::new(1, 2);
+        let other_slab = ExampleSlab::new(2, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(4));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.initialize_slab(&other_slab, 2, false, ());
+        zero_copy_cache.set_pinning_limit_percent(100.0);
+        for index in 0..2 {
+            zero_copy_cache.update_stats((1, index));
+            zero_copy_cache.update_stats((2, index));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 4);
+
+        assert_eq!(zero_copy_cache.remove_slab(1), Ok(2));
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 2);
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(0, 64)), None);
+        assert_eq!(zero_copy_cache.get_segment_id(other_slab.buf(1, 64)), Some((2, 1)));
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 0)), None);
+        assert!(!zero_copy_cache.current_pinned_list.contains(&(1, 0)));
+        assert_eq!(zero_copy_cache.get_pinning_limit(), Some(2 * pagesizes::PGSIZE_4KB));
+        assert_eq!(zero_copy_cache.update_pinned_list(&()).num_unpinned, 0);
+        assert_eq!(zero_copy_cache.remove_slab(1), Err(RemoveSlabError::UnknownSlab));
+        assert_eq!(zero_copy_cache.remove_slab(2), Ok(2));
+        assert_eq!(zero_copy_cache.managed_bytes(), 0);
+    }
+
+    #[test]
+    pub fn test_numa_node_limits_and_local_preference() {
+        let slab = ExampleSlab::new(1, 2);
+        let other_slab = ExampleSlab::new(2, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(4));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.initialize_slab(&other_slab, 2, false, ());
+        zero_copy_cache.set_slab_numa_node(1, 0);
+        zero_copy_cache.set_slab_numa_node(2, 1);
+        for index in 0..2 {
+            zero_copy_cache.update_stats((1, index));
+            zero_copy_cache.update_stats((2, index));
+        }
+        zero_copy_cache.set_node_pinning_limit(1, pagesizes::PGSIZE_4KB);
+        zero_copy_cache.update_pinned_list(&());
+        let per_node = zero_copy_cache.bytes_pinned_per_node();
+        assert_eq!(per_node[&0], 2 * pagesizes::PGSIZE_4KB);
+        assert_eq!(per_node[&1], pagesizes::PGSIZE_4KB);
+        assert_eq!(zero_copy_cache.slab_stats()[&2].numa_node, Some(1));
+
+        zero_copy_cache.clear_node_pinning_limit(1);
+        zero_copy_cache.set_pinning_limit(2 * pagesizes::PGSIZE_4KB);
+        zero_copy_cache.set_numa_preference(1, 0.5);
+        zero_copy_cache.update_pinned_list(&());
+        let per_node = zero_copy_cache.bytes_pinned_per_node();
+        assert_eq!(per_node.get(&0), None);
+        assert_eq!(per_node[&1], 2 * pagesizes::PGSIZE_4KB);
+        zero_copy_cache.unpin_all_segments();
+    }
+
+    #[cfg(feature = "mlock")]
+    #[test]
+    pub fn test_hugepage_allocator_creates_ready_slabs() {
+        use crate::hugepage::{allocate_memfd_slab, allocate_slab};
+
+        let slab = allocate_slab(1, 3 * pagesizes::PGSIZE_4KB, pagesizes::PageSize::PG2MB).unwrap();
+        assert!(slab.get_size() >= 3 * pagesizes::PGSIZE_4KB);
+        assert_eq!(slab.get_size() % slab.get_page_size_as_num(), 0);
+
+        let len = 2 * pagesizes::PGSIZE_4KB + 1;
+        let shared = allocate_memfd_slab(2, "zcc-test", len, pagesizes::PageSize::PG4KB).unwrap();
+        assert_eq!(shared.get_total_num_pages(), 3);
+        assert!(shared.memory().get_fd().is_some());
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::new(1));
+        zero_copy_cache.initialize_slab(&shared, 3, false, ());
+        zero_copy_cache.update_stats((2, 2));
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), pagesizes::PGSIZE_4KB);
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+    }
+
+    #[test]
+    pub fn test_dedup_slab_coalesces_registrations_across_instances() {
+        use crate::dedup::{DedupContext, DedupSlab, RegistrationCache};
+        use crate::mock::MockSlab;
+        use std::sync::Arc;
+
+        let memory = Arc::new(MockSlab::new(1, 2));
+        let registrations = RegistrationCache::new();
+        let first_slab = DedupSlab::new(memory.clone(), registrations.clone());
+        let second_slab = DedupSlab::new(memory.clone(), registrations.clone());
+        let context = DedupContext { device: 0, inner: () };
+        let mut first = ZeroCopyCache::new(MfuCache::<DedupSlab<MockSlab>>::new(1));
+        let mut second = ZeroCopyCache::new(MfuCache::<DedupSlab<MockSlab>>::new(1));
+        first.initialize_slab(&first_slab, 2, false, context.clone());
+        second.initialize_slab(&second_slab, 2, false, context.clone());
+        first.update_stats((1, 0));
+        second.update_stats((1, 0));
+        assert_eq!(first.update_pinned_list(&context).num_pinned, 1);
+        assert_eq!(second.update_pinned_list(&context).num_pinned, 1);
+        assert_eq!(memory.pin_calls(), 1);
+        assert_eq!(registrations.coalesced_pins(), 1);
+        let buf = memory.buf(0, 64);
+        let first_io = first.record_access_and_get_io_info(buf).unwrap();
+        let second_io = second.record_access_and_get_io_info(buf).unwrap();
+        assert_eq!(first_io, second_io);
+        first.record_io_completion(buf);
+        second.record_io_completion(buf);
+
+        assert_eq!(first.unpin_all_segments(), 1);
+        assert_eq!(memory.unpin_calls(), 0);
+        assert_eq!(registrations.refs(0, buf.as_ptr() as _, pagesizes::PGSIZE_4KB), 1);
+        assert_eq!(second.unpin_all_segments(), 1);
+        assert_eq!(memory.unpin_calls(), 1);
+        assert!(registrations.is_empty());
+        // The first instance registered the range but the second released it; it can still
+        // register it again.
+        first.update_stats((1, 0));
+        assert_eq!(first.update_pinned_list(&context).num_pinned, 1);
+        assert_eq!(memory.pin_calls(), 2);
+        assert!(first.record_access_and_get_io_info(buf).is_ok());
+        first.record_io_completion(buf);
+        assert_eq!(first.unpin_all_segments(), 1);
+        assert!(registrations.is_empty());
+        assert!(Arc::ptr_eq(
+            &RegistrationCache::<MockSlab>::global(),
+            &RegistrationCache::<MockSlab>::global()
+        ));
+    }
+
+    #[test]
+    pub fn test_segment_lookup_by_address_range() {
+        use crate::rangeindex::RangeIndex;
+
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(0, 64)), Some((1, 0)));
+        assert_eq!(zero_copy_cache.get_segment_id(&slab.buf(1, 64)[32..]), Some((1, 0)));
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(2, 64)), Some((1, 1)));
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(3, 64)), Some((1, 1)));
+
+        let mut ranges = RangeIndex::default();
+        ranges.insert(0x1000, 0x2000, 'a');
+        ranges.insert(0x4000, 0x1000, 'b');
+        assert_eq!(ranges.get(0xfff), None);
+        assert_eq!(ranges.get(0x2fff), Some('a'));
+        assert_eq!(ranges.get(0x3000), None);
+        assert_eq!(ranges.get(0x4800), Some('b'));
+        assert_eq!(ranges.get(0x5000), None);
+        ranges.retain(|id| *id != 'a');
+        assert_eq!(ranges.get(0x1000), None);
+        assert_eq!(ranges.len(), 1);
+    }
+
+    #[test]
+    pub fn test_page_size_supports_any_power_of_two() {
+        use pagesizes::PageSize;
+
+        assert_eq!(PageSize::new(16 * 1024), Some(PageSize::PG16KB));
+        assert_eq!(PageSize::new(pagesizes::PGSIZE_2MB), Some(PageSize::PG2MB));
+        assert_eq!(PageSize::new(3 * 1024), None);
+        assert_eq!(PageSize::PG64KB.bytes(), 64 * 1024);
+        assert_eq!(format!("{:?}", PageSize::PG64KB), "64KB");
+        assert_eq!(format!("{:?}", PageSize::PG1GB), "1GB");
+        let addr = (5 * 64 * 1024 + 123) as *const u8;
+        assert_eq!(pagesizes::closest_page(addr, &PageSize::PG64KB), 5 * 64 * 1024);
+        assert_eq!(pagesizes::closest_page(addr, &PageSize::PG16KB), 20 * 16 * 1024);
+        assert_eq!(pagesizes::closest_4k_page(addr), 80 * pagesizes::PGSIZE_4KB);
+        assert!(matches!(PageSize::new(1 << 30).unwrap(), PageSize::PG1GB));
+    }
+
+    #[test]
+    pub fn test_detect_hugepages_from_sysfs_and_meminfo() {
+        use pagesizes::{best_hugepage_size, detect_hugepages_in, PageSize};
+        use std::fs;
+
+        let root = std::env::temp_dir().join(format!("zcc-hugepages-{}", std::process::id()));
+        let sysfs = root.join("hugepages");
+        for (name, total, free) in [("hugepages-2048kB", 8, 3), ("hugepages-1048576kB", 1, 1)] {
+            fs::create_dir_all(sysfs.join(name)).unwrap();
+            fs::write(sysfs.join(name).join("nr_hugepages"), format!("{}\n", total)).unwrap();
+            fs::write(sysfs.join(name).join("free_hugepages"), format!("{}\n", free)).unwrap();
+        }
+        let meminfo = root.join("meminfo");
+        fs::write(
+            &meminfo,
+            "HugePages_Total:       4\nHugePages_Free:        2\nHugepagesize:       2048 kB\n",
+        )
+        .unwrap();
+
+        let pools = detect_hugepages_in(&sysfs, &meminfo);
+        assert_eq!(pools.len(), 2);
+        assert_eq!(pools[0].page_size, PageSize::PG2MB);
+        assert_eq!((pools[0].total, pools[0].free), (8, 3));
+        assert_eq!(pools[1].page_size, PageSize::PG1GB);
+        assert_eq!(best_hugepage_size(&pools, pagesizes::PGSIZE_1GB), Some(PageSize::PG1GB));
+        let too_big = 2 * pagesizes::PGSIZE_1GB;
+        assert_eq!(best_hugepage_size(&pools, too_big), None);
+
+        let from_meminfo = detect_hugepages_in(&root.join("missing"), &meminfo);
+        assert_eq!(from_meminfo.len(), 1);
+        assert_eq!(from_meminfo[0].free_bytes(), 2 * pagesizes::PGSIZE_2MB);
+        assert_eq!(best_hugepage_size(&from_meminfo, 1), Some(PageSize::PG2MB));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_buffer_spanning_segments_resolves_every_segment() {
+        let page = pagesizes::PGSIZE_4KB;
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        let buf = &slab.buf(1, 2 * page)[page - 100..page + 50];
+        assert_eq!(zero_copy_cache.get_segment_ids(buf).as_slice(), &[(1, 1), (1, 2)]);
+        assert_eq!(zero_copy_cache.get_segment_ids(slab.buf(3, 64)).as_slice(), &[(1, 3)]);
+
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+        assert!(zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(buf).is_none());
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(0));
+
+        zero_copy_cache.update_stats((1, 2));
+        zero_copy_cache.update_pinned_list(&());
+        let slices = zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(buf).unwrap();
+        let parts: Vec<_> = slices.iter().map(|s| (s.segment_id, s.offset, s.len)).collect();
+        assert_eq!(parts, vec![((1, 1), page - 100, 100), ((1, 2), 0, 50)]);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 2)), Some(1));
+        zero_copy_cache.record_io_completion_of(&slices);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(0));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 2)), Some(0));
+    }
+
+    #[test]
+    pub fn test_last_segment_lookup_follows_slab_changes() {
+        use crate::metrics::MissReason;
+
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        for page in [0, 0, 1, 0] {
+            let result = zero_copy_cache.record_access_and_get_io_info(slab.buf(page, 64));
+            assert_eq!(result, Err(MissReason::NotPinned));
+        }
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 0)), Some(3));
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 1)), Some(1));
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(0, 64)), Some((1, 0)));
+
+        assert_eq!(zero_copy_cache.remove_slab(1), Ok(0));
+        let result = zero_copy_cache.record_access_and_get_io_info(slab.buf(0, 64));
+        assert_eq!(result, Err(MissReason::Unmanaged));
+        zero_copy_cache.initialize_slab(&slab, 1, false, ());
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(1, 64)), Some((1, 0)));
+    }
+
+    #[test]
+    pub fn test_unmanaged_pages_are_remembered_until_slabs_change() {
+        use crate::metrics::MissReason;
+
+        let foreign = vec![0u8; 64];
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        for _ in 0..3 {
+            let result = zero_copy_cache.record_access_and_get_io_info(&foreign);
+            assert_eq!(result, Err(MissReason::Unmanaged));
+        }
+        assert_eq!(zero_copy_cache.num_unmanaged_pages_cached(), 1);
+        assert_eq!(zero_copy_cache.get_metrics().unmanaged, 3);
+
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        assert_eq!(zero_copy_cache.num_unmanaged_pages_cached(), 0);
+        let result = zero_copy_cache.record_access_and_get_io_info(slab.buf(1, 64));
+        assert_eq!(result, Err(MissReason::NotPinned));
+        zero_copy_cache.set_unmanaged_page_cache_size(0);
+        assert!(zero_copy_cache.record_access_and_get_io_info(&foreign).is_err());
+        assert_eq!(zero_copy_cache.num_unmanaged_pages_cached(), 0);
+    }
+
+    #[test]
+    pub fn test_segment_index_uses_slab_page_arithmetic() {
+        use crate::rangeindex::SegmentIndex;
+
+        let mut index = SegmentIndex::default();
+        let page = pagesizes::PGSIZE_2MB;
+        index.insert_slab(7, 10 * page, &pagesizes::PageSize::PG2MB, 4, 10);
+        index.insert_slab(8, 40 * page, &pagesizes::PageSize::PG4KB, 1, 2);
+        assert_eq!(index.num_slabs(), 2);
+        assert_eq!(index.get(10 * page), Some((7, 0)));
+        assert_eq!(index.get(14 * page + 5), Some((7, 1)));
+        assert_eq!(index.get_range(19 * page), Some((18 * page, 20 * page, (7, 2))));
+        assert_eq!(index.get(20 * page), None);
+        assert_eq!(index.get(40 * page + pagesizes::PGSIZE_4KB), Some((8, 1)));
+        let spanned: Vec<_> = index.overlapping(13 * page, 19 * page).map(|r| r.2).collect();
+        assert_eq!(spanned, vec![(7, 0), (7, 1), (7, 2)]);
+        index.retain_slabs(|id| *id != 7);
+        assert_eq!(index.get(10 * page), None);
+        assert_eq!(index.get(40 * page), Some((8, 0)));
+    }
+
+    #[test]
+    pub fn test_builder_validates_and_applies_settings() {
+        use crate::builder::{ConfigError, ZeroCopyCacheBuilder};
+        use crate::data_structures::DecayPolicy;
+
+        let page = pagesizes::PGSIZE_4KB;
+        let invalid = ZeroCopyCacheBuilder::new(MfuCache::<ExampleSlab>::new(2))
+            .segment_size(4 * page)
+            .pinning_limit(2 * page)
+            .build();
+        let expected = ConfigError::SegmentLargerThanLimit {
+            segment_size: 4 * page,
+            pinning_limit: 2 * page,
+        };
+        assert_eq!(invalid.err(), Some(expected));
+        let invalid = ZeroCopyCache::builder(MfuCache::<ExampleSlab>::new(2))
+            .repin_interval(Duration::ZERO)
+            .build();
+        assert_eq!(invalid.err(), Some(ConfigError::ZeroRepinInterval));
+        let invalid = ZeroCopyCache::builder(MfuCache::<ExampleSlab>::new(2))
+            .budget_split(1.5)
+            .build();
+        assert_eq!(invalid.err(), Some(ConfigError::InvalidBudgetSplit(1.5)));
+
+        let mut zero_copy_cache = ZeroCopyCache::builder(MfuCache::<ExampleSlab>::new(2))
+            .segment_size(2 * page)
+            .pinning_limit(2 * page)
+            .repin_interval(Duration::from_millis(50))
+            .decay(DecayPolicy::Halving, Duration::from_secs(10))
+            .hysteresis(1.5, 2)
+            .build()
+            .unwrap();
+        assert_eq!(zero_copy_cache.get_pinning_limit(), Some(2 * page));
+        assert_eq!(zero_copy_cache.get_repin_interval(), Duration::from_millis(50));
+        assert_eq!(zero_copy_cache.get_decay_schedule().unwrap().policy, DecayPolicy::Halving);
+        assert_eq!(zero_copy_cache.get_hysteresis().unwrap().min_pinned_cycles, 2);
+        let slab = ExampleSlab::new(1, 4);
+        zero_copy_cache.add_slab(&slab, false, ());
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(3, 64)), Some((1, 1)));
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(1, 64)), Some((1, 0)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn test_config_loads_from_toml_and_yaml() {
+        use crate::config::{CacheType, ZccConfig};
+
+        let toml = r#"
+            cache_type = "ewma"   # comment
+            pinning_limit = 16_384
+            segment_size = 4096
+            pin_on_demand = true
+            repin_interval_ms = 50
+
+            [hysteresis]
+            min_pinned_cycles = 2
+
+            [slab_budgets]
+            1 = 4096
+        "#;
+        let yaml = "cache_type: ewma\npinning_limit: 16384\nsegment_size: 4096\n\
+                    pin_on_demand: true\nrepin_interval_ms: 50\nhysteresis:\n  \
+                    min_pinned_cycles: 2\nslab_budgets:\n  1: 4096\n";
+        let config = ZccConfig::<usize>::from_toml_str(toml).unwrap();
+        assert_eq!(ZccConfig::from_yaml_str(yaml), Ok(config.clone()));
+        assert_eq!(config.cache_type, CacheType::Ewma);
+        assert_eq!(config.hysteresis.unwrap().min_pinned_cycles, 2);
+        let error = ZccConfig::<usize>::from_toml_str("segment_size = 1\npin = true").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(ZccConfig::from_toml_str(&config.to_toml_string().unwrap()), Ok(config.clone()));
+        assert_eq!(ZccConfig::from_yaml_str(&config.to_yaml_string().unwrap()), Ok(config.clone()));
+
+        // Inline tables, dotted keys and quotes inside comments are plain TOML.
+        let toml = "cache_type = \"ewma\" # it's \"ewma\"\npinning_limit = 16_384\n\
+                    segment_size = 4096\npin_on_demand = true\nrepin_interval_ms = 50\n\
+                    hysteresis.min_pinned_cycles = 2\nslab_budgets = { 1 = 4096 }\n";
+        assert_eq!(ZccConfig::from_toml_str(toml), Ok(config.clone()));
+        let yaml = "{cache_type: ewma, pinning_limit: 16384, segment_size: 4096, \
+                    pin_on_demand: true, repin_interval_ms: 50, \
+                    hysteresis: {min_pinned_cycles: 2}, slab_budgets: {1: 4096}}";
+        assert_eq!(ZccConfig::from_yaml_str(yaml), Ok(config.clone()));
+
+        let page = pagesizes::PGSIZE_4KB;
+        let mut zero_copy_cache =
+            ZeroCopyCache::from_config(&config, MfuCache::<ExampleSlab>::new(4)).unwrap();
+        assert_eq!(zero_copy_cache.get_pinning_limit(), Some(4 * page));
+        assert_eq!(zero_copy_cache.get_repin_interval(), Duration::from_millis(50));
+        assert!(zero_copy_cache.get_pin_on_demand());
+        assert_eq!(zero_copy_cache.get_slab_pinning_limit(1), Some(page));
+        let slab = ExampleSlab::new(1, 2);
+        zero_copy_cache.add_slab(&slab, false, ());
+        for page_index in 0..2 {
+            zero_copy_cache.update_stats((1, page_index));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        // The slab budget holds the slab to one segment, under the global limit.
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), page);
+    }
+
+    #[test]
+    pub fn test_set_cache_type_keeps_slabs_and_seeds_hotset() {
+        use crate::config::CacheType;
+        use crate::policy::CacheBuilderEnum;
+
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache =
+            ZeroCopyCache::new(CacheBuilderEnum::<ExampleSlab>::new(CacheType::Mfu, 2));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((1, 0));
+            zero_copy_cache.update_stats((1, 2));
+        }
+        zero_copy_cache.update_stats((1, 3));
+        zero_copy_cache.update_pinned_list(&());
+        let expected: HashSet<SegmentId<ExampleSlab>> = [(1, 0), (1, 2)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, expected);
+
+        // A seeded switch keeps the hotset, and the slab stays registered.
+        zero_copy_cache.set_cache_type(CacheType::SpaceSaving, true);
+        assert_eq!(zero_copy_cache.get_cache_type(), CacheType::SpaceSaving);
+        assert_eq!(zero_copy_cache.cache_builder.get_num_segments(), 2);
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 0)), Some(3));
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!((churn.num_pinned, churn.num_unpinned), (0, 0));
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(3, 64)), Some((1, 3)));
+
+        // An unseeded switch starts from fresh statistics.
+        zero_copy_cache.set_cache_type(CacheType::Mfu, false);
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 0)), None);
+        zero_copy_cache.update_stats((1, 3));
+        zero_copy_cache.update_pinned_list(&());
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 3)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn test_cache_type_builds_concrete_policy() {
+        use crate::config::{CacheType, ZccConfig};
+
+        let cache_type: CacheType = "Recency-Frequency".parse().unwrap();
+        let zero_copy_cache = ZeroCopyCache::<ExampleSlab, _>::new_with_type(cache_type, 7);
+        assert_eq!(zero_copy_cache.get_cache_type(), CacheType::RecencyFrequency);
+        assert_eq!(zero_copy_cache.cache_builder.get_num_segments(), 7);
+        assert!("lfu".parse::<CacheType>().is_err());
+
+        let toml = "cache_type = \"window\"\nnum_segments = 1\nsegment_size = 4096";
+        let config = ZccConfig::from_toml_str(toml).unwrap();
+        let mut zero_copy_cache = config.build::<ExampleSlab>().unwrap();
+        assert_eq!(zero_copy_cache.get_cache_type(), CacheType::Window);
+        let slab = ExampleSlab::new(1, 2);
+        zero_copy_cache.add_slab(&slab, false, ());
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+        let expected: HashSet<SegmentId<ExampleSlab>> = [(1, 1)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, expected);
+    }
+
+    #[test]
+    pub fn test_segments_reports_managed_segments() {
+        let slab = ExampleSlab::new(1, 3);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 3, false, ());
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+        assert!(zero_copy_cache.record_access_and_get_io_info(slab.buf(1, 64)).is_ok());
+
+        let mut segments: Vec<_> = zero_copy_cache.segments().collect();
+        segments.sort_by_key(|segment| segment.segment_id);
+        assert_eq!(segments.len(), 3);
+        let pinned = segments[1];
+        assert_eq!(pinned.segment_id, (1, 1));
+        assert_eq!(pinned.start_address, slab.buf(1, 64).as_ptr() as usize);
+        assert_eq!(pinned.size, pagesizes::PGSIZE_4KB);
+        assert!(pinned.pinned);
+        assert_eq!(pinned.in_flight_io, 1);
+        assert_eq!(pinned.access_count, Some(2));
+        assert!(!segments[0].pinned);
+        assert_eq!(segments[0].access_count, None);
+    }
+
+    #[test]
+    pub fn test_read_only_pinned_lookup_leaves_stats_alone() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_pinned_list(&());
+
+        assert!(zero_copy_cache.is_address_pinned(slab.buf(0, 64)));
+        assert!(!zero_copy_cache.is_address_pinned(slab.buf(1, 64)));
+        assert!(!zero_copy_cache.is_address_pinned(&[0u8; 8]));
+        let (slab_id, _) = zero_copy_cache.get_io_info_if_pinned(slab.buf(0, 64)).unwrap();
+        assert_eq!(slab_id, 1);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(0));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 0)), Some(1));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), None);
+        assert_eq!(zero_copy_cache.get_metrics().hits, 0);
+    }
+
+    #[derive(Debug, Default)]
+    pub struct RecordingListener {
+        events: std::sync::Mutex<Vec<(&'static str, SegmentId<ExampleSlab>)>>,
+    }
+
+    impl crate::events::ZccEventListener<ExampleSlab> for RecordingListener {
+        fn on_pinned(&self, segment_id: SegmentId<ExampleSlab>, _io_info: usize) {
+            self.events.lock().unwrap().push(("pinned", segment_id));
+        }
+
+        fn on_unpinning(&self, segment_id: SegmentId<ExampleSlab>) {
+            self.events.lock().unwrap().push(("unpinning", segment_id));
+        }
+
+        fn on_unpinned(&self, segment_id: SegmentId<ExampleSlab>) {
+            self.events.lock().unwrap().push(("unpinned", segment_id));
+        }
+    }
+
+    #[test]
+    pub fn test_event_listener_sees_pin_and_unpin() {
+        let slab = ExampleSlab::new(1, 2);
+        let listener = Arc::new(RecordingListener::default());
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.set_event_listener(listener.clone());
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_pinned_list(&());
+        for _ in 0..2 {
+            zero_copy_cache.update_stats((1, 1));
+        }
+        zero_copy_cache.update_pinned_list(&());
+
+        let events = listener.events.lock().unwrap().clone();
+        let expected = vec![
+            ("pinned", (1, 0)),
+            ("unpinning", (1, 0)),
+            ("unpinned", (1, 0)),
+            ("pinned", (1, 1)),
+        ];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    pub fn test_import_state_repins_exported_hotset() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((1, 1));
+        }
+        zero_copy_cache.update_stats((1, 3));
+        zero_copy_cache.update_stats((1, 3));
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_pinned_list(&());
+        let mut state = zero_copy_cache.export_state();
+        assert_eq!(state.pinned.len(), 2);
+        zero_copy_cache.shutdown();
+
+        // A segment from a slab that was not registered again is skipped.
+        let mut stale = state.segments[0].clone();
+        stale.segment_id = (9, 0);
+        state.segments.push(stale);
+        let mut restarted = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        restarted.initialize_slab(&slab, 4, false, ());
+        assert_eq!(restarted.import_state(&state, &()), 3);
+        let expected: HashSet<SegmentId<ExampleSlab>> = [(1, 1), (1, 3)].into_iter().collect();
+        assert_eq!(restarted.current_pinned_list, expected);
+        assert_eq!(restarted.cache_builder.get_access_count((1, 1)), Some(3));
+        assert!(restarted.is_address_pinned(slab.buf(3, 64)));
+    }
+
+    #[test]
+    pub fn test_manual_pin_and_unpin_respect_budget() {
+        use crate::data_structures::PinError;
+
+        let slab = ExampleSlab::new(1, 3);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 3, false, ());
+        zero_copy_cache.set_pinning_limit(2 * pagesizes::PGSIZE_4KB);
+        assert_eq!(zero_copy_cache.pin((1, 2), &()), Ok(()));
+        assert!(zero_copy_cache.is_address_pinned(slab.buf(2, 64)));
+        assert_eq!(zero_copy_cache.pin((7, 0), &()), Err(PinError::UnknownSegment));
+
+        // The policy prefers segment 0, but the manual pin survives the cycle.
+        for _ in 0..3 {
+            zero_copy_cache.update_stats((1, 0));
+        }
+        zero_copy_cache.update_pinned_list(&());
+        let expected: HashSet<SegmentId<ExampleSlab>> = [(1, 0), (1, 2)].into_iter().collect();
+        assert_eq!(zero_copy_cache.current_pinned_list, expected);
+        assert_eq!(zero_copy_cache.pin((1, 1), &()), Err(PinError::OverBudget));
+
+        assert!(zero_copy_cache.unpin((1, 2)));
+        assert!(!zero_copy_cache.unpin((1, 2)));
+        assert!(!zero_copy_cache.is_address_pinned(slab.buf(2, 64)));
+        assert_eq!(zero_copy_cache.get_pin_hint((1, 2)), Priority::Normal);
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), pagesizes::PGSIZE_4KB);
+    }
+
+    #[test]
+    pub fn test_reset_pinned_set_forces_unpin() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+        // Stuck IO would make a `Skip` timeout keep the segment pinned.
+        assert!(zero_copy_cache.record_access_and_get_io_info(slab.buf(0, 64)).is_ok());
+        zero_copy_cache.set_quiesce_timeout(Duration::from_millis(10), QuiesceTimeoutAction::Skip);
+
+        assert_eq!(zero_copy_cache.reset_pinned_set(false), 2);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 0);
+        assert!(zero_copy_cache.current_pinned_list.is_empty());
+        assert!(!zero_copy_cache.is_address_pinned(slab.buf(0, 64)));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), Some(1));
+        assert_eq!(zero_copy_cache.update_pinned_list(&()).num_pinned, 2);
+
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert_eq!(zero_copy_cache.reset_pinned_set(true), 2);
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), None);
+        assert_eq!(zero_copy_cache.get_segment_id(slab.buf(1, 64)), Some((1, 1)));
+    }
+
+    #[test]
+    pub fn test_pinned_usage_tracks_exact_segment_sizes() {
+        use crate::data_structures::PinnedUsage;
+
+        let page = pagesizes::PGSIZE_4KB;
+        let small = ExampleSlab::new(1, 2);
+        let large = ExampleSlab::new(2, 6);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(8));
+        zero_copy_cache.initialize_slab_with_segment_size(&small, page, false, ());
+        // Segments of four pages, the last holding the two leftover pages.
+        zero_copy_cache.initialize_slab_with_segment_size(&large, 4 * page, false, ());
+        assert_eq!(zero_copy_cache.pinned_usage(), PinnedUsage::default());
+
+        let other = zero_copy_cache.clone();
+        zero_copy_cache.update_stats((2, 1));
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_pinned_list(&());
+        assert_eq!(other.pinned_usage(), PinnedUsage { bytes: 3 * page, segments: 2 });
+        assert!(zero_copy_cache.pin((2, 0), &()).is_ok());
+        assert_eq!(zero_copy_cache.current_bytes_pinned(), 7 * page);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 3);
+        zero_copy_cache.unpin_all_segments();
+        assert_eq!(other.pinned_usage(), PinnedUsage::default());
+    }
+
+    #[test]
+    pub fn test_record_accesses_batches_scatter_gather_list() {
+        let slab = ExampleSlab::new(1, 3);
+        let mut zero_copy_cache =
+            ZeroCopyCache::new_sharded(2, |_| MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 3, false, ());
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+
+        let page = pagesizes::PGSIZE_4KB;
+        let spanning = &slab.buf(1, 2 * page)[page - 8..page + 8];
+        let unmanaged = [0u8; 8];
+        let bufs = [slab.buf(0, 64), slab.buf(0, 64), spanning, &unmanaged[..]];
+        let results = zero_copy_cache.record_accesses(&bufs);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap()[0].segment_id, (1, 0));
+        assert!(results[1].is_some());
+        // Segment 2 is not pinned, so the IO counted against segment 1 is completed again.
+        assert!(results[2].is_none());
+        assert!(results[3].is_none());
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 0)), Some(3));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 2)), Some(1));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(2));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(0));
+        let metrics = zero_copy_cache.get_metrics();
+        assert_eq!((metrics.hits, metrics.unmanaged), (3, 1));
+    }
+
+    #[test]
+    pub fn test_io_handle_reports_offset_in_segment() {
+        let page = pagesizes::PGSIZE_4KB;
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        let buf = &slab.buf(2, page)[100..164];
+        let handle = zero_copy_cache.record_access_and_get_io_handle(buf).unwrap();
+        assert_eq!(handle.segment_id, (1, 1));
+        assert_eq!(handle.segment_base, slab.buf(2, 64).as_ptr() as usize);
+        assert_eq!(handle.offset, 100);
+        assert_eq!(handle.remaining_len, 2 * page - 100);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(1));
+        assert_eq!(
+            zero_copy_cache.record_access_and_get_io_handle(&[0u8; 8]),
+            Err(MissReason::Unmanaged)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn test_zcc_error_collects_failure_modes() {
+        use crate::config::ZccConfig;
+        use crate::error::ZccError;
+
+        type Cache = ZeroCopyCache<ExampleSlab, MfuCache<ExampleSlab>>;
+        fn setup(toml: &str) -> Result<Cache, ZccError> {
+            let config = ZccConfig::from_toml_str(toml)?;
+            let mut zero_copy_cache = ZeroCopyCache::from_config(&config, MfuCache::new(2))?;
+            let slab = ExampleSlab::new(1, 2);
+            zero_copy_cache.add_slab(&slab, false, ());
+            zero_copy_cache.pin((1, 0), &())?;
+            Ok(zero_copy_cache)
+        }
+
+        assert!(setup("segment_size = 4096").is_ok());
+        let error = setup("pinning_limit = 100\nsegment_size = 4096").err().unwrap();
+        let expected = "invalid configuration: SegmentLargerThanLimit { segment_size: 4096, \
+                        pinning_limit: 100 }";
+        assert_eq!(error.to_string(), expected);
+        assert!(matches!(setup("segment_size = x"), Err(ZccError::ConfigParse(_))));
+        assert_eq!(
+            setup("pinning_limit = 4096\nslab_budgets = 1").err(),
+            Some(ZccError::ConfigParse(crate::config::ConfigParseError {
+                line: 2,
+                message: "invalid type: integer `1`, expected a map".to_string(),
+            }))
+        );
+        let error: Box<dyn std::error::Error> = Box::new(ZccError::from(MissReason::NotPinned));
+        assert_eq!(error.to_string(), "buffer not served zero-copy: NotPinned");
+    }
+
+    #[test]
+    pub fn test_panic_under_cache_builder_lock_is_recovered() {
+        use crate::metrics::lock_recoveries;
+
+        /// Panics on its first access, with its shard locked.
+        #[derive(Debug)]
+        struct PanicOnceCache {
+            inner: MfuCache<ExampleSlab>,
+            panicked: bool,
+        }
+
+        impl SegmentPolicy<ExampleSlab> for PanicOnceCache {
+            fn update_access(&mut self, segment_id: SegmentId<ExampleSlab>) {
+                self.inner.update_access(segment_id);
+                if !self.panicked {
+                    self.panicked = true;
+                    panic!("cache builder bug");
+                }
+            }
+
+            fn get_access_count(&self, segment_id: SegmentId<ExampleSlab>) -> Option<i64> {
+                self.inner.get_access_count(segment_id)
+            }
+
+            fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<ExampleSlab>> {
+                self.inner.return_top_segments_to_pin()
+            }
+
+            fn get_num_segments(&self) -> usize {
+                self.inner.get_num_segments()
+            }
+
+            fn set_num_segments(&mut self, num_segments: usize) {
+                self.inner.set_num_segments(num_segments)
+            }
+
+            fn reset(&mut self) {
+                self.inner.reset()
+            }
+        }
+
+        let mut zero_copy_cache = ZeroCopyCache::new(PanicOnceCache {
+            inner: MfuCache::new(1),
+            panicked: false,
+        });
+        let slab = ExampleSlab::new(1, 2);
+        zero_copy_cache.add_slab(&slab, false, ());
+        let recoveries = lock_recoveries();
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+        }));
+        assert!(panicked.is_err());
+        assert!(zero_copy_cache.record_access_and_get_io_info_if_pinned(slab.buf(0, 64)).is_none());
+        assert!(lock_recoveries() > recoveries);
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 0)), Some(2));
+        zero_copy_cache.update_pinned_list(&());
+        assert!(zero_copy_cache.is_address_pinned(slab.buf(0, 64)));
+    }
+
+    #[test]
+    pub fn test_io_lease_completes_on_drop() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        let buf = slab.buf(0, 2 * pagesizes::PGSIZE_4KB);
+
+        let lease = zero_copy_cache.record_access_and_get_io_info_if_pinned(buf).unwrap();
+        assert_eq!(lease.len(), 2);
+        assert_eq!(lease.slices()[1].segment_id, (1, 1));
+        let other = zero_copy_cache
+            .record_access_and_get_io_info_if_pinned(slab.buf(0, 64))
+            .unwrap();
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(2));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(1));
+
+        drop(lease);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(1));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(0));
+        other.complete();
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(0));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _lease = zero_copy_cache.record_access_and_get_io_info_if_pinned(slab.buf(1, 64));
+            panic!("send failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(0));
+    }
+
+    #[test]
+    pub fn test_io_completions_by_segment_id() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        let mut completions = Vec::new();
+        for page in [0, 0, 1, 0] {
+            let handle = zero_copy_cache
+                .record_access_and_get_io_handle(slab.buf(page, 64))
+                .unwrap();
+            completions.push(handle.segment_id);
+        }
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(3));
+
+        assert!(zero_copy_cache.record_io_completion_by_id(completions.pop().unwrap()));
+        assert!(!zero_copy_cache.record_io_completion_by_id((2, 0)));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(2));
+        completions.push((2, 0));
+        assert_eq!(zero_copy_cache.record_io_completions(&completions), 3);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(0));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(0));
+    }
+
+    #[test]
+    pub fn test_unmatched_io_completions_are_ignored() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+            .is_some());
+
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert!(!zero_copy_cache.record_io_completion_by_id((1, 1)));
+        assert_eq!(zero_copy_cache.record_io_completions(&[(1, 0), (7, 0)]), 0);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(0));
+        assert_eq!(zero_copy_cache.get_metrics().orphan_completions, 3);
+
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+            .is_some());
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(1));
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert_eq!(zero_copy_cache.get_metrics().orphan_completions, 3);
+        assert!(zero_copy_cache.remove_slab(1).is_ok());
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+        assert_eq!(zero_copy_cache.get_metrics().orphan_completions, 3);
+    }
+
+    #[test]
+    pub fn test_handle_serves_datapath_from_many_threads() {
+        let slab = ExampleSlab::new(1, 4);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 4, false, ());
+        let handle = zero_copy_cache.handle();
+
+        std::thread::scope(|scope| {
+            for page in [2, 3] {
+                let handle = handle.clone();
+                let buf = slab.buf(page, 64);
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        assert!(handle.record_access_and_get_io_info(buf).is_err());
+                    }
+                });
+            }
+        });
+        assert_eq!(handle.get_metrics().misses_not_pinned, 200);
+        zero_copy_cache.update_pinned_list(&());
+        assert!(handle.is_address_pinned(slab.buf(3, 64)));
+
+        let io_handle = handle.record_access_and_get_io_handle(slab.buf(2, 64)).unwrap();
+        let segment_id = io_handle.segment_id;
+        let lease = handle.record_access_and_get_io_info_if_pinned(slab.buf(3, 64)).unwrap();
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io(segment_id), Some(1));
+        assert!(handle.record_io_completion_by_id(segment_id));
+        drop(lease);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 3)), Some(0));
+    }
+
+    #[test]
+    pub fn test_io_handle_generation_goes_stale_on_unpin() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.update_stats((1, 0));
+        zero_copy_cache.update_pinned_list(&());
+        let handle = zero_copy_cache.record_access_and_get_io_handle(slab.buf(0, 64)).unwrap();
+        zero_copy_cache.record_io_completion_by_id(handle.segment_id);
+        assert_eq!(handle.generation, 0);
+        assert!(zero_copy_cache.validate(&handle));
+
+        for (segment, accesses) in [(1, 5), (0, 10)] {
+            for _ in 0..accesses {
+                zero_copy_cache.update_stats((1, segment));
+            }
+            zero_copy_cache.update_pinned_list(&());
+        }
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 0)));
+        assert!(!zero_copy_cache.validate(&handle));
+        let handle = zero_copy_cache.record_access_and_get_io_handle(slab.buf(0, 64)).unwrap();
+        assert_eq!(handle.generation, 1);
+        assert!(zero_copy_cache.validate(&handle));
+    }
+
+    #[test]
+    pub fn test_unbounded_lru_cache_holds_owned_values() {
+        use crate::kv::{KvCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<String, Vec<u8>> = UnboundedLRUCache::new(2);
+        assert_eq!(cache.put("a".to_string(), vec![1]), vec![]);
+        assert_eq!(cache.put("b".to_string(), vec![2; 64]), vec![]);
+        assert_eq!(cache.get(&"a".to_string()), Some(vec![1]));
+        assert_eq!(cache.put("b".to_string(), vec![3]), vec![]);
+        assert_eq!(cache.put("c".to_string(), vec![4]), vec![("a".to_string(), vec![1])]);
+        assert_eq!(cache.get(&"a".to_string()), None);
+        assert_eq!(cache.get(&"b".to_string()), Some(vec![3]));
+        assert_eq!(cache.get_cache_size(), 2);
+
+        let empty: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(0);
+        assert_eq!(empty.put(1, 1), vec![(1, 1)]);
+        assert_eq!(empty.get_cache_size(), 0);
+    }
+
+    #[test]
+    pub fn test_kv_cache_tracks_hit_rate() {
+        use crate::kv::{KvCache, KvStats, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(4);
+        assert_eq!(cache.get_hit_rate(), 0.0);
+        cache.put(1, 10);
+        for key in [1, 1, 1, 2] {
+            cache.get(&key);
+        }
+        let stats = cache.get_stats();
+        assert_eq!(stats, KvStats { hits: 3, misses: 1, puts: 1, ..KvStats::default() });
+        assert_eq!((stats.lookups(), stats.miss_rate()), (4, 0.25));
+        assert_eq!(cache.get_hit_rate(), 0.75);
+
+        cache.reset_stats();
+        assert_eq!(cache.get_stats(), KvStats::default());
+        assert_eq!(cache.get(&1), Some(10));
+    }
+
+    #[test]
+    pub fn test_kv_cache_entries_expire() {
+        use crate::kv::{ExpirySweeper, KvCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(8);
+        cache.put(1, 10);
+        cache.put_with_ttl(2, 20, Duration::from_millis(1));
+        cache.put_with_ttl(3, 30, Duration::from_millis(1));
+        cache.put_with_ttl(4, 40, Duration::from_secs(3600));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get_cache_size(), 3);
+        assert_eq!(cache.purge_expired(), 1);
+        assert_eq!((cache.get(&1), cache.get(&4)), (Some(10), Some(40)));
+        assert_eq!(cache.get_stats().expirations, 2);
+
+        let cache = Arc::new(cache);
+        let sweeper = ExpirySweeper::spawn(cache.clone(), Duration::from_millis(1));
+        cache.put_with_ttl(5, 50, Duration::from_millis(1));
+        let start = Instant::now();
+        while cache.get_cache_size() > 2 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        sweeper.shutdown();
+        assert_eq!(cache.get_cache_size(), 2);
+        assert_eq!(cache.get_stats().expirations, 3);
+    }
+
+    #[test]
+    pub fn test_kv_cache_evicts_by_weight() {
+        use crate::kv::{KvCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<u64, Vec<u8>> = UnboundedLRUCache::new(usize::MAX)
+            .with_weight_limit(1024, |_, value: &Vec<u8>| value.len());
+        for key in 0..4 {
+            assert!(cache.put(key, vec![0; 256]).is_empty());
+        }
+        assert_eq!(cache.get_weight(), 1024);
+        cache.get(&0);
+
+        let evicted = cache.put(4, vec![0; 600]);
+        let evicted: Vec<u64> = evicted.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(evicted, vec![1, 2, 3]);
+        assert_eq!(cache.get_weight(), 856);
+        assert!(cache.put(0, vec![0; 64]).is_empty());
+        assert_eq!(cache.get_weight(), 664);
+
+        assert_eq!(cache.put(5, vec![0; 2048]).len(), 1);
+        assert_eq!(cache.get(&5), None);
+        assert_eq!(cache.get_cache_size(), 2);
+    }
+
+    #[test]
+    pub fn test_kv_cache_hands_dropped_entries_to_callback() {
+        use crate::kv::{KvCache, UnboundedLRUCache};
+
+        let released = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let on_evict = released.clone();
+        let cache: UnboundedLRUCache<u64, &str> = UnboundedLRUCache::new(2)
+            .with_eviction_callback(move |key, value| on_evict.lock().unwrap().push((key, value)));
+        cache.put(1, "one");
+        cache.put(2, "two");
+        assert!(cache.put(3, "three").is_empty());
+        assert!(cache.put(2, "deux").is_empty());
+        cache.put_with_ttl(4, "four", Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(
+            *released.lock().unwrap(),
+            vec![(1, "one"), (2, "two"), (3, "three"), (4, "four")]
+        );
+        let stats = cache.get_stats();
+        assert_eq!((stats.evictions, stats.expirations), (2, 1));
+    }
+
+    #[test]
+    pub fn test_sharded_lru_cache_spreads_keys() {
+        use crate::kv::{KvCache, ShardedLruCache};
+
+        let cache: ShardedLruCache<u64, u64> = ShardedLruCache::with_capacity(4, 4096);
+        assert_eq!(cache.num_shards(), 4);
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for key in thread * 250..(thread + 1) * 250 {
+                        assert!(cache.put(key, key * 2).is_empty());
+                        assert_eq!(cache.get(&key), Some(key * 2));
+                    }
+                });
+            }
+        });
+        assert_eq!(cache.get(&5000), None);
+        assert_eq!(cache.get_cache_size(), 1000);
+        let stats = cache.get_stats();
+        assert_eq!((stats.puts, stats.hits, stats.misses), (1000, 1000, 1));
+        let shard_stats = cache.get_shard_stats();
+        assert!(shard_stats.iter().all(|stats| stats.puts > 0));
+        assert_eq!(shard_stats.iter().map(|stats| stats.puts).sum::<u64>(), 1000);
+        assert_eq!(cache.shard(&7).get(&7), Some(14));
+    }
+
+    #[test]
+    pub fn test_kv_cache_get_or_insert_computes_once() {
+        use crate::kv::{KvCache, UnboundedLRUCache};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cache: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(2);
+        let computed = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let value = cache.get_or_insert_with(1, || {
+                        computed.fetch_add(1, Ordering::SeqCst);
+                        10
+                    });
+                    assert_eq!(value, 10);
+                });
+            }
+        });
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+        let stats = cache.get_stats();
+        assert_eq!((stats.hits, stats.misses, stats.puts), (7, 1, 1));
+
+        let fail = || Err::<u64, &str>("no value");
+        assert_eq!(cache.try_get_or_insert_with(2, fail), Err("no value"));
+        assert_eq!(cache.get_cache_size(), 1);
+        assert_eq!(cache.try_get_or_insert_with(2, || Ok::<u64, &str>(20)), Ok(20));
+        assert_eq!(cache.try_get_or_insert_with(2, fail), Ok(20));
+        // A third key evicts the least recently used one.
+        assert_eq!(cache.get_or_insert_with(3, || 30), 30);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get_stats().evictions, 1);
+    }
+
+    #[test]
+    pub fn test_kv_cache_peek_keeps_eviction_order() {
+        use crate::kv::{KvCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(2);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        assert_eq!(cache.peek(&1), Some(10));
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&3));
+        assert_eq!(cache.get_stats().lookups(), 0);
+        // Key 1 is still the least recently used, so it goes first.
+        assert_eq!(cache.put(3, 30), vec![(1, 10)]);
+        assert_eq!(cache.peek(&1), None);
+
+        cache.put_with_ttl(4, 40, Duration::ZERO);
+        assert_eq!(cache.peek(&4), None);
+        assert!(!cache.contains_key(&4));
+        assert_eq!(cache.get_cache_size(), 2);
+    }
+
+    #[test]
+    pub fn test_kv_cache_batches_gets_and_puts() {
+        use crate::kv::{KvCache, ShardedLruCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(3);
+        assert_eq!(cache.put_many(vec![(1, 10), (2, 20), (3, 30), (4, 40)]), vec![(1, 10)]);
+        assert_eq!(cache.get_many(&[1, 2, 4]), vec![None, Some(20), Some(40)]);
+        let stats = cache.get_stats();
+        assert_eq!((stats.hits, stats.misses, stats.puts), (2, 1, 4));
+
+        let sharded: ShardedLruCache<u64, u64> = ShardedLruCache::with_capacity(4, 128);
+        let keys: Vec<u64> = (0..32).collect();
+        assert!(sharded.put_many(keys.iter().map(|&key| (key, key + 1)).collect()).is_empty());
+        let mut lookups = keys.clone();
+        lookups.push(100);
+        let values = sharded.get_many(&lookups);
+        assert_eq!(values.len(), 33);
+        for (key, value) in keys.iter().zip(values.iter()) {
+            assert_eq!(*value, Some(key + 1));
+        }
+        assert_eq!(values[32], None);
+        assert_eq!(sharded.get_stats().lookups(), 33);
+    }
+
+    #[test]
+    pub fn test_kv_cache_resize_evicts_on_shrink() {
+        use crate::kv::{KvCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(4);
+        cache.put_many((1..=4).map(|key| (key, key * 10)).collect());
+        cache.get(&1);
+        assert_eq!(cache.resize_cache(2), vec![(2, 20), (3, 30)]);
+        assert_eq!(cache.get_capacity(), 2);
+        assert_eq!(cache.get_cache_size(), 2);
+        assert_eq!(cache.get_stats().evictions, 2);
+
+        assert!(cache.resize_cache(3).is_empty());
+        assert!(cache.put(5, 50).is_empty());
+        assert_eq!(cache.put(6, 60), vec![(4, 40)]);
+    }
+
+    #[test]
+    pub fn test_zcc_kv_cache_serves_pinned_values_zero_copy() {
+        use crate::kv::{UnboundedLRUCache, ZccKvCache};
+
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        let kv = ZccKvCache::new(UnboundedLRUCache::new(16), zero_copy_cache.handle());
+        kv.put("hot", slab.buf(1, 64));
+        kv.put("cold", slab.buf(0, 64));
+
+        let value = kv.get(&"hot").unwrap();
+        assert!(!value.is_zero_copy());
+        assert_eq!(value.bytes().as_ptr(), slab.buf(1, 64).as_ptr());
+        kv.get(&"hot");
+        assert!(kv.get(&"missing").is_none());
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 1)), Some(2));
+
+        zero_copy_cache.update_pinned_list(&());
+        let value = kv.get(&"hot").unwrap();
+        let slices = value.slices().unwrap();
+        assert_eq!((slices[0].segment_id, slices[0].offset, slices[0].len), ((1, 1), 0, 64));
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(1));
+        assert!(!kv.get(&"cold").unwrap().is_zero_copy());
+        drop(value);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 1)), Some(0));
+    }
+
+    #[test]
+    pub fn test_kv_cache_iterates_most_recent_first() {
+        use crate::kv::{KvCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<u64, u64> = UnboundedLRUCache::new(4);
+        cache.put_many((1..=3).map(|key| (key, key * 10)).collect());
+        cache.put_with_ttl(4, 40, Duration::ZERO);
+        cache.get(&1);
+        let stats = cache.get_stats();
+        let entries: Vec<(u64, u64)> = cache.iter().collect();
+        assert_eq!(entries, vec![(1, 10), (3, 30), (2, 20)]);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), vec![1, 3, 2]);
+        assert_eq!(cache.get_stats(), stats);
+        // Iterating didn't promote anything: key 2 is still evicted first.
+        cache.purge_expired();
+        cache.put(5, 50);
+        assert_eq!(cache.put(6, 60), vec![(2, 20)]);
+    }
+
+    #[test]
+    pub fn test_kv_cache_dump_and_load_keep_recency() {
+        use crate::kv::{KvCache, ShardedLruCache, UnboundedLRUCache};
+
+        let cache: UnboundedLRUCache<String, Vec<u8>> = UnboundedLRUCache::new(8);
+        for key in ["a", "b", "c", "d"] {
+            cache.put(key.to_string(), key.as_bytes().repeat(3));
+        }
+        cache.put_with_ttl("gone".to_string(), vec![], Duration::ZERO);
+        cache.put_with_ttl("later".to_string(), vec![7], Duration::from_secs(3600));
+        cache.get(&"a".to_string());
+        let mut dump = Vec::new();
+        assert_eq!(cache.dump(&mut dump).unwrap(), 5);
+
+        // A smaller cache keeps the most recently used entries.
+        let restored: UnboundedLRUCache<String, Vec<u8>> = UnboundedLRUCache::new(3);
+        assert_eq!(restored.load(&mut dump.as_slice()).unwrap(), 5);
+        assert_eq!(restored.keys().collect::<Vec<_>>(), vec!["a", "later", "d"]);
+        assert_eq!(restored.peek(&"a".to_string()), Some(b"aaa".to_vec()));
+
+        let sharded: ShardedLruCache<String, Vec<u8>> = ShardedLruCache::with_capacity(2, 16);
+        sharded.load(&mut dump.as_slice()).unwrap();
+        let mut resharded = Vec::new();
+        assert_eq!(sharded.dump(&mut resharded).unwrap(), 5);
+        let again: UnboundedLRUCache<String, Vec<u8>> = UnboundedLRUCache::new(8);
+        again.load(&mut resharded.as_slice()).unwrap();
+        assert_eq!(again.get_cache_size(), 5);
+
+        let error = restored.load(&mut &b"not a dump"[..]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(restored.load(&mut &dump[..dump.len() - 3]).is_err());
+    }
+
+    #[test]
+    pub fn test_trace_recorder_writes_every_access() {
+        use crate::trace::{TraceReader, TraceRecord, TraceRecorder, DEFAULT_TRACE_BUFFER_SIZE};
+
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("zcc-trace-{}.bin", std::process::id()));
+        let recorder = Arc::new(TraceRecorder::create(&path, DEFAULT_TRACE_BUFFER_SIZE).unwrap());
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.set_access_tracer(recorder.clone());
+        let buf = slab.buf(0, 64);
+        assert!(zero_copy_cache.record_access_and_get_io_info(buf).is_err());
+        zero_copy_cache.update_pinned_list(&());
+        zero_copy_cache.record_access_and_get_io_info(buf).unwrap();
+        zero_copy_cache.record_io_completion(buf);
+        assert!(zero_copy_cache.record_access_and_get_io_info(slab.buf(1, 32)).is_err());
+        assert!(zero_copy_cache.record_access_and_get_io_info(&[0u8; 16]).is_err());
+        recorder.finish().unwrap();
+
+        let records: Vec<TraceRecord> =
+            TraceReader::open(&path).unwrap().map(|record| record.unwrap()).collect();
+        let summary: Vec<_> = records.iter().map(|r| (r.segment_id, r.hit, r.len)).collect();
+        let expected = vec![
+            (Some((1, 0)), false, 64),
+            (Some((1, 0)), true, 64),
+            (Some((1, 1)), false, 32),
+            (None, false, 16),
+        ];
+        assert_eq!(summary, expected);
+        assert!(records.windows(2).all(|pair| pair[0].timestamp_ns <= pair[1].timestamp_ns));
+
+        // With buffers of two records, the writer may fall behind and records get dropped,
+        // but every record is either written or counted.
+        let recorder = TraceRecorder::create(&path, 58).unwrap();
+        for segment in 0..1000 {
+            recorder.record(Some((2, segment)), segment % 2 == 0, 128);
+        }
+        recorder.finish().unwrap();
+        let written = TraceReader::open(&path).unwrap().count() as u64;
+        assert_eq!(written + recorder.get_dropped_records(), 1000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    pub fn test_prometheus_exporter_survives_idle_clients() {
+        use crate::prometheus::spawn_exporter_with_timeout;
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        let timeout = Duration::from_millis(100);
+        let exporter = spawn_exporter_with_timeout(zero_copy_cache, "127.0.0.1:0", timeout);
+        let exporter = exporter.unwrap();
+        // Connects and never sends a request.
+        let _idle = TcpStream::connect(exporter.local_addr()).unwrap();
+
+        let mut scrape = TcpStream::connect(exporter.local_addr()).unwrap();
+        scrape.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        scrape.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("zcc_hits_total 0"));
+
+        let exporter_addr = exporter.local_addr();
+        exporter.shutdown();
+        let refused = TcpStream::connect(exporter_addr);
+        assert!(refused.is_err());
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    pub fn test_prometheus_render_types_and_labels() {
+        use crate::prometheus::render;
+
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        zero_copy_cache.record_access_and_get_io_info(slab.buf(0, 64)).unwrap();
+        let text = render(&zero_copy_cache);
+        for line in [
+            "# HELP zcc_hits_total Accesses served zero-copy.",
+            "# TYPE zcc_hits_total counter",
+            "zcc_hits_total 1",
+            "# HELP zcc_slab_hits_total Accesses served zero-copy, per slab.",
+            "# TYPE zcc_slab_hits_total counter",
+            "zcc_slab_hits_total{slab=\"1\"} 1",
+            "# TYPE zcc_slab_accesses_total counter",
+            "# TYPE zcc_slab_bytes_pinned gauge",
+            "zcc_slab_bytes_pinned{slab=\"1\"} 8192",
+            "zcc_slab_segments{slab=\"1\"} 2",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    pub fn test_prometheus_render_escapes_slab_labels() {
+        use crate::prometheus::render;
+
+        /// `ExampleSlab` with an id whose `Debug` output has quotes and a backslash.
+        #[derive(Debug)]
+        struct NamedSlab(ExampleSlab);
+
+        impl DatapathSlab for NamedSlab {
+            type SlabId = &'static str;
+            type IOInfo = usize;
+            type PinningState = bool;
+            type PrivateInfo = ();
+
+            fn default_pinning_state(&self) -> Self::PinningState {
+                false
+            }
+
+            fn get_slab_id(&self) -> Self::SlabId {
+                "rack\"7\\a"
+            }
+
+            fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+                ExampleSlab::is_pinned(pinning_state)
+            }
+
+            fn pin_segment(
+                pinning_state: &mut Self::PinningState,
+                private_info: &Self::PrivateInfo,
+                start_address: *mut ::std::os::raw::c_void,
+                len: usize,
+            ) {
+                ExampleSlab::pin_segment(pinning_state, private_info, start_address, len)
+            }
+
+            fn unpin_segment(pinning_state: &mut Self::PinningState) {
+                ExampleSlab::unpin_segment(pinning_state)
+            }
+
+            fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+                ExampleSlab::get_io_info(pinning_state)
+            }
+
+            fn get_total_num_pages(&self) -> usize {
+                self.0.get_total_num_pages()
+            }
+
+            fn get_start_address(&self) -> *mut ::std::os::raw::c_void {
+                self.0.get_start_address()
+            }
+
+            fn get_page_size(&self) -> pagesizes::PageSize {
+                self.0.get_page_size()
+            }
+        }
+
+        let slab = NamedSlab(ExampleSlab::new(1, 2));
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<NamedSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        let text = render(&zero_copy_cache);
+        let expected = r#"zcc_slab_segments{slab="\"rack\\\"7\\\\a\""} 2"#;
+        assert!(text.lines().any(|l| l == expected), "missing {} in\n{}", expected, text);
+    }
+
+    #[cfg(feature = "trace-spans")]
+    #[test]
+    pub fn test_repin_cycle_emits_pinning_spans() {
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id};
+        use tracing::{Event, Subscriber};
+        use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+        /// Names of the spans opened and messages of the events emitted, in order.
+        #[derive(Clone, Default)]
+        struct Collect(Arc<Mutex<Vec<String>>>);
+
+        struct Message<'a>(&'a mut String);
+
+        impl Visit for Message<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    *self.0 = format!("{:?}", value);
+                }
+            }
+        }
+
+        impl<S: Subscriber> Layer<S> for Collect {
+            fn new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+                self.0.lock().unwrap().push(attrs.metadata().name().to_string());
+            }
+
+            fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+                let mut message = String::new();
+                event.record(&mut Message(&mut message));
+                self.0.lock().unwrap().push(message);
+            }
+        }
+
+        let collect = Collect::default();
+        let subscriber = tracing_subscriber::registry().with(collect.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let slab = ExampleSlab::new(1, 2);
+            let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+            zero_copy_cache.initialize_slab(&slab, 2, false, ());
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+            zero_copy_cache.update_pinned_list(&());
+            collect.0.lock().unwrap().clear();
+            for _ in 0..3 {
+                zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+            }
+            zero_copy_cache.update_pinned_list(&());
+        });
+
+        let collected = collect.0.lock().unwrap().clone();
+        let position = |name: &str| {
+            collected
+                .iter()
+                .position(|entry| entry == name)
+                .unwrap_or_else(|| panic!("{} not in {:?}", name, collected))
+        };
+        let cycle = position("update_pinned_list");
+        assert!(cycle < position("unpin decision"));
+        assert!(position("unpin decision") < position("unregister"));
+        assert!(position("unregister") < position("register"));
+        assert!(position("register") < position("pin decision"));
+    }
+
+    #[cfg(feature = "dpdk")]
+    #[test]
+    pub fn test_dpdk_slab_registers_and_maps_segments() {
+        use crate::dpdk::fake_dpdk::{self, DpdkCall, IOVA_OFFSET};
+        use crate::dpdk::{DpdkDevice, DpdkSlab, RteDevice};
+
+        let memory = ExampleSlab::new(0, 4);
+        let addr = memory.start_address as usize + 2 * pagesizes::PGSIZE_4KB;
+        let len = 2 * pagesizes::PGSIZE_4KB;
+        let iova = addr as u64 + IOVA_OFFSET;
+        let page_size = pagesizes::PageSize::PG4KB;
+        let slab = unsafe { DpdkSlab::new(1, memory.start_address as _, 4, page_size) };
+        let dev = unsafe { DpdkDevice::new(0x1000 as *mut RteDevice) };
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<DpdkSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, dev);
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&dev);
+        let registered = vec![
+            DpdkCall::ExtmemRegister {
+                addr,
+                len,
+                n_pages: 2,
+                page_sz: pagesizes::PGSIZE_4KB,
+            },
+            DpdkCall::DmaMap {
+                dev: 0x1000,
+                addr,
+                iova,
+                len,
+            },
+        ];
+        assert_eq!(fake_dpdk::take_calls(), registered);
+        let buf = memory.buf(2, 64);
+        assert_eq!(zero_copy_cache.record_access_and_get_io_info(buf), Ok((1, iova)));
+        zero_copy_cache.record_io_completion(buf);
+
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+        let unregistered = vec![
+            DpdkCall::DmaUnmap {
+                dev: 0x1000,
+                addr,
+                iova,
+                len,
+            },
+            DpdkCall::ExtmemUnregister { addr, len },
+        ];
+        assert_eq!(fake_dpdk::take_calls(), unregistered);
+
+        // A failed DMA mapping undoes the registration and leaves the segment unpinned.
+        fake_dpdk::fail_dma_map(true);
+        zero_copy_cache.update_pinned_list(&dev);
+        let mut calls = registered;
+        calls.push(DpdkCall::ExtmemUnregister { addr, len });
+        assert_eq!(fake_dpdk::take_calls(), calls);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 0);
+        fake_dpdk::fail_dma_map(false);
+    }
+
+    #[cfg(feature = "ibverbs")]
+    #[test]
+    pub fn test_ibv_slab_registers_memory_regions() {
+        use crate::ibverbs::fake_ibverbs::{self, IbvCall};
+        use crate::ibverbs::{
+            IbvPd, IbvPdRaw, IbvRegError, IbvSlab, IBV_ACCESS_LOCAL_WRITE, IBV_ACCESS_REMOTE_READ,
+        };
+
+        let memory = ExampleSlab::new(0, 4);
+        let addr = memory.start_address as usize + 2 * pagesizes::PGSIZE_4KB;
+        let length = 2 * pagesizes::PGSIZE_4KB;
+        let access = IBV_ACCESS_LOCAL_WRITE | IBV_ACCESS_REMOTE_READ;
+        let page_size = pagesizes::PageSize::PG4KB;
+        let slab = unsafe { IbvSlab::new(1, memory.start_address as _, 4, page_size) };
+        let new_pd = || unsafe { IbvPd::new(0x2000 as *mut IbvPdRaw, access) };
+        let pd = new_pd();
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<IbvSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, new_pd());
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&pd);
+        let registered = IbvCall::RegMr {
+            pd: 0x2000,
+            addr,
+            length,
+            access,
+        };
+        assert_eq!(fake_ibverbs::take_calls(), vec![registered.clone()]);
+        let buf = memory.buf(2, 64);
+        let (_, keys) = zero_copy_cache.record_access_and_get_io_info(buf).unwrap();
+        assert!(keys.lkey != 0 && keys.rkey == keys.lkey + 1000);
+        zero_copy_cache.record_io_completion(buf);
+
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+        assert_eq!(fake_ibverbs::take_calls(), vec![IbvCall::DeregMr { addr, length }]);
+
+        // ENOMEM leaves the segment unpinned and is reported on the protection domain.
+        fake_ibverbs::fail_reg_mr(true);
+        zero_copy_cache.update_pinned_list(&pd);
+        assert_eq!(fake_ibverbs::take_calls(), vec![registered]);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 0);
+        assert_eq!(pd.registration_failures(), 1);
+        assert_eq!(pd.take_last_error(), Some(IbvRegError::OutOfMemory));
+        assert_eq!(
+            zero_copy_cache.record_access_and_get_io_info(buf).map(|(_, keys)| keys),
+            Err(MissReason::NotPinned)
+        );
+        fake_ibverbs::fail_reg_mr(false);
+    }
+
+    #[cfg(feature = "cuda")]
+    #[test]
+    pub fn test_cuda_host_slab_page_locks_segments() {
+        use crate::cuda::fake_cudart::{self, CudaCall, DEVICE_OFFSET};
+        use crate::cuda::{CudaHostSlab, CUDA_HOST_REGISTER_MAPPED, CUDA_HOST_REGISTER_PORTABLE};
+
+        let memory = ExampleSlab::new(0, 4);
+        let ptr = memory.start_address as usize + 2 * pagesizes::PGSIZE_4KB;
+        let size = 2 * pagesizes::PGSIZE_4KB;
+        let page_size = pagesizes::PageSize::PG4KB;
+        let flags = CUDA_HOST_REGISTER_PORTABLE;
+        let slab = unsafe { CudaHostSlab::new(1, memory.start_address as _, 4, page_size, flags) };
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<CudaHostSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.update_stats((1, 1));
+        zero_copy_cache.update_pinned_list(&());
+        // The device pointer needs the mapping, so it is always requested.
+        let registered = vec![
+            CudaCall::Register {
+                ptr,
+                size,
+                flags: CUDA_HOST_REGISTER_PORTABLE | CUDA_HOST_REGISTER_MAPPED,
+            },
+            CudaCall::GetDevicePointer { host: ptr },
+        ];
+        assert_eq!(fake_cudart::take_calls(), registered);
+        let buf = memory.buf(2, 64);
+        let device = ptr + DEVICE_OFFSET;
+        assert_eq!(zero_copy_cache.record_access_and_get_io_info(buf), Ok((1, device)));
+        zero_copy_cache.record_io_completion(buf);
+
+        assert_eq!(zero_copy_cache.unpin_all_segments(), 1);
+        assert_eq!(fake_cudart::take_calls(), vec![CudaCall::Unregister { ptr }]);
+
+        // Without a device pointer the registration is undone.
+        fake_cudart::fail_device_pointer(true);
+        zero_copy_cache.update_pinned_list(&());
+        let mut calls = registered;
+        calls.push(CudaCall::Unregister { ptr });
+        assert_eq!(fake_cudart::take_calls(), calls);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 0);
+        fake_cudart::fail_device_pointer(false);
+    }
+
+    #[test]
+    pub fn test_on_demand_pin_is_recorded_as_hit() {
+        use crate::trace::AccessTracer;
+        use std::sync::Mutex;
+
+        type Access = (Option<SegmentId<ExampleSlab>>, bool, usize);
+
+        #[derive(Debug, Default)]
+        struct Accesses(Mutex<Vec<Access>>);
+
+        impl AccessTracer<SegmentId<ExampleSlab>> for Accesses {
+            fn on_access(&self, segment_id: Option<SegmentId<ExampleSlab>>, hit: bool, len: usize) {
+                self.0.lock().unwrap().push((segment_id, hit, len));
+            }
+        }
+
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(2));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        let accesses = Arc::new(Accesses::default());
+        zero_copy_cache.set_access_tracer(accesses.clone());
+        assert!(zero_copy_cache.record_and_pin_on_demand(slab.buf(0, 64), &()).is_ok());
+        zero_copy_cache.record_io_completion(slab.buf(0, 64));
+
+        let metrics = zero_copy_cache.get_metrics();
+        assert_eq!((metrics.hits, metrics.misses), (1, 0));
+        assert_eq!(*accesses.0.lock().unwrap(), vec![(Some((1, 0)), true, 64)]);
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 0)), Some(1));
+    }
+
+    #[cfg(feature = "dpdk")]
+    #[test]
+    pub fn test_dpdk_slab_page_size_follows_alignment() {
+        use crate::data_structures::DatapathSlab;
+        use crate::dpdk::fake_dpdk::{self, DpdkCall};
+        use crate::dpdk::{DpdkDevice, DpdkPinningState, DpdkSlab};
+
+        // The fake never touches the memory, so the ranges need not be mapped.
+        let len = pagesizes::PGSIZE_2MB;
+        for (addr, n_pages, page_sz) in [
+            (8 * pagesizes::PGSIZE_2MB, 1, pagesizes::PGSIZE_2MB),
+            (8 * pagesizes::PGSIZE_2MB + pagesizes::PGSIZE_4KB, 512, pagesizes::PGSIZE_4KB),
+        ] {
+            let mut state = DpdkPinningState::default();
+            DpdkSlab::pin_segment(&mut state, &DpdkDevice::none(), addr as _, len);
+            assert!(DpdkSlab::is_pinned(&state));
+            DpdkSlab::unpin_segment(&mut state);
+            let register = DpdkCall::ExtmemRegister {
+                addr,
+                len,
+                n_pages,
+                page_sz,
+            };
+            let unregister = DpdkCall::ExtmemUnregister { addr, len };
+            assert_eq!(fake_dpdk::take_calls(), vec![register, unregister]);
+        }
+    }
+
+    #[test]
+    pub fn test_shutdown_unpins_and_clears_segments() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::default());
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        let other = zero_copy_cache.clone();
+        assert_eq!(zero_copy_cache.shutdown(), 2);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 0);
+        assert_eq!(other.current_segments_pinned(), 0);
+        assert!(zero_copy_cache.get_segment_id(slab.buf(0, 64)).is_none());
+    }
+
+    #[test]
+    pub fn test_quiesce_timeout_skips_or_forces_unpin() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        zero_copy_cache.update_pinned_list(&());
+        // One IO in flight against segment 0, which then drops out of the hotset.
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+            .is_some());
+        for _ in 0..3 {
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+        }
+
+        zero_copy_cache.set_quiesce_timeout(Duration::from_millis(10), QuiesceTimeoutAction::Skip);
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!(churn.num_unpinned, 0);
+        assert!(zero_copy_cache.current_pinned_list.contains(&(1, 0)));
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 2);
+
+        zero_copy_cache.set_quiesce_timeout(
+            Duration::from_millis(10),
+            QuiesceTimeoutAction::ForceUnpin,
+        );
+        let churn = zero_copy_cache.update_pinned_list(&());
+        assert_eq!(churn.num_unpinned, 1);
+        assert_eq!(zero_copy_cache.current_segments_pinned(), 1);
+    }
+
+    #[test]
+    pub fn test_quiesce_waits_for_io_completion() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, true, ());
+        for _ in 0..3 {
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+            zero_copy_cache.record_io_completion(slab.buf(1, 64));
+        }
+        assert!(zero_copy_cache
+            .record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64))
+            .is_some());
+
+        let mut completer = zero_copy_cache.clone();
+        let addr = slab.buf(0, 64).as_ptr() as usize;
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            let buf = unsafe { std::slice::from_raw_parts(addr as *const u8, 64) };
+            completer.record_io_completion(buf);
+        });
+        zero_copy_cache.current_pinned_list = [(1, 0), (1, 1)].into_iter().collect();
+        let churn = zero_copy_cache.update_pinned_list(&());
+        handle.join().unwrap();
+        assert_eq!(churn.num_unpinned, 1);
+        assert_eq!(zero_copy_cache.get_segment_in_flight_io((1, 0)), Some(0));
+    }
+
+    #[test]
+    pub fn test_pinned_set_snapshot_published_each_cycle() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        let mut datapath = zero_copy_cache.clone();
+        assert_eq!(
+            datapath.record_access_and_get_io_info(slab.buf(1, 64)),
+            Err(MissReason::NotPinned)
+        );
+
+        zero_copy_cache.update_pinned_list(&());
+        let snapshot = zero_copy_cache.pinned_set_snapshot();
+        assert_eq!(snapshot.get_epoch(), 1);
+        assert!(snapshot.contains(&(1, 1)));
+        assert_eq!(snapshot.len(), 1);
+        // The datapath clone picks up the new epoch on its next lookup.
+        assert!(datapath
+            .record_access_and_get_io_info(slab.buf(1, 64))
+            .is_ok());
+    }
+
+    #[test]
+    pub fn test_batched_access_recording_flushes_by_count() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.enable_batched_access_recording(3, Duration::from_secs(3600));
+        for _ in 0..2 {
+            zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+        }
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), None);
+        assert_eq!(zero_copy_cache.get_segment_access_count((1, 1)), Some(2));
+
+        // A clone buffers separately and does not duplicate the pending accesses.
+        let mut other = zero_copy_cache.clone();
+        other.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        assert_eq!(other.get_segment_access_count((1, 1)), None);
+        drop(other);
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 0)), Some(1));
+
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), Some(3));
+    }
+
+    #[test]
+    pub fn test_access_pipeline_drains_and_counts_drops() {
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        let pipeline = zero_copy_cache.enable_access_pipeline(1024);
+        let mut datapath = zero_copy_cache.clone();
+        for _ in 0..5 {
+            datapath.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+        }
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(0, 64));
+        drop(datapath);
+        zero_copy_cache.disable_access_pipeline();
+        pipeline.join();
+
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 1)), Some(5));
+        assert_eq!(zero_copy_cache.cache_builder.get_access_count((1, 0)), Some(1));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    pub fn test_async_pin_loop_runs_until_shutdown() {
+        use crate::asyncloop::PinLoopShutdown;
+
+        let slab = ExampleSlab::new(1, 2);
+        let mut zero_copy_cache = ZeroCopyCache::new(MfuCache::<ExampleSlab>::new(1));
+        zero_copy_cache.initialize_slab(&slab, 2, false, ());
+        zero_copy_cache.record_access_and_get_io_info_if_pinned_manual(slab.buf(1, 64));
+        let observer = zero_copy_cache.clone();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let cycles = runtime.block_on(async {
+            let (shutdown, signal) = PinLoopShutdown::new();
+            let pin_loop = tokio::spawn(zero_copy_cache.run_pin_loop(
+                (),
+                Duration::from_millis(5),
+                signal,
+            ));
+            while observer.current_segments_pinned() == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            shutdown.shutdown();
+            pin_loop.await.unwrap()
+        });
+        assert!(cycles >= 1);
+        assert_eq!(observer.current_segments_pinned(), 0);
+    }
+
+    #[test]
+    pub fn test_cache_is_send_and_sync_without_unsafe_impls() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<crate::data_structures::DatapathSegment<ExampleSlab>>();
+        assert_send_sync::<ZeroCopyCache<ExampleSlab, MfuCache<ExampleSlab>>>();
+    }
+
     pub fn create_random_array(no_of_segments: usize, no_of_elements: usize) -> Vec<usize> {
         let mut rand_vec: Vec<usize> = Vec::new();
         let mut rand_rng = rand::thread_rng();
@@ -60,10 +3114,10 @@ mod test {
         rand_vec
     }
 
-    pub fn create_segments(no_of_segments: usize) -> Vec<ExampleSegment> {
+    pub fn create_segments(no_of_segments: usize) -> Vec<SegmentId<ExampleSlab>> {
         let mut segment_vector = Vec::new();
         for i in 0..no_of_segments {
-            segment_vector.push(ExampleSegment::new((i + 1).try_into().unwrap(), 4096));
+            segment_vector.push((1, i));
         }
         segment_vector
     }
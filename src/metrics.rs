@@ -0,0 +1,259 @@
+//! Counters for datapath lookups and pinning engine operations. Counters are atomics so the
+//! datapath and the pin/unpin thread can update them without taking any lock.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LockResult, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::data_structures::HotsetChurn;
+
+/// Why an access could not be served zero-copy.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum MissReason {
+    /// The buffer is not inside any managed segment.
+    Unmanaged,
+    /// The segment is managed but currently unpinned.
+    NotPinned,
+    /// The segment is being unpinned and is draining in-flight IO.
+    Quiescing,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    hits: AtomicU64,
+    misses_not_pinned: AtomicU64,
+    misses_quiescing: AtomicU64,
+    unmanaged: AtomicU64,
+    pins: AtomicU64,
+    unpins: AtomicU64,
+    evictions: AtomicU64,
+    orphan_completions: AtomicU64,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub struct MetricsSnapshot {
+    /// Accesses that returned IO info for a pinned segment.
+    pub hits: u64,
+    /// Accesses to a managed segment that could not be served zero-copy.
+    pub misses: u64,
+    pub misses_not_pinned: u64,
+    pub misses_quiescing: u64,
+    /// Accesses to buffers outside any managed segment.
+    pub unmanaged: u64,
+    pub pins: u64,
+    pub unpins: u64,
+    /// Unpins caused by a segment leaving the hotset.
+    pub evictions: u64,
+    /// IO completions for a segment with no IO in flight, which were ignored.
+    pub orphan_completions: u64,
+}
+
+impl MetricsSnapshot {
+    /// Every access recorded, whether it hit, missed or was unmanaged.
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses + self.unmanaged
+    }
+
+    /// Fraction of accesses to managed segments that were hits.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl std::ops::AddAssign for MetricsSnapshot {
+    fn add_assign(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.misses_not_pinned += other.misses_not_pinned;
+        self.misses_quiescing += other.misses_quiescing;
+        self.unmanaged += other.unmanaged;
+        self.pins += other.pins;
+        self.unpins += other.unpins;
+        self.evictions += other.evictions;
+        self.orphan_completions += other.orphan_completions;
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_miss(&self, reason: MissReason) {
+        let counter = match reason {
+            MissReason::Unmanaged => &self.unmanaged,
+            MissReason::NotPinned => &self.misses_not_pinned,
+            MissReason::Quiescing => &self.misses_quiescing,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_pin(&self) {
+        self.pins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_unpin(&self) {
+        self.unpins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_eviction(&self) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_orphan_completions(&self, count: usize) {
+        self.orphan_completions.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let misses_not_pinned = self.misses_not_pinned.load(Ordering::Relaxed);
+        let misses_quiescing = self.misses_quiescing.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: misses_not_pinned + misses_quiescing,
+            misses_not_pinned,
+            misses_quiescing,
+            unmanaged: self.unmanaged.load(Ordering::Relaxed),
+            pins: self.pins.load(Ordering::Relaxed),
+            unpins: self.unpins.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            orphan_completions: self.orphan_completions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.hits,
+            &self.misses_not_pinned,
+            &self.misses_quiescing,
+            &self.unmanaged,
+            &self.pins,
+            &self.unpins,
+            &self.evictions,
+            &self.orphan_completions,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Locks taken over after a thread panicked while holding them, across every cache in the
+/// process. Locks are shared between caches, segments and worker threads, so this is global.
+static LOCK_RECOVERIES: AtomicU64 = AtomicU64::new(0);
+
+/// How often a lock poisoned by a panicked thread was recovered instead of propagating the
+/// panic. Non-zero means some state may have been left half-updated, e.g. access statistics.
+pub fn lock_recoveries() -> u64 {
+    LOCK_RECOVERIES.load(Ordering::Relaxed)
+}
+
+/// The guard of a lock or condvar wait, even if another thread panicked while holding it.
+pub(crate) fn recover<G>(result: LockResult<G>) -> G {
+    result.unwrap_or_else(|poisoned| {
+        LOCK_RECOVERIES.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!("Recovering a lock poisoned by a panicked thread");
+        poisoned.into_inner()
+    })
+}
+
+/// `Mutex::lock` that keeps the datapath running when a thread panicked with the lock held:
+/// the state is taken over as-is and the poison cleared, so it is only counted once.
+pub(crate) trait LockOrRecover<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockOrRecover<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        let guard = recover(self.lock());
+        if self.is_poisoned() {
+            self.clear_poison();
+        }
+        guard
+    }
+}
+
+pub const DEFAULT_TIME_SERIES_LEN: usize = 1024;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct PinningSample {
+    pub timestamp: SystemTime,
+    pub bytes_pinned: usize,
+    pub segments_pinned: usize,
+}
+
+/// Fixed-size ring buffer of pinning samples; the oldest sample is dropped when full.
+#[derive(Debug, Clone)]
+pub struct PinningTimeSeries {
+    capacity: usize,
+    samples: VecDeque<PinningSample>,
+}
+
+impl Default for PinningTimeSeries {
+    fn default() -> Self {
+        PinningTimeSeries::new(DEFAULT_TIME_SERIES_LEN)
+    }
+}
+
+impl PinningTimeSeries {
+    pub fn new(capacity: usize) -> Self {
+        PinningTimeSeries {
+            capacity: capacity.max(1),
+            samples: VecDeque::with_capacity(capacity.max(1)),
+        }
+    }
+
+    pub fn push(&mut self, sample: PinningSample) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Samples, oldest first.
+    pub fn samples(&self) -> Vec<PinningSample> {
+        self.samples.iter().copied().collect()
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the capacity, dropping the oldest samples if it shrinks.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.samples.len() > self.capacity {
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// Receives pinning engine and datapath events as they happen, so embedders can route them
+/// into their own telemetry. Every callback defaults to doing nothing.
+pub trait MetricsSink<ID>: std::fmt::Debug + Send + Sync {
+    /// A segment of `bytes` was registered.
+    fn on_pin(&self, _segment_id: ID, _bytes: usize) {}
+
+    /// A segment of `bytes` was unregistered.
+    fn on_unpin(&self, _segment_id: ID, _bytes: usize) {}
+
+    /// An access was served zero-copy.
+    fn on_hit(&self, _segment_id: ID) {}
+
+    /// An access could not be served zero-copy; `segment_id` is `None` for unmanaged buffers.
+    fn on_miss(&self, _segment_id: Option<ID>, _reason: MissReason) {}
+
+    /// A repin cycle finished.
+    fn on_cycle(&self, _churn: &HotsetChurn) {}
+}
+
+/// Sink that ignores every event.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopMetricsSink;
+
+impl<ID> MetricsSink<ID> for NoopMetricsSink {}
@@ -0,0 +1,130 @@
+//! Most-frequently-used hotset policy: pins the segments with the highest access counts.
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use crate::data_structures::{
+    DatapathSlab, DecayPolicy, SegmentId, SegmentStatMap, Stats, DEFAULT_CACHE_SIZE,
+};
+use crate::segment_policy::SegmentPolicy;
+
+#[derive(Debug)]
+pub struct MfuCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Maximum number of segments returned as the hotset.
+    num_segments: usize,
+    /// Stats maintained for each segment.
+    segment_stats: SegmentStatMap<SegmentId<Slab>>,
+}
+
+impl<Slab> Default for MfuCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        MfuCache::new(DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl<Slab> MfuCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn new(num_segments: usize) -> Self {
+        MfuCache {
+            num_segments,
+            segment_stats: SegmentStatMap::default(),
+        }
+    }
+
+    pub fn get_stats(&self, segment_id: SegmentId<Slab>) -> Option<&Stats> {
+        self.segment_stats.get(&segment_id)
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for MfuCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        self.segment_stats
+            .entry(segment_id)
+            .and_modify(|stats| stats.update_stats())
+            .or_default();
+    }
+
+    fn update_access_by(&mut self, segment_id: SegmentId<Slab>, count: u64) {
+        if count == 0 {
+            return;
+        }
+        let stats = self.segment_stats.entry(segment_id).or_insert(Stats {
+            access_count: 0,
+            last_access_time: SystemTime::now(),
+        });
+        stats.access_count += count as i64;
+        stats.last_access_time = SystemTime::now();
+    }
+
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.segment_stats
+            .get(&segment_id)
+            .map(|stats| stats.get_access_count())
+    }
+
+    fn get_last_access_time(&self, segment_id: SegmentId<Slab>) -> Option<SystemTime> {
+        self.segment_stats
+            .get(&segment_id)
+            .map(|stats| stats.last_access_time)
+    }
+
+    /// Currently ineffecient strategy of sorting through the vector and getting the top segments.
+    /// Need better strategies to performance these actions.
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        let mut sorting_vec: Vec<(SegmentId<Slab>, i64)> = self
+            .segment_stats
+            .iter()
+            .map(|(k, v)| (*k, v.access_count))
+            .collect();
+        sorting_vec.sort_by_key(|b| std::cmp::Reverse(b.1));
+        sorting_vec
+            .into_iter()
+            .take(self.num_segments)
+            .map(|(seg_id, _)| seg_id)
+            .collect()
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    fn set_num_segments(&mut self, num_segments: usize) {
+        self.num_segments = num_segments;
+    }
+
+    fn reset(&mut self) {
+        self.segment_stats.clear();
+    }
+
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        self.segment_stats.remove(&segment_id);
+    }
+
+    fn decay(&mut self, policy: DecayPolicy) {
+        let subtract = match policy {
+            DecayPolicy::Halving => None,
+            DecayPolicy::SubtractMin => self.segment_stats.values().map(|s| s.access_count).min(),
+            DecayPolicy::FullReset => {
+                self.reset();
+                return;
+            }
+        };
+        self.segment_stats.retain(|_, stats| {
+            match subtract {
+                Some(min) => stats.access_count -= min,
+                None => stats.access_count /= 2,
+            }
+            stats.access_count > 0
+        });
+    }
+}
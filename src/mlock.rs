@@ -0,0 +1,131 @@
+//! Reference `DatapathSlab` backed by (huge)page memory from the `hugepage` module, pinned
+//! with `mlock` and unpinned with `munlock`. Useful to try the crate, or to exercise real
+//! pinning in tests, without writing a slab for a specific NIC or driver first.
+//!
+//! Locking counts against `RLIMIT_MEMLOCK`. A segment that could not be locked stays
+//! unpinned, so the datapath keeps treating it as a miss.
+use std::io;
+use std::os::raw::c_void;
+
+use crate::data_structures::DatapathSlab;
+use crate::hugepage::HugepageMemory;
+use crate::pagesizes::PageSize;
+
+/// Address and length of a segment, while it is locked.
+#[derive(Debug, Default)]
+pub struct MlockState {
+    locked: Option<(usize, usize)>,
+}
+
+#[derive(Debug)]
+pub struct MlockSlab {
+    slab_id: usize,
+    memory: HugepageMemory,
+}
+
+impl MlockSlab {
+    /// Map `num_pages` zeroed pages of `page_size`. Pages larger than the base page come from
+    /// the hugetlb pool, which fails if the system has no free hugepages of that size.
+    pub fn new(slab_id: usize, num_pages: usize, page_size: PageSize) -> io::Result<Self> {
+        let len = num_pages * page_size.bytes();
+        Ok(Self::from_memory(slab_id, HugepageMemory::anonymous(len, page_size)?))
+    }
+
+    /// Map at least `len` bytes with `page_size` pages, falling back to base pages if no
+    /// hugepages are available.
+    pub fn new_with_fallback(slab_id: usize, len: usize, page_size: PageSize) -> io::Result<Self> {
+        let memory = HugepageMemory::anonymous_with_fallback(len, page_size)?;
+        Ok(Self::from_memory(slab_id, memory))
+    }
+
+    /// Manage memory allocated by the `hugepage` helpers, e.g. a shared memfd.
+    pub fn from_memory(slab_id: usize, memory: HugepageMemory) -> Self {
+        MlockSlab { slab_id, memory }
+    }
+
+    pub fn memory(&self) -> &HugepageMemory {
+        &self.memory
+    }
+
+    /// Size of the mapping in bytes.
+    pub fn get_size(&self) -> usize {
+        self.memory.get_size()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        let start_address = self.get_start_address() as *const u8;
+        unsafe { std::slice::from_raw_parts(start_address, self.get_size()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        let start_address = self.get_start_address() as *mut u8;
+        unsafe { std::slice::from_raw_parts_mut(start_address, self.get_size()) }
+    }
+}
+
+impl DatapathSlab for MlockSlab {
+    type SlabId = usize;
+    /// Start address of the locked segment.
+    type IOInfo = usize;
+    type PinningState = MlockState;
+    type PrivateInfo = ();
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        MlockState::default()
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.slab_id
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        pinning_state.locked.is_some()
+    }
+
+    // The range comes from a segment of this slab's mapping; the trait fixes the signature.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        _private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        if pinning_state.locked.is_some() {
+            return;
+        }
+        if unsafe { libc::mlock(start_address, len) } != 0 {
+            tracing::warn!(
+                "mlock of {} bytes at {:?} failed: {}",
+                len,
+                start_address,
+                io::Error::last_os_error()
+            );
+            return;
+        }
+        pinning_state.locked = Some((start_address as usize, len));
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        if let Some((start_address, len)) = pinning_state.locked.take() {
+            if unsafe { libc::munlock(start_address as *const c_void, len) } != 0 {
+                tracing::warn!("munlock of {} bytes failed: {}", len, io::Error::last_os_error());
+            }
+        }
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        pinning_state.locked.map_or(0, |(start_address, _)| start_address)
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.memory.get_num_pages()
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.memory.get_start_address()
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.memory.get_page_size()
+    }
+}
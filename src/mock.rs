@@ -0,0 +1,182 @@
+//! `MockSlab`, a `DatapathSlab` over plain heap memory for testing integrations. It counts
+//! pin and unpin calls, can fail a chosen pin and can slow pins and unpins down, so error
+//! paths and slow registrations can be exercised without real hardware.
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::data_structures::DatapathSlab;
+use crate::metrics::LockOrRecover;
+use crate::pagesizes::{self, PageSize};
+
+/// Call counts and injected behaviour, shared by a slab and the pinning state of all of its
+/// segments.
+#[derive(Debug, Default)]
+struct MockControl {
+    pins: AtomicUsize,
+    unpins: AtomicUsize,
+    failed_pins: AtomicUsize,
+    /// 1-based pin call to fail, 0 for none.
+    fail_nth_pin: AtomicUsize,
+    pin_latency: Mutex<Duration>,
+    unpin_latency: Mutex<Duration>,
+    /// Source of registration keys handed out as `IOInfo`.
+    next_key: AtomicUsize,
+}
+
+#[derive(Debug)]
+pub struct MockPinningState {
+    /// Registration key while pinned.
+    key: Option<usize>,
+    control: Arc<MockControl>,
+}
+
+#[derive(Debug)]
+pub struct MockSlab {
+    slab_id: usize,
+    num_pages: usize,
+    start_address: *mut u8,
+    control: Arc<MockControl>,
+}
+
+// SAFETY: the allocation is owned by the slab and only freed on drop.
+unsafe impl Send for MockSlab {}
+unsafe impl Sync for MockSlab {}
+
+impl MockSlab {
+    /// A slab of `num_pages` zeroed 4KB pages.
+    pub fn new(slab_id: usize, num_pages: usize) -> Self {
+        let start_address = unsafe { alloc_zeroed(Self::layout(num_pages)) };
+        assert!(!start_address.is_null(), "MockSlab allocation failed");
+        MockSlab {
+            slab_id,
+            num_pages,
+            start_address,
+            control: Arc::new(MockControl::default()),
+        }
+    }
+
+    fn layout(num_pages: usize) -> Layout {
+        Layout::from_size_align(num_pages.max(1) * pagesizes::PGSIZE_4KB, pagesizes::PGSIZE_4KB)
+            .unwrap()
+    }
+
+    /// `len` bytes starting at page `page`.
+    pub fn buf(&self, page: usize, len: usize) -> &[u8] {
+        assert!(page * pagesizes::PGSIZE_4KB + len <= self.num_pages * pagesizes::PGSIZE_4KB);
+        unsafe {
+            std::slice::from_raw_parts(self.start_address.add(page * pagesizes::PGSIZE_4KB), len)
+        }
+    }
+
+    /// Fail the `n`th pin call from now on (1 for the next one), leaving that segment
+    /// unpinned. 0 disables failure injection.
+    pub fn fail_nth_pin(&self, n: usize) {
+        let target = match n {
+            0 => 0,
+            n => self.control.pins.load(Ordering::SeqCst) + n,
+        };
+        self.control.fail_nth_pin.store(target, Ordering::SeqCst);
+    }
+
+    /// Sleep for `latency` in every pin call.
+    pub fn set_pin_latency(&self, latency: Duration) {
+        *self.control.pin_latency.lock_or_recover() = latency;
+    }
+
+    /// Sleep for `latency` in every unpin call.
+    pub fn set_unpin_latency(&self, latency: Duration) {
+        *self.control.unpin_latency.lock_or_recover() = latency;
+    }
+
+    /// Pin calls so far, including failed ones.
+    pub fn pin_calls(&self) -> usize {
+        self.control.pins.load(Ordering::SeqCst)
+    }
+
+    pub fn failed_pins(&self) -> usize {
+        self.control.failed_pins.load(Ordering::SeqCst)
+    }
+
+    /// Unpin calls so far, including ones for segments that were not pinned.
+    pub fn unpin_calls(&self) -> usize {
+        self.control.unpins.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockSlab {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.start_address, Self::layout(self.num_pages)) };
+    }
+}
+
+impl DatapathSlab for MockSlab {
+    type SlabId = usize;
+    /// Registration key, unique per successful pin.
+    type IOInfo = usize;
+    type PinningState = MockPinningState;
+    type PrivateInfo = ();
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        MockPinningState {
+            key: None,
+            control: self.control.clone(),
+        }
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.slab_id
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        pinning_state.key.is_some()
+    }
+
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        _private_info: &Self::PrivateInfo,
+        _start_address: *mut c_void,
+        _len: usize,
+    ) {
+        let control = &pinning_state.control;
+        let call = control.pins.fetch_add(1, Ordering::SeqCst) + 1;
+        let latency = *control.pin_latency.lock_or_recover();
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+        if call == control.fail_nth_pin.load(Ordering::SeqCst) {
+            control.failed_pins.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+        pinning_state.key = Some(control.next_key.fetch_add(1, Ordering::SeqCst) + 1);
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        let control = &pinning_state.control;
+        control.unpins.fetch_add(1, Ordering::SeqCst);
+        let latency = *control.unpin_latency.lock_or_recover();
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+        pinning_state.key = None;
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        pinning_state.key.unwrap_or(0)
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.start_address as _
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        PageSize::PG4KB
+    }
+}
@@ -0,0 +1,154 @@
+//! Pinning one segment on several devices at once, e.g. a buffer that may be sent on either
+//! of two NICs. `MultiDeviceSlab` wraps a slab and registers every segment against each of
+//! `N` `PrivateInfo` contexts; `IOInfo` holds one entry per device, which
+//! `record_access_and_get_io_info_on_device` selects from.
+//!
+//! A segment is pinned on all devices or none: if registration fails on one device the
+//! others are rolled back, and the segment is retried next cycle. The pinning limit thus
+//! applies per device, and `bytes_pinned_per_device` reports each device's share.
+use std::os::raw::c_void;
+
+use crate::data_structures::{DatapathSlab, ZeroCopyCache};
+use crate::metrics::MissReason;
+use crate::pagesizes::PageSize;
+use crate::segment_policy::SegmentPolicy;
+
+/// Per-device IO info of a segment, `None` for devices it is not pinned on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceIoInfo<I, const N: usize>(pub [Option<I>; N]);
+
+impl<I: Copy, const N: usize> DeviceIoInfo<I, N> {
+    pub fn device(&self, device: usize) -> Option<I> {
+        self.0.get(device).copied().flatten()
+    }
+}
+
+#[derive(Debug)]
+pub struct MultiDeviceSlab<S, const N: usize>
+where
+    S: DatapathSlab + std::fmt::Debug,
+{
+    inner: S,
+}
+
+impl<S, const N: usize> MultiDeviceSlab<S, N>
+where
+    S: DatapathSlab + std::fmt::Debug,
+{
+    pub fn new(inner: S) -> Self {
+        MultiDeviceSlab { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, const N: usize> DatapathSlab for MultiDeviceSlab<S, N>
+where
+    S: DatapathSlab + std::fmt::Debug,
+{
+    type SlabId = S::SlabId;
+    type IOInfo = DeviceIoInfo<S::IOInfo, N>;
+    type PinningState = [S::PinningState; N];
+    /// One context per device, in device order.
+    type PrivateInfo = [S::PrivateInfo; N];
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        std::array::from_fn(|_| self.inner.default_pinning_state())
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.inner.get_slab_id()
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        N > 0 && pinning_state.iter().all(S::is_pinned)
+    }
+
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        for device in 0..N {
+            S::pin_segment(&mut pinning_state[device], &private_info[device], start_address, len);
+            if !S::is_pinned(&pinning_state[device]) {
+                tracing::warn!("Pinning on device {} failed, rolling back", device);
+                for state in pinning_state[..device].iter_mut() {
+                    S::unpin_segment(state);
+                }
+                return;
+            }
+        }
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        for state in pinning_state.iter_mut() {
+            if S::is_pinned(state) {
+                S::unpin_segment(state);
+            }
+        }
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        DeviceIoInfo(std::array::from_fn(|device| {
+            let state = &pinning_state[device];
+            S::is_pinned(state).then(|| S::get_io_info(state))
+        }))
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.inner.get_total_num_pages()
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.inner.get_start_address()
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.inner.get_page_size()
+    }
+
+    fn get_numa_node(&self) -> Option<usize> {
+        self.inner.get_numa_node()
+    }
+}
+
+impl<S, CB, const N: usize> ZeroCopyCache<MultiDeviceSlab<S, N>, CB>
+where
+    S: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<MultiDeviceSlab<S, N>>,
+{
+    /// Like `record_access_and_get_io_info`, but return the IO info for one device.
+    pub fn record_access_and_get_io_info_on_device(
+        &mut self,
+        buf: &[u8],
+        device: usize,
+    ) -> Result<(S::SlabId, S::IOInfo), MissReason> {
+        let (slab_id, io_info) = self.record_access_and_get_io_info(buf)?;
+        match io_info.device(device) {
+            Some(io_info) => Ok((slab_id, io_info)),
+            None => {
+                // The IO was handed out; complete it since the caller cannot use it.
+                self.record_io_completion(buf);
+                Err(MissReason::NotPinned)
+            }
+        }
+    }
+
+    /// Bytes registered on each device.
+    pub fn bytes_pinned_per_device(&self) -> [usize; N] {
+        let mut bytes = [0; N];
+        for segment in self.segment_entries() {
+            let io_info = segment.get_io_info();
+            for (device, bytes) in bytes.iter_mut().enumerate() {
+                if io_info.device(device).is_some() {
+                    *bytes += segment.get_size();
+                }
+            }
+        }
+        bytes
+    }
+}
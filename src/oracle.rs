@@ -0,0 +1,141 @@
+//! Offline Belady/oracle policy. Given the full future access trace, it pins the segments
+//! that will be used soonest, giving an upper bound to compare online policies against.
+//! Simulation-only: it has to be handed the trace up front.
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::data_structures::{DatapathSlab, DecayPolicy, SegmentId};
+use crate::segment_policy::SegmentPolicy;
+
+#[derive(Debug)]
+pub struct OracleCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Maximum number of segments returned as the hotset.
+    num_segments: usize,
+    /// Every access that will be made, in order.
+    trace: Vec<SegmentId<Slab>>,
+    /// Index of the next access within the trace.
+    position: usize,
+    /// Accesses seen so far.
+    access_counts: HashMap<SegmentId<Slab>, i64>,
+}
+
+impl<Slab> OracleCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn new(num_segments: usize, trace: Vec<SegmentId<Slab>>) -> Self {
+        OracleCache {
+            num_segments,
+            trace,
+            position: 0,
+            access_counts: HashMap::default(),
+        }
+    }
+
+    /// For every access, the index of the next access to the same segment
+    /// (`usize::MAX` if it is never accessed again).
+    fn compute_next_use(trace: &[SegmentId<Slab>]) -> Vec<usize> {
+        let mut next_use = vec![usize::MAX; trace.len()];
+        let mut last_seen: HashMap<SegmentId<Slab>, usize> = HashMap::default();
+        for (i, segment_id) in trace.iter().enumerate().rev() {
+            if let Some(next) = last_seen.insert(*segment_id, i) {
+                next_use[i] = next;
+            }
+        }
+        next_use
+    }
+
+    /// Number of hits Belady's MIN algorithm (with bypass) achieves on `trace` when at most
+    /// `capacity` segments can be pinned at once and every access may change the pinned set.
+    pub fn belady_hit_count(trace: &[SegmentId<Slab>], capacity: usize) -> usize {
+        if capacity == 0 {
+            return 0;
+        }
+        let next_use = Self::compute_next_use(trace);
+        // Resident segments, keyed by (next use, trace index) so the farthest is last.
+        let mut resident: HashMap<SegmentId<Slab>, (usize, usize)> = HashMap::default();
+        let mut order: BTreeSet<(usize, usize)> = BTreeSet::new();
+        let mut hits = 0;
+        for (i, segment_id) in trace.iter().enumerate() {
+            let key = (next_use[i], i);
+            if let Some(old_key) = resident.remove(segment_id) {
+                order.remove(&old_key);
+                hits += 1;
+            } else if order.len() >= capacity {
+                let farthest = *order.last().unwrap();
+                if farthest < key {
+                    // This segment is needed later than everything resident, don't pin it.
+                    continue;
+                }
+                order.pop_last();
+                resident.remove(&trace[farthest.1]);
+            }
+            resident.insert(*segment_id, key);
+            order.insert(key);
+        }
+        hits
+    }
+
+    /// Hits the oracle achieves on its own trace.
+    pub fn optimal_hit_count(&self) -> usize {
+        Self::belady_hit_count(&self.trace, self.num_segments)
+    }
+
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for OracleCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        if self.trace.get(self.position) != Some(&segment_id) {
+            tracing::warn!(
+                "Access to {:?} at position {} diverges from the oracle trace",
+                segment_id,
+                self.position
+            );
+        }
+        self.position += 1;
+        *self.access_counts.entry(segment_id).or_insert(0) += 1;
+    }
+
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.access_counts.get(&segment_id).copied()
+    }
+
+    /// Pins the segments whose next use is soonest from the current trace position.
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        let mut pinned_list = HashSet::new();
+        for segment_id in self.trace.iter().skip(self.position) {
+            if pinned_list.len() >= self.num_segments {
+                break;
+            }
+            pinned_list.insert(*segment_id);
+        }
+        pinned_list
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    fn set_num_segments(&mut self, num_segments: usize) {
+        self.num_segments = num_segments;
+    }
+
+    fn reset(&mut self) {
+        self.access_counts.clear();
+    }
+
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        self.access_counts.remove(&segment_id);
+    }
+
+    /// Decisions come from the trace, so there is nothing to age.
+    fn decay(&mut self, _policy: DecayPolicy) {}
+}
@@ -1,20 +1,14 @@
 //! This module contains ways to interface with custom memory allocation/registration.
 //! How we will exactly achieve that, I am not sure yet.
 //! It seems like standard library containers don't allow custom allocators yet.
+use std::path::{Path, PathBuf};
+
 const PGSHIFT_4KB: usize = 12;
 const PGSHIFT_2MB: usize = 21;
 const PGSHIFT_1GB: usize = 30;
 pub const PGSIZE_4KB: usize = 1 << PGSHIFT_4KB;
 pub const PGSIZE_2MB: usize = 1 << PGSHIFT_2MB;
 pub const PGSIZE_1GB: usize = 1 << PGSHIFT_1GB;
-const PGMASK_4KB: usize = PGSIZE_4KB - 1;
-const PGMASK_2MB: usize = PGSIZE_2MB - 1;
-const PGMASK_1GB: usize = PGSIZE_1GB - 1;
-
-#[inline]
-fn pgoff4kb(addr: *const u8) -> usize {
-    (addr as usize) & PGMASK_4KB
-}
 
 #[inline]
 pub fn pgn2mb(off: usize) -> usize {
@@ -22,36 +16,147 @@ pub fn pgn2mb(off: usize) -> usize {
 }
 
 #[inline]
-fn pgoff2mb(addr: *const u8) -> usize {
-    (addr as usize) & PGMASK_2MB
+pub fn closest_1g_page(addr: *const u8) -> usize {
+    closest_page(addr, &PageSize::PG1GB)
 }
 
-fn pgoff1gb(addr: *const u8) -> usize {
-    (addr as usize) & PGMASK_1GB
+#[inline]
+pub fn closest_4k_page(addr: *const u8) -> usize {
+    closest_page(addr, &PageSize::PG4KB)
 }
 
 #[inline]
-pub fn closest_1g_page(addr: *const u8) -> usize {
-    let off = pgoff1gb(addr);
-    addr as usize - off
+pub fn closest_2mb_page(addr: *const u8) -> usize {
+    closest_page(addr, &PageSize::PG2MB)
 }
 
+/// Start of the `page_size` page containing `addr`.
 #[inline]
-pub fn closest_4k_page(addr: *const u8) -> usize {
-    let off = pgoff4kb(addr);
-    addr as usize - off
+pub fn closest_page(addr: *const u8, page_size: &PageSize) -> usize {
+    (addr as usize) & !(page_size.bytes() - 1)
 }
 
-#[inline]
-pub fn closest_2mb_page(addr: *const u8) -> usize {
-    let off = pgoff2mb(addr);
-    tracing::debug!("Off: {}, addr as usize, {}", off, addr as usize);
-    addr as usize - off
+/// Size of a page, any power of two. The common sizes are associated constants, which
+/// also work as patterns.
+#[derive(Eq, PartialEq, Hash, Clone)]
+pub struct PageSize {
+    shift: u32,
+}
+
+impl PageSize {
+    pub const PG4KB: PageSize = PageSize { shift: PGSHIFT_4KB as u32 };
+    /// Base page size on some ARM servers.
+    pub const PG16KB: PageSize = PageSize { shift: 14 };
+    /// Base page size on some ARM servers.
+    pub const PG64KB: PageSize = PageSize { shift: 16 };
+    pub const PG2MB: PageSize = PageSize { shift: PGSHIFT_2MB as u32 };
+    pub const PG1GB: PageSize = PageSize { shift: PGSHIFT_1GB as u32 };
+
+    /// A page of `bytes`, which must be a power of two.
+    pub fn new(bytes: usize) -> Option<Self> {
+        bytes.is_power_of_two().then(|| PageSize {
+            shift: bytes.trailing_zeros(),
+        })
+    }
+
+    pub fn bytes(&self) -> usize {
+        1 << self.shift
+    }
+
+    /// log2 of the size.
+    pub fn shift(&self) -> u32 {
+        self.shift
+    }
+}
+
+impl std::fmt::Debug for PageSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.shift {
+            shift if shift >= 30 => write!(f, "{}GB", 1usize << (shift - 30)),
+            shift if shift >= 20 => write!(f, "{}MB", 1usize << (shift - 20)),
+            shift if shift >= 10 => write!(f, "{}KB", 1usize << (shift - 10)),
+            shift => write!(f, "{}B", 1usize << shift),
+        }
+    }
+}
+
+/// A hugetlb pool of one page size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HugepagePool {
+    pub page_size: PageSize,
+    /// Pages reserved for the pool.
+    pub total: usize,
+    /// Pages not yet handed out.
+    pub free: usize,
+}
+
+impl HugepagePool {
+    pub fn free_bytes(&self) -> usize {
+        self.free * self.page_size.bytes()
+    }
+}
+
+/// The hugepage sizes the kernel supports and how many pages of each are free, smallest
+/// first. Empty if the system has no hugetlb support.
+pub fn detect_hugepages() -> Vec<HugepagePool> {
+    detect_hugepages_in(
+        Path::new("/sys/kernel/mm/hugepages"),
+        Path::new("/proc/meminfo"),
+    )
+}
+
+/// `detect_hugepages` reading the per-size `hugepages-<N>kB` directories under `sysfs_dir`,
+/// or the default pool from `meminfo` where sysfs is unavailable.
+pub fn detect_hugepages_in(
+    sysfs_dir: &Path,
+    meminfo: &Path,
+) -> Vec<HugepagePool> {
+    let read_count = |path: PathBuf| -> Option<usize> {
+        std::fs::read_to_string(path).ok()?.trim().parse().ok()
+    };
+    let mut pools: Vec<HugepagePool> = std::fs::read_dir(sysfs_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let kb: usize = name.strip_prefix("hugepages-")?.strip_suffix("kB")?.parse().ok()?;
+            Some(HugepagePool {
+                page_size: PageSize::new(kb * 1024)?,
+                total: read_count(entry.path().join("nr_hugepages"))?,
+                free: read_count(entry.path().join("free_hugepages"))?,
+            })
+        })
+        .collect();
+    if pools.is_empty() {
+        pools.extend(meminfo_pool(meminfo));
+    }
+    pools.sort_by_key(|pool| pool.page_size.bytes());
+    pools
+}
+
+/// The default hugepage pool described by `/proc/meminfo`.
+fn meminfo_pool(meminfo: &Path) -> Option<HugepagePool> {
+    let contents = std::fs::read_to_string(meminfo).ok()?;
+    let field = |name: &str| -> Option<usize> {
+        let line = contents.lines().find(|line| line.starts_with(name))?;
+        line[name.len()..].split_whitespace().next()?.parse().ok()
+    };
+    Some(HugepagePool {
+        page_size: PageSize::new(field("Hugepagesize:")? * 1024)?,
+        total: field("HugePages_Total:")?,
+        free: field("HugePages_Free:")?,
+    })
 }
 
-#[derive(Eq, PartialEq, Hash, Debug, Clone)]
-pub enum PageSize {
-    PG4KB,
-    PG2MB,
-    PG1GB,
+/// The largest hugepage size with enough free pages to back `len` bytes, if any.
+pub fn best_hugepage_size(pools: &[HugepagePool], len: usize) -> Option<PageSize> {
+    pools
+        .iter()
+        .filter(|pool| {
+            let page = pool.page_size.bytes();
+            pool.free_bytes() >= len.div_ceil(page).max(1) * page
+        })
+        .max_by_key(|pool| pool.page_size.bytes())
+        .map(|pool| pool.page_size.clone())
 }
@@ -0,0 +1,141 @@
+//! Channel-based access recording. Datapath clones push access events into a bounded
+//! channel and a dedicated statistics thread drains them into the cache builder. When the
+//! channel is full the event is dropped and counted, so the datapath never blocks.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::data_structures::{DatapathSlab, SegmentId};
+use crate::segment_policy::SegmentPolicy;
+use crate::sharded::ShardedCacheBuilder;
+
+#[derive(Debug, Default)]
+struct PipelineCounters {
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    processed: AtomicU64,
+}
+
+/// Sending half, cloned into every clone of the cache.
+#[derive(Debug)]
+pub struct AccessEventSender<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    sender: SyncSender<SegmentId<Slab>>,
+    counters: Arc<PipelineCounters>,
+}
+
+impl<Slab> Clone for AccessEventSender<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn clone(&self) -> Self {
+        AccessEventSender {
+            sender: self.sender.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+impl<Slab> AccessEventSender<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Queue an access. Returns false if the channel was full or closed and the event was
+    /// dropped.
+    pub fn send(&self, segment_id: SegmentId<Slab>) -> bool {
+        match self.sender.try_send(segment_id) {
+            Ok(()) => {
+                self.counters.sent.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    pub fn dropped_events(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Handle to the statistics thread. The thread exits once every sender is gone.
+#[derive(Debug)]
+pub struct AccessPipeline {
+    counters: Arc<PipelineCounters>,
+    handle: JoinHandle<()>,
+}
+
+impl AccessPipeline {
+    /// Start a statistics thread draining a channel of `capacity` events into
+    /// `cache_builder`, and return it along with the sending half.
+    pub fn spawn<Slab, CB>(
+        cache_builder: Arc<ShardedCacheBuilder<Slab, CB>>,
+        capacity: usize,
+    ) -> (Self, AccessEventSender<Slab>)
+    where
+        Slab: DatapathSlab + std::fmt::Debug + 'static,
+        CB: SegmentPolicy<Slab> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity.max(1));
+        let counters = Arc::new(PipelineCounters::default());
+        let thread_counters = counters.clone();
+        let handle = thread::spawn(move || drain(receiver, &cache_builder, &thread_counters));
+        (
+            AccessPipeline {
+                counters: counters.clone(),
+                handle,
+            },
+            AccessEventSender { sender, counters },
+        )
+    }
+
+    pub fn sent_events(&self) -> u64 {
+        self.counters.sent.load(Ordering::Relaxed)
+    }
+
+    /// Events dropped because the channel was full.
+    pub fn dropped_events(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Events handed to the cache builder so far.
+    pub fn processed_events(&self) -> u64 {
+        self.counters.processed.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the statistics thread to drain the channel and exit. Every sender must have
+    /// been dropped, or this blocks forever.
+    pub fn join(self) {
+        if self.handle.join().is_err() {
+            tracing::error!("Access pipeline thread panicked");
+        }
+    }
+}
+
+/// Receive events, coalescing whatever is already queued into one count per segment.
+fn drain<Slab, CB>(
+    receiver: Receiver<SegmentId<Slab>>,
+    cache_builder: &ShardedCacheBuilder<Slab, CB>,
+    counters: &PipelineCounters,
+) where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    let mut batch: HashMap<SegmentId<Slab>, u64> = HashMap::default();
+    while let Ok(segment_id) = receiver.recv() {
+        batch.insert(segment_id, 1);
+        while let Ok(segment_id) = receiver.try_recv() {
+            *batch.entry(segment_id).or_insert(0) += 1;
+        }
+        for (segment_id, count) in batch.drain() {
+            cache_builder.update_access_by(segment_id, count);
+            counters.processed.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+}
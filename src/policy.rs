@@ -0,0 +1,181 @@
+//! A policy chosen at runtime. `CacheBuilderEnum` wraps one of the crate's cache builders,
+//! each with its default tuning, so the policy can come from a `CacheType` instead of a type
+//! parameter, and can be switched on a live cache with `ZeroCopyCache::set_cache_type`.
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use crate::builder::ConfigError;
+use crate::config::{CacheType, ZccConfig};
+use crate::data_structures::{
+    DatapathSlab, DecayPolicy, PinCost, SegmentId, ZeroCopyCache, DEFAULT_CACHE_SIZE,
+};
+use crate::ewma::EwmaCache;
+use crate::mfu::MfuCache;
+use crate::recencyfrequency::RecencyFrequencyCache;
+use crate::segment_policy::SegmentPolicy;
+use crate::sketch::SketchCache;
+use crate::spacesaving::SpaceSavingCache;
+use crate::window::WindowCache;
+
+#[derive(Debug)]
+pub enum CacheBuilderEnum<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    Mfu(MfuCache<Slab>),
+    Ewma(EwmaCache<Slab>),
+    Window(WindowCache<Slab>),
+    Sketch(SketchCache<Slab>),
+    RecencyFrequency(RecencyFrequencyCache<Slab>),
+    SpaceSaving(SpaceSavingCache<Slab>),
+}
+
+impl<Slab> Default for CacheBuilderEnum<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        CacheBuilderEnum::new(CacheType::default(), DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl<Slab> CacheBuilderEnum<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// A `cache_type` builder with its default tuning and a hotset of `num_segments`.
+    pub fn new(cache_type: CacheType, num_segments: usize) -> Self {
+        let mut cache_builder = match cache_type {
+            CacheType::Mfu => CacheBuilderEnum::Mfu(MfuCache::default()),
+            CacheType::Ewma => CacheBuilderEnum::Ewma(EwmaCache::default()),
+            CacheType::Window => CacheBuilderEnum::Window(WindowCache::default()),
+            CacheType::Sketch => CacheBuilderEnum::Sketch(SketchCache::default()),
+            CacheType::RecencyFrequency => {
+                CacheBuilderEnum::RecencyFrequency(RecencyFrequencyCache::default())
+            }
+            CacheType::SpaceSaving => CacheBuilderEnum::SpaceSaving(SpaceSavingCache::default()),
+        };
+        cache_builder.set_num_segments(num_segments);
+        cache_builder
+    }
+
+    pub fn cache_type(&self) -> CacheType {
+        match self {
+            CacheBuilderEnum::Mfu(_) => CacheType::Mfu,
+            CacheBuilderEnum::Ewma(_) => CacheType::Ewma,
+            CacheBuilderEnum::Window(_) => CacheType::Window,
+            CacheBuilderEnum::Sketch(_) => CacheType::Sketch,
+            CacheBuilderEnum::RecencyFrequency(_) => CacheType::RecencyFrequency,
+            CacheBuilderEnum::SpaceSaving(_) => CacheType::SpaceSaving,
+        }
+    }
+
+    fn inner(&self) -> &dyn SegmentPolicy<Slab> {
+        match self {
+            CacheBuilderEnum::Mfu(cache_builder) => cache_builder,
+            CacheBuilderEnum::Ewma(cache_builder) => cache_builder,
+            CacheBuilderEnum::Window(cache_builder) => cache_builder,
+            CacheBuilderEnum::Sketch(cache_builder) => cache_builder,
+            CacheBuilderEnum::RecencyFrequency(cache_builder) => cache_builder,
+            CacheBuilderEnum::SpaceSaving(cache_builder) => cache_builder,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut dyn SegmentPolicy<Slab> {
+        match self {
+            CacheBuilderEnum::Mfu(cache_builder) => cache_builder,
+            CacheBuilderEnum::Ewma(cache_builder) => cache_builder,
+            CacheBuilderEnum::Window(cache_builder) => cache_builder,
+            CacheBuilderEnum::Sketch(cache_builder) => cache_builder,
+            CacheBuilderEnum::RecencyFrequency(cache_builder) => cache_builder,
+            CacheBuilderEnum::SpaceSaving(cache_builder) => cache_builder,
+        }
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for CacheBuilderEnum<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        self.inner_mut().update_access(segment_id)
+    }
+
+    fn update_access_by(&mut self, segment_id: SegmentId<Slab>, count: u64) {
+        self.inner_mut().update_access_by(segment_id, count)
+    }
+
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.inner().get_access_count(segment_id)
+    }
+
+    fn get_last_access_time(&self, segment_id: SegmentId<Slab>) -> Option<SystemTime> {
+        self.inner().get_last_access_time(segment_id)
+    }
+
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        self.inner_mut().return_top_segments_to_pin()
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.inner().get_num_segments()
+    }
+
+    fn set_num_segments(&mut self, num_segments: usize) {
+        self.inner_mut().set_num_segments(num_segments)
+    }
+
+    fn reset(&mut self) {
+        self.inner_mut().reset()
+    }
+
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        self.inner_mut().remove_segment(segment_id)
+    }
+
+    fn record_pin_cost(&mut self, segment_id: SegmentId<Slab>, pin_cost: PinCost) {
+        self.inner_mut().record_pin_cost(segment_id, pin_cost)
+    }
+
+    fn decay(&mut self, policy: DecayPolicy) {
+        self.inner_mut().decay(policy)
+    }
+}
+
+impl<Slab> ZeroCopyCache<Slab, CacheBuilderEnum<Slab>>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// A cache whose policy is picked at runtime, e.g. with `"ewma".parse()`.
+    pub fn new_with_type(cache_type: CacheType, num_segments: usize) -> Self {
+        ZeroCopyCache::new(CacheBuilderEnum::new(cache_type, num_segments))
+    }
+
+    /// Switch every shard to a `cache_type` builder with the same hotset size, keeping the
+    /// registered slabs; see `replace_cache_builder` for `seed`.
+    pub fn set_cache_type(&self, cache_type: CacheType, seed: bool) {
+        let num_segments = self.cache_builder.get_num_segments();
+        self.replace_cache_builder(|_| CacheBuilderEnum::new(cache_type, num_segments), seed);
+    }
+
+    pub fn get_cache_type(&self) -> CacheType {
+        let mut cache_type = CacheType::default();
+        self.cache_builder.for_each_shard(|cache_builder| cache_type = cache_builder.cache_type());
+        cache_type
+    }
+}
+
+impl<SlabId> ZccConfig<SlabId>
+where
+    SlabId: std::hash::Hash + Eq + Copy,
+{
+    /// A cache with every setting of this config, including its `cache_type` and
+    /// `num_segments`.
+    pub fn build<Slab>(&self) -> Result<ZeroCopyCache<Slab, CacheBuilderEnum<Slab>>, ConfigError>
+    where
+        Slab: DatapathSlab<SlabId = SlabId> + std::fmt::Debug,
+    {
+        self.to_builder(CacheBuilderEnum::new(self.cache_type, self.num_segments))
+            .build()
+    }
+}
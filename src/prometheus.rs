@@ -0,0 +1,294 @@
+//! Prometheus exporter for cache metrics. `render` produces the text exposition format and
+//! `spawn_exporter` serves it on `GET /metrics` from a background thread, so an existing
+//! Prometheus setup can scrape the cache directly. The returned `PrometheusExporter` stops
+//! the thread when dropped.
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::data_structures::{DatapathSlab, SlabStats, ZeroCopyCache};
+use crate::metrics::lock_recoveries;
+use crate::segment_policy::SegmentPolicy;
+
+/// How long a scrape may take to send its request or read the response before the exporter
+/// gives up on it and serves the next one, unless set with `spawn_exporter_with_timeout`.
+pub const EXPORTER_IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads one per-slab value out of the slab's stats.
+type SlabStatField = fn(&SlabStats) -> usize;
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Escape a label value for the text format: backslashes, double quotes and newlines.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render the cache's current metrics in the Prometheus text exposition format.
+pub fn render<Slab, CB>(zero_copy_cache: &ZeroCopyCache<Slab, CB>) -> String
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    let metrics = zero_copy_cache.get_metrics();
+    let slab_stats = zero_copy_cache.slab_stats();
+    let mut out = String::new();
+    write_metric(
+        &mut out,
+        "zcc_hits_total",
+        "counter",
+        "Accesses served zero-copy.",
+        metrics.hits,
+    );
+    write_metric(
+        &mut out,
+        "zcc_misses_total",
+        "counter",
+        "Accesses to managed segments that were not served zero-copy.",
+        metrics.misses,
+    );
+    let _ = writeln!(out, "# HELP zcc_misses_by_reason_total Misses, by reason.");
+    let _ = writeln!(out, "# TYPE zcc_misses_by_reason_total counter");
+    for (reason, value) in [
+        ("not_pinned", metrics.misses_not_pinned),
+        ("quiescing", metrics.misses_quiescing),
+    ] {
+        let _ = writeln!(
+            out,
+            "zcc_misses_by_reason_total{{reason=\"{}\"}} {}",
+            reason, value
+        );
+    }
+    write_metric(
+        &mut out,
+        "zcc_unmanaged_total",
+        "counter",
+        "Accesses to buffers outside managed segments.",
+        metrics.unmanaged,
+    );
+    write_metric(
+        &mut out,
+        "zcc_pins_total",
+        "counter",
+        "Segment pin operations.",
+        metrics.pins,
+    );
+    write_metric(
+        &mut out,
+        "zcc_unpins_total",
+        "counter",
+        "Segment unpin operations.",
+        metrics.unpins,
+    );
+    write_metric(
+        &mut out,
+        "zcc_evictions_total",
+        "counter",
+        "Segments unpinned after leaving the hotset.",
+        metrics.evictions,
+    );
+    write_metric(
+        &mut out,
+        "zcc_orphan_completions_total",
+        "counter",
+        "IO completions for segments with no IO in flight.",
+        metrics.orphan_completions,
+    );
+    write_metric(
+        &mut out,
+        "zcc_lock_recoveries_total",
+        "counter",
+        "Locks recovered after a thread panicked while holding them, process-wide.",
+        lock_recoveries(),
+    );
+    write_metric(
+        &mut out,
+        "zcc_hit_rate",
+        "gauge",
+        "Hits over accesses to managed segments.",
+        metrics.hit_rate(),
+    );
+    write_metric(
+        &mut out,
+        "zcc_bytes_pinned",
+        "gauge",
+        "Bytes currently registered.",
+        slab_stats.values().map(|s| s.pinned_bytes).sum::<usize>(),
+    );
+
+    let per_slab_counters: [(&str, &str, SlabStatField); 3] = [
+        ("zcc_slab_accesses_total", "Accesses, per slab.", |s| {
+            s.metrics.accesses() as usize
+        }),
+        ("zcc_slab_hits_total", "Accesses served zero-copy, per slab.", |s| {
+            s.metrics.hits as usize
+        }),
+        ("zcc_slab_evictions_total", "Segments evicted, per slab.", |s| {
+            s.metrics.evictions as usize
+        }),
+    ];
+    let per_slab_gauges: [(&str, &str, SlabStatField); 3] = [
+        (
+            "zcc_slab_bytes_pinned",
+            "Bytes currently registered, per slab.",
+            |s| s.pinned_bytes,
+        ),
+        (
+            "zcc_slab_segments_pinned",
+            "Segments currently registered, per slab.",
+            |s| s.pinned_segments,
+        ),
+        ("zcc_slab_segments", "Segments managed, per slab.", |s| {
+            s.num_segments
+        }),
+    ];
+    let per_slab = per_slab_counters
+        .into_iter()
+        .map(|metric| ("counter", metric))
+        .chain(per_slab_gauges.into_iter().map(|metric| ("gauge", metric)));
+    for (kind, (name, help, value)) in per_slab {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (slab_id, stats) in slab_stats.iter() {
+            let label = escape_label_value(&format!("{:?}", slab_id));
+            let _ = writeln!(out, "{}{{slab=\"{}\"}} {}", name, label, value(stats));
+        }
+    }
+    out
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    io_timeout: Duration,
+    body: impl FnOnce() -> String,
+) -> io::Result<()> {
+    // Connections are served one at a time, so a client that never sends its request must
+    // not hold up the others.
+    stream.set_read_timeout(Some(io_timeout))?;
+    stream.set_write_timeout(Some(io_timeout))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let response = if request_line.starts_with("GET /metrics") {
+        let body = body();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes())
+}
+
+/// Handle to the thread serving `GET /metrics`. Dropping it stops the thread.
+#[derive(Debug)]
+pub struct PrometheusExporter {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PrometheusExporter {
+    /// Address the exporter listens on, e.g. to find the port picked for port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting scrapes and join the thread, after the scrape in progress if any.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.shutdown.store(true, Ordering::SeqCst);
+            // The thread is blocked accepting; connect once so it sees the flag.
+            let mut wake_addr = self.local_addr;
+            if wake_addr.ip().is_unspecified() {
+                wake_addr.set_ip(match wake_addr.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                });
+            }
+            if let Err(e) = TcpStream::connect_timeout(&wake_addr, EXPORTER_IO_TIMEOUT) {
+                tracing::warn!("Could not wake the Prometheus exporter: {:?}", e);
+            }
+            if handle.join().is_err() {
+                tracing::error!("Prometheus exporter thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for PrometheusExporter {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Serve `GET /metrics` for the cache on `addr` from a background thread.
+pub fn spawn_exporter<Slab, CB>(
+    zero_copy_cache: ZeroCopyCache<Slab, CB>,
+    addr: impl ToSocketAddrs,
+) -> io::Result<PrometheusExporter>
+where
+    Slab: DatapathSlab + std::fmt::Debug + 'static,
+    CB: SegmentPolicy<Slab> + 'static,
+    ZeroCopyCache<Slab, CB>: Send,
+{
+    spawn_exporter_with_timeout(zero_copy_cache, addr, EXPORTER_IO_TIMEOUT)
+}
+
+/// `spawn_exporter`, giving each scrape `io_timeout` to send its request and read the
+/// response.
+pub fn spawn_exporter_with_timeout<Slab, CB>(
+    zero_copy_cache: ZeroCopyCache<Slab, CB>,
+    addr: impl ToSocketAddrs,
+    io_timeout: Duration,
+) -> io::Result<PrometheusExporter>
+where
+    Slab: DatapathSlab + std::fmt::Debug + 'static,
+    CB: SegmentPolicy<Slab> + 'static,
+    ZeroCopyCache<Slab, CB>: Send,
+{
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    tracing::info!("Serving Prometheus metrics on {:?}", local_addr);
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let exporter_shutdown = shutdown.clone();
+    let handle = thread::spawn(move || {
+        for stream in listener.incoming() {
+            if exporter_shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            let result = stream
+                .and_then(|s| handle_connection(s, io_timeout, || render(&zero_copy_cache)));
+            if let Err(e) = result {
+                tracing::warn!("Prometheus exporter connection failed: {:?}", e);
+            }
+        }
+    });
+    Ok(PrometheusExporter {
+        local_addr,
+        shutdown,
+        handle: Some(handle),
+    })
+}
@@ -0,0 +1,168 @@
+//! Address lookup for segments. Slabs are kept as address ranges sorted by start address,
+//! and a slab's segments are equal runs of pages of its own page size, so resolving a buffer
+//! is a binary search over slabs followed by a shift and a divide. Memory grows with the
+//! number of slabs rather than the number of pages they span.
+use std::collections::BTreeMap;
+
+use crate::pagesizes::PageSize;
+
+#[derive(Debug, Clone)]
+pub struct RangeIndex<ID> {
+    /// Start address to (end address, id), for non-overlapping ranges.
+    ranges: BTreeMap<usize, (usize, ID)>,
+}
+
+impl<ID> Default for RangeIndex<ID> {
+    fn default() -> Self {
+        RangeIndex {
+            ranges: BTreeMap::default(),
+        }
+    }
+}
+
+impl<ID> RangeIndex<ID>
+where
+    ID: Copy,
+{
+    /// Map `len` bytes starting at `start` to `id`, replacing any range starting there.
+    pub fn insert(&mut self, start: usize, len: usize, id: ID) {
+        self.ranges.insert(start, (start + len, id));
+    }
+
+    /// The id of the range containing `addr`.
+    pub fn get(&self, addr: usize) -> Option<ID> {
+        self.get_range(addr).map(|(_, _, id)| id)
+    }
+
+    /// `(start, end, id)` of the range containing `addr`.
+    pub fn get_range(&self, addr: usize) -> Option<(usize, usize, ID)> {
+        let (&start, &(end, id)) = self.ranges.range(..=addr).next_back()?;
+        (addr < end).then_some((start, end, id))
+    }
+
+    /// `(start, end, id)` of every range overlapping `[start, end)`, in address order.
+    pub fn overlapping(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = (usize, usize, ID)> + '_ {
+        let first = match self.ranges.range(..=start).next_back() {
+            Some((&range_start, &(range_end, _))) if start < range_end => range_start,
+            _ => start,
+        };
+        self.ranges
+            .range(first..end)
+            .map(|(&range_start, &(range_end, id))| (range_start, range_end, id))
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&ID) -> bool) {
+        self.ranges.retain(|_, (_, id)| keep(id))
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/// How a slab is carved into segments, picked when it is initialized.
+#[derive(Debug, Clone, Copy)]
+struct SlabLayout<SlabId> {
+    slab_id: SlabId,
+    page_shift: u32,
+    pages_per_segment: usize,
+}
+
+/// Segment lookup over slabs carved into equal segments (the last may be shorter).
+#[derive(Debug, Clone)]
+pub struct SegmentIndex<SlabId> {
+    slabs: RangeIndex<SlabLayout<SlabId>>,
+}
+
+impl<SlabId> Default for SegmentIndex<SlabId> {
+    fn default() -> Self {
+        SegmentIndex {
+            slabs: RangeIndex::default(),
+        }
+    }
+}
+
+impl<SlabId> SegmentIndex<SlabId>
+where
+    SlabId: Copy,
+{
+    /// Index `num_pages` pages of `page_size` at `start`, in segments of `pages_per_segment`.
+    pub fn insert_slab(
+        &mut self,
+        slab_id: SlabId,
+        start: usize,
+        page_size: &PageSize,
+        pages_per_segment: usize,
+        num_pages: usize,
+    ) {
+        let layout = SlabLayout {
+            slab_id,
+            page_shift: page_size.shift(),
+            pages_per_segment: pages_per_segment.max(1),
+        };
+        self.slabs.insert(start, num_pages << page_size.shift(), layout);
+    }
+
+    /// `(start, end, segment id)` of the segment containing `addr`.
+    pub fn get_range(&self, addr: usize) -> Option<(usize, usize, (SlabId, usize))> {
+        let (start, end, layout) = self.slabs.get_range(addr)?;
+        let segment_bytes = layout.pages_per_segment << layout.page_shift;
+        let index = ((addr - start) >> layout.page_shift) / layout.pages_per_segment;
+        let segment_start = start + index * segment_bytes;
+        let segment_end = end.min(segment_start + segment_bytes);
+        Some((segment_start, segment_end, (layout.slab_id, index)))
+    }
+
+    /// The segment containing `addr`.
+    pub fn get(&self, addr: usize) -> Option<(SlabId, usize)> {
+        self.get_range(addr).map(|(_, _, segment_id)| segment_id)
+    }
+
+    /// `(start, end, segment id)` of every segment overlapping `[start, end)`, in address
+    /// order.
+    pub fn overlapping(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = (usize, usize, (SlabId, usize))> + '_ {
+        self.slabs
+            .overlapping(start, end)
+            .flat_map(move |(slab_start, slab_end, _)| {
+                let mut addr = start.max(slab_start);
+                let last = end.min(slab_end);
+                std::iter::from_fn(move || {
+                    if addr >= last {
+                        return None;
+                    }
+                    let range = self.get_range(addr)?;
+                    addr = range.1;
+                    Some(range)
+                })
+            })
+    }
+
+    pub fn retain_slabs(&mut self, mut keep: impl FnMut(&SlabId) -> bool) {
+        self.slabs.retain(|layout| keep(&layout.slab_id))
+    }
+
+    pub fn clear(&mut self) {
+        self.slabs.clear();
+    }
+
+    /// Slabs indexed.
+    pub fn num_slabs(&self) -> usize {
+        self.slabs.len()
+    }
+}
@@ -0,0 +1,163 @@
+//! Combined recency-frequency policy. Each segment is scored as
+//! `alpha * frequency + (1 - alpha) * recency`, both normalized to [0, 1] across the tracked
+//! segments, so `alpha = 1` behaves like MFU and `alpha = 0` like LRU.
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use crate::data_structures::{
+    DatapathSlab, DecayPolicy, SegmentId, SegmentStatMap, Stats, DEFAULT_CACHE_SIZE,
+};
+use crate::segment_policy::SegmentPolicy;
+
+pub const DEFAULT_ALPHA: f64 = 0.5;
+
+#[derive(Debug)]
+pub struct RecencyFrequencyCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Maximum number of segments returned as the hotset.
+    num_segments: usize,
+    /// Weight of frequency against recency, in [0, 1].
+    alpha: f64,
+    segment_stats: SegmentStatMap<SegmentId<Slab>>,
+}
+
+impl<Slab> Default for RecencyFrequencyCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        RecencyFrequencyCache::new(DEFAULT_CACHE_SIZE, DEFAULT_ALPHA)
+    }
+}
+
+impl<Slab> RecencyFrequencyCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn new(num_segments: usize, alpha: f64) -> Self {
+        RecencyFrequencyCache {
+            num_segments,
+            alpha: alpha.clamp(0.0, 1.0),
+            segment_stats: SegmentStatMap::default(),
+        }
+    }
+
+    pub fn get_alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    /// Move the knob between LRU-like (0.0) and MFU-like (1.0) behavior.
+    pub fn set_alpha(&mut self, alpha: f64) {
+        self.alpha = alpha.clamp(0.0, 1.0);
+    }
+
+    pub fn update_access_at(&mut self, segment_id: SegmentId<Slab>, now: SystemTime) {
+        let stats = self.segment_stats.entry(segment_id).or_insert(Stats {
+            access_count: 0,
+            last_access_time: now,
+        });
+        stats.access_count += 1;
+        stats.last_access_time = now;
+    }
+
+    /// Combined score of every tracked segment.
+    pub fn scores(&self) -> Vec<(SegmentId<Slab>, f64)> {
+        let max_count = self
+            .segment_stats
+            .values()
+            .map(|s| s.access_count)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let oldest = self.segment_stats.values().map(|s| s.last_access_time).min();
+        let newest = self.segment_stats.values().map(|s| s.last_access_time).max();
+        let span = match (oldest, newest) {
+            (Some(oldest), Some(newest)) => newest.duration_since(oldest).unwrap_or_default(),
+            _ => Duration::ZERO,
+        };
+        self.segment_stats
+            .iter()
+            .map(|(seg_id, stats)| {
+                let frequency = stats.access_count as f64 / max_count as f64;
+                let recency = if span.is_zero() {
+                    1.0
+                } else {
+                    let age = stats
+                        .last_access_time
+                        .duration_since(oldest.unwrap())
+                        .unwrap_or_default();
+                    age.as_secs_f64() / span.as_secs_f64()
+                };
+                (*seg_id, self.alpha * frequency + (1.0 - self.alpha) * recency)
+            })
+            .collect()
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for RecencyFrequencyCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        self.update_access_at(segment_id, SystemTime::now());
+    }
+
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.segment_stats
+            .get(&segment_id)
+            .map(|stats| stats.get_access_count())
+    }
+
+    fn get_last_access_time(&self, segment_id: SegmentId<Slab>) -> Option<SystemTime> {
+        self.segment_stats
+            .get(&segment_id)
+            .map(|stats| stats.last_access_time)
+    }
+
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        let mut sorting_vec = self.scores();
+        sorting_vec.sort_by(|a, b| b.1.total_cmp(&a.1));
+        sorting_vec
+            .into_iter()
+            .take(self.num_segments)
+            .map(|(seg_id, _)| seg_id)
+            .collect()
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    fn set_num_segments(&mut self, num_segments: usize) {
+        self.num_segments = num_segments;
+    }
+
+    fn reset(&mut self) {
+        self.segment_stats.clear();
+    }
+
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        self.segment_stats.remove(&segment_id);
+    }
+
+    /// Only frequency is decayed; recency is relative and ages on its own.
+    fn decay(&mut self, policy: DecayPolicy) {
+        let subtract = match policy {
+            DecayPolicy::Halving => None,
+            DecayPolicy::SubtractMin => self.segment_stats.values().map(|s| s.access_count).min(),
+            DecayPolicy::FullReset => {
+                self.reset();
+                return;
+            }
+        };
+        self.segment_stats.retain(|_, stats| {
+            match subtract {
+                Some(min) => stats.access_count -= min,
+                None => stats.access_count /= 2,
+            }
+            stats.access_count > 0
+        });
+    }
+}
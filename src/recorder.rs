@@ -0,0 +1,94 @@
+//! Batched access recording. A `ThreadLocalRecorder` counts accesses in a private buffer
+//! and hands them to the shared cache builder every `max_batch` accesses or `max_delay`,
+//! so the datapath takes the builder lock once per batch instead of once per packet.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::data_structures::{DatapathSlab, SegmentId};
+use crate::segment_policy::SegmentPolicy;
+use crate::sharded::ShardedCacheBuilder;
+
+pub const DEFAULT_MAX_BATCH: usize = 1024;
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+pub struct ThreadLocalRecorder<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    cache_builder: Arc<ShardedCacheBuilder<Slab, CB>>,
+    pending: HashMap<SegmentId<Slab>, u64>,
+    /// Accesses buffered since the last flush.
+    buffered: usize,
+    max_batch: usize,
+    max_delay: Duration,
+    last_flush: Instant,
+}
+
+impl<Slab, CB> ThreadLocalRecorder<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    pub fn new(
+        cache_builder: Arc<ShardedCacheBuilder<Slab, CB>>,
+        max_batch: usize,
+        max_delay: Duration,
+    ) -> Self {
+        ThreadLocalRecorder {
+            cache_builder,
+            pending: HashMap::default(),
+            buffered: 0,
+            max_batch: max_batch.max(1),
+            max_delay,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// An empty recorder feeding the same builder with the same limits.
+    pub fn fork(&self) -> Self {
+        ThreadLocalRecorder::new(self.cache_builder.clone(), self.max_batch, self.max_delay)
+    }
+
+    pub fn get_max_batch(&self) -> usize {
+        self.max_batch
+    }
+
+    pub fn get_max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    /// Accesses buffered and not yet seen by the cache builder.
+    pub fn pending_count(&self, segment_id: &SegmentId<Slab>) -> u64 {
+        self.pending.get(segment_id).copied().unwrap_or(0)
+    }
+
+    pub fn record(&mut self, segment_id: SegmentId<Slab>) {
+        *self.pending.entry(segment_id).or_insert(0) += 1;
+        self.buffered += 1;
+        if self.buffered >= self.max_batch || self.last_flush.elapsed() >= self.max_delay {
+            self.flush();
+        }
+    }
+
+    /// Hand every buffered access to the cache builder.
+    pub fn flush(&mut self) {
+        for (segment_id, count) in self.pending.drain() {
+            self.cache_builder.update_access_by(segment_id, count);
+        }
+        self.buffered = 0;
+        self.last_flush = Instant::now();
+    }
+}
+
+impl<Slab, CB> Drop for ThreadLocalRecorder<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
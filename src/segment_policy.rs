@@ -0,0 +1,61 @@
+//! Segment-level pinning policies: a `SegmentPolicy` is fed the accesses the datapath records
+//! and picks the segments to keep pinned each repin cycle. The crate's policies (`MfuCache`,
+//! `EwmaCache` and the rest) implement it; `ZeroCopyCache` owns one, called its cache builder
+//! throughout. Object-level key-value caches are a separate trait, `kv::KvCache`.
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use crate::data_structures::{DatapathSlab, DecayPolicy, PinCost, SegmentId};
+
+/// Policy deciding which segments should be pinned, based on the accesses it is fed.
+pub trait SegmentPolicy<Slab>: std::fmt::Debug
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Record one access to a segment.
+    fn update_access(&mut self, segment_id: SegmentId<Slab>);
+
+    /// Record `count` accesses at once, e.g. when draining batched counters. Builders that
+    /// can add counts directly should override the default, which calls `update_access`.
+    fn update_access_by(&mut self, segment_id: SegmentId<Slab>, count: u64) {
+        for _ in 0..count {
+            self.update_access(segment_id);
+        }
+    }
+
+    /// Number of accesses recorded for a segment, if it has been seen.
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64>;
+
+    /// When the segment was last accessed, for builders that track it.
+    fn get_last_access_time(&self, _segment_id: SegmentId<Slab>) -> Option<SystemTime> {
+        None
+    }
+
+    /// Segments the policy wants pinned for the next cycle.
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>>;
+
+    /// Maximum number of segments returned as the hotset.
+    fn get_num_segments(&self) -> usize;
+
+    /// Change the hotset size; takes effect from the next repin cycle.
+    fn set_num_segments(&mut self, num_segments: usize);
+
+    /// Drop all access statistics.
+    fn reset(&mut self);
+
+    /// Forget one segment, e.g. when its slab is removed. Builders keeping per-segment
+    /// statistics should override this; the default keeps them, which only costs memory
+    /// since the pinning engine ignores segments it does not manage.
+    fn remove_segment(&mut self, _segment_id: SegmentId<Slab>) {}
+
+    /// Called by the pinning engine after it pins or unpins a segment, with the
+    /// latencies measured so far, so policies can weigh re-registration cost
+    /// against expected hits.
+    fn record_pin_cost(&mut self, _segment_id: SegmentId<Slab>, _pin_cost: PinCost) {}
+
+    /// Age the access statistics. Builders that cannot scale their statistics fall back
+    /// to a full reset.
+    fn decay(&mut self, _policy: DecayPolicy) {
+        self.reset();
+    }
+}
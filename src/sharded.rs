@@ -0,0 +1,218 @@
+//! Cache builder statistics sharded by segment-id hash across several mutexes, so concurrent
+//! `update_access` calls from the datapath rarely contend. The hotset is computed by merging
+//! each shard's candidates and keeping the most-accessed ones, after any segments the
+//! application gave a pinning priority.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::data_structures::{DatapathSlab, DecayPolicy, PinCost, Priority, SegmentId};
+use crate::metrics::LockOrRecover;
+use crate::segment_policy::SegmentPolicy;
+
+#[derive(Debug)]
+pub struct ShardedCacheBuilder<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    shards: Vec<Mutex<CB>>,
+    /// Segments with a priority other than `Normal`.
+    priorities: Mutex<HashMap<SegmentId<Slab>, Priority>>,
+    _slab: PhantomData<fn() -> Slab>,
+}
+
+impl<Slab, CB> ShardedCacheBuilder<Slab, CB>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    /// Create `num_shards` shards (at least one), building shard `i` with `make_builder(i)`.
+    /// Each shard should be sized for the full hotset, since the merge picks from their union.
+    pub fn new(num_shards: usize, mut make_builder: impl FnMut(usize) -> CB) -> Self {
+        ShardedCacheBuilder {
+            shards: (0..num_shards.max(1))
+                .map(|shard| Mutex::new(make_builder(shard)))
+                .collect(),
+            priorities: Mutex::new(HashMap::default()),
+            _slab: PhantomData,
+        }
+    }
+
+    /// A single shard, which behaves exactly like the builder itself.
+    pub fn from_builder(cache_builder: CB) -> Self {
+        ShardedCacheBuilder {
+            shards: vec![Mutex::new(cache_builder)],
+            priorities: Mutex::new(HashMap::default()),
+            _slab: PhantomData,
+        }
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard_index(&self, segment_id: &SegmentId<Slab>) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        let mut hasher = DefaultHasher::new();
+        segment_id.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Lock the shard holding `segment_id`'s statistics.
+    pub fn lock_shard(&self, segment_id: &SegmentId<Slab>) -> MutexGuard<'_, CB> {
+        self.shards[self.shard_index(segment_id)].lock_or_recover()
+    }
+
+    /// Run `f` on every shard in turn, e.g. to change a policy knob everywhere.
+    pub fn for_each_shard(&self, mut f: impl FnMut(&mut CB)) {
+        for shard in self.shards.iter() {
+            f(&mut shard.lock_or_recover());
+        }
+    }
+
+    /// Replace every shard with `make_builder(i, &old_shard_i)`. All shards stay locked until
+    /// the last is replaced, so no access or hotset computation sees a mix of old and new
+    /// builders. Priorities are kept.
+    pub fn replace_shards(&self, mut make_builder: impl FnMut(usize, &CB) -> CB) {
+        let mut shards: Vec<MutexGuard<'_, CB>> =
+            self.shards.iter().map(|shard| shard.lock_or_recover()).collect();
+        for (index, shard) in shards.iter_mut().enumerate() {
+            let cache_builder = make_builder(index, shard);
+            **shard = cache_builder;
+        }
+    }
+
+    pub fn update_access(&self, segment_id: SegmentId<Slab>) {
+        self.lock_shard(&segment_id).update_access(segment_id);
+    }
+
+    pub fn update_access_by(&self, segment_id: SegmentId<Slab>, count: u64) {
+        self.lock_shard(&segment_id).update_access_by(segment_id, count);
+    }
+
+    /// `update_access_by` for each segment, locking each shard once.
+    pub fn update_accesses_by(&self, counts: &[(SegmentId<Slab>, u64)]) {
+        if self.shards.len() == 1 {
+            let mut shard = self.shards[0].lock_or_recover();
+            for &(segment_id, count) in counts {
+                shard.update_access_by(segment_id, count);
+            }
+            return;
+        }
+        let mut by_shard: Vec<Vec<(SegmentId<Slab>, u64)>> = vec![Vec::new(); self.shards.len()];
+        for &(segment_id, count) in counts {
+            by_shard[self.shard_index(&segment_id)].push((segment_id, count));
+        }
+        for (shard, counts) in self.shards.iter().zip(by_shard) {
+            if counts.is_empty() {
+                continue;
+            }
+            let mut shard = shard.lock_or_recover();
+            for (segment_id, count) in counts {
+                shard.update_access_by(segment_id, count);
+            }
+        }
+    }
+
+    pub fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.lock_shard(&segment_id).get_access_count(segment_id)
+    }
+
+    pub fn get_last_access_time(&self, segment_id: SegmentId<Slab>) -> Option<SystemTime> {
+        self.lock_shard(&segment_id).get_last_access_time(segment_id)
+    }
+
+    pub fn record_pin_cost(&self, segment_id: SegmentId<Slab>, pin_cost: PinCost) {
+        self.lock_shard(&segment_id)
+            .record_pin_cost(segment_id, pin_cost);
+    }
+
+    /// Set how strongly a segment should be kept pinned. `Normal` clears any earlier hint.
+    pub fn set_priority(&self, segment_id: SegmentId<Slab>, priority: Priority) {
+        let mut priorities = self.priorities.lock_or_recover();
+        match priority {
+            Priority::Normal => priorities.remove(&segment_id),
+            _ => priorities.insert(segment_id, priority),
+        };
+    }
+
+    pub fn get_priority(&self, segment_id: &SegmentId<Slab>) -> Priority {
+        self.priorities
+            .lock_or_recover()
+            .get(segment_id)
+            .copied()
+            .unwrap_or(Priority::Normal)
+    }
+
+    pub fn clear_priorities(&self) {
+        self.priorities.lock_or_recover().clear();
+    }
+
+    /// Union of every shard's hotset, trimmed to the most-accessed `get_num_segments()`.
+    /// Segments with a priority rank above the rest, and `Forever` segments are always kept,
+    /// even past the limit.
+    pub fn return_top_segments_to_pin(&self) -> HashSet<SegmentId<Slab>> {
+        let priorities = self.priorities.lock_or_recover().clone();
+        if self.shards.len() == 1 && priorities.is_empty() {
+            return self.shards[0].lock_or_recover().return_top_segments_to_pin();
+        }
+        let num_segments = self.get_num_segments();
+        let mut candidates: HashMap<SegmentId<Slab>, i64> = HashMap::default();
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock_or_recover();
+            for segment_id in shard.return_top_segments_to_pin() {
+                candidates.insert(segment_id, shard.get_access_count(segment_id).unwrap_or(0));
+            }
+        }
+        for segment_id in priorities.keys() {
+            if !candidates.contains_key(segment_id) {
+                let count = self.get_access_count(*segment_id).unwrap_or(0);
+                candidates.insert(*segment_id, count);
+            }
+        }
+        let priority = |segment_id: &SegmentId<Slab>| {
+            priorities.get(segment_id).copied().unwrap_or(Priority::Normal)
+        };
+        let mut candidates: Vec<(SegmentId<Slab>, i64)> = candidates.into_iter().collect();
+        candidates.sort_by_key(|(segment_id, count)| {
+            std::cmp::Reverse((priority(segment_id), *count))
+        });
+        let num_forever = priorities
+            .values()
+            .filter(|priority| **priority == Priority::Forever)
+            .count();
+        candidates
+            .into_iter()
+            .take(num_segments.max(num_forever))
+            .map(|(segment_id, _)| segment_id)
+            .collect()
+    }
+
+    /// Forget a segment's statistics and priority.
+    pub fn remove_segment(&self, segment_id: SegmentId<Slab>) {
+        self.lock_shard(&segment_id).remove_segment(segment_id);
+        self.priorities.lock_or_recover().remove(&segment_id);
+    }
+
+    pub fn reset(&self) {
+        self.for_each_shard(|cb| cb.reset());
+    }
+
+    pub fn decay(&self, policy: DecayPolicy) {
+        self.for_each_shard(|cb| cb.decay(policy));
+    }
+
+    pub fn get_num_segments(&self) -> usize {
+        self.shards[0].lock_or_recover().get_num_segments()
+    }
+
+    pub fn set_num_segments(&self, num_segments: usize) {
+        self.for_each_shard(|cb| cb.set_num_segments(num_segments));
+    }
+}
@@ -0,0 +1,151 @@
+//! Shared-memory stats export. The cache writes a versioned `SharedStats` struct into a
+//! POSIX shm object (or a memfd) after every repin cycle, so monitoring agents can map it
+//! and read cache state without any in-process integration.
+//!
+//! Writers follow a seqlock protocol: `sequence` is odd while an update is in progress.
+//! Readers should copy the struct and retry if `sequence` was odd or changed meanwhile.
+use std::ffi::CString;
+use std::io;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::metrics::MetricsSnapshot;
+
+pub const SHARED_STATS_MAGIC: u64 = 0x5a43_435f_5354_4154; // "ZCC_STAT"
+pub const SHARED_STATS_VERSION: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct SharedStats {
+    pub magic: u64,
+    pub version: u32,
+    pub size: u32,
+    pub sequence: AtomicU64,
+    /// Nanoseconds since the unix epoch of the last update.
+    pub timestamp_ns: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub unmanaged: u64,
+    pub pins: u64,
+    pub unpins: u64,
+    pub evictions: u64,
+    pub bytes_pinned: u64,
+    pub segments_pinned: u64,
+}
+
+#[derive(Debug)]
+pub struct SharedStatsRegion {
+    fd: libc::c_int,
+    stats: *mut SharedStats,
+    /// Name to unlink on drop, for regions created with `shm_open`.
+    shm_name: Option<CString>,
+}
+
+// SAFETY: the mapping lives until drop, and every write goes through the seqlock in `write`.
+unsafe impl Send for SharedStatsRegion {}
+unsafe impl Sync for SharedStatsRegion {}
+
+impl SharedStatsRegion {
+    /// Create (or truncate) a POSIX shm object, e.g. `/zcc_stats`.
+    pub fn create_shm(name: &str) -> io::Result<Self> {
+        let c_name = CString::new(name)?;
+        let fd = unsafe { libc::shm_open(c_name.as_ptr(), libc::O_CREAT | libc::O_RDWR, 0o644) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Self::map(fd, Some(c_name))
+    }
+
+    /// Create an anonymous memfd; share it with agents through `get_fd`.
+    pub fn create_memfd(name: &str) -> io::Result<Self> {
+        let c_name = CString::new(name)?;
+        let fd = unsafe { libc::memfd_create(c_name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Self::map(fd, None)
+    }
+
+    fn map(fd: libc::c_int, shm_name: Option<CString>) -> io::Result<Self> {
+        let size = std::mem::size_of::<SharedStats>();
+        let fail = |fd: libc::c_int, shm_name: &Option<CString>| {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(fd);
+                if let Some(name) = shm_name {
+                    libc::shm_unlink(name.as_ptr());
+                }
+            }
+            err
+        };
+        if unsafe { libc::ftruncate(fd, size as libc::off_t) } != 0 {
+            return Err(fail(fd, &shm_name));
+        }
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(fail(fd, &shm_name));
+        }
+        let stats = addr as *mut SharedStats;
+        unsafe {
+            std::ptr::write_bytes(stats as *mut u8, 0, size);
+            (*stats).magic = SHARED_STATS_MAGIC;
+            (*stats).version = SHARED_STATS_VERSION;
+            (*stats).size = size as u32;
+        }
+        Ok(SharedStatsRegion { fd, stats, shm_name })
+    }
+
+    pub fn get_fd(&self) -> libc::c_int {
+        self.fd
+    }
+
+    /// The mapped stats, as an external reader would see them.
+    pub fn get_stats(&self) -> &SharedStats {
+        unsafe { &*self.stats }
+    }
+
+    /// Publish a new set of stats.
+    pub fn write(&self, metrics: &MetricsSnapshot, bytes_pinned: usize, segments_pinned: usize) {
+        let stats = self.stats;
+        unsafe {
+            let sequence = &(*stats).sequence;
+            let seq = sequence.load(Ordering::Relaxed);
+            sequence.store(seq.wrapping_add(1), Ordering::Relaxed);
+            fence(Ordering::Release);
+            (*stats).timestamp_ns = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+            (*stats).hits = metrics.hits;
+            (*stats).misses = metrics.misses;
+            (*stats).unmanaged = metrics.unmanaged;
+            (*stats).pins = metrics.pins;
+            (*stats).unpins = metrics.unpins;
+            (*stats).evictions = metrics.evictions;
+            (*stats).bytes_pinned = bytes_pinned as u64;
+            (*stats).segments_pinned = segments_pinned as u64;
+            sequence.store(seq.wrapping_add(2), Ordering::Release);
+        }
+    }
+}
+
+impl Drop for SharedStatsRegion {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.stats as *mut libc::c_void, std::mem::size_of::<SharedStats>());
+            libc::close(self.fd);
+            if let Some(name) = &self.shm_name {
+                libc::shm_unlink(name.as_ptr());
+            }
+        }
+    }
+}
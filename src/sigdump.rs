@@ -0,0 +1,133 @@
+//! SIGUSR1-triggered stats dump, for debugging hung processes nothing else can be attached to.
+//! The signal handler only writes a byte to a pipe; a background thread blocks on the pipe and
+//! writes the dump, so no allocation or locking happens in signal context.
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread::{self, JoinHandle};
+
+use crate::data_structures::{DatapathSlab, ZeroCopyCache};
+use crate::segment_policy::SegmentPolicy;
+
+/// Write end of the pipe the signal handler notifies, or -1 before installation.
+static SIGNAL_PIPE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Where a dump goes.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum DumpTarget {
+    /// Emit the dump as an `info` event.
+    Tracing,
+    /// Append the dump to a file.
+    File(PathBuf),
+}
+
+extern "C" fn handle_sigusr1(_signal: libc::c_int) {
+    let fd = SIGNAL_PIPE_WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = 1u8;
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+}
+
+/// Metrics snapshot and pinned-set summary, one `key: value` per line.
+pub fn render_dump<Slab, CB>(zero_copy_cache: &ZeroCopyCache<Slab, CB>) -> String
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+    CB: SegmentPolicy<Slab>,
+{
+    let metrics = zero_copy_cache.get_metrics();
+    let churn = zero_copy_cache.get_churn_stats();
+    let slab_stats = zero_copy_cache.slab_stats();
+    let mut out = String::new();
+    let _ = writeln!(out, "zcc stats dump");
+    let _ = writeln!(out, "metrics: {:?}", metrics);
+    let _ = writeln!(out, "hit_rate: {:.4}", metrics.hit_rate());
+    let _ = writeln!(out, "churn: {:?}", churn);
+    let _ = writeln!(
+        out,
+        "pinned: {} segments, {} bytes",
+        slab_stats.values().map(|s| s.pinned_segments).sum::<usize>(),
+        slab_stats.values().map(|s| s.pinned_bytes).sum::<usize>()
+    );
+    for (slab_id, stats) in slab_stats.iter() {
+        let _ = writeln!(
+            out,
+            "slab {:?}: {}/{} segments pinned, {}/{} bytes pinned",
+            slab_id,
+            stats.pinned_segments,
+            stats.num_segments,
+            stats.pinned_bytes,
+            stats.total_bytes
+        );
+    }
+    out
+}
+
+fn write_dump(target: &DumpTarget, dump: &str) -> io::Result<()> {
+    match target {
+        DumpTarget::Tracing => {
+            tracing::info!("{}", dump);
+            Ok(())
+        }
+        DumpTarget::File(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(dump.as_bytes()),
+    }
+}
+
+/// Install a SIGUSR1 handler and dump the cache's stats to `target` every time it fires.
+/// Only one handler can be installed per process.
+pub fn spawn_sigusr1_dumper<Slab, CB>(
+    zero_copy_cache: ZeroCopyCache<Slab, CB>,
+    target: DumpTarget,
+) -> io::Result<JoinHandle<()>>
+where
+    Slab: DatapathSlab + std::fmt::Debug + 'static,
+    CB: SegmentPolicy<Slab> + 'static,
+    ZeroCopyCache<Slab, CB>: Send,
+{
+    let mut fds = [0 as libc::c_int; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read_fd, write_fd] = fds;
+    if SIGNAL_PIPE_WRITE_FD
+        .compare_exchange(-1, write_fd, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "SIGUSR1 dumper already installed",
+        ));
+    }
+    let handler = handle_sigusr1 as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGUSR1, handler) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(thread::spawn(move || {
+        let mut byte = 0u8;
+        loop {
+            let read = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if read < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            if read <= 0 {
+                tracing::warn!("SIGUSR1 dumper pipe closed");
+                return;
+            }
+            if let Err(e) = write_dump(&target, &render_dump(&zero_copy_cache)) {
+                tracing::warn!("SIGUSR1 stats dump failed: {:?}", e);
+            }
+        }
+    }))
+}
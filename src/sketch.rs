@@ -0,0 +1,247 @@
+//! Sketch-backed access counting. A count-min sketch estimates per-segment counts in constant
+//! memory, and a small exact table keeps the current top-k candidates for pinning.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use crate::data_structures::{DatapathSlab, DecayPolicy, SegmentId, DEFAULT_CACHE_SIZE};
+use crate::segment_policy::SegmentPolicy;
+
+pub const DEFAULT_SKETCH_WIDTH: usize = 2048;
+pub const DEFAULT_SKETCH_DEPTH: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct CountMinSketch<T>
+where
+    T: Hash,
+{
+    width: usize,
+    depth: usize,
+    /// `depth` rows of `width` counters, stored row after row.
+    counters: Vec<u64>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> CountMinSketch<T>
+where
+    T: Hash,
+{
+    pub fn new(width: usize, depth: usize) -> Self {
+        assert!(width > 0 && depth > 0, "sketch dimensions must be non-zero");
+        CountMinSketch {
+            width,
+            depth,
+            counters: vec![0; width * depth],
+            _marker: PhantomData,
+        }
+    }
+
+    fn index(&self, row: usize, item: &T) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        item.hash(&mut hasher);
+        row * self.width + (hasher.finish() as usize) % self.width
+    }
+
+    /// Add one to the item's counters and return its new estimate.
+    pub fn increment(&mut self, item: &T) -> u64 {
+        let mut estimate = u64::MAX;
+        for row in 0..self.depth {
+            let idx = self.index(row, item);
+            self.counters[idx] += 1;
+            estimate = estimate.min(self.counters[idx]);
+        }
+        estimate
+    }
+
+    /// Estimated count; never lower than the true count.
+    pub fn estimate(&self, item: &T) -> u64 {
+        (0..self.depth)
+            .map(|row| self.counters[self.index(row, item)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Scale every counter by `numerator / denominator`, e.g. 1/2 to halve all counts.
+    pub fn scale(&mut self, numerator: u64, denominator: u64) {
+        for counter in self.counters.iter_mut() {
+            *counter = *counter * numerator / denominator;
+        }
+    }
+
+    /// Subtract `amount` from every counter, saturating at zero.
+    pub fn subtract(&mut self, amount: u64) {
+        for counter in self.counters.iter_mut() {
+            *counter = counter.saturating_sub(amount);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.iter_mut().for_each(|c| *c = 0);
+    }
+}
+
+#[derive(Debug)]
+pub struct SketchCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Maximum number of segments returned as the hotset.
+    num_segments: usize,
+    sketch: CountMinSketch<SegmentId<Slab>>,
+    /// Exact table of at most `num_segments` candidates, with their estimated counts and the
+    /// tag that orders candidates with the same count.
+    top_segments: HashMap<SegmentId<Slab>, (u64, u64)>,
+    /// The same candidates ordered by count, so the coldest is found in O(log k).
+    by_count: BTreeMap<(u64, u64), SegmentId<Slab>>,
+    next_tag: u64,
+}
+
+impl<Slab> Default for SketchCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        SketchCache::new(DEFAULT_CACHE_SIZE, DEFAULT_SKETCH_WIDTH, DEFAULT_SKETCH_DEPTH)
+    }
+}
+
+impl<Slab> SketchCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn new(num_segments: usize, width: usize, depth: usize) -> Self {
+        SketchCache {
+            num_segments,
+            sketch: CountMinSketch::new(width, depth),
+            top_segments: HashMap::default(),
+            by_count: BTreeMap::new(),
+            next_tag: 0,
+        }
+    }
+
+    pub fn get_sketch(&self) -> &CountMinSketch<SegmentId<Slab>> {
+        &self.sketch
+    }
+
+    /// Scale all counts, keeping the top-k table consistent with the sketch.
+    pub fn scale(&mut self, numerator: u64, denominator: u64) {
+        self.sketch.scale(numerator, denominator);
+        for (count, _) in self.top_segments.values_mut() {
+            *count = *count * numerator / denominator;
+        }
+        self.rebuild_order();
+    }
+
+    fn coldest_top_segment(&self) -> Option<(SegmentId<Slab>, u64)> {
+        self.by_count
+            .first_key_value()
+            .map(|((count, _), id)| (*id, *count))
+    }
+
+    /// Insert or update a candidate's count.
+    fn set_count(&mut self, segment_id: SegmentId<Slab>, count: u64) {
+        let tag = match self.top_segments.get(&segment_id) {
+            Some(&(old_count, tag)) => {
+                self.by_count.remove(&(old_count, tag));
+                tag
+            }
+            None => {
+                self.next_tag += 1;
+                self.next_tag
+            }
+        };
+        self.top_segments.insert(segment_id, (count, tag));
+        self.by_count.insert((count, tag), segment_id);
+    }
+
+    fn remove_candidate(&mut self, segment_id: &SegmentId<Slab>) {
+        if let Some(key) = self.top_segments.remove(segment_id) {
+            self.by_count.remove(&key);
+        }
+    }
+
+    /// Re-sort after counts in `top_segments` changed in place.
+    fn rebuild_order(&mut self) {
+        self.by_count = self
+            .top_segments
+            .iter()
+            .map(|(id, key)| (*key, *id))
+            .collect();
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for SketchCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        let estimate = self.sketch.increment(&segment_id);
+        if self.top_segments.contains_key(&segment_id)
+            || self.top_segments.len() < self.num_segments
+        {
+            self.set_count(segment_id, estimate);
+            return;
+        }
+        if let Some((coldest, coldest_count)) = self.coldest_top_segment() {
+            if estimate > coldest_count {
+                self.remove_candidate(&coldest);
+                self.set_count(segment_id, estimate);
+            }
+        }
+    }
+
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        match self.sketch.estimate(&segment_id) {
+            0 => None,
+            n => Some(n as i64),
+        }
+    }
+
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        self.top_segments.keys().copied().collect()
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    /// Shrinking drops the coldest candidates from the top-k table.
+    fn set_num_segments(&mut self, num_segments: usize) {
+        self.num_segments = num_segments;
+        while self.top_segments.len() > num_segments {
+            match self.coldest_top_segment() {
+                Some((coldest, _)) => self.remove_candidate(&coldest),
+                None => break,
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.sketch.clear();
+        self.top_segments.clear();
+        self.by_count.clear();
+    }
+
+    /// The sketch's counters are shared between segments, so only the candidate is dropped.
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        self.remove_candidate(&segment_id);
+    }
+
+    fn decay(&mut self, policy: DecayPolicy) {
+        match policy {
+            DecayPolicy::Halving => self.scale(1, 2),
+            DecayPolicy::SubtractMin => {
+                let min = self.coldest_top_segment().map_or(0, |(_, count)| count);
+                self.sketch.subtract(min);
+                for (count, _) in self.top_segments.values_mut() {
+                    *count -= min;
+                }
+            }
+            DecayPolicy::FullReset => self.reset(),
+        }
+        self.top_segments.retain(|_, (count, _)| *count > 0);
+        self.rebuild_order();
+    }
+}
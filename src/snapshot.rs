@@ -0,0 +1,39 @@
+//! Point-in-time view of the hotset, meant to be persisted and used to warm up a restarted
+//! cache. Enable the `serde` feature to serialize it.
+use std::time::SystemTime;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegmentSnapshot<ID> {
+    pub segment_id: ID,
+    pub pinned: bool,
+    pub access_count: Option<i64>,
+    pub last_access_time: Option<SystemTime>,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HotsetSnapshot<ID> {
+    pub taken_at: SystemTime,
+    /// Segments registered when the snapshot was taken.
+    pub pinned: Vec<ID>,
+    /// Every managed segment, pinned or not.
+    pub segments: Vec<SegmentSnapshot<ID>>,
+}
+
+impl<ID> HotsetSnapshot<ID>
+where
+    ID: Clone,
+{
+    pub fn new(segments: Vec<SegmentSnapshot<ID>>) -> Self {
+        HotsetSnapshot {
+            taken_at: SystemTime::now(),
+            pinned: segments
+                .iter()
+                .filter(|s| s.pinned)
+                .map(|s| s.segment_id.clone())
+                .collect(),
+            segments,
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Space-Saving heavy hitter tracking. Only `capacity` segments are monitored at any time; an
+//! unmonitored segment replaces the one with the smallest count and inherits that count as its
+//! error. Counters live in a stream-summary (buckets of equal count in a sorted linked list),
+//! so every update is O(1).
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::data_structures::{DatapathSlab, SegmentId, DEFAULT_CACHE_SIZE};
+use crate::segment_policy::SegmentPolicy;
+
+#[derive(Debug, Clone)]
+struct Bucket<T> {
+    count: u64,
+    items: Vec<T>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+    bucket: usize,
+    /// Position of the item within its bucket's `items`.
+    pos: usize,
+    /// Upper bound on how much the count over-estimates the true count.
+    error: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpaceSavingSummary<T>
+where
+    T: Hash + Eq + Copy,
+{
+    capacity: usize,
+    counters: HashMap<T, Counter>,
+    buckets: Vec<Bucket<T>>,
+    free_buckets: Vec<usize>,
+    /// Bucket with the smallest count.
+    head: Option<usize>,
+    /// Bucket with the largest count.
+    tail: Option<usize>,
+}
+
+impl<T> SpaceSavingSummary<T>
+where
+    T: Hash + Eq + Copy,
+{
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "space-saving capacity must be non-zero");
+        SpaceSavingSummary {
+            capacity,
+            counters: HashMap::default(),
+            buckets: Vec::new(),
+            free_buckets: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Monitor up to `capacity` items from now on. The summary never shrinks, so a smaller
+    /// `capacity` is ignored.
+    pub fn grow(&mut self, capacity: usize) {
+        self.capacity = self.capacity.max(capacity);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    /// Estimated count of a monitored item; an upper bound on its true count.
+    pub fn count(&self, item: &T) -> Option<u64> {
+        self.counters.get(item).map(|c| self.buckets[c.bucket].count)
+    }
+
+    /// Count the item is guaranteed to have reached.
+    pub fn guaranteed_count(&self, item: &T) -> Option<u64> {
+        self.counters
+            .get(item)
+            .map(|c| self.buckets[c.bucket].count - c.error)
+    }
+
+    fn alloc_bucket(&mut self, count: u64, prev: Option<usize>, next: Option<usize>) -> usize {
+        let bucket = Bucket {
+            count,
+            items: Vec::new(),
+            prev,
+            next,
+        };
+        let idx = match self.free_buckets.pop() {
+            Some(idx) => {
+                self.buckets[idx] = bucket;
+                idx
+            }
+            None => {
+                self.buckets.push(bucket);
+                self.buckets.len() - 1
+            }
+        };
+        match prev {
+            Some(p) => self.buckets[p].next = Some(idx),
+            None => self.head = Some(idx),
+        }
+        match next {
+            Some(n) => self.buckets[n].prev = Some(idx),
+            None => self.tail = Some(idx),
+        }
+        idx
+    }
+
+    fn unlink_bucket(&mut self, idx: usize) {
+        let (prev, next) = (self.buckets[idx].prev, self.buckets[idx].next);
+        match prev {
+            Some(p) => self.buckets[p].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(n) => self.buckets[n].prev = prev,
+            None => self.tail = prev,
+        }
+        self.free_buckets.push(idx);
+    }
+
+    fn push_item(&mut self, bucket: usize, item: T, error: u64) {
+        let pos = self.buckets[bucket].items.len();
+        self.buckets[bucket].items.push(item);
+        self.counters.insert(item, Counter { bucket, pos, error });
+    }
+
+    /// Remove the item from its bucket, freeing the bucket if it becomes empty.
+    fn take_item(&mut self, item: &T) -> Counter {
+        let counter = self.counters.remove(item).unwrap();
+        let items = &mut self.buckets[counter.bucket].items;
+        items.swap_remove(counter.pos);
+        if let Some(moved) = items.get(counter.pos).copied() {
+            self.counters.get_mut(&moved).unwrap().pos = counter.pos;
+        }
+        counter
+    }
+
+    /// Move a monitored item from its bucket into the bucket for `count + 1`.
+    fn promote(&mut self, item: T, error: u64) {
+        let bucket = self.counters[&item].bucket;
+        let count = self.buckets[bucket].count;
+        let next = self.buckets[bucket].next;
+        self.take_item(&item);
+        let target = match next {
+            Some(n) if self.buckets[n].count == count + 1 => n,
+            _ => self.alloc_bucket(count + 1, Some(bucket), next),
+        };
+        if self.buckets[bucket].items.is_empty() {
+            self.unlink_bucket(bucket);
+        }
+        self.push_item(target, item, error);
+    }
+
+    /// Record one occurrence of the item. Returns the item evicted to make room, if any.
+    pub fn increment(&mut self, item: T) -> Option<T> {
+        if let Some(counter) = self.counters.get(&item) {
+            let error = counter.error;
+            self.promote(item, error);
+            return None;
+        }
+        if self.counters.len() < self.capacity {
+            let target = match self.head {
+                Some(h) if self.buckets[h].count == 1 => h,
+                head => self.alloc_bucket(1, None, head),
+            };
+            self.push_item(target, item, 0);
+            return None;
+        }
+        // Replace an item with the minimum count and inherit that count as our error.
+        let head = self.head.unwrap();
+        let evicted = *self.buckets[head].items.last().unwrap();
+        let min_count = self.buckets[head].count;
+        self.take_item(&evicted);
+        self.push_item(head, item, min_count);
+        self.promote(item, min_count);
+        Some(evicted)
+    }
+
+    /// Stop monitoring an item. Returns its estimated count, if it was monitored.
+    pub fn remove(&mut self, item: &T) -> Option<u64> {
+        let count = self.count(item)?;
+        let counter = self.take_item(item);
+        if self.buckets[counter.bucket].items.is_empty() {
+            self.unlink_bucket(counter.bucket);
+        }
+        Some(count)
+    }
+
+    /// Up to `k` monitored items, hottest first.
+    pub fn top_k(&self, k: usize) -> Vec<(T, u64)> {
+        let mut top = Vec::with_capacity(k);
+        let mut cursor = self.tail;
+        while let Some(idx) = cursor {
+            for item in self.buckets[idx].items.iter() {
+                if top.len() >= k {
+                    return top;
+                }
+                top.push((*item, self.buckets[idx].count));
+            }
+            cursor = self.buckets[idx].prev;
+        }
+        top
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.clear();
+        self.buckets.clear();
+        self.free_buckets.clear();
+        self.head = None;
+        self.tail = None;
+    }
+}
+
+#[derive(Debug)]
+pub struct SpaceSavingCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Maximum number of segments returned as the hotset.
+    num_segments: usize,
+    summary: SpaceSavingSummary<SegmentId<Slab>>,
+}
+
+impl<Slab> Default for SpaceSavingCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        SpaceSavingCache::new(DEFAULT_CACHE_SIZE, 2 * DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl<Slab> SpaceSavingCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// `capacity` is the number of monitored segments and should be at least `num_segments`;
+    /// a larger capacity gives more accurate counts near the top-k boundary.
+    pub fn new(num_segments: usize, capacity: usize) -> Self {
+        SpaceSavingCache {
+            num_segments,
+            summary: SpaceSavingSummary::new(capacity.max(num_segments).max(1)),
+        }
+    }
+
+    pub fn get_summary(&self) -> &SpaceSavingSummary<SegmentId<Slab>> {
+        &self.summary
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for SpaceSavingCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        self.summary.increment(segment_id);
+    }
+
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.summary.count(&segment_id).map(|c| c as i64)
+    }
+
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        self.summary
+            .top_k(self.num_segments)
+            .into_iter()
+            .map(|(seg_id, _)| seg_id)
+            .collect()
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    /// Growing past the summary's capacity grows the summary too, keeping the slack it had
+    /// over the hotset size, so the larger hotset can actually be returned.
+    fn set_num_segments(&mut self, num_segments: usize) {
+        let capacity = self.summary.capacity();
+        if num_segments > capacity {
+            let slack = capacity.saturating_sub(self.num_segments);
+            self.summary.grow(num_segments + slack);
+        }
+        self.num_segments = num_segments;
+    }
+
+    fn reset(&mut self) {
+        self.summary.clear();
+    }
+
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        self.summary.remove(&segment_id);
+    }
+}
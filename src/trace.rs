@@ -0,0 +1,265 @@
+//! Access traces, for evaluating hotset policies offline on real workloads. A `TraceRecorder`
+//! installed with `ZeroCopyCache::set_access_tracer` appends a fixed-size record of every
+//! segment access (time since the recorder started, segment id, hit or miss, bytes) to an
+//! in-memory buffer. When it fills up, it is swapped with a second buffer and a writer thread
+//! writes it out, so the datapath never waits on the file. `TraceReader` reads a trace back.
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::metrics::LockOrRecover;
+
+/// Bytes buffered before a buffer is handed to the writer thread.
+pub const DEFAULT_TRACE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Start of every trace file, followed by a format version byte.
+const TRACE_MAGIC: &[u8; 4] = b"ZCTR";
+const TRACE_VERSION: u8 = 1;
+/// Bytes per record: timestamp, slab id, segment index, flags and length.
+pub const TRACE_RECORD_SIZE: usize = 29;
+
+const FLAG_HIT: u8 = 1;
+const FLAG_MANAGED: u8 = 2;
+
+/// Receives every datapath access, hit or miss.
+pub trait AccessTracer<ID>: std::fmt::Debug + Send + Sync {
+    /// An access of `len` bytes to `segment_id`, `None` for unmanaged buffers, served
+    /// zero-copy if `hit`. A buffer covering several segments is one access per segment.
+    fn on_access(&self, segment_id: Option<ID>, hit: bool, len: usize);
+}
+
+/// One access, as written to a trace.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct TraceRecord {
+    /// Nanoseconds since the recorder was created.
+    pub timestamp_ns: u64,
+    /// Slab id and segment index, `None` for unmanaged buffers.
+    pub segment_id: Option<(u64, u64)>,
+    pub hit: bool,
+    pub len: u32,
+}
+
+impl TraceRecord {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (slab_id, segment) = self.segment_id.unwrap_or_default();
+        let mut flags = 0;
+        if self.hit {
+            flags |= FLAG_HIT;
+        }
+        if self.segment_id.is_some() {
+            flags |= FLAG_MANAGED;
+        }
+        out.extend_from_slice(&self.timestamp_ns.to_le_bytes());
+        out.extend_from_slice(&slab_id.to_le_bytes());
+        out.extend_from_slice(&segment.to_le_bytes());
+        out.push(flags);
+        out.extend_from_slice(&self.len.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8; TRACE_RECORD_SIZE]) -> Self {
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let flags = bytes[24];
+        TraceRecord {
+            timestamp_ns: u64_at(0),
+            segment_id: (flags & FLAG_MANAGED != 0).then(|| (u64_at(8), u64_at(16))),
+            hit: flags & FLAG_HIT != 0,
+            len: u32::from_le_bytes(bytes[25..29].try_into().unwrap()),
+        }
+    }
+}
+
+/// The buffer being filled, and the way to the writer thread and back.
+#[derive(Debug)]
+struct FillState {
+    buffer: Vec<u8>,
+    /// Full buffers to write; `None` once the recorder is finished.
+    full: Option<Sender<Vec<u8>>>,
+    /// Written buffers, to fill again.
+    spare: Receiver<Vec<u8>>,
+}
+
+/// Writes every access it is told about to a trace file, see the module docs. If the writer
+/// thread still has the other buffer when one fills up, records are dropped rather than
+/// stalling the datapath, and counted in `get_dropped_records`.
+#[derive(Debug)]
+pub struct TraceRecorder {
+    started: Instant,
+    buffer_size: usize,
+    state: Mutex<FillState>,
+    dropped_records: AtomicU64,
+    writer: Mutex<Option<JoinHandle<io::Result<()>>>>,
+}
+
+impl TraceRecorder {
+    /// Trace to a new file at `path`, replacing any existing one.
+    pub fn create(path: impl AsRef<Path>, buffer_size: usize) -> io::Result<Self> {
+        TraceRecorder::from_writer(File::create(path)?, buffer_size)
+    }
+
+    /// Trace to `writer`, writing the header right away. `buffer_size` is rounded up to hold
+    /// at least one record.
+    pub fn from_writer(
+        mut writer: impl Write + Send + 'static,
+        buffer_size: usize,
+    ) -> io::Result<Self> {
+        writer.write_all(TRACE_MAGIC)?;
+        writer.write_all(&[TRACE_VERSION])?;
+        let buffer_size = buffer_size.max(TRACE_RECORD_SIZE);
+        let (full_tx, full_rx) = mpsc::channel();
+        let (spare_tx, spare_rx) = mpsc::channel();
+        // The second buffer, written while the first fills.
+        let _ = spare_tx.send(Vec::with_capacity(buffer_size));
+        let writer = thread::spawn(move || write_buffers(writer, full_rx, spare_tx));
+        Ok(TraceRecorder {
+            started: Instant::now(),
+            buffer_size,
+            state: Mutex::new(FillState {
+                buffer: Vec::with_capacity(buffer_size),
+                full: Some(full_tx),
+                spare: spare_rx,
+            }),
+            dropped_records: AtomicU64::new(0),
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+
+    /// Append a record of an access, see `AccessTracer::on_access`.
+    pub fn record(&self, segment_id: Option<(u64, u64)>, hit: bool, len: usize) {
+        let record = TraceRecord {
+            timestamp_ns: self.started.elapsed().as_nanos() as u64,
+            segment_id,
+            hit,
+            len: len.try_into().unwrap_or(u32::MAX),
+        };
+        let mut state = self.state.lock_or_recover();
+        let full = state.buffer.len() + TRACE_RECORD_SIZE > self.buffer_size;
+        if full && !Self::swap(&mut state) {
+            self.dropped_records.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        record.encode(&mut state.buffer);
+    }
+
+    /// Hand the full buffer to the writer thread and fill the spare one instead. False if
+    /// there is no spare yet, or the recorder is finished.
+    fn swap(state: &mut FillState) -> bool {
+        let full = match &state.full {
+            Some(full) => full,
+            None => return false,
+        };
+        let spare = match state.spare.try_recv() {
+            Ok(spare) => spare,
+            Err(_) => return false,
+        };
+        full.send(std::mem::replace(&mut state.buffer, spare)).is_ok()
+    }
+
+    /// Records not written because both buffers were full, or after `finish`.
+    pub fn get_dropped_records(&self) -> u64 {
+        self.dropped_records.load(Ordering::Relaxed)
+    }
+
+    /// Write out what is buffered and stop the writer thread, returning the first write
+    /// error if any. Later accesses are dropped. Also done on drop, logging the error.
+    pub fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock_or_recover();
+        if let Some(full) = state.full.take() {
+            let _ = full.send(std::mem::take(&mut state.buffer));
+        }
+        drop(state);
+        match self.writer.lock_or_recover().take() {
+            Some(writer) => writer
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("trace writer thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S> AccessTracer<(S, usize)> for TraceRecorder
+where
+    S: TryInto<u64>,
+{
+    fn on_access(&self, segment_id: Option<(S, usize)>, hit: bool, len: usize) {
+        let segment_id = segment_id.map(|(slab_id, segment)| {
+            (slab_id.try_into().unwrap_or(u64::MAX), segment as u64)
+        });
+        self.record(segment_id, hit, len);
+    }
+}
+
+impl Drop for TraceRecorder {
+    fn drop(&mut self) {
+        if let Err(error) = self.finish() {
+            tracing::error!("Failed to write access trace: {}", error);
+        }
+    }
+}
+
+/// Writer thread: write each full buffer and send it back empty.
+fn write_buffers(
+    mut writer: impl Write,
+    full: Receiver<Vec<u8>>,
+    spare: Sender<Vec<u8>>,
+) -> io::Result<()> {
+    for mut buffer in full {
+        writer.write_all(&buffer)?;
+        buffer.clear();
+        let _ = spare.send(buffer);
+    }
+    writer.flush()
+}
+
+/// Records of a trace written by a `TraceRecorder`, in the order they were recorded.
+#[derive(Debug)]
+pub struct TraceReader<R> {
+    reader: R,
+}
+
+impl TraceReader<io::BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        TraceReader::new(io::BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R> TraceReader<R>
+where
+    R: Read,
+{
+    /// Check the header and read records from `reader`.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != TRACE_MAGIC || header[4] != TRACE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an access trace"));
+        }
+        Ok(TraceReader { reader })
+    }
+}
+
+impl<R> Iterator for TraceReader<R>
+where
+    R: Read,
+{
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; TRACE_RECORD_SIZE];
+        let mut filled = 0;
+        while filled < TRACE_RECORD_SIZE {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => return Some(Err(io::ErrorKind::UnexpectedEof.into())),
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        Some(Ok(TraceRecord::decode(&bytes)))
+    }
+}
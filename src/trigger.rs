@@ -0,0 +1,87 @@
+//! Event-driven repinning. A `MissRateTrigger` watches datapath hits and misses in windows
+//! of `window` accesses and, when the miss rate of a window exceeds the threshold, wakes the
+//! pin worker to run a repin cycle right away, at most once every `min_interval`.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics::LockOrRecover;
+use crate::worker::RepinTrigger;
+
+#[derive(Debug)]
+pub struct MissRateTrigger {
+    window: u64,
+    threshold: f64,
+    min_interval: Duration,
+    accesses: AtomicU64,
+    misses: AtomicU64,
+    fired: AtomicU64,
+    last_fired: Mutex<Option<Instant>>,
+    target: Mutex<Option<RepinTrigger>>,
+}
+
+impl MissRateTrigger {
+    pub fn new(window: u64, threshold: f64, min_interval: Duration) -> Self {
+        MissRateTrigger {
+            window: window.max(1),
+            threshold,
+            min_interval,
+            accesses: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            fired: AtomicU64::new(0),
+            last_fired: Mutex::new(None),
+            target: Mutex::new(None),
+        }
+    }
+
+    /// Wake `target` when the trigger fires, replacing any earlier target.
+    pub fn attach(&self, target: RepinTrigger) {
+        *self.target.lock_or_recover() = Some(target);
+    }
+
+    pub fn detach(&self) {
+        *self.target.lock_or_recover() = None;
+    }
+
+    /// Times a repin cycle was requested.
+    pub fn fired(&self) -> u64 {
+        self.fired.load(Ordering::Relaxed)
+    }
+
+    /// Record one datapath access. Returns whether it completed a window that fired.
+    pub fn record(&self, hit: bool) -> bool {
+        if !hit {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        let accesses = self.accesses.fetch_add(1, Ordering::Relaxed) + 1;
+        if accesses < self.window {
+            return false;
+        }
+        // Whoever completes the window closes it; concurrent accesses land in the next one.
+        if self
+            .accesses
+            .compare_exchange(accesses, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        let misses = self.misses.swap(0, Ordering::Relaxed);
+        let miss_rate = misses as f64 / accesses as f64;
+        if miss_rate <= self.threshold {
+            return false;
+        }
+        {
+            let mut last_fired = self.last_fired.lock_or_recover();
+            if last_fired.is_some_and(|at| at.elapsed() < self.min_interval) {
+                return false;
+            }
+            *last_fired = Some(Instant::now());
+        }
+        tracing::debug!("Miss rate {:.3} over {} accesses, triggering repin", miss_rate, accesses);
+        self.fired.fetch_add(1, Ordering::Relaxed);
+        if let Some(target) = self.target.lock_or_recover().as_ref() {
+            target.trigger();
+        }
+        true
+    }
+}
@@ -0,0 +1,184 @@
+//! Sliding-window access statistics. Accesses are counted into a ring of buckets that each
+//! span a slice of the window; expired buckets are subtracted from the running totals, so
+//! the hotset reflects only the last window of time or accesses.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::data_structures::{DatapathSlab, DecayPolicy, SegmentId, DEFAULT_CACHE_SIZE};
+use crate::segment_policy::SegmentPolicy;
+
+pub const DEFAULT_NUM_BUCKETS: usize = 10;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Window {
+    /// Count accesses made within the last duration.
+    Time(Duration),
+    /// Count the last N accesses.
+    Accesses(usize),
+}
+
+#[derive(Debug)]
+pub struct WindowCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    /// Maximum number of segments returned as the hotset.
+    num_segments: usize,
+    window: Window,
+    num_buckets: usize,
+    /// Per-bucket counts, oldest first; the back is the bucket being filled.
+    buckets: VecDeque<HashMap<SegmentId<Slab>, i64>>,
+    /// Sum of all buckets.
+    totals: HashMap<SegmentId<Slab>, i64>,
+    /// Start of the current bucket, for time windows.
+    bucket_start: Instant,
+    /// Accesses recorded in the current bucket, for access-count windows.
+    bucket_accesses: usize,
+}
+
+impl<Slab> Default for WindowCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn default() -> Self {
+        WindowCache::new(
+            DEFAULT_CACHE_SIZE,
+            Window::Time(Duration::from_secs(10)),
+            DEFAULT_NUM_BUCKETS,
+        )
+    }
+}
+
+impl<Slab> WindowCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    pub fn new(num_segments: usize, window: Window, num_buckets: usize) -> Self {
+        assert!(num_buckets > 0, "window needs at least one bucket");
+        match window {
+            Window::Time(d) => assert!(!d.is_zero(), "window duration must be non-zero"),
+            Window::Accesses(n) => assert!(n >= num_buckets, "window shorter than bucket count"),
+        }
+        let mut buckets = VecDeque::with_capacity(num_buckets);
+        buckets.push_back(HashMap::default());
+        WindowCache {
+            num_segments,
+            window,
+            num_buckets,
+            buckets,
+            totals: HashMap::default(),
+            bucket_start: Instant::now(),
+            bucket_accesses: 0,
+        }
+    }
+
+    pub fn get_window(&self) -> Window {
+        self.window
+    }
+
+    /// Close the current bucket and open a new one, expiring the oldest if the ring is full.
+    fn rotate(&mut self) {
+        if self.buckets.len() == self.num_buckets {
+            let expired = self.buckets.pop_front().unwrap();
+            for (segment_id, count) in expired {
+                if let Some(total) = self.totals.get_mut(&segment_id) {
+                    *total -= count;
+                    if *total <= 0 {
+                        self.totals.remove(&segment_id);
+                    }
+                }
+            }
+        }
+        self.buckets.push_back(HashMap::default());
+        self.bucket_accesses = 0;
+    }
+
+    /// Expire time buckets that have fallen out of the window as of `now`.
+    pub fn advance_to(&mut self, now: Instant) {
+        if let Window::Time(duration) = self.window {
+            let span = duration / self.num_buckets as u32;
+            let elapsed = now.saturating_duration_since(self.bucket_start);
+            let steps = (elapsed.as_nanos() / span.as_nanos().max(1)) as usize;
+            for _ in 0..steps.min(self.num_buckets) {
+                self.rotate();
+            }
+            if steps >= self.num_buckets {
+                self.bucket_start = now;
+            } else {
+                self.bucket_start += span * steps as u32;
+            }
+        }
+    }
+
+    pub fn update_access_at(&mut self, segment_id: SegmentId<Slab>, now: Instant) {
+        self.advance_to(now);
+        if let Window::Accesses(n) = self.window {
+            if self.bucket_accesses >= n / self.num_buckets {
+                self.rotate();
+            }
+        }
+        *self.buckets.back_mut().unwrap().entry(segment_id).or_insert(0) += 1;
+        *self.totals.entry(segment_id).or_insert(0) += 1;
+        self.bucket_accesses += 1;
+    }
+
+    pub fn return_top_segments_to_pin_at(&mut self, now: Instant) -> HashSet<SegmentId<Slab>> {
+        self.advance_to(now);
+        let mut sorting_vec: Vec<(SegmentId<Slab>, i64)> =
+            self.totals.iter().map(|(k, v)| (*k, *v)).collect();
+        sorting_vec.sort_by_key(|b| std::cmp::Reverse(b.1));
+        sorting_vec
+            .into_iter()
+            .take(self.num_segments)
+            .map(|(seg_id, _)| seg_id)
+            .collect()
+    }
+}
+
+impl<Slab> SegmentPolicy<Slab> for WindowCache<Slab>
+where
+    Slab: DatapathSlab + std::fmt::Debug,
+{
+    fn update_access(&mut self, segment_id: SegmentId<Slab>) {
+        self.update_access_at(segment_id, Instant::now());
+    }
+
+    /// Accesses within the window, as of the last update.
+    fn get_access_count(&self, segment_id: SegmentId<Slab>) -> Option<i64> {
+        self.totals.get(&segment_id).copied()
+    }
+
+    fn return_top_segments_to_pin(&mut self) -> HashSet<SegmentId<Slab>> {
+        self.return_top_segments_to_pin_at(Instant::now())
+    }
+
+    fn get_num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    fn set_num_segments(&mut self, num_segments: usize) {
+        self.num_segments = num_segments;
+    }
+
+    fn reset(&mut self) {
+        self.buckets.clear();
+        self.buckets.push_back(HashMap::default());
+        self.totals.clear();
+        self.bucket_start = Instant::now();
+        self.bucket_accesses = 0;
+    }
+
+    fn remove_segment(&mut self, segment_id: SegmentId<Slab>) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.remove(&segment_id);
+        }
+        self.totals.remove(&segment_id);
+    }
+
+    /// Expired buckets already age the statistics, so only a full reset has any effect.
+    fn decay(&mut self, policy: DecayPolicy) {
+        if policy == DecayPolicy::FullReset {
+            self.reset();
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Background pin/unpin worker. `ZeroCopyCache::spawn_pin_worker` runs repin cycles on its
+//! own thread and returns a `PinWorker` handle to pause, resume, trigger or stop it;
+//! `spawn_sharded_pin_worker` does the same with each cycle's pins and unpins spread over
+//! several threads.
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::data_structures::{DatapathSlab, HotsetChurn, ZeroCopyCache};
+use crate::metrics::{recover, LockOrRecover};
+use crate::segment_policy::SegmentPolicy;
+
+#[derive(Debug, Default)]
+struct WorkerControl {
+    paused: bool,
+    triggered: bool,
+    shutdown: bool,
+    cycles: u64,
+}
+
+#[derive(Debug, Default)]
+struct WorkerShared {
+    control: Mutex<WorkerControl>,
+    wakeup: Condvar,
+}
+
+/// Cheap handle that asks a pin worker for an immediate repin cycle, e.g. from a
+/// `MissRateTrigger`. Does nothing once the worker is gone.
+#[derive(Clone, Debug)]
+pub struct RepinTrigger {
+    shared: Arc<WorkerShared>,
+}
+
+impl RepinTrigger {
+    pub fn trigger(&self) {
+        self.shared.control.lock_or_recover().triggered = true;
+        self.shared.wakeup.notify_all();
+    }
+}
+
+/// Handle to a background pin/unpin thread. Dropping it shuts the worker down.
+#[derive(Debug)]
+pub struct PinWorker {
+    shared: Arc<WorkerShared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PinWorker {
+    /// Run `cycle` on a background thread every `interval`, or as the cache's adaptive
+    /// interval says.
+    pub(crate) fn spawn<Slab, CB, F>(
+        mut zero_copy_cache: ZeroCopyCache<Slab, CB>,
+        priv_info: Slab::PrivateInfo,
+        interval: Duration,
+        mut cycle: F,
+    ) -> Self
+    where
+        Slab: DatapathSlab + std::fmt::Debug + 'static,
+        Slab::PrivateInfo: Send + 'static,
+        CB: SegmentPolicy<Slab> + 'static,
+        ZeroCopyCache<Slab, CB>: Send,
+        F: FnMut(&mut ZeroCopyCache<Slab, CB>, &Slab::PrivateInfo) -> HotsetChurn + Send + 'static,
+    {
+        let shared = Arc::new(WorkerShared::default());
+        if let Some(miss_rate_trigger) = zero_copy_cache.get_miss_rate_trigger() {
+            miss_rate_trigger.attach(RepinTrigger {
+                shared: shared.clone(),
+            });
+        }
+        let worker_shared = shared.clone();
+        let handle = thread::spawn(move || {
+            let mut next_cycle = Instant::now() + interval;
+            loop {
+                {
+                    let mut control = worker_shared.control.lock_or_recover();
+                    loop {
+                        if control.shutdown {
+                            drop(control);
+                            let unpinned = zero_copy_cache.unpin_all_segments();
+                            tracing::debug!("Pin worker stopped, unpinned {} segments", unpinned);
+                            return;
+                        }
+                        let now = Instant::now();
+                        if control.triggered || (!control.paused && now >= next_cycle) {
+                            control.triggered = false;
+                            break;
+                        }
+                        let timeout = next_cycle.saturating_duration_since(now);
+                        control = if control.paused {
+                            recover(worker_shared.wakeup.wait(control))
+                        } else {
+                            recover(worker_shared.wakeup.wait_timeout(control, timeout)).0
+                        };
+                    }
+                }
+                let churn = cycle(&mut zero_copy_cache, &priv_info);
+                next_cycle = Instant::now() + zero_copy_cache.next_repin_interval(&churn, interval);
+                worker_shared.control.lock_or_recover().cycles += 1;
+                worker_shared.wakeup.notify_all();
+            }
+        });
+        PinWorker {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WorkerControl)) {
+        f(&mut self.shared.control.lock_or_recover());
+        self.shared.wakeup.notify_all();
+    }
+
+    /// Stop running scheduled repin cycles; `trigger_now` still runs one.
+    pub fn pause(&self) {
+        self.update(|control| control.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|control| control.paused = false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.control.lock_or_recover().paused
+    }
+
+    /// Run a repin cycle as soon as possible, without waiting for the interval.
+    pub fn trigger_now(&self) {
+        self.update(|control| control.triggered = true);
+    }
+
+    pub fn repin_trigger(&self) -> RepinTrigger {
+        RepinTrigger {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Repin cycles completed so far.
+    pub fn cycles(&self) -> u64 {
+        self.shared.control.lock_or_recover().cycles
+    }
+
+    /// Block until at least `cycles` repin cycles have completed or `timeout` elapses.
+    /// Returns whether they completed.
+    pub fn wait_for_cycles(&self, cycles: u64, timeout: Duration) -> bool {
+        let control = self.shared.control.lock_or_recover();
+        let (control, _) = recover(self.shared.wakeup.wait_timeout_while(
+            control,
+            timeout,
+            |control| control.cycles < cycles,
+        ));
+        control.cycles >= cycles
+    }
+
+    /// Finish any cycle in progress, unpin every segment and join the thread.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.update(|control| control.shutdown = true);
+            if handle.join().is_err() {
+                tracing::error!("Pin worker thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for PinWorker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
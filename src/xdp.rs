@@ -0,0 +1,172 @@
+//! `DatapathSlab` adapter for AF_XDP. A UMEM cannot be resized once registered, so each
+//! pinned segment is registered as a UMEM of its own on a fresh AF_XDP socket, which makes
+//! the kernel pin its pages; unpinning closes the socket and releases them. `IOInfo` is the
+//! socket fd, which the datapath binds to a queue (or shares with `XDP_SHARED_UMEM`) and sets
+//! the rings up on.
+//!
+//! Creating AF_XDP sockets requires `CAP_NET_RAW`.
+use std::io;
+use std::os::raw::c_void;
+use std::os::unix::io::RawFd;
+
+use crate::data_structures::DatapathSlab;
+use crate::pagesizes::PageSize;
+
+/// How segments are carved into UMEM frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XdpUmemConfig {
+    /// Frame size, a power of two between 2048 and the page size unless unaligned.
+    pub chunk_size: u32,
+    /// Bytes reserved at the start of every frame.
+    pub headroom: u32,
+    /// Let frames start anywhere (`XDP_UMEM_UNALIGNED_CHUNK_FLAG`).
+    pub unaligned_chunks: bool,
+}
+
+impl Default for XdpUmemConfig {
+    fn default() -> Self {
+        XdpUmemConfig {
+            chunk_size: 4096,
+            headroom: 0,
+            unaligned_chunks: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct XdpPinningState {
+    /// Socket the segment is registered on, while pinned.
+    fd: Option<RawFd>,
+    config: XdpUmemConfig,
+}
+
+#[derive(Debug)]
+pub struct XdpUmemSlab {
+    slab_id: usize,
+    start_address: *mut c_void,
+    num_pages: usize,
+    page_size: PageSize,
+    config: XdpUmemConfig,
+}
+
+// SAFETY: the slab only describes memory owned by the application; see `XdpUmemSlab::new`.
+unsafe impl Send for XdpUmemSlab {}
+unsafe impl Sync for XdpUmemSlab {}
+
+impl XdpUmemSlab {
+    /// Describe `num_pages` pages of `page_size` at `start_address`, registered with
+    /// `config` when pinned.
+    ///
+    /// # Safety
+    /// The memory must stay mapped for as long as the slab's segments are managed by a cache.
+    pub unsafe fn new(
+        slab_id: usize,
+        start_address: *mut c_void,
+        num_pages: usize,
+        page_size: PageSize,
+        config: XdpUmemConfig,
+    ) -> Self {
+        XdpUmemSlab {
+            slab_id,
+            start_address,
+            num_pages,
+            page_size,
+            config,
+        }
+    }
+}
+
+/// Open an AF_XDP socket and register `len` bytes at `addr` as its UMEM.
+fn register_umem(addr: *mut c_void, len: usize, config: &XdpUmemConfig) -> io::Result<RawFd> {
+    let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let reg = libc::xdp_umem_reg {
+        addr: addr as u64,
+        len: len as u64,
+        chunk_size: config.chunk_size,
+        headroom: config.headroom,
+        flags: if config.unaligned_chunks {
+            libc::XDP_UMEM_UNALIGNED_CHUNK_FLAG
+        } else {
+            0
+        },
+        tx_metadata_len: 0,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_XDP,
+            libc::XDP_UMEM_REG,
+            &reg as *const libc::xdp_umem_reg as *const c_void,
+            std::mem::size_of::<libc::xdp_umem_reg>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+impl DatapathSlab for XdpUmemSlab {
+    type SlabId = usize;
+    /// AF_XDP socket holding the segment's UMEM, -1 while it is not pinned.
+    type IOInfo = RawFd;
+    type PinningState = XdpPinningState;
+    type PrivateInfo = ();
+
+    fn default_pinning_state(&self) -> Self::PinningState {
+        XdpPinningState {
+            fd: None,
+            config: self.config,
+        }
+    }
+
+    fn get_slab_id(&self) -> Self::SlabId {
+        self.slab_id
+    }
+
+    fn is_pinned(pinning_state: &Self::PinningState) -> bool {
+        pinning_state.fd.is_some()
+    }
+
+    fn pin_segment(
+        pinning_state: &mut Self::PinningState,
+        _private_info: &Self::PrivateInfo,
+        start_address: *mut c_void,
+        len: usize,
+    ) {
+        if pinning_state.fd.is_some() {
+            return;
+        }
+        match register_umem(start_address, len, &pinning_state.config) {
+            Ok(fd) => pinning_state.fd = Some(fd),
+            Err(e) => tracing::warn!("Registering a {} byte UMEM failed: {}", len, e),
+        }
+    }
+
+    fn unpin_segment(pinning_state: &mut Self::PinningState) {
+        if let Some(fd) = pinning_state.fd.take() {
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    fn get_io_info(pinning_state: &Self::PinningState) -> Self::IOInfo {
+        pinning_state.fd.unwrap_or(-1)
+    }
+
+    fn get_total_num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn get_start_address(&self) -> *mut c_void {
+        self.start_address
+    }
+
+    fn get_page_size(&self) -> PageSize {
+        self.page_size.clone()
+    }
+}